@@ -13,6 +13,7 @@ from agentica_internal.core.utils import is_type_like
 from agentica_internal.warpc.frame import ResourceHandle
 from agentica_internal.warpc.requests import ResourceCallSystemMethod
 from agentica_internal.warpc.worlds.agent_world import AgentWorld
+from agentica_internal.warpc.worlds.interface import QUIT
 from typeguard import check_type
 
 from . import (
@@ -127,8 +128,12 @@ class WitWorld(wit_world.WitWorld):
         COUNT += 1
         self.COUNT = COUNT
 
-    def init_exec_env(self, id_name: str, log_tags: str | None) -> None:
-        global AGENT_WORLD, AGENT_REPL, EVENT_LOOP, INITIALIZED
+    def init_exec_env(
+        self, id_name: str, log_tags: str | None, config: 'wit_world.InitConfig | None' = None
+    ) -> None:
+        global AGENT_WORLD, AGENT_REPL, EVENT_LOOP, INITIALIZED, INIT_CONFIG
+
+        INIT_CONFIG = config
 
         if log_tags is not None:
             set_log_tags(log_tags)
@@ -147,13 +152,20 @@ class WitWorld(wit_world.WitWorld):
         assert INITIALIZED
         AGENT_WORLD.run_msg_loop(
             wit_world.send_bytes,
-            wit_world.recv_bytes,
+            recv_bytes,
             wit_world.recv_ready,
         )
 
 
+def recv_bytes() -> bytes:
+    # an empty payload is end of stream (see recv-bytes in env.wit), which the
+    # agent loop only stops on as QUIT
+    return wit_world.recv_bytes() or QUIT
+
+
 COUNT: int = 0
 INITIALIZED: bool = False
+INIT_CONFIG: 'wit_world.InitConfig | None' = None
 AGENT_WORLD: AgentWorld
 AGENT_REPL: AgentRepl
 EVENT_LOOP: AbstractEventLoop
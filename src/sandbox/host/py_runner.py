@@ -1,12 +1,14 @@
 import asyncio
 import concurrent.futures
+import enum
 import importlib.util
 import sys
 import threading
 import types
 from asyncio import AbstractEventLoopPolicy
+from dataclasses import dataclass
 from pathlib import Path
-from typing import Protocol
+from typing import Any, Protocol
 
 from agentica_internal.core import print as P
 from agentica_internal.core.log import LogBase, set_log_tags
@@ -15,12 +17,44 @@ from agentica_internal.warpc.worlds.interface import *
 
 __all__ = [
     'PyWasmRunner',
+    'LoopOutcome',
+    'OutcomeKind',
 ]
 
 
+class OutcomeKind(enum.Enum):
+    """Mirrors host.OutcomeKind. Only NormalExit occurs here: failures raise as they are."""
+
+    NormalExit = enum.auto()
+    Trap = enum.auto()
+    HostError = enum.auto()
+    Timeout = enum.auto()
+    InitTimeout = enum.auto()
+    FuelExhausted = enum.auto()
+    StackOverflow = enum.auto()
+    MemoryLimit = enum.auto()
+    MessageTooLarge = enum.auto()
+    CodecError = enum.auto()
+    ExitStatus = enum.auto()
+    Error = enum.auto()
+
+
+@dataclass(frozen=True)
+class LoopOutcome:
+    """What PyWasmRunner.run_msg_loop resolves to, shaped like host.LoopOutcome."""
+
+    kind: OutcomeKind
+    exit_code: int | None = None
+    reason: str | None = None
+    stop_reason: str | None = None
+    backtrace: str | None = None
+    py_exception: BaseException | None = None
+    host_traceback: str | None = None
+
+
 class ExecEnv(Protocol):
     def __init__(self): ...
-    def init_exec_env(self, id_name: str, log_tags: str | None) -> None: ...
+    def init_exec_env(self, id_name: str, log_tags: str | None, config: Any = None) -> None: ...
     def get_event_loop(self) -> asyncio.AbstractEventLoop: ...
     def run_msg_loop(self): ...
 
@@ -251,13 +285,14 @@ class PyWasmRunner(LogBase, AbstractEventLoopPolicy):
             assert self.guest_task is not None, "could not create guest task"
             return self.guest_task  # type: ignore[return-value]
 
-    async def run_msg_loop(self) -> None:
+    async def run_msg_loop(self) -> LoopOutcome:
         with self.log_as('guest_run_msg_loop') as ctx:
             try:
                 task = self.host_run_msg_loop()
                 ctx.log('task = ', task)
                 ctx.log('result = ', await task)
                 ctx.log('task = ', task)
+                return LoopOutcome(OutcomeKind.NormalExit)
             finally:
                 self.guest_task = None
 
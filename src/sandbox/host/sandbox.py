@@ -57,6 +57,19 @@ type WasmRunnerMode = Literal['no_sandbox', 'wasm']
 type ExceptionHandlerFn = Callable[[BaseException], Awaitable[None]]
 
 
+class LoopOutcomeP(Protocol):
+    """How a run_msg_loop call ended: host.LoopOutcome or py_runner.LoopOutcome."""
+
+    @property
+    def kind(self) -> Any: ...
+    @property
+    def exit_code(self) -> int | None: ...
+    @property
+    def reason(self) -> str | None: ...
+    @property
+    def stop_reason(self) -> str | None: ...
+
+
 class WasmRunnerP(Protocol):
     def __init__(
         self,
@@ -73,7 +86,7 @@ class WasmRunnerP(Protocol):
         wasm_inherit_io: bool = True,
     ) -> None: ...
 
-    async def run_msg_loop(self) -> None: ...
+    async def run_msg_loop(self) -> LoopOutcomeP: ...
 
     def close(self) -> None: ...
 
@@ -93,7 +106,7 @@ class Sandbox(LogBase):
 
     _runner: WasmRunnerP | None
     _runner_cls: type[WasmRunnerP]
-    _future: asyncio.Future[None] | None
+    _future: asyncio.Future[LoopOutcomeP] | None
     _run_task: asyncio.Task[None] | None
 
     _lazy_init: dict[str, Any]
@@ -0,0 +1,128 @@
+//! Bounded sends, for `max_inflight_messages=` and `max_inflight_bytes=`.
+//! Without them `send_bytes` returns to the guest only once the callback's
+//! coroutine has finished. With them the coroutine is left running and the
+//! guest carries on while the window has room; once that many messages (or
+//! payload bytes) are still being handled, the next `send_bytes` waits for
+//! one to finish. A fast guest is throttled to the consumer's pace instead
+//! of queueing without bound.
+//!
+//! The callback is still called in send order, only its completion
+//! overlaps. A send that fails after the guest moved on fails the guest's
+//! next `send_bytes`, or the loop once its guest returns, when the window
+//! is flushed. Waiting for room ends at `close()` and at the `timeout_ms`
+//! deadline, so a stalled consumer fails the loop rather than hanging it.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, watch};
+
+use crate::errors::{Closed, Timeout};
+
+#[derive(Clone, Copy)]
+pub(crate) struct WindowLimits {
+    pub messages: Option<u32>,
+    pub bytes: Option<u32>,
+}
+
+/// One store's window; a fresh store starts with an empty one.
+pub(crate) struct SendWindow {
+    messages: Option<(Arc<Semaphore>, u32)>,
+    bytes: Option<(Arc<Semaphore>, u32)>,
+    /* first failure of a send the guest has already moved past */
+    failed: Arc<Mutex<Option<wasmtime::Error>>>,
+}
+
+/// Room for one message, given back when its send finishes.
+pub(crate) struct Slot {
+    _permits: Vec<OwnedSemaphorePermit>,
+}
+
+impl SendWindow {
+    pub fn new(limits: WindowLimits) -> Self {
+        let window = |max: u32| (Arc::new(Semaphore::new(max as usize)), max);
+        SendWindow {
+            messages: limits.messages.map(window),
+            bytes: limits.bytes.map(window),
+            failed: Arc::default(),
+        }
+    }
+
+    /// Wait for room for a `len`-byte message. A message larger than
+    /// `max_inflight_bytes` waits for the whole window and goes alone.
+    pub async fn reserve(
+        &self,
+        len: usize,
+        closed: watch::Receiver<bool>,
+        deadline: Option<(Instant, Duration)>,
+    ) -> wasmtime::Result<Slot> {
+        self.check()?;
+        let wanted = [
+            self.messages.as_ref().map(|(sem, _)| (sem, 1)),
+            self.bytes.as_ref().map(|(sem, max)| (sem, u32::try_from(len).unwrap_or(u32::MAX).clamp(1, *max))),
+        ];
+        let mut permits = Vec::with_capacity(2);
+        for (sem, n) in wanted.into_iter().flatten() {
+            permits.push(bounded(sem.clone().acquire_many_owned(n), closed.clone(), deadline).await?);
+        }
+        Ok(Slot { _permits: permits })
+    }
+
+    /// Run a send the guest no longer waits for, keeping its failure for
+    /// `check`.
+    pub fn spawn<F>(&self, slot: Slot, send: F)
+    where
+        F: Future<Output = wasmtime::Result<()>> + Send + 'static,
+    {
+        let failed = self.failed.clone();
+        tokio::spawn(async move {
+            let _slot = slot;
+            if let Err(e) = send.await {
+                crate::shutdown::lock(&failed).get_or_insert(e);
+            }
+        });
+    }
+
+    /// Fail with the first send that failed since the last check.
+    pub fn check(&self) -> wasmtime::Result<()> {
+        match crate::shutdown::lock(&self.failed).take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Wait for every outstanding send to finish, then `check`.
+    pub async fn flush(&self, closed: watch::Receiver<bool>, deadline: Option<(Instant, Duration)>) -> wasmtime::Result<()> {
+        for (sem, max) in [&self.messages, &self.bytes].into_iter().flatten() {
+            // holding the whole window means nothing is outstanding
+            drop(bounded(sem.clone().acquire_many_owned(*max), closed.clone(), deadline).await?);
+        }
+        self.check()
+    }
+}
+
+/// `acquire`, given up at `close()` or the loop deadline.
+async fn bounded(
+    acquire: impl Future<Output = Result<OwnedSemaphorePermit, tokio::sync::AcquireError>>,
+    mut closed: watch::Receiver<bool>,
+    deadline: Option<(Instant, Duration)>,
+) -> wasmtime::Result<OwnedSemaphorePermit> {
+    let expired = async {
+        match deadline {
+            Some((at, limit)) => {
+                tokio::time::sleep_until(at.into()).await;
+                limit
+            }
+            None => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        permit = acquire => Ok(permit.expect("window semaphores are never closed")),
+        () = async {
+            // a dropped runner never sets the flag
+            if closed.wait_for(|c| *c).await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        } => Err(wasmtime::Error::new(Closed)),
+        limit = expired => Err(wasmtime::Error::new(Timeout(limit))),
+    }
+}
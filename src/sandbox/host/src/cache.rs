@@ -0,0 +1,313 @@
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Suffix identifying compiled artifacts in a cache directory.
+pub(crate) const COMPILED_SUFFIX: &str = ".compiled";
+/// Appended to an artifact's path for its `ArtifactMeta` sidecar.
+const META_SUFFIX: &str = ".meta";
+/// Appended to an artifact's path for the lock serialising its writers.
+const LOCK_SUFFIX: &str = ".lock";
+
+/// What a compiled artifact was built from, kept in a sidecar next to it.
+/// An artifact is only reused when its sidecar matches exactly, which
+/// unlike mtimes survives checkouts, copies and coarse timestamps.
+///
+/// `engine` fingerprints the engine via `precompile_compatibility_hash`,
+/// which covers the wasmtime version, target and compilation settings, so
+/// an artifact from another wasmtime release or an incompatibly configured
+/// engine is never even handed to `deserialize_file`.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub(crate) struct ArtifactMeta {
+    wasm_sha256: String,
+    engine: String,
+}
+
+/// Feeds a `Hash` impl into SHA-256, for a fingerprint that is stable
+/// across processes (unlike `DefaultHasher`, which is unspecified).
+struct Sha256Hasher(Sha256);
+
+impl std::hash::Hasher for Sha256Hasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    // only the digest is used
+    fn finish(&self) -> u64 {
+        0
+    }
+}
+
+fn to_hex(digest: &[u8]) -> String {
+    let mut hex = String::with_capacity(digest.len() * 2);
+    for byte in digest {
+        let _ = write!(hex, "{byte:02x}");
+    }
+    hex
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+fn meta_path(compiled: &Path) -> PathBuf {
+    with_suffix(compiled, META_SUFFIX)
+}
+
+/// Write `contents` to a temporary file beside `path` and rename it into
+/// place, so readers see either the old file or the complete new one.
+fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let tmp = with_suffix(path, &format!(".tmp.{}", std::process::id()));
+    let res = fs::write(&tmp, contents).and_then(|()| fs::rename(&tmp, path));
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    res
+}
+
+/// Take the advisory lock for `compiled`, blocking until other writers
+/// (in this or another process) are done. Released when the file is
+/// dropped. Best effort: None if the lock cannot be taken, e.g. on a
+/// read-only cache directory.
+pub(crate) fn lock(compiled: &Path) -> Option<fs::File> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(with_suffix(compiled, LOCK_SUFFIX))
+        .ok()?;
+    file.lock().ok()?;
+    Some(file)
+}
+
+/// Store a freshly compiled artifact and its sidecar. The old sidecar is
+/// removed first and the new one written last, so a reader never pairs a
+/// sidecar with an artifact it does not describe.
+pub(crate) fn write_artifact(
+    compiled: &Path,
+    blob: &[u8],
+    meta: &ArtifactMeta,
+) -> std::io::Result<()> {
+    ArtifactMeta::clear(compiled);
+    write_atomic(compiled, blob)?;
+    meta.write(compiled)
+}
+
+impl ArtifactMeta {
+    pub fn new(engine: &wasmtime::Engine, wasm: &[u8]) -> Self {
+        use std::hash::Hash;
+        let mut hasher = Sha256Hasher(Sha256::new());
+        engine.precompile_compatibility_hash().hash(&mut hasher);
+        ArtifactMeta {
+            wasm_sha256: to_hex(&Sha256::digest(wasm)),
+            engine: to_hex(&hasher.0.finalize()),
+        }
+    }
+
+    /// The sidecar stored for `compiled`, or None if missing or unreadable.
+    pub fn read(compiled: &Path) -> Option<Self> {
+        let text = fs::read_to_string(meta_path(compiled)).ok()?;
+        let (mut wasm_sha256, mut engine) = (None, None);
+        for line in text.lines() {
+            match line.split_once(' ') {
+                Some(("wasm-sha256", value)) => wasm_sha256 = Some(value.to_string()),
+                Some(("engine", value)) => engine = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        Some(ArtifactMeta {
+            wasm_sha256: wasm_sha256?,
+            engine: engine?,
+        })
+    }
+
+    pub fn write(&self, compiled: &Path) -> std::io::Result<()> {
+        let text = format!("wasm-sha256 {}\nengine {}\n", self.wasm_sha256, self.engine);
+        write_atomic(&meta_path(compiled), text.as_bytes())
+    }
+
+    /// Remove the sidecar for `compiled`, so the artifact is not trusted
+    /// while it is being rewritten.
+    pub fn clear(compiled: &Path) {
+        let _ = fs::remove_file(meta_path(compiled));
+    }
+}
+
+/// Limits for `evict`; `None` leaves that dimension unbounded.
+pub(crate) struct EvictionBudget {
+    pub max_bytes: Option<u64>,
+    pub max_files: Option<usize>,
+    pub max_age: Option<Duration>,
+}
+
+struct Entry {
+    path: PathBuf,
+    len: u64,
+    last_used: SystemTime,
+}
+
+/// Record that a cached artifact was just used, so LRU eviction keeps it.
+/// Filesystems mounted `noatime` never update access times on their own.
+/// Best effort: a read-only cache simply stays in mtime order.
+pub(crate) fn touch(path: &Path) {
+    if let Ok(file) = fs::File::open(path) {
+        let _ = file.set_times(fs::FileTimes::new().set_accessed(SystemTime::now()));
+    }
+}
+
+/// Remove least-recently-used compiled artifacts in `dir` until the budget
+/// is met, returning the removed paths.
+///
+/// Safe to run while loaders are active: on Unix a loader that already
+/// opened an artifact keeps its inode, and a missing artifact just makes the
+/// next loader recompile. Files that disappear underneath us (another
+/// evictor, a concurrent rewrite) are skipped rather than reported as errors.
+pub(crate) fn evict(dir: &Path, budget: &EvictionBudget) -> std::io::Result<Vec<PathBuf>> {
+    let now = SystemTime::now();
+    let mut entries = Vec::new();
+    for dirent in fs::read_dir(dir)? {
+        let dirent = dirent?;
+        let name = dirent.file_name();
+        if !name.to_string_lossy().ends_with(COMPILED_SUFFIX) {
+            continue;
+        }
+        let Ok(meta) = dirent.metadata() else { continue };
+        if !meta.is_file() {
+            continue;
+        }
+        let modified = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        let accessed = meta.accessed().unwrap_or(modified);
+        entries.push(Entry {
+            path: dirent.path(),
+            len: meta.len(),
+            last_used: accessed.max(modified),
+        });
+    }
+
+    // newest first, so the budget is spent on the most recently used artifacts
+    entries.sort_by_key(|e| std::cmp::Reverse(e.last_used));
+
+    let mut kept_bytes = 0u64;
+    let mut kept_files = 0usize;
+    let mut evicted = Vec::new();
+    for entry in entries {
+        let too_old = budget.max_age.is_some_and(|max| {
+            now.duration_since(entry.last_used).unwrap_or_default() > max
+        });
+        let too_many = budget.max_files.is_some_and(|max| kept_files >= max);
+        let too_big = budget.max_bytes.is_some_and(|max| kept_bytes + entry.len > max);
+        if too_old || too_many || too_big {
+            match fs::remove_file(&entry.path) {
+                Ok(()) => {
+                    ArtifactMeta::clear(&entry.path);
+                    evicted.push(entry.path)
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        } else {
+            kept_bytes += entry.len;
+            kept_files += 1;
+        }
+    }
+    Ok(evicted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Scratch, component};
+
+    #[test]
+    fn sidecar_round_trips() {
+        let dir = Scratch::new("sidecar");
+        let compiled = dir.join("env.compiled");
+        let meta = ArtifactMeta::new(&wasmtime::Engine::default(), &component("a"));
+        assert!(ArtifactMeta::read(&compiled).is_none());
+        meta.write(&compiled).unwrap();
+        assert!(ArtifactMeta::read(&compiled) == Some(meta));
+        ArtifactMeta::clear(&compiled);
+        assert!(ArtifactMeta::read(&compiled).is_none());
+    }
+
+    #[test]
+    fn keyed_on_the_wasm_bytes() {
+        let engine = wasmtime::Engine::default();
+        let a = ArtifactMeta::new(&engine, &component("a"));
+        assert!(a == ArtifactMeta::new(&engine, &component("a")));
+        // same length, and no mtime involved: only the contents differ
+        assert!(a != ArtifactMeta::new(&engine, &component("b")));
+    }
+
+    #[test]
+    fn incomplete_sidecar_is_ignored() {
+        let dir = Scratch::new("incomplete");
+        let compiled = dir.join("env.compiled");
+        fs::write(meta_path(&compiled), "wasm-sha256 00\n").unwrap();
+        assert!(ArtifactMeta::read(&compiled).is_none());
+    }
+
+    #[test]
+    fn keyed_on_the_engine() {
+        let mut fuel = wasmtime::Config::new();
+        fuel.consume_fuel(true);
+        let fuel = wasmtime::Engine::new(&fuel).unwrap();
+        let bytes = component("a");
+        assert!(ArtifactMeta::new(&wasmtime::Engine::default(), &bytes) != ArtifactMeta::new(&fuel, &bytes));
+    }
+
+    /// An artifact of `len` bytes last used `age_secs` ago.
+    fn artifact(dir: &Scratch, name: &str, len: usize, age_secs: u64) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, vec![0u8; len]).unwrap();
+        let then = SystemTime::now() - Duration::from_secs(age_secs);
+        let times = fs::FileTimes::new().set_accessed(then).set_modified(then);
+        fs::File::options().write(true).open(&path).unwrap().set_times(times).unwrap();
+        path
+    }
+
+    fn budget(max_bytes: Option<u64>, max_files: Option<usize>, max_age: Option<Duration>) -> EvictionBudget {
+        EvictionBudget {
+            max_bytes,
+            max_files,
+            max_age,
+        }
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_first() {
+        let dir = Scratch::new("evict-files");
+        let old = artifact(&dir, "old.compiled", 10, 300);
+        let mid = artifact(&dir, "mid.compiled", 10, 200);
+        let new = artifact(&dir, "new.compiled", 10, 100);
+        let other = artifact(&dir, "notes.txt", 10, 900);
+        let evicted = evict(dir.path(), &budget(None, Some(2), None)).unwrap();
+        assert_eq!(evicted, vec![old.clone()]);
+        assert!(!old.exists() && mid.exists() && new.exists() && other.exists());
+    }
+
+    #[test]
+    fn keeps_the_newest_within_the_byte_budget() {
+        let dir = Scratch::new("evict-bytes");
+        let old = artifact(&dir, "old.compiled", 40, 300);
+        let mid = artifact(&dir, "mid.compiled", 40, 200);
+        let new = artifact(&dir, "new.compiled", 40, 100);
+        let evicted = evict(dir.path(), &budget(Some(100), None, None)).unwrap();
+        assert_eq!(evicted, vec![old]);
+        assert!(mid.exists() && new.exists());
+    }
+
+    #[test]
+    fn evicts_by_age() {
+        let dir = Scratch::new("evict-age");
+        let old = artifact(&dir, "old.compiled", 10, 3_600);
+        let new = artifact(&dir, "new.compiled", 10, 0);
+        let evicted = evict(dir.path(), &budget(None, None, Some(Duration::from_secs(60)))).unwrap();
+        assert_eq!(evicted, vec![old]);
+        assert!(new.exists());
+    }
+}
@@ -0,0 +1,113 @@
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Power-of-two latency buckets in microseconds: bucket `i` holds calls
+/// under `2^i` us, the last one everything slower.
+const BUCKETS: usize = 32;
+
+/// Call counts and latency per host callback, for `host_call_stats=True`.
+/// A call is timed from the guest entering the import to it getting the
+/// result back, so it includes awaiting an async callback. Percentiles are
+/// read off the buckets and so are upper bounds, within a factor of two.
+#[derive(Default)]
+pub(crate) struct CallStats {
+    imports: HashMap<&'static str, ImportStats>,
+}
+
+struct ImportStats {
+    calls: u64,
+    total: Duration,
+    max: Duration,
+    buckets: [u64; BUCKETS],
+}
+
+impl CallStats {
+    pub fn record(&mut self, import: &'static str, spent: Duration) {
+        let stats = self.imports.entry(import).or_insert(ImportStats {
+            calls: 0,
+            total: Duration::ZERO,
+            max: Duration::ZERO,
+            buckets: [0; BUCKETS],
+        });
+        stats.calls += 1;
+        stats.total += spent;
+        stats.max = stats.max.max(spent);
+        let micros = spent.as_micros().min(u64::MAX as u128) as u64;
+        let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+        stats.buckets[bucket.min(BUCKETS - 1)] += 1;
+    }
+
+    /// `{name: {"calls", "total_ms", "mean_ms", "max_ms", "p50_ms",
+    /// "p90_ms", "p99_ms"}}`.
+    pub fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let d = PyDict::new(py);
+        for (name, stats) in &self.imports {
+            let s = PyDict::new(py);
+            s.set_item("calls", stats.calls)?;
+            s.set_item("total_ms", ms(stats.total))?;
+            s.set_item("mean_ms", ms(stats.total) / stats.calls as f64)?;
+            s.set_item("max_ms", ms(stats.max))?;
+            s.set_item("p50_ms", ms(stats.percentile(0.50)))?;
+            s.set_item("p90_ms", ms(stats.percentile(0.90)))?;
+            s.set_item("p99_ms", ms(stats.percentile(0.99)))?;
+            d.set_item(*name, s)?;
+        }
+        Ok(d)
+    }
+}
+
+impl ImportStats {
+    /// Upper edge of the bucket holding the `q` quantile, capped at the
+    /// slowest call seen.
+    fn percentile(&self, q: f64) -> Duration {
+        let rank = ((self.calls as f64) * q).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_micros(1 << i).min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+fn ms(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recorded(micros: &[u64]) -> CallStats {
+        let mut stats = CallStats::default();
+        for &us in micros {
+            stats.record("send_bytes", Duration::from_micros(us));
+        }
+        stats
+    }
+
+    #[test]
+    fn counts_and_totals_each_import() {
+        let mut stats = recorded(&[10, 20, 30]);
+        stats.record("recv_bytes", Duration::from_micros(7));
+        let send = &stats.imports["send_bytes"];
+        assert_eq!(send.calls, 3);
+        assert_eq!(send.total, Duration::from_micros(60));
+        assert_eq!(send.max, Duration::from_micros(30));
+        assert_eq!(stats.imports["recv_bytes"].calls, 1);
+    }
+
+    #[test]
+    fn percentiles_are_bucket_upper_bounds() {
+        let stats = recorded(&[10, 20, 30, 40, 5000]);
+        let send = &stats.imports["send_bytes"];
+        // the third call, 30us, lies in the bucket under 32us
+        assert_eq!(send.percentile(0.50), Duration::from_micros(32));
+        // the slowest call's bucket ends at 8192us, beyond the call itself
+        assert_eq!(send.percentile(0.99), Duration::from_micros(5000));
+    }
+}
@@ -0,0 +1,146 @@
+//! Structured messages, for `codec="json"` or `codec="msgpack"`: the host
+//! encodes and decodes so Python hands objects back and forth while the
+//! guest still sees bytes. The `send_bytes` callback receives the decoded
+//! message, `recv_bytes` may return any encodable object and
+//! `preload_messages` takes objects too. It is a layer over the byte
+//! imports: size limits, transforms and metrics still apply to the encoded
+//! bytes, and None from `recv_bytes` is still end of stream.
+//!
+//! JSON takes what `serde_json` represents (no bytes); MessagePack also
+//! carries bytes, which decode to `bytes`. A message that does not encode
+//! or decode fails the loop with `WasmCodecError`, never `WasmHostError`.
+
+use pyo3::prelude::*;
+
+use crate::errors::CodecError;
+
+#[derive(Clone, Copy)]
+pub(crate) enum Codec {
+    Json,
+    MsgPack,
+}
+
+impl Codec {
+    pub fn parse(name: &str) -> PyResult<Self> {
+        match name {
+            "json" => Ok(Codec::Json),
+            "msgpack" => Ok(Codec::MsgPack),
+            _ => Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "WasmRunner: unknown codec {name:?}; expected \"json\" or \"msgpack\""
+            ))),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Codec::Json => "json",
+            Codec::MsgPack => "msgpack",
+        }
+    }
+
+    /// A message from Python, as bytes for the guest.
+    pub fn encode(self, import: &'static str, obj: &Bound<'_, PyAny>) -> wasmtime::Result<Vec<u8>> {
+        let value = match self {
+            Codec::Json => pythonize::depythonize(obj).map(Value::Json),
+            Codec::MsgPack => pythonize::depythonize(obj).map(Value::MsgPack),
+        };
+        value
+            .map_err(|e| e.to_string())
+            .and_then(|value| value.to_bytes())
+            .map_err(|message| self.error(import, message))
+    }
+
+    /// A message from the guest, as a Python object.
+    pub fn decode(self, py: Python<'_>, import: &'static str, bytes: &[u8]) -> wasmtime::Result<PyObject> {
+        let decoded = self.read(bytes).and_then(|value| {
+            match &value {
+                Value::Json(value) => pythonize::pythonize(py, value),
+                Value::MsgPack(value) => pythonize::pythonize(py, value),
+            }
+            .map_err(|e| e.to_string())
+        });
+        decoded.map(Bound::unbind).map_err(|message| self.error(import, message))
+    }
+
+    fn read(self, bytes: &[u8]) -> Result<Value, String> {
+        match self {
+            Codec::Json => serde_json::from_slice(bytes).map(Value::Json).map_err(|e| e.to_string()),
+            Codec::MsgPack => {
+                let mut rest = bytes;
+                let value = rmpv::decode::read_value(&mut rest).map_err(|e| e.to_string())?;
+                if !rest.is_empty() {
+                    return Err(format!("{} trailing bytes after the message", rest.len()));
+                }
+                Ok(Value::MsgPack(value))
+            }
+        }
+    }
+
+    fn error(self, import: &'static str, message: String) -> wasmtime::Error {
+        wasmtime::Error::new(CodecError {
+            import,
+            codec: self.name(),
+            message,
+        })
+    }
+}
+
+/// A message between its Python and its encoded form.
+#[derive(Debug, PartialEq)]
+enum Value {
+    Json(serde_json::Value),
+    MsgPack(rmpv::Value),
+}
+
+impl Value {
+    fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        match self {
+            Value::Json(value) => serde_json::to_vec(value).map_err(|e| e.to_string()),
+            Value::MsgPack(value) => {
+                let mut out = Vec::new();
+                rmpv::encode::write_value(&mut out, value).map_err(|e| e.to_string())?;
+                Ok(out)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_round_trips() {
+        let value = Value::Json(serde_json::json!({"op": "eval", "args": [1, 2.5, null, "x"], "ok": true}));
+        let bytes = value.to_bytes().unwrap();
+        assert_eq!(Codec::Json.read(&bytes).unwrap(), value);
+    }
+
+    #[test]
+    fn msgpack_round_trips_bytes() {
+        let value = Value::MsgPack(rmpv::Value::Map(vec![
+            ("op".into(), "eval".into()),
+            ("blob".into(), rmpv::Value::Binary(vec![0, 159, 255])),
+            ("n".into(), (-7).into()),
+        ]));
+        let bytes = value.to_bytes().unwrap();
+        assert_eq!(Codec::MsgPack.read(&bytes).unwrap(), value);
+    }
+
+    #[test]
+    fn msgpack_rejects_trailing_bytes() {
+        let mut bytes = Value::MsgPack(1.into()).to_bytes().unwrap();
+        bytes.extend_from_slice(&[0xc0, 0xc0]);
+        assert_eq!(
+            Codec::MsgPack.read(&bytes).unwrap_err(),
+            "2 trailing bytes after the message"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_messages() {
+        assert!(Codec::Json.read(b"{\"op\": ").is_err());
+        assert!(Codec::Json.read(b"").is_err());
+        assert!(Codec::MsgPack.read(&[0x92, 0x01]).is_err());
+    }
+}
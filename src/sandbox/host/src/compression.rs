@@ -0,0 +1,90 @@
+//! Reading components stored compressed. `wasm_path` may point at a gzip
+//! or zstd file (usually `.wasm.gz` / `.wasm.zst`); the format is sniffed
+//! from its magic bytes, so the extension does not matter. The compiled
+//! cache is keyed by the decompressed bytes, so recompressing a component
+//! does not invalidate its artifact.
+
+use std::io::{self, Read};
+use std::path::Path;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Largest decompressed component accepted, so a small malicious file
+/// cannot expand without bound.
+const MAX_DECOMPRESSED: u64 = 1 << 30;
+
+/// Read the component at `path`, decompressing it if needed.
+pub(crate) fn read_wasm(path: &Path) -> io::Result<Vec<u8>> {
+    let bytes = std::fs::read(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: cannot read component: {e}", path.display())))?;
+    if bytes.starts_with(GZIP_MAGIC) {
+        decompress(path, flate2::read::GzDecoder::new(bytes.as_slice()))
+    } else if bytes.starts_with(ZSTD_MAGIC) {
+        decompress(path, zstd::stream::read::Decoder::new(bytes.as_slice())?)
+    } else {
+        Ok(bytes)
+    }
+}
+
+fn decompress(path: &Path, decoder: impl Read) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    decoder.take(MAX_DECOMPRESSED + 1).read_to_end(&mut out).map_err(|e| {
+        io::Error::new(e.kind(), format!("{}: cannot decompress component: {e}", path.display()))
+    })?;
+    if out.len() as u64 > MAX_DECOMPRESSED {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{}: decompressed component exceeds {} bytes",
+                path.display(),
+                MAX_DECOMPRESSED
+            ),
+        ));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Scratch, component};
+    use std::io::Write;
+
+    #[test]
+    fn reads_gzip() {
+        let dir = Scratch::new("gzip");
+        let path = dir.join("env.wasm.gz");
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&component("gzip")).unwrap();
+        std::fs::write(&path, encoder.finish().unwrap()).unwrap();
+        assert_eq!(read_wasm(&path).unwrap(), component("gzip"));
+    }
+
+    #[test]
+    fn reads_zstd() {
+        let dir = Scratch::new("zstd");
+        // sniffed, so the extension does not matter
+        let path = dir.join("env.wasm");
+        std::fs::write(&path, zstd::encode_all(component("zstd").as_slice(), 0).unwrap()).unwrap();
+        assert_eq!(read_wasm(&path).unwrap(), component("zstd"));
+    }
+
+    #[test]
+    fn reads_plain() {
+        let dir = Scratch::new("plain");
+        let path = dir.join("env.wasm");
+        std::fs::write(&path, component("plain")).unwrap();
+        assert_eq!(read_wasm(&path).unwrap(), component("plain"));
+    }
+
+    #[test]
+    fn refuses_truncated_input() {
+        let dir = Scratch::new("truncated");
+        let path = dir.join("env.wasm.zst");
+        let encoded = zstd::encode_all(component("zstd").as_slice(), 0).unwrap();
+        std::fs::write(&path, &encoded[..encoded.len() - 4]).unwrap();
+        let err = read_wasm(&path).unwrap_err();
+        assert!(err.to_string().contains("cannot decompress"), "{err}");
+    }
+}
@@ -0,0 +1,264 @@
+//! Extra host imports backed by Python callables, for components whose
+//! worlds need more than the fixed `exec:env` imports.
+//!
+//! `custom_imports` maps an import name to `(params, result, callable)`,
+//! where `params` is a list of type names and `result` a type name or None.
+//! Supported types are `bytes` (`list<u8>`), `string`, `bool`, `s32`/`i32`,
+//! `u32`, `s64`/`i64`, `u64`, `f32` and `f64`. The callable gets one
+//! positional argument per parameter; if it returns an awaitable, that is
+//! awaited on the runtime before its result goes back to the guest.
+//!
+//! `exports` types guest exports the same way, as `(params, result)`, so
+//! `WasmRunner.call_export` can call them by name.
+
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyTuple};
+use std::collections::HashMap;
+use std::sync::Arc;
+use wasmtime::component::{LinkerInstance, Val};
+
+use crate::{Ctx, extract_payload, pyerr_to_wasmtime_err, with_gil_maybe_blocking};
+
+#[derive(Clone, Copy)]
+enum ValType {
+    Bytes,
+    String,
+    Bool,
+    S32,
+    U32,
+    S64,
+    U64,
+    F32,
+    F64,
+}
+
+impl ValType {
+    /// `what` names the import or export being typed, for errors.
+    fn parse(what: &str, name: &str) -> PyResult<Self> {
+        Ok(match name {
+            "bytes" => ValType::Bytes,
+            "string" => ValType::String,
+            "bool" => ValType::Bool,
+            "s32" | "i32" => ValType::S32,
+            "u32" => ValType::U32,
+            "s64" | "i64" => ValType::S64,
+            "u64" => ValType::U64,
+            "f32" => ValType::F32,
+            "f64" => ValType::F64,
+            _ => {
+                return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "WasmRunner: {what} uses unknown type {name:?}; expected bytes, \
+                     string, bool, s32, u32, s64, u64, f32 or f64"
+                )));
+            }
+        })
+    }
+
+    fn to_py(self, py: Python<'_>, val: &Val) -> wasmtime::Result<PyObject> {
+        let obj = match (self, val) {
+            (ValType::Bytes, Val::List(items)) => {
+                let bytes = items
+                    .iter()
+                    .map(|v| match v {
+                        Val::U8(b) => Ok(*b),
+                        _ => Err(wasmtime::Error::msg("WasmRunner: expected list<u8>")),
+                    })
+                    .collect::<wasmtime::Result<Vec<u8>>>()?;
+                pyo3::types::PyBytes::new(py, &bytes).into_any()
+            }
+            (ValType::String, Val::String(s)) => s.into_pyobject(py)?.into_any(),
+            (ValType::Bool, Val::Bool(b)) => b.into_pyobject(py)?.to_owned().into_any(),
+            (ValType::S32, Val::S32(n)) => n.into_pyobject(py)?.into_any(),
+            (ValType::U32, Val::U32(n)) => n.into_pyobject(py)?.into_any(),
+            (ValType::S64, Val::S64(n)) => n.into_pyobject(py)?.into_any(),
+            (ValType::U64, Val::U64(n)) => n.into_pyobject(py)?.into_any(),
+            (ValType::F32, Val::Float32(n)) => n.into_pyobject(py)?.into_any(),
+            (ValType::F64, Val::Float64(n)) => n.into_pyobject(py)?.into_any(),
+            _ => {
+                return Err(wasmtime::Error::msg(format!(
+                    "WasmRunner: guest passed {val:?} where the schema expects {}",
+                    self.name()
+                )));
+            }
+        };
+        Ok(obj.unbind())
+    }
+
+    fn extract(self, obj: &Bound<'_, PyAny>) -> PyResult<Val> {
+        Ok(match self {
+            ValType::Bytes => Val::List(extract_payload(obj)?.into_iter().map(Val::U8).collect()),
+            ValType::String => Val::String(obj.extract()?),
+            ValType::Bool => Val::Bool(obj.extract()?),
+            ValType::S32 => Val::S32(obj.extract()?),
+            ValType::U32 => Val::U32(obj.extract()?),
+            ValType::S64 => Val::S64(obj.extract()?),
+            ValType::U64 => Val::U64(obj.extract()?),
+            ValType::F32 => Val::Float32(obj.extract()?),
+            ValType::F64 => Val::Float64(obj.extract()?),
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ValType::Bytes => "bytes",
+            ValType::String => "string",
+            ValType::Bool => "bool",
+            ValType::S32 => "s32",
+            ValType::U32 => "u32",
+            ValType::S64 => "s64",
+            ValType::U64 => "u64",
+            ValType::F32 => "f32",
+            ValType::F64 => "f64",
+        }
+    }
+}
+
+pub(crate) struct CustomImport {
+    name: String,
+    params: Vec<ValType>,
+    result: Option<ValType>,
+    callable: Arc<PyObject>,
+}
+
+/// Parse the `custom_imports` constructor argument.
+pub(crate) fn from_dict(dict: &Bound<'_, PyDict>) -> PyResult<Vec<CustomImport>> {
+    let py = dict.py();
+    let mut imports = Vec::new();
+    for (name, spec) in dict.iter() {
+        let name: String = name.extract()?;
+        let (params, result, callable): (Vec<String>, Option<String>, PyObject) =
+            spec.extract().map_err(|_| {
+                pyo3::exceptions::PyTypeError::new_err(format!(
+                    "WasmRunner: custom import {name:?} must map to (params, result, callable)"
+                ))
+            })?;
+        let what = format!("custom import {name:?}");
+        let params = params
+            .iter()
+            .map(|ty| ValType::parse(&what, ty))
+            .collect::<PyResult<Vec<_>>>()?;
+        let result = result.map(|ty| ValType::parse(&what, &ty)).transpose()?;
+        crate::check_callback_arity(py, &name, &callable, params.len())?;
+        imports.push(CustomImport {
+            name,
+            params,
+            result,
+            callable: Arc::new(callable),
+        });
+    }
+    Ok(imports)
+}
+
+/// Define each import at the component's root. A name clashing with a
+/// built-in import is an error.
+pub(crate) fn register(root: &mut LinkerInstance<'_, Ctx>, imports: Vec<CustomImport>) -> PyResult<()> {
+    for import in imports {
+        let name = import.name.clone();
+        let import = Arc::new(import);
+        root.func_new_async(&name, move |store, _ty, params, results| {
+            let import = import.clone();
+            Box::new(async move {
+                store.data().check_open()?;
+                let blocking = store.data().blocking_callbacks;
+                let pending = with_gil_maybe_blocking(blocking, |py| -> wasmtime::Result<_> {
+                    let args = params
+                        .iter()
+                        .zip(&import.params)
+                        .map(|(val, ty)| ty.to_py(py, val))
+                        .collect::<wasmtime::Result<Vec<_>>>()?;
+                    let ret = import
+                        .callable
+                        .bind(py)
+                        .call1(PyTuple::new(py, args)?)
+                        .map_err(|e| import.failed(e))?;
+                    let awaitable = py
+                        .import("inspect")?
+                        .call_method1("isawaitable", (&ret,))?
+                        .is_truthy()?;
+                    if !awaitable {
+                        return Ok(Returned::Ready(ret.unbind()));
+                    }
+                    let fut = pyo3_async_runtimes::tokio::into_future(ret).map_err(|e| import.failed(e))?;
+                    Ok(Returned::Awaiting(Box::pin(fut)))
+                })?;
+                let ret = match pending {
+                    Returned::Ready(ret) => ret,
+                    Returned::Awaiting(fut) => fut.await.map_err(|e| import.failed(e))?,
+                };
+                if let Some(ty) = import.result {
+                    results[0] = with_gil_maybe_blocking(blocking, |py| ty.extract(ret.bind(py)))
+                        .map_err(|e| import.failed(e))?;
+                }
+                Ok(())
+            })
+        })
+        .map_err(|e| pyo3::exceptions::PyValueError::new_err(format!("WasmRunner: custom import {name:?}: {e}")))?;
+    }
+    Ok(())
+}
+
+/// What the callable gave back: a value, or an awaitable still to run.
+enum Returned {
+    Ready(PyObject),
+    Awaiting(std::pin::Pin<Box<dyn Future<Output = PyResult<PyObject>> + Send>>),
+}
+
+impl CustomImport {
+    fn failed(&self, e: PyErr) -> wasmtime::Error {
+        pyerr_to_wasmtime_err(e).context(format!("WasmRunner: custom import {:?} failed", self.name))
+    }
+}
+
+/// How to marshal one export's arguments and result.
+pub(crate) struct ExportSig {
+    params: Vec<ValType>,
+    result: Option<ValType>,
+}
+
+/// Parse the `exports` constructor argument.
+pub(crate) fn exports_from_dict(dict: &Bound<'_, PyDict>) -> PyResult<HashMap<String, ExportSig>> {
+    let mut exports = HashMap::new();
+    for (name, spec) in dict.iter() {
+        let name: String = name.extract()?;
+        let (params, result): (Vec<String>, Option<String>) = spec.extract().map_err(|_| {
+            pyo3::exceptions::PyTypeError::new_err(format!(
+                "WasmRunner: export {name:?} must map to (params, result)"
+            ))
+        })?;
+        let what = format!("export {name:?}");
+        let params = params
+            .iter()
+            .map(|ty| ValType::parse(&what, ty))
+            .collect::<PyResult<Vec<_>>>()?;
+        let result = result.map(|ty| ValType::parse(&what, &ty)).transpose()?;
+        exports.insert(name, ExportSig { params, result });
+    }
+    Ok(exports)
+}
+
+impl ExportSig {
+    /// The guest values for a call of export `name` with `args`.
+    pub fn args(&self, name: &str, args: &Bound<'_, PyTuple>) -> PyResult<Vec<Val>> {
+        if args.len() != self.params.len() {
+            return Err(pyo3::exceptions::PyTypeError::new_err(format!(
+                "WasmRunner: export {name:?} takes {} argument(s), got {}",
+                self.params.len(),
+                args.len()
+            )));
+        }
+        args.iter().zip(&self.params).map(|(arg, ty)| ty.extract(&arg)).collect()
+    }
+
+    /// How many results the export returns, zero or one.
+    pub fn results(&self) -> usize {
+        usize::from(self.result.is_some())
+    }
+
+    /// The Python value of the export's result; None when it has none.
+    pub fn result(&self, py: Python<'_>, results: &[Val]) -> wasmtime::Result<PyObject> {
+        match (self.result, results.first()) {
+            (Some(ty), Some(val)) => ty.to_py(py, val),
+            _ => Ok(py.None()),
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! `deterministic=True`: virtual WASI clocks and seeded randomness, so a
+//! guest fed the same messages behaves the same across runs.
+//!
+//! Both clocks read one virtual counter that only moves when the guest reads
+//! a clock (by `clock_tick_ns` per read) or the host calls `advance_clock`.
+//! The wall clock starts at `EPOCH`. `wasi:random` (secure and insecure) and
+//! the insecure seed come from ChaCha20 streams keyed by `seed`.
+//!
+//! Limitations: anything the host callbacks do is outside this, so message
+//! contents, their timing and their ordering must be reproducible too.
+//! Timed waits (`subscribe-duration`, `sleep`) still wait real time, though
+//! they do not advance the virtual clock. Preopened directories and
+//! inherited stdio are the host's.
+
+use rand_chacha::ChaCha20Rng;
+use rand_chacha::rand_core::{RngCore, SeedableRng};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use wasmtime_wasi::{HostMonotonicClock, HostWallClock, WasiCtxBuilder};
+
+/// Wall-clock time at virtual time zero: 2024-01-01T00:00:00Z.
+const EPOCH: Duration = Duration::from_secs(1_704_067_200);
+
+/// Separate streams so the secure and insecure generators never repeat
+/// each other's output.
+const SECURE_STREAM: u64 = 0;
+const INSECURE_STREAM: u64 = 1;
+
+#[derive(Clone)]
+pub(crate) struct Deterministic {
+    seed: u64,
+    clock: VirtualClock,
+}
+
+impl Deterministic {
+    pub fn new(seed: u64, tick_ns: u64) -> Self {
+        Deterministic {
+            seed,
+            clock: VirtualClock {
+                nanos: Arc::new(AtomicU64::new(0)),
+                tick_ns,
+            },
+        }
+    }
+
+    /// The virtual time counter, for `advance_clock`.
+    pub fn nanos(&self) -> Arc<AtomicU64> {
+        self.clock.nanos.clone()
+    }
+
+    /// Install the clocks and generators. Each call starts the generators
+    /// over, so a fresh store replays the same randomness; the clock keeps
+    /// running.
+    pub fn apply(&self, builder: &mut WasiCtxBuilder) {
+        builder.wall_clock(self.clock.clone());
+        builder.monotonic_clock(self.clock.clone());
+        builder.secure_random(self.rng(SECURE_STREAM));
+        let mut insecure = self.rng(INSECURE_STREAM);
+        let seed = (u128::from(insecure.next_u64()) << 64) | u128::from(insecure.next_u64());
+        builder.insecure_random_seed(seed);
+        builder.insecure_random(insecure);
+    }
+
+    fn rng(&self, stream: u64) -> ChaCha20Rng {
+        let mut rng = ChaCha20Rng::seed_from_u64(self.seed);
+        rng.set_stream(stream);
+        rng
+    }
+}
+
+#[derive(Clone)]
+struct VirtualClock {
+    nanos: Arc<AtomicU64>,
+    tick_ns: u64,
+}
+
+impl VirtualClock {
+    fn read(&self) -> u64 {
+        self.nanos.fetch_add(self.tick_ns, Ordering::Relaxed) + self.tick_ns
+    }
+}
+
+impl HostWallClock for VirtualClock {
+    fn resolution(&self) -> Duration {
+        Duration::from_nanos(1)
+    }
+
+    fn now(&self) -> Duration {
+        EPOCH + Duration::from_nanos(self.read())
+    }
+}
+
+impl HostMonotonicClock for VirtualClock {
+    fn resolution(&self) -> u64 {
+        1
+    }
+
+    fn now(&self) -> u64 {
+        self.read()
+    }
+}
@@ -0,0 +1,100 @@
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::LogLevel;
+
+/// Python `logging` levels. `trace` has no stdlib level and maps below DEBUG.
+const TRACE: i32 = 5;
+const DEBUG: i32 = 10;
+const INFO: i32 = 20;
+const WARNING: i32 = 30;
+const ERROR: i32 = 40;
+
+pub(crate) fn python_level(level: LogLevel) -> i32 {
+    match level {
+        LogLevel::Trace => TRACE,
+        LogLevel::Debug => DEBUG,
+        LogLevel::Info => INFO,
+        LogLevel::Warn => WARNING,
+        LogLevel::Error => ERROR,
+    }
+}
+
+/// `logging.getLogger(name)`.
+pub(crate) fn get_logger(py: Python<'_>, name: &str) -> PyResult<PyObject> {
+    Ok(py.import("logging")?.call_method1("getLogger", (name,))?.unbind())
+}
+
+/// `logger.log(level, msg)`, with `extra={"wasm_tags": tags}` when tags are
+/// given. Failures go to `sys.unraisablehook`: a broken handler must not
+/// fail the guest.
+pub(crate) fn log(py: Python<'_>, logger: &PyObject, level: i32, msg: &str, tags: Option<Bound<'_, PyDict>>) {
+    let logger = logger.bind(py);
+    let res = match tags {
+        Some(tags) => (|| {
+            let extra = PyDict::new(py);
+            extra.set_item("wasm_tags", tags)?;
+            let kwargs = PyDict::new(py);
+            kwargs.set_item("extra", extra)?;
+            logger.call_method("log", (level, msg), Some(&kwargs))
+        })(),
+        None => logger.call_method1("log", (level, msg)),
+    };
+    if let Err(e) = res {
+        e.write_unraisable(py, Some(logger));
+    }
+}
+
+/// The runner's own diagnostics. With a `logger_name` they go to the
+/// `<logger_name>.<id_name>` logger, which decides what is shown; otherwise
+/// they are printed to stderr when `runner_logging=True`. Each line starts
+/// with `[<id_name> <instance_id>]` to tell runners sharing an `id_name`
+/// apart. Clones share the stderr switch, so `set_logging()` reaches all of
+/// them.
+#[derive(Clone)]
+pub(crate) struct Diag {
+    stderr: Arc<AtomicBool>,
+    logger: Option<Arc<PyObject>>,
+    ident: Arc<str>,
+}
+
+impl Diag {
+    pub fn new(stderr: bool, logger: Option<PyObject>, id_name: &str, instance_id: &str) -> Self {
+        Diag {
+            stderr: Arc::new(AtomicBool::new(stderr)),
+            logger: logger.map(Arc::new),
+            ident: format!("[{id_name} {instance_id}]").into(),
+        }
+    }
+
+    pub fn set_stderr(&self, on: bool) {
+        self.stderr.store(on, Ordering::Relaxed);
+    }
+
+    pub fn debug(&self, args: std::fmt::Arguments<'_>) {
+        self.emit(DEBUG, args);
+    }
+
+    /// Also printed to stderr without `runner_logging`, for problems the
+    /// caller should hear about regardless.
+    pub fn warn(&self, args: std::fmt::Arguments<'_>) {
+        match &self.logger {
+            Some(logger) => Python::with_gil(|py| log(py, logger, WARNING, &self.line(args), None)),
+            None => eprintln!("{} {args}", self.ident),
+        }
+    }
+
+    fn emit(&self, level: i32, args: std::fmt::Arguments<'_>) {
+        match &self.logger {
+            Some(logger) => Python::with_gil(|py| log(py, logger, level, &self.line(args), None)),
+            None if self.stderr.load(Ordering::Relaxed) => eprintln!("{} {args}", self.ident),
+            None => {}
+        }
+    }
+
+    fn line(&self, args: std::fmt::Arguments<'_>) -> String {
+        format!("{} {args}", self.ident)
+    }
+}
@@ -0,0 +1,435 @@
+//! Engine construction: the runner settings that shape the wasmtime
+//! engine, and `EngineHandle`, which lets several runners share one.
+
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use wasmtime::component::Component;
+use wasmtime::{Config, Engine};
+
+use crate::limits::Metrics;
+use crate::options::{CompileOptions, LimitOptions};
+use crate::{DropBehavior, cache, epoch, features, hugepages, meminit, pyerr, shutdown};
+
+/// Runner settings that shape the engine. Epoch interruption, fuel, the
+/// optimization level and debug info change the generated code, so compiled
+/// artifacts are only reusable by an engine built with the same ones; all
+/// four feed the engine's compatibility hash, and so the cache key, as do
+/// `engine_features` and the memory settings of `engine_config`.
+/// `parallel_compilation` only changes how fast the code is produced.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) struct EngineOptions {
+    pub(crate) epochs: bool,
+    /* epochs advanced only by increment_epoch(), with no ticker thread */
+    pub(crate) manual_epochs: bool,
+    pub(crate) fuel: bool,
+    pub(crate) huge_pages: bool,
+    pub(crate) pool: Option<PoolOptions>,
+    pub(crate) opt_level: OptLevel,
+    pub(crate) debug_info: bool,
+    pub(crate) parallel_compilation: bool,
+    /* guest stack ceiling in bytes; wasmtime's default when None */
+    pub(crate) max_wasm_stack: Option<usize>,
+    pub(crate) features: features::WasmFeatures,
+    pub(crate) memory: meminit::MemoryConfig,
+}
+
+/// Stack the host side of an async call keeps beyond `max_wasm_stack`,
+/// wasmtime's default gap between `async_stack_size` and `max_wasm_stack`.
+pub(crate) const ASYNC_STACK_HEADROOM: usize = (2 << 20) - (512 << 10);
+
+impl Default for EngineOptions {
+    fn default() -> Self {
+        EngineOptions {
+            epochs: false,
+            manual_epochs: false,
+            fuel: false,
+            huge_pages: false,
+            pool: None,
+            opt_level: OptLevel::default(),
+            debug_info: false,
+            // wasmtime's default
+            parallel_compilation: true,
+            max_wasm_stack: None,
+            features: features::WasmFeatures::default(),
+            memory: meminit::MemoryConfig::default(),
+        }
+    }
+}
+
+/// Cranelift optimization level: `none` compiles fastest, `speed` (the
+/// default) and `speed_and_size` produce faster code.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub(crate) enum OptLevel {
+    None,
+    #[default]
+    Speed,
+    SpeedAndSize,
+}
+
+impl OptLevel {
+    pub(crate) fn parse(level: &str) -> PyResult<Self> {
+        match level {
+            "none" => Ok(OptLevel::None),
+            "speed" => Ok(OptLevel::Speed),
+            "speed_and_size" => Ok(OptLevel::SpeedAndSize),
+            _ => Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "WasmRunner: unknown opt_level {level:?}; expected none, speed or speed_and_size"
+            ))),
+        }
+    }
+
+    fn cranelift(self) -> wasmtime::OptLevel {
+        match self {
+            OptLevel::None => wasmtime::OptLevel::None,
+            OptLevel::Speed => wasmtime::OptLevel::Speed,
+            OptLevel::SpeedAndSize => wasmtime::OptLevel::SpeedAndSize,
+        }
+    }
+}
+
+/// Sizing for the pooling instance allocator, which preallocates slots for
+/// every instance, memory and table up front and recycles them, avoiding
+/// the per-instance mmap churn of the default on-demand allocator. Only
+/// useful on a shared `EngineHandle`, since each slot is reserved for the
+/// engine's lifetime.
+///
+/// `max_instances` counts component instances, i.e. runners instantiated
+/// at once. Each may use up to `CORE_INSTANCES_PER_COMPONENT` core
+/// instances and `MEMORIES_PER_COMPONENT` memories, all reserved in
+/// advance, so the virtual address space needed grows with
+/// `max_instances * MEMORIES_PER_COMPONENT * max_memory_bytes`.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) struct PoolOptions {
+    max_instances: u32,
+    max_memory_bytes: usize,
+    table_elements: usize,
+}
+
+const CORE_INSTANCES_PER_COMPONENT: u32 = 64;
+const MEMORIES_PER_COMPONENT: u32 = 4;
+const TABLES_PER_COMPONENT: u32 = 16;
+
+impl Default for PoolOptions {
+    fn default() -> Self {
+        PoolOptions {
+            max_instances: 100,
+            max_memory_bytes: 1 << 30,
+            table_elements: 20_000,
+        }
+    }
+}
+
+impl PoolOptions {
+    fn config(self) -> wasmtime::PoolingAllocationConfig {
+        let mut pool = wasmtime::PoolingAllocationConfig::new();
+        pool.total_component_instances(self.max_instances)
+            .total_core_instances(self.max_instances * CORE_INSTANCES_PER_COMPONENT)
+            .max_core_instances_per_component(CORE_INSTANCES_PER_COMPONENT)
+            .total_memories(self.max_instances * MEMORIES_PER_COMPONENT)
+            .max_memories_per_component(MEMORIES_PER_COMPONENT)
+            .total_tables(self.max_instances * TABLES_PER_COMPONENT)
+            .max_tables_per_component(TABLES_PER_COMPONENT)
+            .total_stacks(self.max_instances)
+            .max_memory_size(self.max_memory_bytes)
+            .table_elements(self.table_elements);
+        pool
+    }
+}
+
+impl std::fmt::Display for PoolOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "pool_max_instances={}, pool_max_memory_bytes={}, pool_table_elements={}",
+            self.max_instances, self.max_memory_bytes, self.table_elements
+        )
+    }
+}
+
+/// The `WasmRunner` keyword arguments `from_runner_kwargs` accepts, i.e.
+/// those shaping the engine rather than a single runner.
+pub(crate) const RUNNER_ENGINE_KWARGS: &[&str] = &["limits", "compile", "manual_epochs", "drop_behavior", "huge_pages"];
+
+/// The config keys selecting the pooling allocator, which no single runner
+/// takes; see `PoolOptions`.
+const POOL_KWARGS: &[&str] = &["pooling", "pool_max_instances", "pool_max_memory_bytes", "pool_table_elements"];
+
+impl EngineOptions {
+    /// The options a `WasmRunner` would use, from a dict of its keyword
+    /// arguments. Only the engine-shaping ones, `RUNNER_ENGINE_KWARGS`, are
+    /// accepted, plus `POOL_KWARGS`: `pooling=True` or any `pool_*` key
+    /// selects the pooling allocator.
+    pub(crate) fn from_runner_kwargs(d: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+        let mut opts = EngineOptions::default();
+        let Some(d) = d else { return Ok(opts) };
+        for (key, value) in d.iter() {
+            let key: String = key.extract()?;
+            let pool = || opts.pool.unwrap_or_default();
+            match key.as_str() {
+                "limits" => {
+                    let limits = LimitOptions::from_dict(value.extract::<Option<Bound<'_, PyDict>>>()?.as_ref())?;
+                    opts.epochs |= limits.ticks();
+                    opts.fuel = limits.fuel_limit.is_some();
+                }
+                "compile" => {
+                    CompileOptions::from_dict(value.extract::<Option<Bound<'_, PyDict>>>()?.as_ref())?.apply(&mut opts)
+                }
+                "manual_epochs" => {
+                    opts.manual_epochs = value.extract()?;
+                    opts.epochs |= opts.manual_epochs;
+                }
+                "drop_behavior" => {
+                    let behavior = DropBehavior::parse(&value.extract::<String>()?, 0)?;
+                    opts.epochs |= behavior == DropBehavior::Interrupt;
+                }
+                "huge_pages" => opts.huge_pages = value.extract()?,
+                "pooling" => opts.pool = value.extract::<bool>()?.then(pool),
+                "pool_max_instances" => {
+                    opts.pool = Some(PoolOptions { max_instances: value.extract()?, ..pool() })
+                }
+                "pool_max_memory_bytes" => {
+                    opts.pool = Some(PoolOptions { max_memory_bytes: value.extract()?, ..pool() })
+                }
+                "pool_table_elements" => {
+                    opts.pool = Some(PoolOptions { table_elements: value.extract()?, ..pool() })
+                }
+                _ => {
+                    let expected: Vec<&str> = RUNNER_ENGINE_KWARGS.iter().chain(POOL_KWARGS).copied().collect();
+                    return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                        "engine config: unknown field {key:?}; expected one of {}",
+                        expected.join(", ")
+                    )));
+                }
+            }
+        }
+        if opts.huge_pages && opts.pool.is_some() {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "engine config: huge_pages only applies to the on-demand allocator; \
+                 it cannot be combined with pooling",
+            ));
+        }
+        Ok(opts)
+    }
+
+    pub(crate) fn build(self) -> PyResult<Engine> {
+        let mut cfg = Config::new();
+        cfg.async_support(true);
+        // keep guest stacks on traps; they surface as `wasm_frames`
+        cfg.wasm_backtrace(true);
+        cfg.native_unwind_info(true);
+        cfg.cranelift_opt_level(self.opt_level.cranelift());
+        cfg.debug_info(self.debug_info);
+        cfg.parallel_compilation(self.parallel_compilation);
+        if let Some(size) = self.max_wasm_stack {
+            cfg.max_wasm_stack(size);
+            cfg.async_stack_size(size.saturating_add(ASYNC_STACK_HEADROOM));
+        }
+        self.features.apply(&mut cfg);
+        self.memory.apply(&mut cfg);
+        if self.epochs {
+            cfg.epoch_interruption(true);
+        }
+        if self.fuel {
+            cfg.consume_fuel(true);
+        }
+        if self.huge_pages {
+            hugepages::install(&mut cfg);
+        }
+        if let Some(pool) = self.pool {
+            cfg.allocation_strategy(wasmtime::InstanceAllocationStrategy::Pooling(pool.config()));
+        }
+        Engine::new(&cfg).map_err(pyerr)
+    }
+
+    /// Fail if a runner needing `wanted` cannot use an engine built with
+    /// these options. Extra engine features are fine: the runner installs a
+    /// no-op epoch callback or an unlimited fuel budget to neutralise them.
+    /// The `compile` settings in `given` (`opt_level`, `debug_info`,
+    /// `max_wasm_stack`, `engine_features`, `engine_config`) only have to
+    /// match when the runner asked for them.
+    pub(crate) fn check_supports(self, wanted: EngineOptions, given: &[&str]) -> PyResult<()> {
+        for &name in given {
+            let same = match name {
+                "opt_level" => wanted.opt_level == self.opt_level,
+                "debug_info" => wanted.debug_info == self.debug_info,
+                "max_wasm_stack" => wanted.max_wasm_stack == self.max_wasm_stack,
+                "engine_features" => wanted.features == self.features,
+                "engine_config" => wanted.memory == self.memory,
+                _ => true,
+            };
+            if !same {
+                return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "WasmRunner: compile {name} must match the shared engine's; set it in the EngineHandle \
+                     config instead"
+                )));
+            }
+        }
+        let missing = [
+            (
+                wanted.epochs && !self.epochs,
+                "epoch interruption (the timeouts or yield_interval_ms in limits, or drop_behavior)",
+            ),
+            (wanted.manual_epochs && !self.manual_epochs, "manual_epochs"),
+            (wanted.fuel && !self.fuel, "fuel metering (limits fuel_limit)"),
+            (wanted.huge_pages && !self.huge_pages, "huge_pages"),
+        ];
+        match missing.iter().find(|(missing, _)| *missing) {
+            Some((_, what)) => Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "WasmRunner: the shared engine was created without {what}; \
+                 enable it in the EngineHandle config"
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// An engine shared by many runners, so each component is compiled once and
+/// its code is mapped once however many runners instantiate it.
+///
+/// Created with the same engine-shaping config dict as `precompile`.
+/// Runners that need a feature the handle lacks are rejected at
+/// construction. The handle is immutable and safe to share across threads
+/// and event loops: every runner still gets its own store, so runners built
+/// from one handle run concurrently without coordination. Compiled
+/// components are kept for the handle's lifetime, keyed by wasm content and
+/// engine fingerprint; drop the handle (and its runners) to release them.
+#[pyclass(frozen)]
+pub(crate) struct EngineHandle {
+    pub(crate) engine: Engine,
+    pub(crate) options: EngineOptions,
+    /* one ticker for every runner on this engine that sets timeout_ms */
+    pub(crate) ticker: Option<Arc<epoch::Ticker>>,
+    components: std::sync::Mutex<std::collections::HashMap<cache::ArtifactMeta, Component>>,
+    /* the runners built on this handle, summed by stats(); pruned as they drop */
+    runners: std::sync::Mutex<Vec<RunnerRef>>,
+}
+
+/// What `EngineHandle.stats()` reads from a runner, held weakly so the
+/// handle never keeps a dropped runner's state alive.
+struct RunnerRef {
+    metrics: std::sync::Weak<Metrics>,
+    instantiated: std::sync::Weak<AtomicBool>,
+}
+
+#[pymethods]
+impl EngineHandle {
+    #[new]
+    #[pyo3(signature = (config=None))]
+    pub(crate) fn new(config: Option<Bound<'_, PyDict>>) -> PyResult<Self> {
+        let options = EngineOptions::from_runner_kwargs(config.as_ref())?;
+        let engine = options.build()?;
+        let ticker = match options.epochs && !options.manual_epochs {
+            true => Some(Arc::new(epoch::Ticker::start(engine.clone()).map_err(pyerr)?)),
+            false => None,
+        };
+        Ok(EngineHandle {
+            engine,
+            options,
+            ticker,
+            components: Default::default(),
+            runners: Default::default(),
+        })
+    }
+
+    /// Number of distinct components compiled through this handle.
+    fn cached_components(&self) -> usize {
+        shutdown::lock(&self.components).len()
+    }
+
+    /// Engine-wide usage, summed over every live runner built on this
+    /// handle, for judging how much headroom it has left. Only reads
+    /// counters, so it is cheap to poll while loops run.
+    ///
+    /// `components` and `compiled_code_bytes` cover the compiled components
+    /// the handle keeps; `runners` and `instantiated` count its live runners
+    /// and those holding an instance; `memory_bytes`, `live_tasks` and the
+    /// message and byte counters are those runners' `memory_bytes`,
+    /// `metrics()["live_tasks"]` and lifetime I/O added up. Under
+    /// `pooling=True`, `pooling` holds the allocator's occupancy
+    /// (`component_instances`, `core_instances`, `memories`, `tables`,
+    /// `stacks`, `unused_warm_memories`, `unused_memory_bytes_resident`)
+    /// next to its `max_instances`; it is None otherwise.
+    fn stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        use std::sync::atomic::Ordering::Relaxed;
+        let d = PyDict::new(py);
+        {
+            let components = shutdown::lock(&self.components);
+            let code: usize = components
+                .values()
+                .map(|c| {
+                    let range = c.image_range();
+                    range.end as usize - range.start as usize
+                })
+                .sum();
+            d.set_item("components", components.len())?;
+            d.set_item("compiled_code_bytes", code)?;
+        }
+        let (mut runners, mut instantiated, mut memory_bytes, mut live_tasks) = (0usize, 0usize, 0usize, 0usize);
+        let mut io = [0u64; 4];
+        {
+            let mut refs = shutdown::lock(&self.runners);
+            refs.retain(|r| r.metrics.strong_count() > 0);
+            for r in refs.iter() {
+                let Some(m) = r.metrics.upgrade() else { continue };
+                runners += 1;
+                instantiated += r.instantiated.upgrade().is_some_and(|i| i.load(Relaxed)) as usize;
+                memory_bytes += m.memory_bytes.load(Relaxed);
+                live_tasks += m.live_tasks.load(Relaxed);
+                let c = m.message_counts();
+                for (sum, n) in io.iter_mut().zip([c.messages_sent, c.messages_received, c.bytes_sent, c.bytes_received]) {
+                    *sum += n;
+                }
+            }
+        }
+        d.set_item("runners", runners)?;
+        d.set_item("instantiated", instantiated)?;
+        d.set_item("memory_bytes", memory_bytes)?;
+        d.set_item("live_tasks", live_tasks)?;
+        for (name, n) in ["messages_sent", "messages_received", "bytes_sent", "bytes_received"].iter().zip(io) {
+            d.set_item(name, n)?;
+        }
+        let pooling = match self.engine.pooling_allocator_metrics() {
+            Some(p) => {
+                let pd = PyDict::new(py);
+                pd.set_item("component_instances", p.component_instances())?;
+                pd.set_item("core_instances", p.core_instances())?;
+                pd.set_item("memories", p.memories())?;
+                pd.set_item("tables", p.tables())?;
+                pd.set_item("stacks", p.stacks())?;
+                pd.set_item("unused_warm_memories", p.unused_warm_memories())?;
+                pd.set_item("unused_memory_bytes_resident", p.unused_memory_bytes_resident())?;
+                pd.set_item("max_instances", self.options.pool.map(|pool| pool.max_instances))?;
+                Some(pd)
+            }
+            None => None,
+        };
+        d.set_item("pooling", pooling)?;
+        Ok(d)
+    }
+}
+
+impl EngineHandle {
+    /// Count a new runner on this handle in `stats()`.
+    pub(crate) fn register(&self, metrics: &Arc<Metrics>, instantiated: &Arc<AtomicBool>) {
+        shutdown::lock(&self.runners).push(RunnerRef {
+            metrics: Arc::downgrade(metrics),
+            instantiated: Arc::downgrade(instantiated),
+        });
+    }
+
+    pub(crate) fn component(
+        &self,
+        meta: cache::ArtifactMeta,
+        compile: impl FnOnce() -> Result<Component, String>,
+    ) -> Result<Component, String> {
+        let mut components = shutdown::lock(&self.components);
+        if let Some(component) = components.get(&meta) {
+            return Ok(component.clone());
+        }
+        let component = compile()?;
+        components.insert(meta, component.clone());
+        Ok(component)
+    }
+}
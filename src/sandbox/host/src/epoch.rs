@@ -0,0 +1,112 @@
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use wasmtime::{Engine, Store, UpdateDeadline};
+
+use crate::Ctx;
+use crate::errors::{Closed, CpuTimeout, InitTimeout, Timeout};
+
+/// How often the ticker advances the epoch, and so the granularity of
+/// `timeout_ms`.
+const TICK: Duration = Duration::from_millis(10);
+
+/// Background thread advancing an engine's epoch every `TICK`. Dropping it
+/// stops and joins the thread, which takes at most one tick.
+pub(crate) struct Ticker {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Ticker {
+    pub fn start(engine: Engine) -> std::io::Result<Self> {
+        let (stop, rx) = mpsc::channel::<()>();
+        let thread = std::thread::Builder::new()
+            .name("wasm-epoch-ticker".into())
+            .spawn(move || {
+                while let Err(mpsc::RecvTimeoutError::Timeout) = rx.recv_timeout(TICK) {
+                    engine.increment_epoch();
+                }
+            })?;
+        Ok(Ticker {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for Ticker {
+    fn drop(&mut self) {
+        // disconnecting the channel wakes the thread immediately
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// `epoch_budget` when `set_epoch_deadline` set none.
+pub(crate) const NO_DEADLINE: u64 = u64::MAX;
+
+/// Whole ticks in `interval`, at least one.
+pub(crate) fn ticks(interval: Duration) -> u64 {
+    (interval.as_nanos().div_ceil(TICK.as_nanos()) as u64).max(1)
+}
+
+/// Check the store's interrupt and close flags, its `set_epoch_deadline`
+/// budget, its init and loop deadlines and its CPU budget at every epoch
+/// tick. All five are read from `Ctx` on each tick, so they can change
+/// between loops (the flags and the epoch budget even during one) without
+/// touching the store's epoch configuration. With `yield_ticks`, the guest
+/// also yields to the async runtime every that many ticks; it is not
+/// interrupted, only suspended until tokio polls it again.
+pub(crate) fn install(store: &mut Store<Ctx>, yield_ticks: Option<u64>) {
+    let mut since_yield = 0;
+    store.set_epoch_deadline(1);
+    store.epoch_deadline_callback(move |mut ctx| {
+        let data = ctx.data();
+        if data.interrupted.load(Ordering::Relaxed) {
+            return Err(wasmtime::Trap::Interrupt.into());
+        }
+        if *data.closed.borrow() {
+            return Err(wasmtime::Error::new(Closed));
+        }
+        let budget = data.epoch_budget.load(Ordering::Relaxed);
+        if budget != NO_DEADLINE {
+            // a concurrent set_epoch_deadline wins over this tick's count
+            let next = if budget <= 1 { NO_DEADLINE } else { budget - 1 };
+            let _ = data
+                .epoch_budget
+                .compare_exchange(budget, next, Ordering::Relaxed, Ordering::Relaxed);
+            if budget <= 1 {
+                return Err(wasmtime::Trap::Interrupt.into());
+            }
+        }
+        if let Some((deadline, limit)) = data.init_deadline
+            && Instant::now() >= deadline
+        {
+            return Err(wasmtime::Error::new(InitTimeout(limit)));
+        }
+        if let Some((deadline, limit)) = data.deadline
+            && Instant::now() >= deadline
+        {
+            return Err(wasmtime::Error::new(Timeout(limit)));
+        }
+        // the callback only runs while guest code does: ticks that pass
+        // while a host call is pending surface as one on its return
+        if let Some((left, limit)) = &mut ctx.data_mut().cpu_budget {
+            if *left <= 1 {
+                return Err(wasmtime::Error::new(CpuTimeout(*limit)));
+            }
+            *left -= 1;
+        }
+        if let Some(every) = yield_ticks {
+            since_yield += 1;
+            if since_yield >= every {
+                since_yield = 0;
+                return Ok(UpdateDeadline::Yield(1));
+            }
+        }
+        Ok(UpdateDeadline::Continue(1))
+    });
+}
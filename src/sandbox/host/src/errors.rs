@@ -0,0 +1,448 @@
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+
+use crate::limits::MessageCounts;
+
+pyo3::create_exception!(host, WasmError, PyRuntimeError, "Base class for WasmRunner errors.");
+pyo3::create_exception!(host, WasmTrapError, WasmError, "The guest trapped.");
+pyo3::create_exception!(host, WasmStackOverflowError, WasmTrapError, "The guest exceeded max_wasm_stack.");
+pyo3::create_exception!(host, WasmHostError, WasmError, "A host callback raised while the guest called it.");
+pyo3::create_exception!(host, WasmTimeoutError, WasmError, "run_msg_loop exceeded timeout_ms.");
+pyo3::create_exception!(host, WasmInitTimeoutError, WasmTimeoutError, "init-exec-env exceeded init_timeout_ms.");
+pyo3::create_exception!(host, WasmFuelExhaustedError, WasmError, "The guest used up its fuel_limit.");
+pyo3::create_exception!(host, WasmMemoryLimitError, WasmError, "The guest failed after hitting max_memory_bytes.");
+pyo3::create_exception!(host, WasmMessageTooLargeError, WasmError, "A message exceeded max_message_size.");
+pyo3::create_exception!(host, WasmCodecError, WasmError, "A message could not be encoded or decoded with the runner's codec.");
+pyo3::create_exception!(host, WasmSignatureError, WasmError, "The component failed signature verification.");
+pyo3::create_exception!(host, WasmShutdownError, WasmError, "The tokio runtime shut down under a pending call.");
+pyo3::create_exception!(host, WasmPoolExhaustedError, WasmError, "No pooled runner became free within acquire_timeout_ms.");
+pyo3::create_exception!(
+    host,
+    WasmSourceChangedError,
+    WasmError,
+    "The wasm file changed since it was loaded, under on_source_change=\"error\"."
+);
+
+/// Marks a `wasmtime::Error` as coming from a failed Python host callback
+/// rather than from the guest, so it can be surfaced as `WasmHostError`.
+/// Keeps the original exception to chain it as the `__cause__`, and its
+/// formatted traceback for `host_traceback`.
+#[derive(Debug)]
+pub(crate) struct HostCallbackError {
+    pub summary: String,
+    pub traceback: Option<String>,
+    pub source: PyErr,
+}
+
+impl std::fmt::Display for HostCallbackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.summary)
+    }
+}
+
+impl std::error::Error for HostCallbackError {}
+
+/// Raised from the epoch callback when a loop passes its `timeout_ms`.
+#[derive(Debug)]
+pub(crate) struct Timeout(pub std::time::Duration);
+
+impl std::fmt::Display for Timeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "WasmRunner: run_msg_loop exceeded timeout_ms={}", self.0.as_millis())
+    }
+}
+
+impl std::error::Error for Timeout {}
+
+/// Raised from the epoch callback when the guest has computed for its
+/// `cpu_timeout_ms`; surfaces as a `Timeout` outcome.
+#[derive(Debug)]
+pub(crate) struct CpuTimeout(pub std::time::Duration);
+
+impl std::fmt::Display for CpuTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "WasmRunner: the guest computed past cpu_timeout_ms={}", self.0.as_millis())
+    }
+}
+
+impl std::error::Error for CpuTimeout {}
+
+/// Raised from the epoch callback when `init-exec-env` passes its
+/// `init_timeout_ms`.
+#[derive(Debug)]
+pub(crate) struct InitTimeout(pub std::time::Duration);
+
+impl std::fmt::Display for InitTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "WasmRunner: init-exec-env exceeded init_timeout_ms={}", self.0.as_millis())
+    }
+}
+
+impl std::error::Error for InitTimeout {}
+
+/// Unwinds the guest after `close()`; the loop then resolves normally.
+#[derive(Debug)]
+pub(crate) struct Closed;
+
+impl std::fmt::Display for Closed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("WasmRunner: closed")
+    }
+}
+
+impl std::error::Error for Closed {}
+
+/// Context attached to a loop error that followed a refused memory growth.
+#[derive(Debug)]
+pub(crate) struct MemoryLimit(pub usize);
+
+impl std::fmt::Display for MemoryLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "WasmRunner: guest exceeded max_memory_bytes={}", self.0)
+    }
+}
+
+/// Raised by `send_bytes`/`recv_bytes`, their batch variants and the frame
+/// imports for a message over `max_message_size`.
+#[derive(Debug)]
+pub(crate) struct MessageTooLarge {
+    pub import: &'static str,
+    pub len: usize,
+    pub max: usize,
+}
+
+impl std::fmt::Display for MessageTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "WasmRunner: {} message of {} bytes exceeds max_message_size={}",
+            self.import, self.len, self.max
+        )
+    }
+}
+
+impl std::error::Error for MessageTooLarge {}
+
+/// Raised by `send_bytes`/`recv_bytes` when a message does not encode or
+/// decode under `codec`, as opposed to the transport failing.
+#[derive(Debug)]
+pub(crate) struct CodecError {
+    pub import: &'static str,
+    pub codec: &'static str,
+    pub message: String,
+}
+
+impl std::fmt::Display for CodecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let what = match self.import {
+            "send_bytes" => "could not decode the guest's message",
+            _ => "could not encode the message for the guest",
+        };
+        write!(f, "WasmRunner: {}: {what} as {}: {}", self.import, self.codec, self.message)
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+/// How a call into the guest ended. `run_msg_loop` resolves to a
+/// `NormalExit` outcome, or `ExitStatus` when the guest called `proc_exit`
+/// with a nonzero status (`proc_exit(0)` is a `NormalExit`); every raised `WasmError` carries the failing one
+/// as `outcome`, so retry logic can match on `kind` instead of classes.
+#[pyclass(eq, eq_int, frozen)]
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum OutcomeKind {
+    NormalExit,
+    Trap,
+    HostError,
+    Timeout,
+    InitTimeout,
+    FuelExhausted,
+    StackOverflow,
+    MemoryLimit,
+    MessageTooLarge,
+    CodecError,
+    /// The guest called `proc_exit` with a nonzero status, see `exit_code`.
+    ExitStatus,
+    /// Any other failure, e.g. a link or instantiation error.
+    Error,
+}
+
+/// `reason` is the error message and `backtrace` the guest's wasm stack
+/// when one was captured. For `HostError`, `py_exception` is the exception
+/// the host callback raised and `host_traceback` its formatted traceback.
+/// `exit_code` is the status the guest passed to `proc_exit`, or None if
+/// it returned without calling it. `stop_reason` is the reason given to
+/// `drain()` or `close()` when the loop ended because of one.
+#[pyclass(frozen, get_all)]
+pub(crate) struct LoopOutcome {
+    kind: OutcomeKind,
+    exit_code: Option<i32>,
+    reason: Option<String>,
+    stop_reason: Option<String>,
+    backtrace: Option<String>,
+    py_exception: Option<PyObject>,
+    host_traceback: Option<String>,
+}
+
+#[pymethods]
+impl LoopOutcome {
+    fn __repr__(&self) -> String {
+        match (&self.reason, self.exit_code) {
+            (Some(reason), _) => format!("LoopOutcome({:?}, {reason:?})", self.kind_name()),
+            (None, Some(code)) => format!("LoopOutcome({:?}, exit_code={code})", self.kind_name()),
+            (None, None) => format!("LoopOutcome({:?})", self.kind_name()),
+        }
+    }
+}
+
+impl LoopOutcome {
+    pub fn normal_exit() -> Self {
+        LoopOutcome {
+            kind: OutcomeKind::NormalExit,
+            exit_code: None,
+            reason: None,
+            stop_reason: None,
+            backtrace: None,
+            py_exception: None,
+            host_traceback: None,
+        }
+    }
+
+    /// The guest ended the loop with `proc_exit(code)`.
+    pub fn exited(code: i32) -> Self {
+        LoopOutcome {
+            kind: if code == 0 { OutcomeKind::NormalExit } else { OutcomeKind::ExitStatus },
+            exit_code: Some(code),
+            ..LoopOutcome::normal_exit()
+        }
+    }
+
+    pub fn with_stop_reason(self, stop_reason: Option<String>) -> Self {
+        LoopOutcome { stop_reason, ..self }
+    }
+
+    fn from_error(py: Python<'_>, e: &wasmtime::Error) -> Self {
+        let host = e.downcast_ref::<HostCallbackError>();
+        LoopOutcome {
+            kind: kind_of(e),
+            exit_code: e.downcast_ref::<wasmtime_wasi::I32Exit>().map(|exit| exit.0),
+            // alternate form, so context added on the way up keeps the cause
+            reason: Some(format!("{e:#}")),
+            backtrace: e.downcast_ref::<wasmtime::WasmBacktrace>().map(|bt| bt.to_string()),
+            py_exception: host.map(|h| h.source.value(py).clone().unbind().into_any()),
+            host_traceback: host.and_then(|h| h.traceback.clone()),
+            stop_reason: None,
+        }
+    }
+
+    fn kind_name(&self) -> &'static str {
+        kind_name(self.kind)
+    }
+}
+
+fn kind_of(e: &wasmtime::Error) -> OutcomeKind {
+    let host = e.downcast_ref::<HostCallbackError>();
+    let trap = e.downcast_ref::<wasmtime::Trap>();
+    if e.downcast_ref::<MemoryLimit>().is_some() {
+        OutcomeKind::MemoryLimit
+    } else if e.downcast_ref::<MessageTooLarge>().is_some() {
+        OutcomeKind::MessageTooLarge
+    } else if e.downcast_ref::<CodecError>().is_some() {
+        OutcomeKind::CodecError
+    } else if e.downcast_ref::<InitTimeout>().is_some() {
+        OutcomeKind::InitTimeout
+    } else if e.downcast_ref::<Timeout>().is_some() || e.downcast_ref::<CpuTimeout>().is_some() {
+        OutcomeKind::Timeout
+    } else if e.downcast_ref::<wasmtime_wasi::I32Exit>().is_some() {
+        OutcomeKind::ExitStatus
+    } else if matches!(trap, Some(wasmtime::Trap::OutOfFuel)) {
+        OutcomeKind::FuelExhausted
+    } else if matches!(trap, Some(wasmtime::Trap::StackOverflow)) {
+        OutcomeKind::StackOverflow
+    } else if host.is_some() {
+        OutcomeKind::HostError
+    } else if trap.is_some() {
+        OutcomeKind::Trap
+    } else {
+        OutcomeKind::Error
+    }
+}
+
+fn kind_name(kind: OutcomeKind) -> &'static str {
+    match kind {
+        OutcomeKind::NormalExit => "NormalExit",
+        OutcomeKind::Trap => "Trap",
+        OutcomeKind::HostError => "HostError",
+        OutcomeKind::Timeout => "Timeout",
+        OutcomeKind::InitTimeout => "InitTimeout",
+        OutcomeKind::FuelExhausted => "FuelExhausted",
+        OutcomeKind::StackOverflow => "StackOverflow",
+        OutcomeKind::MemoryLimit => "MemoryLimit",
+        OutcomeKind::MessageTooLarge => "MessageTooLarge",
+        OutcomeKind::CodecError => "CodecError",
+        OutcomeKind::ExitStatus => "ExitStatus",
+        OutcomeKind::Error => "Error",
+    }
+}
+
+/// A runner's most recent `instantiate` or `run_msg_loop` failure, for
+/// `WasmRunner.last_error`: its `kind`, `message`, `phase` and `timestamp`
+/// (seconds since the Unix epoch).
+#[pyclass(frozen, get_all)]
+#[derive(Clone)]
+pub(crate) struct ErrorRecord {
+    kind: OutcomeKind,
+    message: String,
+    phase: String,
+    timestamp: f64,
+}
+
+#[pymethods]
+impl ErrorRecord {
+    fn __repr__(&self) -> String {
+        format!("ErrorRecord({:?}, {:?}, {:?})", kind_name(self.kind), self.phase, self.message)
+    }
+}
+
+impl ErrorRecord {
+    pub fn new(e: &wasmtime::Error, phase: &str) -> Self {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0.0, |d| d.as_secs_f64());
+        ErrorRecord {
+            kind: kind_of(e),
+            message: format!("{e:#}"),
+            phase: phase.to_string(),
+            timestamp,
+        }
+    }
+}
+
+/// Convert a wasmtime error into the matching `WasmError` subclass. Every
+/// error carries `id_name` and `phase` (where it happened, e.g.
+/// `"run_msg_loop"`), `trap_code` (the wasmtime trap name, or None),
+/// `host_traceback` and `wasm_frames` (the guest stack, see `wasm_frames`;
+/// both may be None) and `outcome`. A failed host callback's
+/// exception is chained as `__cause__`, so tracebacks show its frames.
+pub(crate) fn to_pyerr(e: wasmtime::Error, id_name: &str, phase: &str) -> PyErr {
+    let trap = e.downcast_ref::<wasmtime::Trap>().map(|t| format!("{t:?}"));
+    Python::with_gil(|py| {
+        let outcome = LoopOutcome::from_error(py, &e);
+        let msg = outcome.reason.clone().unwrap_or_default();
+        let err = match outcome.kind {
+            OutcomeKind::MemoryLimit => WasmMemoryLimitError::new_err(msg),
+            OutcomeKind::MessageTooLarge => WasmMessageTooLargeError::new_err(msg),
+            OutcomeKind::CodecError => WasmCodecError::new_err(msg),
+            OutcomeKind::Timeout => WasmTimeoutError::new_err(msg),
+            OutcomeKind::InitTimeout => WasmInitTimeoutError::new_err(msg),
+            OutcomeKind::FuelExhausted => WasmFuelExhaustedError::new_err(msg),
+            OutcomeKind::StackOverflow => WasmStackOverflowError::new_err(format!(
+                "WasmRunner: guest stack overflow; raise max_wasm_stack for deeper recursion: {msg}"
+            )),
+            OutcomeKind::HostError => WasmHostError::new_err(msg),
+            OutcomeKind::Trap => WasmTrapError::new_err(msg),
+            OutcomeKind::NormalExit | OutcomeKind::ExitStatus | OutcomeKind::Error => WasmError::new_err(msg),
+        };
+        if let Some(host) = e.downcast_ref::<HostCallbackError>() {
+            err.set_cause(py, Some(host.source.clone_ref(py)));
+        }
+        let value = err.value(py);
+        let frames = match e.downcast_ref::<wasmtime::WasmBacktrace>() {
+            Some(bt) => wasm_frames(py, bt).ok().map(Bound::into_any),
+            None => None,
+        };
+        let _ = value.setattr("wasm_frames", frames);
+        let _ = value.setattr("host_traceback", outcome.host_traceback.clone());
+        let _ = value.setattr("trap_code", trap);
+        let _ = value.setattr("id_name", id_name);
+        let _ = value.setattr("phase", phase);
+        let _ = value.setattr("outcome", outcome);
+        err
+    })
+}
+
+/// Tell the caller of a call that timed out what had already crossed the
+/// boundary, since those messages were delivered and a streaming caller
+/// may still use them: sets `partial_output` on a `WasmTimeoutError` (init
+/// timeouts included) to a dict of the `messages_sent`,
+/// `messages_received`, `bytes_sent` and `bytes_received` of this call,
+/// and `send_offset`/`recv_offset`, the runner's byte totals each way when
+/// it stopped. Other errors are left alone.
+pub(crate) fn note_partial_output(err: PyErr, delta: MessageCounts, totals: MessageCounts) -> PyErr {
+    Python::with_gil(|py| {
+        if !err.is_instance_of::<WasmTimeoutError>(py) {
+            return;
+        }
+        let partial = || -> PyResult<Bound<'_, PyDict>> {
+            let d = PyDict::new(py);
+            d.set_item("messages_sent", delta.messages_sent)?;
+            d.set_item("messages_received", delta.messages_received)?;
+            d.set_item("bytes_sent", delta.bytes_sent)?;
+            d.set_item("bytes_received", delta.bytes_received)?;
+            d.set_item("send_offset", totals.bytes_sent)?;
+            d.set_item("recv_offset", totals.bytes_received)?;
+            Ok(d)
+        };
+        if let Ok(d) = partial() {
+            let _ = err.value(py).setattr("partial_output", d);
+        }
+    });
+    err
+}
+
+/// The guest stack of a trap, innermost first, one dict per frame with
+/// `module`, `func_index`, `func_name`, `func_offset` and `module_offset`,
+/// plus `file`/`line`/`column` when the component carries DWARF and
+/// `WASMTIME_BACKTRACE_DETAILS=1` is set.
+fn wasm_frames<'py>(py: Python<'py>, bt: &wasmtime::WasmBacktrace) -> PyResult<Bound<'py, PyList>> {
+    let frames = PyList::empty(py);
+    for frame in bt.frames() {
+        let d = PyDict::new(py);
+        d.set_item("module", frame.module().name())?;
+        d.set_item("func_index", frame.func_index())?;
+        d.set_item("func_name", frame.func_name())?;
+        d.set_item("func_offset", frame.func_offset())?;
+        d.set_item("module_offset", frame.module_offset())?;
+        if let Some(sym) = frame.symbols().first() {
+            d.set_item("file", sym.file())?;
+            d.set_item("line", sym.line())?;
+            d.set_item("column", sym.column())?;
+        }
+        frames.append(d)?;
+    }
+    Ok(frames)
+}
+
+/// Name -> class for every exception type the module raises.
+#[pyfunction]
+pub(crate) fn error_types(py: Python<'_>) -> PyResult<Bound<'_, PyDict>> {
+    let d = PyDict::new(py);
+    d.set_item("WasmError", py.get_type::<WasmError>())?;
+    d.set_item("WasmTrapError", py.get_type::<WasmTrapError>())?;
+    d.set_item("WasmStackOverflowError", py.get_type::<WasmStackOverflowError>())?;
+    d.set_item("WasmHostError", py.get_type::<WasmHostError>())?;
+    d.set_item("WasmTimeoutError", py.get_type::<WasmTimeoutError>())?;
+    d.set_item("WasmInitTimeoutError", py.get_type::<WasmInitTimeoutError>())?;
+    d.set_item("WasmFuelExhaustedError", py.get_type::<WasmFuelExhaustedError>())?;
+    d.set_item("WasmMemoryLimitError", py.get_type::<WasmMemoryLimitError>())?;
+    d.set_item("WasmMessageTooLargeError", py.get_type::<WasmMessageTooLargeError>())?;
+    d.set_item("WasmCodecError", py.get_type::<WasmCodecError>())?;
+    d.set_item("WasmSignatureError", py.get_type::<WasmSignatureError>())?;
+    d.set_item("WasmShutdownError", py.get_type::<WasmShutdownError>())?;
+    d.set_item("WasmPoolExhaustedError", py.get_type::<WasmPoolExhaustedError>())?;
+    d.set_item("WasmSourceChangedError", py.get_type::<WasmSourceChangedError>())?;
+    Ok(d)
+}
+
+pub(crate) fn register(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    let py = m.py();
+    for (name, ty) in error_types(py)?.iter() {
+        m.add(name.extract::<String>()?, ty)?;
+    }
+    m.add_class::<OutcomeKind>()?;
+    m.add_class::<LoopOutcome>()?;
+    m.add_class::<ErrorRecord>()?;
+    m.add_function(wrap_pyfunction!(error_types, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,103 @@
+//! Per-engine toggles for wasm proposals, from the `engine_features` dict.
+//! Components must be compiled against the features the engine enables, so
+//! the toggles shape the engine and are part of its compatibility hash (and
+//! so of the compiled cache key). Unset toggles keep wasmtime's defaults.
+
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use wasmtime::Config;
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub(crate) struct WasmFeatures {
+    simd: Option<bool>,
+    relaxed_simd: Option<bool>,
+    bulk_memory: Option<bool>,
+    multi_memory: Option<bool>,
+    threads: Option<bool>,
+}
+
+/// Validation messages for a component using a disabled proposal, and the
+/// toggle that enables it. Relaxed SIMD comes before SIMD, whose message is
+/// a suffix of its own.
+const MISSING: &[(&str, &str)] = &[
+    ("relaxed SIMD support is not enabled", "relaxed_simd"),
+    ("SIMD support is not enabled", "simd"),
+    ("bulk memory support is not enabled", "bulk_memory"),
+    ("multiple memories", "multi_memory"),
+    ("threads support is not enabled", "threads"),
+    ("threads must be enabled", "threads"),
+];
+
+impl WasmFeatures {
+    pub fn from_dict(d: &Bound<'_, PyDict>) -> PyResult<Self> {
+        let mut features = WasmFeatures::default();
+        for (key, value) in d.iter() {
+            let key: String = key.extract()?;
+            let value: Option<bool> = value.extract()?;
+            match key.as_str() {
+                "simd" => features.simd = value,
+                "relaxed_simd" => features.relaxed_simd = value,
+                "bulk_memory" => features.bulk_memory = value,
+                "multi_memory" => features.multi_memory = value,
+                "threads" => features.threads = value,
+                _ => {
+                    return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                        "engine_features: unknown feature {key:?}; expected simd, relaxed_simd, \
+                         bulk_memory, multi_memory or threads"
+                    )));
+                }
+            }
+        }
+        Ok(features)
+    }
+
+    pub fn apply(self, cfg: &mut Config) {
+        if let Some(on) = self.simd {
+            cfg.wasm_simd(on);
+        }
+        // relaxed SIMD builds on SIMD, so turning SIMD off turns it off too
+        // unless relaxed_simd is set explicitly
+        if let Some(on) = self.relaxed_simd.or(self.simd.filter(|on| !on)) {
+            cfg.wasm_relaxed_simd(on);
+        }
+        if let Some(on) = self.bulk_memory {
+            cfg.wasm_bulk_memory(on);
+        }
+        if let Some(on) = self.multi_memory {
+            cfg.wasm_multi_memory(on);
+        }
+        if let Some(on) = self.threads {
+            cfg.wasm_threads(on);
+        }
+    }
+}
+
+/// Name the toggle behind a compile error caused by a disabled proposal,
+/// which wasmtime only reports as a validation failure at some offset.
+pub(crate) fn explain(err: String) -> String {
+    match MISSING.iter().find(|(msg, _)| err.contains(msg)) {
+        Some((_, feature)) => format!(
+            "{err}\nthe component needs the {feature} feature, which this engine has disabled; \
+             enable it with engine_features={{\"{feature}\": True}}"
+        ),
+        None => err,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn explain_names_the_disabled_toggle() {
+        let simd = explain("offset 315: SIMD support is not enabled".into());
+        assert!(simd.ends_with(r#"engine_features={"simd": True}"#), "{simd}");
+        let relaxed = explain("offset 9: relaxed SIMD support is not enabled".into());
+        assert!(relaxed.ends_with(r#"engine_features={"relaxed_simd": True}"#), "{relaxed}");
+    }
+
+    #[test]
+    fn explain_keeps_other_errors() {
+        assert_eq!(explain("unknown import".into()), "unknown import");
+    }
+}
@@ -0,0 +1,155 @@
+use crate::errors::MessageTooLarge;
+
+/// Length-prefixed framing for `length_prefixed=True`. Each message crosses
+/// the Python transport as a 4-byte big-endian length followed by that many
+/// bytes, so `recv_bytes` may hand back any split of the stream (partial
+/// frames, several frames at once) and the guest still sees whole messages.
+pub(crate) struct Framer {
+    max_message_size: usize,
+    /* stream bytes received but not yet handed to the guest */
+    buf: Vec<u8>,
+}
+
+const PREFIX: usize = 4;
+
+/// `max_message_size` when framing is on and none was given, so a declared
+/// length can never make the host buffer without bound.
+pub(crate) const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 << 20;
+
+impl Framer {
+    pub fn new(max_message_size: usize) -> Self {
+        Framer {
+            max_message_size,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Prefix an outgoing message with its length. A message the prefix
+    /// cannot express is too large whatever `max_message_size` says.
+    pub fn encode(&self, payload: &[u8]) -> wasmtime::Result<Vec<u8>> {
+        self.check_size("send_bytes", payload.len())?;
+        let len = u32::try_from(payload.len()).map_err(|_| {
+            wasmtime::Error::new(MessageTooLarge {
+                import: "send_bytes",
+                len: payload.len(),
+                max: u32::MAX as usize,
+            })
+        })?;
+        let mut out = Vec::with_capacity(PREFIX + payload.len());
+        out.extend_from_slice(&len.to_be_bytes());
+        out.extend_from_slice(payload);
+        Ok(out)
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) {
+        self.buf.extend_from_slice(chunk);
+    }
+
+    /// True while nothing is buffered, i.e. the stream is between frames.
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Whether a complete frame is buffered.
+    pub fn has_frame(&self) -> bool {
+        self.declared_len().is_some_and(|len| self.buf.len() >= PREFIX + len)
+    }
+
+    /// Take the next complete frame. The declared length is checked as soon
+    /// as the prefix is buffered, so an oversized frame fails before the host
+    /// waits for (and buffers) the rest of its body; whatever arrived in the
+    /// same chunk as the prefix is already held.
+    pub fn next_frame(&mut self) -> wasmtime::Result<Option<Vec<u8>>> {
+        let Some(len) = self.declared_len() else {
+            return Ok(None);
+        };
+        self.check_size("recv_bytes", len)?;
+        if self.buf.len() < PREFIX + len {
+            return Ok(None);
+        }
+        let frame = self.buf[PREFIX..PREFIX + len].to_vec();
+        self.buf.drain(..PREFIX + len);
+        Ok(Some(frame))
+    }
+
+    fn declared_len(&self) -> Option<usize> {
+        let prefix: [u8; PREFIX] = self.buf.get(..PREFIX)?.try_into().ok()?;
+        Some(u32::from_be_bytes(prefix) as usize)
+    }
+
+    fn check_size(&self, import: &'static str, len: usize) -> wasmtime::Result<()> {
+        if len > self.max_message_size {
+            return Err(wasmtime::Error::new(MessageTooLarge {
+                import,
+                len,
+                max: self.max_message_size,
+            }));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        Framer::new(DEFAULT_MAX_MESSAGE_SIZE).encode(payload).unwrap()
+    }
+
+    #[test]
+    fn round_trips_whole_frames() {
+        let mut framer = Framer::new(DEFAULT_MAX_MESSAGE_SIZE);
+        framer.push(&frame(b"hello"));
+        assert!(framer.has_frame());
+        assert_eq!(framer.next_frame().unwrap().as_deref(), Some(&b"hello"[..]));
+        assert!(framer.is_empty());
+        assert_eq!(framer.next_frame().unwrap(), None);
+    }
+
+    #[test]
+    fn reassembles_split_and_joined_chunks() {
+        let mut stream = frame(b"first");
+        stream.extend(frame(b""));
+        stream.extend(frame(b"third"));
+        let mut framer = Framer::new(DEFAULT_MAX_MESSAGE_SIZE);
+        let mut frames = Vec::new();
+        for byte in stream.chunks(3) {
+            framer.push(byte);
+            while let Some(frame) = framer.next_frame().unwrap() {
+                frames.push(frame);
+            }
+        }
+        assert_eq!(frames, [&b"first"[..], b"", b"third"]);
+        assert!(framer.is_empty());
+    }
+
+    #[test]
+    fn partial_frame_stays_buffered() {
+        let mut framer = Framer::new(DEFAULT_MAX_MESSAGE_SIZE);
+        let whole = frame(b"payload");
+        framer.push(&whole[..6]);
+        assert!(!framer.has_frame());
+        assert_eq!(framer.next_frame().unwrap(), None);
+        assert!(!framer.is_empty());
+        framer.push(&whole[6..]);
+        assert_eq!(framer.next_frame().unwrap().as_deref(), Some(&b"payload"[..]));
+    }
+
+    #[test]
+    fn rejects_oversized_declared_length_from_prefix_alone() {
+        let mut framer = Framer::new(1024);
+        framer.push(&u32::MAX.to_be_bytes());
+        let err = framer.next_frame().unwrap_err();
+        let too_large = err.downcast_ref::<MessageTooLarge>().unwrap();
+        assert_eq!((too_large.len, too_large.max), (u32::MAX as usize, 1024));
+    }
+
+    #[test]
+    fn rejects_oversized_send() {
+        let framer = Framer::new(4);
+        assert!(framer.encode(b"1234").is_ok());
+        let err = framer.encode(b"12345").unwrap_err();
+        assert!(err.downcast_ref::<MessageTooLarge>().is_some());
+    }
+}
@@ -0,0 +1,67 @@
+use pyo3::prelude::*;
+use std::sync::{Arc, mpsc};
+use std::time::{Duration, Instant};
+
+/// `heartbeat=`: a plain function called as `heartbeat(count, elapsed_ms)`
+/// every `heartbeat_interval_ms` while `run_msg_loop` runs, so a supervisor
+/// can tell a live loop from a stuck one. `count` starts at 1 for each loop
+/// and `elapsed_ms` is the loop's monotonic running time. The beat runs on
+/// its own thread, so it keeps going while the guest computes, even when
+/// that guest holds the runtime's only worker. Returning False, or raising
+/// (reported through `sys.unraisablehook`), asks the loop to stop as
+/// `close()` would.
+#[derive(Clone)]
+pub(crate) struct Heartbeat {
+    cb: Arc<PyObject>,
+    interval: Duration,
+}
+
+/// Stops the heartbeat when dropped, at the end of the loop: the thread
+/// wakes as the channel disconnects and exits. It is not joined, since it
+/// may be waiting for the GIL.
+pub(crate) struct Beating {
+    _stop: mpsc::Sender<()>,
+}
+
+impl Heartbeat {
+    pub fn new(cb: PyObject, interval: Duration) -> Self {
+        Heartbeat {
+            cb: Arc::new(cb),
+            interval,
+        }
+    }
+
+    /// Beat until the returned guard is dropped; `stop` is called once if
+    /// the callback asks to stop. A beat that runs late moves the later
+    /// ones back rather than firing a burst to catch up.
+    pub fn start(&self, stop: impl FnOnce() + Send + 'static) -> std::io::Result<Beating> {
+        let beat = self.clone();
+        let (tx, rx) = mpsc::channel::<()>();
+        std::thread::Builder::new().name("wasm-heartbeat".into()).spawn(move || {
+            let started = Instant::now();
+            for count in 1u64.. {
+                if rx.recv_timeout(beat.interval) != Err(mpsc::RecvTimeoutError::Timeout) {
+                    return;
+                }
+                if !beat.call(count, started.elapsed()) {
+                    stop();
+                    return;
+                }
+            }
+        })?;
+        Ok(Beating { _stop: tx })
+    }
+
+    fn call(&self, count: u64, elapsed: Duration) -> bool {
+        Python::with_gil(|py| {
+            let cb = self.cb.bind(py);
+            match cb.call1((count, elapsed.as_secs_f64() * 1000.0)) {
+                Ok(ret) => !ret.is(pyo3::types::PyBool::new(py, false)),
+                Err(e) => {
+                    e.write_unraisable(py, Some(cb));
+                    false
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,814 @@
+//! Host imports: the functions a component calls into, and the linker
+//! that provides them.
+//!
+//! Concurrency: runners share the tokio runtime and only meet at the GIL.
+//! Every import takes it in short synchronous `with_gil_maybe_blocking`
+//! closures (call the callback, convert the result) and awaits with it
+//! released, so one runner waiting on its transport never stalls another.
+//! The compiler holds us to this: `Python<'py>` is `!Send` and the import
+//! futures must be `Send`, so a GIL token kept across an `.await` is
+//! rejected. Under `blocking_callbacks=True` even acquiring the GIL moves
+//! off the worker, so a runner blocked on it does not hold up the others.
+
+use crate::errors::Closed;
+use crate::tasks::TaskResult;
+use crate::{
+    Ctx, FrameHeader, LogLevel, custom, extract_payload, extract_recv_payload, log_level_name, pyerr,
+    pyerr_to_wasmtime_err, vfs, with_gil_maybe_blocking,
+};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::future::Future;
+use wasmtime::component::Linker;
+use wasmtime::{AsContextMut, Engine};
+use wasmtime_wasi::p2::add_to_linker_async;
+
+macro_rules! host_fn_sync_ret {
+    ($fn_name:ident, $py_field:ident, ($($argn:ident : $argt:ty),*), $ret:ty) => {
+        pub fn $fn_name(
+            mut store: wasmtime::StoreContextMut<Ctx>,
+            ($($argn,)*): ($($argt,)*),
+        ) -> wasmtime::Result<($ret,)> {
+            let started_call = store.data().call_start();
+            let started = store.data().profile_start();
+            let data = store.data();
+            let res = with_gil_maybe_blocking(data.blocking_callbacks, |py| {
+                use pyo3::types::PyAnyMethods;
+                let obj = data.imports.$py_field.bind(py).call1(($($argn,)*))?;
+                obj.extract::<$ret>()
+            }).map(|v| (v,)).map_err(pyerr_to_wasmtime_err);
+            store.data_mut().profile_record(concat!("host;", stringify!($py_field)), started);
+            store.data().call_record(stringify!($py_field), started_call);
+            res
+        }
+    };
+}
+
+macro_rules! host_fn_sync_void {
+    ($fn_name:ident, $py_field:ident, ($($argn:ident : $argt:ty),*)) => {
+        pub fn $fn_name(
+            mut store: wasmtime::StoreContextMut<Ctx>,
+            ($($argn,)*): ($($argt,)*),
+        ) -> wasmtime::Result<()> {
+            let started_call = store.data().call_start();
+            let started = store.data().profile_start();
+            let data = store.data();
+            let res = with_gil_maybe_blocking(data.blocking_callbacks, |py| {
+                use pyo3::types::PyAnyMethods;
+                data.imports.$py_field.bind(py).call1(($($argn,)*)).map(|_| ())
+            }).map_err(pyerr_to_wasmtime_err);
+            store.data_mut().profile_record(concat!("host;", stringify!($py_field)), started);
+            store.data().call_record(stringify!($py_field), started_call);
+            res
+        }
+    };
+}
+
+macro_rules! host_fn_async_ret {
+    ($fn_name:ident, $py_field:ident, ($($argn:ident : $argt:ty),*), $ret:ty) => {
+        host_fn_async_ret!($fn_name, $py_field, ($($argn : $argt),*), $ret, |obj| obj.extract::<$ret>());
+    };
+    ($fn_name:ident, $py_field:ident, ($($argn:ident : $argt:ty),*), $ret:ty, $extract:expr) => {
+        pub fn $fn_name(
+            mut store: wasmtime::StoreContextMut<Ctx>,
+            ($($argn,)*): ($($argt,)*),
+        ) -> Box<dyn std::future::Future<Output = wasmtime::Result<($ret,)>> + Send + '_> {
+            Box::new(async move {
+                let started_call = store.data().call_start();
+                let res = async {
+                    let blocking = store.data().blocking_callbacks;
+                    let started = store.data().profile_start();
+                    let fut = with_gil_maybe_blocking(blocking, |py| {
+                        use pyo3::types::PyAnyMethods;
+                        let imports = &store.data().imports;
+                        let coro = imports.$py_field.bind(py).call1(($($argn,)*))?;
+                        crate::callback_result(imports.sync, coro)
+                    }).map_err(pyerr_to_wasmtime_err)?;
+                    store.data_mut().profile_record(concat!("host;", stringify!($py_field)), started);
+                    let started = store.data().profile_start();
+                    let obj = fut.await.map_err(pyerr_to_wasmtime_err)?;
+                    store.data_mut().profile_record(concat!("wait;", stringify!($py_field)), started);
+                    let started = store.data().profile_start();
+                    let extract: fn(&pyo3::Bound<'_, pyo3::PyAny>) -> pyo3::PyResult<$ret> = $extract;
+                    let r = with_gil_maybe_blocking(blocking, |py| extract(obj.bind(py)).map_err(pyerr_to_wasmtime_err))?;
+                    store.data_mut().profile_record(concat!("host;", stringify!($py_field)), started);
+                    Ok((r,))
+                }
+                .await;
+                store.data().call_record(stringify!($py_field), started_call);
+                res
+            })
+        }
+    };
+}
+
+macro_rules! host_fn_async_void {
+    ($fn_name:ident, $py_field:ident, ($($argn:ident : $argt:ty),*)) => {
+        pub fn $fn_name(
+            mut store: wasmtime::StoreContextMut<Ctx>,
+            ($($argn,)*): ($($argt,)*),
+        ) -> Box<dyn std::future::Future<Output = wasmtime::Result<()>> + Send + '_> {
+            Box::new(async move {
+                let started_call = store.data().call_start();
+                let res = async {
+                    let blocking = store.data().blocking_callbacks;
+                    let started = store.data().profile_start();
+                    let fut = with_gil_maybe_blocking(blocking, |py| {
+                        use pyo3::types::PyAnyMethods;
+                        let imports = &store.data().imports;
+                        let coro = imports.$py_field.bind(py).call1(($($argn,)*))?;
+                        crate::callback_result(imports.sync, coro)
+                    }).map_err(pyerr_to_wasmtime_err)?;
+                    store.data_mut().profile_record(concat!("host;", stringify!($py_field)), started);
+
+                    let started = store.data().profile_start();
+                    let _obj = fut.await.map_err(pyerr_to_wasmtime_err)?;
+                    store.data_mut().profile_record(concat!("wait;", stringify!($py_field)), started);
+                    Ok(())
+                }
+                .await;
+                store.data().call_record(stringify!($py_field), started_call);
+                res
+            })
+        }
+    };
+}
+
+/// A linker providing WASI and every host import, as a runner built with
+/// these settings links its component against.
+pub(crate) fn host_linker(
+    engine: &Engine,
+    async_recv_ready: bool,
+    virtual_files: bool,
+    custom_imports: Option<&Bound<'_, PyDict>>,
+) -> PyResult<Linker<Ctx>> {
+    let mut linker = Linker::<Ctx>::new(engine);
+    add_to_linker_async(&mut linker).map_err(pyerr)?;
+    if virtual_files {
+        vfs::VirtualFs::add_to_linker(&mut linker).map_err(pyerr)?;
+    }
+    let mut root = linker.root();
+    root.func_wrap_async("send-bytes", send_bytes)
+        .map_err(pyerr)?;
+    root.func_wrap_async("recv-bytes", recv_bytes)
+        .map_err(pyerr)?;
+    root.func_wrap_async("try-recv-bytes", try_recv_bytes)
+        .map_err(pyerr)?;
+    if async_recv_ready {
+        root.func_wrap_async("recv-ready", recv_ready_async)
+            .map_err(pyerr)?;
+    } else {
+        root.func_wrap("recv-ready", recv_ready)
+            .map_err(pyerr)?;
+    }
+    root.func_wrap("write-log", write_log)
+        .map_err(pyerr)?;
+    root.func_wrap("write-log-record", write_log_record)
+        .map_err(pyerr)?;
+    root.func_wrap_async("send-bytes-batch", send_bytes_batch)
+        .map_err(pyerr)?;
+    root.func_wrap_async("recv-bytes-batch", recv_bytes_batch)
+        .map_err(pyerr)?;
+    root.func_wrap_async("send-frame", send_frame)
+        .map_err(pyerr)?;
+    root.func_wrap_async("recv-frame", recv_frame)
+        .map_err(pyerr)?;
+    root.func_wrap("next-id", next_id)
+        .map_err(pyerr)?;
+    root.func_wrap("spawn-task", spawn_task)
+        .map_err(pyerr)?;
+    root.func_wrap_async("sleep", sleep)
+        .map_err(pyerr)?;
+    root.func_wrap("stop-reason", stop_reason)
+        .map_err(pyerr)?;
+    root.func_wrap_async("await-task", await_task)
+        .map_err(pyerr)?;
+    if let Some(custom_imports) = custom_imports {
+        custom::register(&mut root, custom::from_dict(custom_imports)?)?;
+    }
+    Ok(linker)
+}
+
+host_fn_async_void!(py_send_bytes, send_bytes, (payload: Vec<u8>));
+// the same callback under codec=, taking objects
+host_fn_async_void!(py_send_value, send_bytes, (message: pyo3::PyObject));
+host_fn_sync_ret!(py_recv_ready, recv_ready, (), bool);
+host_fn_async_ret!(py_recv_ready_async, recv_ready, (), bool);
+host_fn_sync_void!(py_write_log, write_log, (text: String));
+
+/// Run an `on_*_transform` callback over a message crossing the
+/// boundary. Unset transforms return the payload untouched without
+/// taking the GIL; a transform that raises aborts the message and traps
+/// the guest with a `WasmHostError` naming the transform.
+fn transform(
+    data: &Ctx,
+    name: &str,
+    cb: Option<&pyo3::PyObject>,
+    payload: Vec<u8>,
+) -> wasmtime::Result<Vec<u8>> {
+    let Some(cb) = cb else { return Ok(payload) };
+    with_gil_maybe_blocking(data.blocking_callbacks, |py| {
+        extract_payload(&cb.bind(py).call1((payload,))?)
+    })
+    .map_err(|e| pyerr_to_wasmtime_err(e).context(format!("WasmRunner: {name} failed")))
+}
+
+/// Metrics count what the guest sent, before `on_send_transform` and the
+/// length prefix.
+pub fn send_bytes(
+    store: wasmtime::StoreContextMut<Ctx>,
+    (payload,): (Vec<u8>,),
+) -> Box<dyn Future<Output = wasmtime::Result<()>> + Send + '_> {
+    let data = store.data();
+    if let Err(e) = data.check_open().and_then(|()| data.check_message_size("send_bytes", payload.len())) {
+        return Box::new(async move { Err(e) });
+    }
+    data.metrics.sent(payload.len());
+    let payload = transform(data, "on_send_transform", data.imports.on_send_transform.as_ref(), payload)
+        .and_then(|payload| match &data.framing {
+            Some(framer) => framer.encode(&payload),
+            None => Ok(payload),
+        });
+    if let Some(window) = data.send_window.clone() {
+        return Box::new(async move { send_windowed(store, &window, payload?).await });
+    }
+    let payload = match (payload, data.imports.codec) {
+        (Ok(payload), None) => return py_send_bytes(store, (payload,)),
+        (Ok(payload), Some(codec)) => with_gil_maybe_blocking(data.blocking_callbacks, |py| {
+            codec.decode(py, "send_bytes", &payload)
+        }),
+        (Err(e), _) => Err(e),
+    };
+    match payload {
+        Ok(message) => py_send_value(store, (message,)),
+        Err(e) => Box::new(async move { Err(e) }),
+    }
+}
+
+/// `send_bytes` under `max_inflight_*`: wait for room in the window,
+/// call the callback, and leave its coroutine running past the return.
+async fn send_windowed(
+    store: wasmtime::StoreContextMut<'_, Ctx>,
+    window: &crate::backpressure::SendWindow,
+    payload: Vec<u8>,
+) -> wasmtime::Result<()> {
+    let data = store.data();
+    let slot = window.reserve(payload.len(), data.closed.clone(), data.deadline).await?;
+    let data = store.data();
+    let fut = with_gil_maybe_blocking(data.blocking_callbacks, |py| {
+        let cb = data.imports.send_bytes.bind(py);
+        let coro = match data.imports.codec {
+            Some(codec) => cb.call1((codec.decode(py, "send_bytes", &payload)?,)),
+            None => cb.call1((payload,)),
+        };
+        crate::callback_result(data.imports.sync, coro.map_err(pyerr_to_wasmtime_err)?)
+            .map_err(pyerr_to_wasmtime_err)
+    })?;
+    window.spawn(slot, async move { fut.await.map(|_| ()).map_err(pyerr_to_wasmtime_err) });
+    Ok(())
+}
+
+/// The `recv_bytes` callback's next message, encoded under `codec`.
+/// None from the callback is end of stream either way. The callback's
+/// coroutine is kept in `Ctx` while it runs, so a wait given up (at
+/// `deadline`, or by `drain()` dropping this future) resumes it on the
+/// next call rather than losing its message; None means `deadline`
+/// passed first.
+async fn recv_payload(
+    mut store: wasmtime::StoreContextMut<'_, Ctx>,
+    deadline: Option<tokio::time::Instant>,
+) -> wasmtime::Result<Option<Vec<u8>>> {
+    let started_call = store.data().call_start();
+    let blocking = store.data().blocking_callbacks;
+    if store.data().pending_recv.is_none() {
+        let started = store.data().profile_start();
+        let fut = with_gil_maybe_blocking(blocking, |py| {
+            let imports = &store.data().imports;
+            let coro = imports.recv_bytes.bind(py).call1(())?;
+            crate::callback_result(imports.sync, coro)
+        })
+        .map_err(pyerr_to_wasmtime_err)?;
+        store.data_mut().profile_record("host;recv_bytes", started);
+        store.data_mut().pending_recv = Some(fut);
+    }
+    let started = store.data().profile_start();
+    let fut = store.data_mut().pending_recv.as_mut().expect("set above");
+    let obj = match deadline {
+        Some(deadline) => match tokio::time::timeout_at(deadline, fut).await {
+            Ok(res) => res,
+            Err(_) => return Ok(None),
+        },
+        None => fut.await,
+    };
+    store.data_mut().pending_recv = None;
+    store.data_mut().profile_record("wait;recv_bytes", started);
+    let codec = store.data().imports.codec;
+    let res = obj.map_err(pyerr_to_wasmtime_err).and_then(|obj| {
+        with_gil_maybe_blocking(blocking, |py| {
+            let obj = obj.bind(py);
+            match codec {
+                Some(_) if obj.is_none() => Ok(Vec::new()),
+                Some(codec) => codec.encode("recv_bytes", obj),
+                None => extract_recv_payload(obj).map_err(pyerr_to_wasmtime_err),
+            }
+        })
+    });
+    store.data().call_record("recv_bytes", started_call);
+    res.map(Some)
+}
+
+/// `recv-bytes`, or with `within` the bounded wait of `try-recv-bytes`:
+/// None once `within` passes without a whole message.
+async fn recv_message(
+    mut store: wasmtime::StoreContextMut<'_, Ctx>,
+    within: Option<std::time::Duration>,
+) -> wasmtime::Result<Option<Vec<u8>>> {
+    let metrics = store.data().metrics.clone();
+    let closed = store.data().closed.clone();
+    let draining = store.data().draining.clone();
+    store.data().check_open()?;
+    if *draining.borrow() {
+        return Ok(Some(Vec::new()));
+    }
+    let deadline = within.map(|within| tokio::time::Instant::now() + within);
+    let payload = match store.data_mut().preloaded.pop_front() {
+        Some(payload) => payload,
+        None if store.data().framing.is_some() => loop {
+            let framer = store.data_mut().framing.as_mut().unwrap();
+            if let Some(frame) = framer.next_frame()? {
+                break frame;
+            }
+            let chunk = tokio::select! {
+                res = recv_payload(store.as_context_mut(), deadline) => res?,
+                () = until_set(closed.clone()) => return Err(wasmtime::Error::new(Closed)),
+                () = until_set(draining.clone()) => return Ok(Some(Vec::new())),
+            };
+            // a partial frame stays buffered for the next call
+            let Some(chunk) = chunk else { return Ok(None) };
+            let framer = store.data_mut().framing.as_mut().unwrap();
+            if chunk.is_empty() {
+                // end of stream: passed on as before, unless it cuts a frame short
+                if framer.is_empty() {
+                    break chunk;
+                }
+                return Err(wasmtime::Error::msg("WasmRunner: recv_bytes stream ended mid-frame"));
+            }
+            framer.push(&chunk);
+        },
+        None => {
+            let payload = tokio::select! {
+                res = recv_payload(store.as_context_mut(), deadline) => res?,
+                () = until_set(closed) => return Err(wasmtime::Error::new(Closed)),
+                () = until_set(draining) => return Ok(Some(Vec::new())),
+            };
+            let Some(payload) = payload else { return Ok(None) };
+            store.data().check_message_size("recv_bytes", payload.len())?;
+            payload
+        }
+    };
+    let data = store.data();
+    let payload = transform(data, "on_recv_transform", data.imports.on_recv_transform.as_ref(), payload)?;
+    metrics.received(payload.len());
+    Ok(Some(payload))
+}
+
+/// An empty payload is end of stream, telling the guest to leave its
+/// loop; the callback signals it by returning None or `b""`. Once
+/// `drain()` is called this returns one too, without consuming buffered
+/// or preloaded messages.
+/// `on_recv_transform` applies to preloaded messages too; metrics count
+/// what the guest received, after the transform.
+/// Under `length_prefixed=True` preloaded messages are already whole and
+/// skip framing; callback results are stream chunks, read until a whole
+/// frame is buffered.
+pub fn recv_bytes(
+    mut store: wasmtime::StoreContextMut<Ctx>,
+    (): (),
+) -> Box<dyn Future<Output = wasmtime::Result<(Vec<u8>,)>> + Send + '_> {
+    Box::new(async move {
+        let payload = recv_message(store.as_context_mut(), None).await?;
+        Ok((payload.expect("no deadline to pass"),))
+    })
+}
+
+/// `recv-bytes` waiting at most `recv_timeout_ms`: none when no message
+/// arrived in time, so a polling guest can do other work and ask again.
+/// The callback's coroutine is not cancelled; the next call (to either
+/// import) picks up its message. Otherwise as `recv-bytes`, with
+/// `some` of an empty payload for end of stream; without
+/// `recv_timeout_ms` it never returns none.
+pub fn try_recv_bytes(
+    mut store: wasmtime::StoreContextMut<Ctx>,
+    (): (),
+) -> Box<dyn Future<Output = wasmtime::Result<(Option<Vec<u8>>,)>> + Send + '_> {
+    Box::new(async move {
+        let within = store.data().recv_timeout;
+        Ok((recv_message(store.as_context_mut(), within).await?,))
+    })
+}
+
+/// With a `send_bytes_batch` callback the whole batch reaches Python in
+/// one call, under one GIL acquisition, as a list of what `send_bytes`
+/// would have received in turn; without one each message goes through
+/// `send_bytes`. Size limits, transforms, framing and metrics apply per
+/// message either way. The batch callback is awaited before returning,
+/// outside any `max_inflight_*` window.
+pub fn send_bytes_batch(
+    mut store: wasmtime::StoreContextMut<Ctx>,
+    (payloads,): (Vec<Vec<u8>>,),
+) -> Box<dyn Future<Output = wasmtime::Result<()>> + Send + '_> {
+    Box::new(async move {
+        if store.data().imports.send_bytes_batch.is_none() {
+            for payload in payloads {
+                std::pin::Pin::from(send_bytes(store.as_context_mut(), (payload,))).await?;
+            }
+            return Ok(());
+        }
+        let data = store.data();
+        data.check_open()?;
+        let mut batch = Vec::with_capacity(payloads.len());
+        for payload in payloads {
+            data.check_message_size("send_bytes", payload.len())?;
+            data.metrics.sent(payload.len());
+            let payload = transform(data, "on_send_transform", data.imports.on_send_transform.as_ref(), payload)?;
+            batch.push(match &data.framing {
+                Some(framer) => framer.encode(&payload)?,
+                None => payload,
+            });
+        }
+        let started_call = data.call_start();
+        let fut = with_gil_maybe_blocking(data.blocking_callbacks, |py| {
+            let cb = data.imports.send_bytes_batch.as_ref().expect("checked above").bind(py);
+            let coro = match data.imports.codec {
+                Some(codec) => {
+                    let messages = batch
+                        .iter()
+                        .map(|payload| codec.decode(py, "send_bytes_batch", payload))
+                        .collect::<wasmtime::Result<Vec<_>>>()?;
+                    cb.call1((messages,))
+                }
+                None => cb.call1((batch,)),
+            };
+            crate::callback_result(data.imports.sync, coro.map_err(pyerr_to_wasmtime_err)?)
+                .map_err(pyerr_to_wasmtime_err)
+        })?;
+        let res = fut.await.map(|_| ()).map_err(pyerr_to_wasmtime_err);
+        store.data().call_record("send_bytes_batch", started_call);
+        res
+    })
+}
+
+/// One `recv_bytes_batch` call, its messages as bytes (encoded under
+/// `codec`). None or an empty list is end of stream.
+async fn fetch_batch(store: wasmtime::StoreContextMut<'_, Ctx>) -> wasmtime::Result<Vec<Vec<u8>>> {
+    let started_call = store.data().call_start();
+    let blocking = store.data().blocking_callbacks;
+    let fut = with_gil_maybe_blocking(blocking, |py| {
+        let imports = &store.data().imports;
+        let coro = imports.recv_bytes_batch.as_ref().expect("checked by caller").bind(py).call1(())?;
+        crate::callback_result(imports.sync, coro)
+    })
+    .map_err(pyerr_to_wasmtime_err)?;
+    let obj = fut.await.map_err(pyerr_to_wasmtime_err)?;
+    let codec = store.data().imports.codec;
+    let res = with_gil_maybe_blocking(blocking, |py| {
+        let obj = obj.bind(py);
+        if obj.is_none() {
+            return Ok(Vec::new());
+        }
+        obj.try_iter()
+            .map_err(pyerr_to_wasmtime_err)?
+            .map(|item| {
+                let item = item.map_err(pyerr_to_wasmtime_err)?;
+                match codec {
+                    Some(codec) => codec.encode("recv_bytes_batch", &item),
+                    None => extract_payload(&item).map_err(pyerr_to_wasmtime_err),
+                }
+            })
+            .collect()
+    });
+    store.data().call_record("recv_bytes_batch", started_call);
+    res
+}
+
+/// With a `recv_bytes_batch` callback, everything one call returns (a
+/// list, None or `[]` for end of stream) reaches the guest together,
+/// under one GIL acquisition; queued preloaded messages, or the whole
+/// frames buffered under `length_prefixed=True`, are handed out first,
+/// all at once. Without the callback this is `recv_bytes`, one message
+/// per call. An empty list is end of stream, including after `drain()`,
+/// so outside framing an empty message in a batch is refused.
+pub fn recv_bytes_batch(
+    mut store: wasmtime::StoreContextMut<Ctx>,
+    (): (),
+) -> Box<dyn Future<Output = wasmtime::Result<(Vec<Vec<u8>>,)>> + Send + '_> {
+    Box::new(async move {
+        if store.data().imports.recv_bytes_batch.is_none() {
+            let (payload,) = std::pin::Pin::from(recv_bytes(store.as_context_mut(), ())).await?;
+            return Ok((if payload.is_empty() { Vec::new() } else { vec![payload] },));
+        }
+        store.data().check_open()?;
+        let closed = store.data().closed.clone();
+        let draining = store.data().draining.clone();
+        if *draining.borrow() {
+            return Ok((Vec::new(),));
+        }
+        let mut payloads: Vec<Vec<u8>> = store.data_mut().preloaded.drain(..).collect();
+        while payloads.is_empty() {
+            if let Some(framer) = store.data_mut().framing.as_mut() {
+                while let Some(frame) = framer.next_frame()? {
+                    payloads.push(frame);
+                }
+                if !payloads.is_empty() {
+                    break;
+                }
+            }
+            let chunks = tokio::select! {
+                res = fetch_batch(store.as_context_mut()) => res?,
+                () = until_set(closed.clone()) => return Err(wasmtime::Error::new(Closed)),
+                () = until_set(draining.clone()) => return Ok((Vec::new(),)),
+            };
+            if chunks.is_empty() {
+                if store.data().framing.as_ref().is_some_and(|framer| !framer.is_empty()) {
+                    return Err(wasmtime::Error::msg("WasmRunner: recv_bytes_batch stream ended mid-frame"));
+                }
+                return Ok((Vec::new(),));
+            }
+            match store.data_mut().framing.as_mut() {
+                Some(framer) => chunks.iter().for_each(|chunk| framer.push(chunk)),
+                None => {
+                    for payload in &chunks {
+                        if payload.is_empty() {
+                            return Err(wasmtime::Error::msg(
+                                "WasmRunner: recv_bytes_batch returned an empty message; \
+                                 return None or [] for end of stream",
+                            ));
+                        }
+                        store.data().check_message_size("recv_bytes", payload.len())?;
+                    }
+                    payloads = chunks;
+                }
+            }
+        }
+        let data = store.data();
+        let payloads = payloads
+            .into_iter()
+            .map(|payload| {
+                let payload =
+                    transform(data, "on_recv_transform", data.imports.on_recv_transform.as_ref(), payload)?;
+                data.metrics.received(payload.len());
+                Ok(payload)
+            })
+            .collect::<wasmtime::Result<Vec<_>>>()?;
+        Ok((payloads,))
+    })
+}
+
+/// Under `length_prefixed=True` a buffered whole frame counts as ready;
+/// otherwise the callback decides, and may report a partial frame.
+/// Always false once `drain()` was called.
+pub fn recv_ready(store: wasmtime::StoreContextMut<Ctx>, (): ()) -> wasmtime::Result<(bool,)> {
+    store.data().check_open()?;
+    if *store.data().draining.borrow() {
+        return Ok((false,));
+    }
+    if buffered(store.data()) {
+        return Ok((true,));
+    }
+    py_recv_ready(store, ())
+}
+
+/// `recv_ready` under `async_recv_ready=True`: the callback is an async
+/// function and its coroutine is awaited on the runtime, so a readiness
+/// check doing I/O does not hold up other tasks.
+pub fn recv_ready_async(
+    store: wasmtime::StoreContextMut<Ctx>,
+    (): (),
+) -> Box<dyn Future<Output = wasmtime::Result<(bool,)>> + Send + '_> {
+    if let Err(e) = store.data().check_open() {
+        return Box::new(async move { Err(e) });
+    }
+    if buffered(store.data()) {
+        return Box::new(async { Ok((true,)) });
+    }
+    py_recv_ready_async(store, ())
+}
+
+fn buffered(data: &Ctx) -> bool {
+    !data.preloaded.is_empty() || data.framing.as_ref().is_some_and(|f| f.has_frame())
+}
+
+/// Plain log line, forwarded as an `info` record when `write_log_record`
+/// is set.
+pub fn write_log(store: wasmtime::StoreContextMut<Ctx>, (text,): (String,)) -> wasmtime::Result<()> {
+    let imports = &store.data().imports;
+    if imports.write_log_record.is_some() || imports.guest_logger.is_some() {
+        return log_record(store, LogLevel::Info, text, Vec::new());
+    }
+    py_write_log(store, (text,))
+}
+
+/// Structured log line. Without a `write_log_record` callback only the
+/// message reaches `write_log`, so older hosts keep working.
+pub fn write_log_record(
+    store: wasmtime::StoreContextMut<Ctx>,
+    (level, msg, tags): (Option<LogLevel>, String, Vec<(String, String)>),
+) -> wasmtime::Result<()> {
+    let imports = &store.data().imports;
+    if imports.write_log_record.is_none() && imports.guest_logger.is_none() {
+        return py_write_log(store, (msg,));
+    }
+    log_record(store, level.unwrap_or(LogLevel::Info), msg, tags)
+}
+
+/// Call `write_log_record(level, msg, tags)` with the level name and the
+/// tags as a dict, or log to the `logger_name` logger with the tags as
+/// `extra={"wasm_tags": ...}`. A repeated key keeps its last value.
+fn log_record(
+    mut store: wasmtime::StoreContextMut<Ctx>,
+    level: LogLevel,
+    msg: String,
+    tags: Vec<(String, String)>,
+) -> wasmtime::Result<()> {
+    let started = store.data().profile_start();
+    let data = store.data();
+    let res = with_gil_maybe_blocking(data.blocking_callbacks, |py| {
+        let tags_dict = pyo3::types::PyDict::new(py);
+        for (key, value) in tags {
+            pyo3::types::PyDictMethods::set_item(&tags_dict, key, value)?;
+        }
+        if let Some(logger) = &data.imports.guest_logger {
+            let tags_dict = (!pyo3::types::PyDictMethods::is_empty(&tags_dict)).then_some(tags_dict);
+            crate::diag::log(py, logger, crate::diag::python_level(level), &msg, tags_dict);
+            return Ok(());
+        }
+        let cb = data.imports.write_log_record.as_ref().expect("checked by caller");
+        cb.bind(py).call1((log_level_name(level), msg, tags_dict)).map(|_| ())
+    })
+    .map_err(pyerr_to_wasmtime_err);
+    store.data_mut().profile_record("host;write_log_record", started);
+    res
+}
+
+/// Why the host asked the loop to stop, once `drain()` or `close()` has.
+pub fn stop_reason(store: wasmtime::StoreContextMut<Ctx>, (): ()) -> wasmtime::Result<(Option<String>,)> {
+    Ok((crate::shutdown::lock(&store.data().stop_reason).clone(),))
+}
+
+/// Monotonic IDs, starting from the `id_base` given at construction so
+/// callers can keep them unique across runner restarts.
+pub fn next_id(store: wasmtime::StoreContextMut<Ctx>, (): ()) -> wasmtime::Result<(u64,)> {
+    use std::sync::atomic::Ordering;
+    Ok((store.data().next_id.fetch_add(1, Ordering::Relaxed),))
+}
+
+/// Start the `spawn_task` callback on the tokio runtime and return a
+/// handle for `await-task`. Errors (no callback, limit reached, callback
+/// raised synchronously) go back to the guest as `err`, not a trap.
+pub fn spawn_task(
+    mut store: wasmtime::StoreContextMut<Ctx>,
+    (payload,): (Vec<u8>,),
+) -> wasmtime::Result<(Result<u64, String>,)> {
+    let data = store.data();
+    let Some(cb) = data.imports.spawn_task.as_ref() else {
+        return Ok((Err(missing_callback("spawn_task").to_string()),));
+    };
+    let permit = match data.tasks.try_permit() {
+        Ok(permit) => permit,
+        Err(e) => return Ok((Err(e),)),
+    };
+    let fut = with_gil_maybe_blocking(data.blocking_callbacks, |py| {
+        let coro = cb.bind(py).call1((payload,))?;
+        crate::awaited(coro)
+    });
+    let fut = match fut {
+        Ok(fut) => fut,
+        Err(e) => return Ok((Err(pyerr_to_wasmtime_err(e).to_string()),)),
+    };
+    let blocking = data.blocking_callbacks;
+    let handle = store.data_mut().tasks.spawn(permit, async move {
+        let obj = fut.await.map_err(|e| pyerr_to_wasmtime_err(e).to_string())?;
+        with_gil_maybe_blocking(blocking, |py| extract_payload(obj.bind(py)))
+            .map_err(|e| pyerr_to_wasmtime_err(e).to_string())
+    });
+    Ok((Ok(handle),))
+}
+
+/// Wait for a task started by `spawn-task`. Each handle can be awaited once.
+pub fn await_task(
+    mut store: wasmtime::StoreContextMut<Ctx>,
+    (handle,): (u64,),
+) -> Box<dyn Future<Output = wasmtime::Result<(TaskResult,)>> + Send + '_> {
+    let join = store.data_mut().tasks.take(handle);
+    Box::new(async move {
+        let Some(join) = join else {
+            return Ok((Err(format!("WasmRunner: unknown task handle {handle}")),));
+        };
+        let res = join.await.unwrap_or_else(|e| Err(format!("WasmRunner: task failed: {e}")));
+        Ok((res,))
+    })
+}
+
+/// Pause the guest for `ms` milliseconds without holding a thread. The
+/// sleep counts against the call's `timeout_ms` and `init_timeout_ms`:
+/// one that would outlast a deadline wakes at it and traps as the epoch
+/// check would, so sleeping cannot stretch a call past its budget. It
+/// ends at once on `close()`, with the usual unwind, and on `drain()`,
+/// returning early so the guest reaches its end of stream. Time slept
+/// accumulates in `metrics()["slept_ms"]`, apart from guest compute.
+pub fn sleep(
+    store: wasmtime::StoreContextMut<Ctx>,
+    (ms,): (u64,),
+) -> Box<dyn Future<Output = wasmtime::Result<()>> + Send + '_> {
+    Box::new(async move {
+        store.data().check_open()?;
+        let started_call = store.data().call_start();
+        let started = std::time::Instant::now();
+        let data = store.data();
+        let wake = started.checked_add(std::time::Duration::from_millis(ms));
+        // a deadline that falls before the wake-up, as the error it raises
+        let expiry = data.next_deadline().filter(|(at, _)| wake.is_none_or(|wake| *at < wake));
+        let until = expiry.as_ref().map(|(at, _)| *at).or(wake);
+        let (closed, draining) = (data.closed.clone(), data.draining.clone());
+        let res = tokio::select! {
+            () = async {
+                match until {
+                    Some(until) => tokio::time::sleep_until(until.into()).await,
+                    None => std::future::pending().await,
+                }
+            } => expiry.map_or(Ok(()), |(_, e)| Err(e)),
+            () = until_set(closed) => Err(wasmtime::Error::new(Closed)),
+            () = until_set(draining) => Ok(()),
+        };
+        store.data().metrics.slept(started.elapsed());
+        store.data().call_record("sleep", started_call);
+        res
+    })
+}
+
+/// Resolves once the flag (`close()` or `drain()`) is set; never if the
+/// runner is dropped first.
+async fn until_set(mut flag: tokio::sync::watch::Receiver<bool>) {
+    if flag.wait_for(|c| *c).await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+fn missing_callback(name: &str) -> wasmtime::Error {
+    wasmtime::Error::msg(format!("WasmRunner: guest called {name} but no {name} callback was provided"))
+}
+
+/// Framed send: the Python callback receives `(kind, flags, body)`.
+/// `max_message_size` bounds the body, as it does a `send_bytes` payload.
+pub fn send_frame(
+    store: wasmtime::StoreContextMut<Ctx>,
+    (header, body): (FrameHeader, Vec<u8>),
+) -> Box<dyn std::future::Future<Output = wasmtime::Result<()>> + Send + '_> {
+    Box::new(async move {
+        store.data().check_open()?;
+        store.data().check_message_size("send_frame", body.len())?;
+        store.data().metrics.sent(body.len());
+        let started_call = store.data().call_start();
+        let blocking = store.data().blocking_callbacks;
+        let fut = with_gil_maybe_blocking(blocking, |py| {
+            let cb = store.data().imports.send_frame.as_ref().ok_or_else(|| missing_callback("send_frame"))?;
+            let coro = cb.bind(py).call1((header.kind, header.flags, body)).map_err(pyerr_to_wasmtime_err)?;
+            crate::callback_result(store.data().imports.sync, coro).map_err(pyerr_to_wasmtime_err)
+        })?;
+        let res = fut.await.map(|_| ()).map_err(pyerr_to_wasmtime_err);
+        store.data().call_record("send_frame", started_call);
+        res
+    })
+}
+
+type Frame = (FrameHeader, Vec<u8>);
+
+/// Framed recv: the Python callback returns `(kind, flags, body)`.
+/// `max_message_size` bounds the body, as it does a `recv_bytes` payload.
+pub fn recv_frame(
+    store: wasmtime::StoreContextMut<Ctx>,
+    (): (),
+) -> Box<dyn std::future::Future<Output = wasmtime::Result<(Frame,)>> + Send + '_> {
+    Box::new(async move {
+        store.data().check_open()?;
+        let started_call = store.data().call_start();
+        let blocking = store.data().blocking_callbacks;
+        let fut = with_gil_maybe_blocking(blocking, |py| {
+            let cb = store.data().imports.recv_frame.as_ref().ok_or_else(|| missing_callback("recv_frame"))?;
+            let coro = cb.bind(py).call1(()).map_err(pyerr_to_wasmtime_err)?;
+            crate::callback_result(store.data().imports.sync, coro).map_err(pyerr_to_wasmtime_err)
+        })?;
+        let obj = tokio::select! {
+            res = fut => res.map_err(pyerr_to_wasmtime_err)?,
+            () = until_set(store.data().closed.clone()) => return Err(wasmtime::Error::new(Closed)),
+        };
+        let (kind, flags, body) = with_gil_maybe_blocking(blocking, |py| {
+            let (kind, flags, body) = obj.extract::<(u32, u32, pyo3::Bound<'_, pyo3::PyAny>)>(py)?;
+            Ok((kind, flags, extract_payload(&body)?))
+        }).map_err(pyerr_to_wasmtime_err)?;
+        store.data().call_record("recv_frame", started_call);
+        store.data().check_message_size("recv_frame", body.len())?;
+        store.data().metrics.received(body.len());
+        Ok(((FrameHeader { kind, flags }, body),))
+    })
+}
@@ -0,0 +1,166 @@
+//! Transparent-huge-page backing for guest linear memory.
+//!
+//! Wasmtime has no huge-page switch of its own, so `huge_pages=True` installs
+//! a custom `MemoryCreator` that reserves each linear memory with `mmap`,
+//! aligns it to a 2 MiB boundary and marks it `MADV_HUGEPAGE`. The kernel then
+//! backs fully-touched 2 MiB spans with huge pages.
+//!
+//! Platform requirements: Linux with transparent huge pages set to `always`
+//! or `madvise` in `/sys/kernel/mm/transparent_hugepage/enabled`. If THP is
+//! disabled or `madvise` fails, the memory behaves like ordinary 4 KiB pages
+//! and no error is raised. On other platforms the option is ignored.
+//!
+//! Interaction with the memory configuration: the creator only applies to
+//! the on-demand allocator (the default here). A pooling allocator would
+//! manage its own slots and ignore it. Memories never move: each one reserves
+//! its full reservation (the engine's `memory_reservation`, 4 GiB for 32-bit
+//! memories by default) up front, so growth past it fails as with static
+//! memories. Wasmtime's copy-on-write memory initialisation only works with
+//! its own memories, so data segments are copied in at instantiation instead.
+
+use wasmtime::Config;
+
+#[cfg(target_os = "linux")]
+pub(crate) fn install(cfg: &mut Config) {
+    cfg.with_host_memory(std::sync::Arc::new(linux::HugePageCreator));
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn install(_cfg: &mut Config) {}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use wasmtime::{LinearMemory, MemoryCreator, MemoryType};
+
+    const HUGE_PAGE: usize = 2 << 20;
+    /// Reservation for memories that leave it to the creator (those the
+    /// engine allows to move): the full 32-bit address space.
+    const DEFAULT_RESERVATION: usize = 1 << 32;
+
+    pub(super) struct HugePageCreator;
+
+    unsafe impl MemoryCreator for HugePageCreator {
+        fn new_memory(
+            &self,
+            _ty: MemoryType,
+            minimum: usize,
+            maximum: Option<usize>,
+            reserved_size_in_bytes: Option<usize>,
+            guard_size_in_bytes: usize,
+        ) -> Result<Box<dyn LinearMemory>, String> {
+            let capacity = reserved_size_in_bytes
+                .unwrap_or_else(|| maximum.unwrap_or(DEFAULT_RESERVATION).min(DEFAULT_RESERVATION))
+                .max(minimum);
+            let mut memory = HugePageMemory::reserve(capacity, guard_size_in_bytes)?;
+            memory.grow_to(minimum).map_err(|e| e.to_string())?;
+            Ok(Box::new(memory))
+        }
+    }
+
+    struct HugePageMemory {
+        base: *mut u8,
+        /// Mapping length including guard pages.
+        mapped: usize,
+        capacity: usize,
+        size: usize,
+        /// Prefix made readable and writable; `size` rounded up to host pages.
+        accessible: usize,
+        page_size: usize,
+    }
+
+    // The mapping is owned exclusively by this value; wasmtime serialises
+    // access to it through the store.
+    unsafe impl Send for HugePageMemory {}
+    unsafe impl Sync for HugePageMemory {}
+
+    impl HugePageMemory {
+        fn reserve(capacity: usize, guard: usize) -> Result<Self, String> {
+            let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+            let mapped = (capacity + guard).next_multiple_of(page_size);
+            // over-reserve so the memory can start on a huge page boundary
+            let len = mapped + HUGE_PAGE;
+            let raw = unsafe {
+                libc::mmap(
+                    std::ptr::null_mut(),
+                    len,
+                    libc::PROT_NONE,
+                    libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+                    -1,
+                    0,
+                )
+            };
+            if raw == libc::MAP_FAILED {
+                let err = std::io::Error::last_os_error();
+                return Err(format!("mmap of {len} bytes failed: {err}"));
+            }
+            let start = raw as usize;
+            let aligned = start.next_multiple_of(HUGE_PAGE);
+            unsafe {
+                if aligned > start {
+                    libc::munmap(raw, aligned - start);
+                }
+                let tail = start + len - (aligned + mapped);
+                if tail > 0 {
+                    libc::munmap((aligned + mapped) as *mut libc::c_void, tail);
+                }
+                // advisory only: fails with EINVAL when THP is unavailable
+                libc::madvise(aligned as *mut libc::c_void, capacity, libc::MADV_HUGEPAGE);
+            }
+            Ok(HugePageMemory {
+                base: aligned as *mut u8,
+                mapped,
+                capacity,
+                size: 0,
+                accessible: 0,
+                page_size,
+            })
+        }
+    }
+
+    unsafe impl LinearMemory for HugePageMemory {
+        fn byte_size(&self) -> usize {
+            self.size
+        }
+
+        fn byte_capacity(&self) -> usize {
+            self.capacity
+        }
+
+        fn grow_to(&mut self, new_size: usize) -> wasmtime::Result<()> {
+            if new_size > self.capacity {
+                return Err(wasmtime::Error::msg(format!(
+                    "memory of {new_size} bytes exceeds reservation of {}",
+                    self.capacity
+                )));
+            }
+            let accessible = new_size.next_multiple_of(self.page_size).min(self.capacity);
+            if accessible > self.accessible {
+                let rc = unsafe {
+                    libc::mprotect(
+                        self.base.add(self.accessible).cast(),
+                        accessible - self.accessible,
+                        libc::PROT_READ | libc::PROT_WRITE,
+                    )
+                };
+                if rc != 0 {
+                    return Err(std::io::Error::last_os_error().into());
+                }
+                self.accessible = accessible;
+            }
+            self.size = self.size.max(new_size);
+            Ok(())
+        }
+
+        fn as_ptr(&self) -> *mut u8 {
+            self.base
+        }
+    }
+
+    impl Drop for HugePageMemory {
+        fn drop(&mut self) {
+            unsafe {
+                libc::munmap(self.base.cast(), self.mapped);
+            }
+        }
+    }
+}
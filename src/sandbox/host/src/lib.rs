@@ -1,20 +1,234 @@
 use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::collections::VecDeque;
 use std::path::Path;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use tokio::sync::Mutex;
 use wasmtime::component::ResourceTable;
-use wasmtime::{Config, Engine, Error, Store, component::*};
+use wasmtime::{Engine, Error, Store, component::*};
 use wasmtime_wasi::{WasiCtx, WasiCtxBuilder, WasiCtxView, WasiView};
-use wasmtime_wasi::p2::add_to_linker_async;
 use wasmtime_wasi_io::IoView;
 
-wasmtime::component::bindgen!({ path: "../wit/", world: "env", imports: { default: async }, exports: { default: async } });
+mod backpressure;
+mod cache;
+mod callstats;
+mod codec;
+mod compression;
+mod custom;
+mod determinism;
+mod diag;
+mod engine;
+mod epoch;
+mod errors;
+mod features;
+mod framing;
+mod heartbeat;
+mod host_imports;
+mod hugepages;
+mod limits;
+mod loading;
+mod meminit;
+mod network;
+mod options;
+mod paths;
+mod pool;
+mod profile;
+mod shutdown;
+mod signing;
+mod stdio;
+mod tasks;
+#[cfg(test)]
+mod testing;
+mod timings;
+mod vfs;
+mod worlds;
+use engine::{EngineHandle, EngineOptions, PoolOptions};
+use host_imports::host_linker;
+use loading::{CacheWrite, load_or_precompile_component, load_precompiled, with_compile_threads};
+use limits::{Limits, MessageCounts, Metrics};
+use profile::Profile;
+use tasks::Tasks;
+
+wasmtime::component::bindgen!({ path: "../wit/", world: "env-config", imports: { default: async }, exports: { default: async } });
 
 struct Imports {
     recv_bytes: PyObject,
     send_bytes: PyObject,
+    /* plain function returning bool, or with async_recv_ready=True an
+    async function whose coroutine resolves to bool */
     recv_ready: PyObject,
-    write_log: PyObject
+    write_log: PyObject,
+    /* (level, msg, tags) for structured logs; write_log(msg) gets them otherwise */
+    write_log_record: Option<PyObject>,
+    /* logging.Logger taking guest logs in place of both callbacks, from logger_name */
+    guest_logger: Option<PyObject>,
+    /* list-at-a-time variants of send_bytes/recv_bytes; the batch imports
+    fall back to those without them */
+    send_bytes_batch: Option<PyObject>,
+    recv_bytes_batch: Option<PyObject>,
+    /* framed variants; optional since most components only use raw bytes */
+    send_frame: Option<PyObject>,
+    recv_frame: Option<PyObject>,
+    /* async callable run by spawn-task */
+    spawn_task: Option<PyObject>,
+    /* bytes -> bytes rewrites applied at the boundary */
+    on_send_transform: Option<PyObject>,
+    on_recv_transform: Option<PyObject>,
+    /* mode="sync": send/recv callbacks are plain functions, not async ones */
+    sync: bool,
+    /* Some under codec=: send_bytes/recv_bytes callbacks exchange objects */
+    codec: Option<codec::Codec>,
+}
+
+impl Imports {
+    fn clone_ref(&self, py: Python<'_>) -> Self {
+        let opt = |cb: &Option<PyObject>| cb.as_ref().map(|cb| cb.clone_ref(py));
+        Imports {
+            recv_bytes: self.recv_bytes.clone_ref(py),
+            send_bytes: self.send_bytes.clone_ref(py),
+            recv_ready: self.recv_ready.clone_ref(py),
+            write_log: self.write_log.clone_ref(py),
+            write_log_record: opt(&self.write_log_record),
+            guest_logger: opt(&self.guest_logger),
+            send_bytes_batch: opt(&self.send_bytes_batch),
+            recv_bytes_batch: opt(&self.recv_bytes_batch),
+            send_frame: opt(&self.send_frame),
+            recv_frame: opt(&self.recv_frame),
+            spawn_task: opt(&self.spawn_task),
+            on_send_transform: opt(&self.on_send_transform),
+            on_recv_transform: opt(&self.on_recv_transform),
+            sync: self.sync,
+            codec: self.codec,
+        }
+    }
+}
+
+/// Everything needed to build a runner's `Store`, kept so `reset()` can
+/// replace a store without the constructor's arguments.
+struct StoreSpec {
+    engine: Engine,
+    wasm_inherit_io: bool,
+    stdin: Option<stdio::PyInput>,
+    stdout: Option<stdio::PyOutput>,
+    /* Some under capture_stdout=True; shared by every store */
+    captured_stdout: Option<stdio::Captured>,
+    stderr: Option<stdio::PyOutput>,
+    env: Vec<(String, String)>,
+    args: Vec<String>,
+    preopen_dirs: Vec<(String, String, bool)>,
+    imports: Imports,
+    blocking_callbacks: bool,
+    max_table_elements: Option<usize>,
+    max_memory_bytes: Option<usize>,
+    max_concurrent_tasks: usize,
+    metrics: Arc<Metrics>,
+    interrupted: Arc<AtomicBool>,
+    epoch_budget: Arc<AtomicU64>,
+    closed: tokio::sync::watch::Receiver<bool>,
+    draining: tokio::sync::watch::Receiver<bool>,
+    stop_reason: StopReason,
+    /* Some(max) under length_prefixed=True */
+    framing: Option<usize>,
+    max_message_size: Option<usize>,
+    epochs: bool,
+    /* epoch ticks between cooperative yields, from yield_interval_ms */
+    yield_ticks: Option<u64>,
+    fuel_limit: Option<u64>,
+    engine_fuel: bool,
+    deterministic: Option<determinism::Deterministic>,
+    call_stats: Option<Arc<std::sync::Mutex<callstats::CallStats>>>,
+    /* Some under allow_network=True */
+    network: Option<network::NetworkPolicy>,
+    /* Some under virtual_files=; shared by every store */
+    vfs: Option<Arc<vfs::VirtualFs>>,
+    /* Some under max_inflight_messages= or max_inflight_bytes= */
+    send_window: Option<backpressure::WindowLimits>,
+    recv_timeout: Option<std::time::Duration>,
+}
+
+impl StoreSpec {
+    fn build(&self, next_id: u64, profile: Option<Profile>) -> PyResult<Store<Ctx>> {
+        let mut wasi_builder = WasiCtxBuilder::new();
+        if self.wasm_inherit_io {
+            wasi_builder.inherit_stdin();
+            wasi_builder.inherit_stdout();
+            wasi_builder.inherit_stderr();
+        }
+        // callbacks take precedence over wasm_inherit_io for their stream
+        if let Some(input) = &self.stdin {
+            wasi_builder.stdin(input.clone());
+        }
+        if let Some(out) = &self.stdout {
+            wasi_builder.stdout(out.clone());
+        }
+        if let Some(out) = &self.captured_stdout {
+            wasi_builder.stdout(out.clone());
+        }
+        if let Some(out) = &self.stderr {
+            wasi_builder.stderr(out.clone());
+        }
+        wasi_builder.envs(&self.env);
+        wasi_builder.args(&self.args);
+        for (host_path, guest_path, writable) in &self.preopen_dirs {
+            preopen_dir(&mut wasi_builder, host_path, guest_path, *writable)?;
+        }
+        if let Some(deterministic) = &self.deterministic {
+            deterministic.apply(&mut wasi_builder);
+        }
+        if let Some(network) = &self.network {
+            network.apply(&mut wasi_builder);
+        }
+        let imports = Python::with_gil(|py| self.imports.clone_ref(py));
+
+        let mut store = Store::new(
+            &self.engine,
+            Ctx {
+                table: ResourceTable::new(),
+                wasi: wasi_builder.build(),
+                imports,
+                blocking_callbacks: self.blocking_callbacks,
+                limits: Limits {
+                    max_table_elements: self.max_table_elements,
+                    max_memory_bytes: self.max_memory_bytes,
+                    metrics: self.metrics.clone(),
+                },
+                metrics: self.metrics.clone(),
+                next_id: AtomicU64::new(next_id),
+                preloaded: VecDeque::new(),
+                tasks: Tasks::new(self.max_concurrent_tasks, self.metrics.clone()),
+                profile,
+                deadline: None,
+                init_deadline: None,
+                cpu_budget: None,
+                call_stats: self.call_stats.clone(),
+                interrupted: self.interrupted.clone(),
+                epoch_budget: self.epoch_budget.clone(),
+                closed: self.closed.clone(),
+                draining: self.draining.clone(),
+                stop_reason: self.stop_reason.clone(),
+                framing: self.framing.map(framing::Framer::new),
+                max_message_size: self.max_message_size,
+                vfs: self.vfs.clone(),
+                send_window: self.send_window.map(|limits| Arc::new(backpressure::SendWindow::new(limits))),
+                pending_recv: None,
+                recv_timeout: self.recv_timeout,
+            },
+        );
+        store.limiter(|ctx| &mut ctx.limits);
+        if self.epochs {
+            epoch::install(&mut store, self.yield_ticks);
+        }
+        if self.fuel_limit.is_some() {
+            // yield to tokio periodically so a compute-bound guest does not
+            // starve the host callbacks sharing its runtime
+            store.fuel_async_yield_interval(Some(FUEL_YIELD_INTERVAL)).map_err(pyerr)?;
+        } else if self.engine_fuel {
+            // metering comes from a shared engine this runner does not meter
+            store.set_fuel(u64::MAX).map_err(pyerr)?;
+        }
+        Ok(store)
+    }
 }
 
 struct Ctx {
@@ -22,6 +236,115 @@ struct Ctx {
     wasi: WasiCtx,
     /* wit imports */
     imports: Imports,
+    /* run Python callbacks under tokio::task::block_in_place */
+    blocking_callbacks: bool,
+    limits: Limits,
+    metrics: Arc<Metrics>,
+    /* next value handed out by the next-id import */
+    next_id: AtomicU64,
+    /* messages recv-bytes hands out before falling back to the callback */
+    preloaded: VecDeque<Vec<u8>>,
+    tasks: Tasks,
+    /* None unless profile=True, so the hot path is a single branch */
+    profile: Option<Profile>,
+    /* end of the current run_msg_loop call and its timeout, if any */
+    deadline: Option<(std::time::Instant, std::time::Duration)>,
+    /* the same for init-exec-env under init_timeout_ms, set only during that call */
+    init_deadline: Option<(std::time::Instant, std::time::Duration)>,
+    /* epoch ticks of guest compute left in the current call, and cpu_timeout_ms */
+    cpu_budget: Option<(u64, std::time::Duration)>,
+    /* set by Drop under drop_behavior="interrupt" */
+    interrupted: Arc<AtomicBool>,
+    /* epoch increments left before the guest is interrupted, from
+    set_epoch_deadline; epoch::NO_DEADLINE when unset */
+    epoch_budget: Arc<AtomicU64>,
+    /* flips to true on close() */
+    closed: tokio::sync::watch::Receiver<bool>,
+    /* true from drain() until the loop returns */
+    draining: tokio::sync::watch::Receiver<bool>,
+    /* for the stop-reason import */
+    stop_reason: StopReason,
+    /* Some under length_prefixed=True */
+    framing: Option<framing::Framer>,
+    /* cap on a send_bytes/recv_bytes payload; frames carry their own under framing */
+    max_message_size: Option<usize>,
+    /* None unless host_call_stats=True; shared with the runner and kept across reset() */
+    call_stats: Option<Arc<std::sync::Mutex<callstats::CallStats>>>,
+    /* the filesystem preopened at / under virtual_files= */
+    vfs: Option<Arc<vfs::VirtualFs>>,
+    /* sends still running after send_bytes returned, under max_inflight_* */
+    send_window: Option<Arc<backpressure::SendWindow>>,
+    /* a recv_bytes coroutine a try-recv-bytes or drain() stopped waiting for */
+    pending_recv: Option<CallbackResult>,
+    /* how long try-recv-bytes waits, from recv_timeout_ms */
+    recv_timeout: Option<std::time::Duration>,
+}
+
+/// Why `drain()` or `close()` stopped the loop; None while nobody has. Set
+/// by the first of them (a `close()` overrides a `drain()`), and cleared
+/// with the drain when the loop returns.
+type StopReason = Arc<std::sync::Mutex<Option<String>>>;
+
+/// The reason recorded when a caller gives none.
+const UNSPECIFIED: &str = "unspecified";
+
+impl Ctx {
+    /// The nearer of the init and loop deadlines, with the error it raises.
+    fn next_deadline(&self) -> Option<(std::time::Instant, Error)> {
+        [
+            self.init_deadline.map(|(at, limit)| (at, Error::new(errors::InitTimeout(limit)))),
+            self.deadline.map(|(at, limit)| (at, Error::new(errors::Timeout(limit)))),
+        ]
+        .into_iter()
+        .flatten()
+        .min_by_key(|(at, _)| *at)
+    }
+
+    /// Fail the current import if `close()` was called, unwinding the guest
+    /// out of its loop.
+    fn check_open(&self) -> wasmtime::Result<()> {
+        if *self.closed.borrow() {
+            return Err(wasmtime::Error::new(errors::Closed));
+        }
+        Ok(())
+    }
+
+    /// Refuse a message or frame body over `max_message_size`.
+    fn check_message_size(&self, import: &'static str, len: usize) -> wasmtime::Result<()> {
+        match self.max_message_size {
+            Some(max) if len > max => Err(wasmtime::Error::new(errors::MessageTooLarge { import, len, max })),
+            _ => Ok(()),
+        }
+    }
+
+    fn profile_start(&self) -> Option<std::time::Instant> {
+        self.profile.as_ref().map(|_| std::time::Instant::now())
+    }
+
+    fn profile_record(&mut self, frame: &'static str, started: Option<std::time::Instant>) {
+        if let (Some(profile), Some(started)) = (&mut self.profile, started) {
+            profile.record(frame, started.elapsed());
+        }
+    }
+
+    fn call_start(&self) -> Option<std::time::Instant> {
+        self.call_stats.as_ref().map(|_| std::time::Instant::now())
+    }
+
+    fn call_record(&self, import: &'static str, started: Option<std::time::Instant>) {
+        if let (Some(stats), Some(started)) = (&self.call_stats, started) {
+            shutdown::lock(stats).record(import, started.elapsed());
+        }
+    }
+
+    fn profile_phase(&mut self, phase: Option<&'static str>) {
+        if let Some(profile) = &mut self.profile {
+            match phase {
+                Some(name) => profile.begin(name),
+                None => profile.end(),
+            }
+        }
+    }
 }
 
 impl IoView for Ctx {
@@ -40,115 +363,988 @@ fn pyerr<E: std::fmt::Display>(e: E) -> PyErr {
     pyo3::exceptions::PyRuntimeError::new_err(e.to_string())
 }
 
-fn pyerr_to_wasmtime_err(e: PyErr) -> wasmtime::Error {
-    let msg = Python::with_gil(|py| {
-        let ty = e.get_type(py);
-        let val = e.value(py);
-
-        // Prefer Python-side formatting: "TypeError: message\n"
-        if let Ok(tbmod) = py.import("traceback") {
-            if let Ok(list_obj) = tbmod.call_method1("format_exception_only", (&ty, &val)) {
-                if let Ok(parts) = list_obj.extract::<Vec<String>>() {
-                    return parts.concat();
-                }
-            }
-        }
+/// Acquire the GIL and run `f`. When `blocking` is set, this first tells tokio
+/// that the current worker is about to block, so tasks queued behind it (e.g.
+/// other runners' loops) are moved to another worker while Python runs.
+///
+/// We use `block_in_place` rather than `spawn_blocking`: the callback borrows
+/// the store and needs the pyo3-async-runtimes task locals of the current
+/// task, neither of which survive a move to the blocking pool. The cost is a
+/// worker hand-off on every callback, so this is opt-in per runner. It also
+/// requires the multi-threaded tokio runtime (the pyo3-async-runtimes default).
+fn with_gil_maybe_blocking<F, R>(blocking: bool, f: F) -> R
+where
+    F: for<'py> FnOnce(Python<'py>) -> R,
+{
+    if blocking {
+        tokio::task::block_in_place(|| Python::with_gil(f))
+    } else {
+        Python::with_gil(f)
+    }
+}
+
+/// A transport callback's result as a future. Under `mode="sync"` the
+/// callback is a plain function and its return value is the result;
+/// otherwise it returned an awaitable, which runs on the runtime.
+type CallbackResult = std::pin::Pin<Box<dyn Future<Output = PyResult<PyObject>> + Send>>;
+
+fn callback_result(sync: bool, ret: Bound<'_, PyAny>) -> PyResult<CallbackResult> {
+    if sync {
+        let ret = ret.unbind();
+        return Ok(Box::pin(async move { Ok(ret) }));
+    }
+    Ok(Box::pin(awaited(ret)?))
+}
+
+/// Await what an async callback returned on the runtime. Anything else is
+/// refused here: `into_future` would only fail once on the event loop,
+/// where nothing sees the error, and the call would never resolve.
+fn awaited(ret: Bound<'_, PyAny>) -> PyResult<impl Future<Output = PyResult<PyObject>> + Send + use<>> {
+    let py = ret.py();
+    if !py.import("inspect")?.call_method1("isawaitable", (&ret,))?.is_truthy()? {
+        return Err(pyo3::exceptions::PyTypeError::new_err(format!(
+            "WasmRunner: an async callback returned {}, not an awaitable",
+            ret.get_type().name()?
+        )));
+    }
+    pyo3_async_runtimes::tokio::into_future(ret)
+}
 
-        // Fallback: "Type: message", both owned strings
-        let ty_name = ty
-            .name()
-            .ok()
-            .map(|s| s.to_string_lossy().into_owned())
-            .unwrap_or_else(|| "PyErr".to_string());
-        let val_str = val
-            .str()
-            .ok()
-            .map(|s| s.to_string_lossy().into_owned())
-            .unwrap_or_else(|| "<error>".to_string());
-        format!("{ty_name}: {val_str}")
+/// Wrap a failed host callback's exception for the trip back through the
+/// guest. The one-line `Type: message` summary becomes the wasmtime message;
+/// the full traceback and the exception itself travel alongside it.
+fn pyerr_to_wasmtime_err(e: PyErr) -> wasmtime::Error {
+    let (summary, traceback) = Python::with_gil(|py| {
+        let traceback = py
+            .import("traceback")
+            .and_then(|tb| {
+                tb.call_method1("format_exception", (e.get_type(py), e.value(py), e.traceback(py)))
+            })
+            .and_then(|lines| lines.extract::<Vec<String>>())
+            .map(|lines| lines.concat())
+            .ok();
+        (pyerr_summary(py, &e), traceback)
     });
-    wasmtime::Error::msg(msg)
+    wasmtime::Error::new(errors::HostCallbackError {
+        summary,
+        traceback,
+        source: e,
+    })
+}
+
+/// Copy a message payload out of Python in one `memcpy`. `bytes` is read in
+/// place and any other buffer-protocol object (`bytearray`, `memoryview`,
+/// contiguous `array('B')`) through `PyBuffer<u8>`; anything else falls back
+/// to sequence extraction, which converts element by element.
+fn extract_payload(obj: &Bound<'_, PyAny>) -> PyResult<Vec<u8>> {
+    if let Ok(bytes) = obj.downcast::<pyo3::types::PyBytes>() {
+        return Ok(bytes.as_bytes().to_vec());
+    }
+    if let Ok(buf) = pyo3::buffer::PyBuffer::<u8>::get(obj) {
+        return buf.to_vec(obj.py());
+    }
+    obj.extract::<Vec<u8>>()
+}
+
+/// `extract_payload` for a `recv_bytes` result, where None is end of
+/// stream and reaches the guest as an empty payload.
+fn extract_recv_payload(obj: &Bound<'_, PyAny>) -> PyResult<Vec<u8>> {
+    if obj.is_none() {
+        return Ok(Vec::new());
+    }
+    extract_payload(obj)
+}
+
+fn pyerr_summary(py: Python<'_>, e: &PyErr) -> String {
+    let ty = e.get_type(py);
+    let val = e.value(py);
+
+    // Prefer Python-side formatting: "TypeError: message\n"
+    if let Ok(tbmod) = py.import("traceback")
+        && let Ok(list_obj) = tbmod.call_method1("format_exception_only", (&ty, &val))
+        && let Ok(parts) = list_obj.extract::<Vec<String>>()
+    {
+        return parts.concat();
+    }
+
+    // Fallback: "Type: message", both owned strings
+    let ty_name = ty
+        .name()
+        .ok()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "PyErr".to_string());
+    let val_str = val
+        .str()
+        .ok()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "<error>".to_string());
+    format!("{ty_name}: {val_str}")
 }
 
 struct WasmData {
     store: Store<Ctx>,
     comp: Component,
     linker: Linker<Ctx>,
-    env: Option<Env>,
-    logging: bool,
+    env: Option<worlds::Guest>,
+    world: worlds::World,
+    /* kept alongside env to look up optional exports such as reinit-exec-env */
+    instance: Option<Instance>,
+    diag: diag::Diag,
     log_tags: Option<String>,
     id_name: String,
+    init_config: InitConfig,
+    /* what reload() loads the component from */
+    source: ComponentSource,
+    /* Some under public_key=; checks every load, reloads included */
+    verifier: Option<signing::Verifier>,
+    /* what each run_msg_loop call does when the wasm file changed */
+    on_source_change: SourceChange,
+    interface: Arc<std::sync::RwLock<worlds::Interface>>,
+    /* bound on init-exec-env alone, from init_timeout_ms */
+    init_timeout: Option<std::time::Duration>,
+    init_max_retries: u32,
+    init_retry_backoff: std::time::Duration,
+    /* fuel granted at the start of each run_msg_loop call */
+    fuel_limit: Option<u64>,
+    /* guest compute allowed per call, from cpu_timeout_ms */
+    cpu_timeout: Option<std::time::Duration>,
+    max_memory_bytes: Option<usize>,
+    /* sizing of a pooled engine, to explain instantiation failures */
+    pool: Option<PoolOptions>,
+    /* mirrors env.is_some() for the `instantiated` getter */
+    instantiated: Arc<AtomicBool>,
+    /* for the `last_error` getter; cleared by a clean loop or reset() */
+    last_error: Arc<std::sync::Mutex<Option<errors::ErrorRecord>>>,
+    /* set when a call panicked while holding the lock; see recover() */
+    panicked: Arc<AtomicBool>,
+    /* set while a guest call runs and kept if it failed or was dropped; see recover() */
+    failed: bool,
+    /* for startup_timings(); readable while a loop runs */
+    timings: Arc<std::sync::Mutex<timings::StartupTimings>>,
+    spec: StoreSpec,
+}
+
+/// What a runner does when its `wasm_path` no longer holds the component it
+/// loaded, checked against the stored content hash before each
+/// `run_msg_loop` call.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SourceChange {
+    /// Keep running the loaded component.
+    Ignore,
+    /// Reload it, as `reload()`.
+    Reload,
+    /// Refuse the call with `WasmSourceChangedError`.
+    Error,
+}
+
+impl SourceChange {
+    fn parse(policy: &str) -> PyResult<Self> {
+        match policy {
+            "ignore" => Ok(SourceChange::Ignore),
+            "reload" => Ok(SourceChange::Reload),
+            "error" => Ok(SourceChange::Error),
+            other => Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "WasmRunner: on_source_change must be \"ignore\", \"reload\" or \"error\", got {other:?}"
+            ))),
+        }
+    }
+}
+
+/// Where a runner's component came from, so `reload()` can load it again.
+enum ComponentSource {
+    /// `wasm_path`, compiled through the cache at `compiled_cache`; `meta`
+    /// identifies the wasm currently loaded.
+    Wasm {
+        path: String,
+        compiled_cache: String,
+        /* wasm_compiled_cache was given, so failing to write it is an error */
+        cache_required: bool,
+        force_recompile: bool,
+        compile_threads: Option<usize>,
+        meta: cache::ArtifactMeta,
+    },
+    Precompiled {
+        path: String,
+    },
+    /// `wasm_bytes`: nothing on disk to reload from.
+    Bytes,
 }
 
 impl WasmData {
-    async fn instantiate(&mut self) {
-        if self.env.is_none() {
-            if self.logging {
-                eprintln!("WASMRunner: instantiating");
+    /// Apply `on_source_change` before a loop: nothing for a component not
+    /// loaded from `wasm_path` or under `"ignore"`, otherwise compare the
+    /// file's contents against the loaded ones and reload or raise.
+    fn check_source(&mut self) -> PyResult<()> {
+        let ComponentSource::Wasm { path, meta, .. } = &self.source else {
+            return Ok(());
+        };
+        match self.on_source_change {
+            SourceChange::Ignore => Ok(()),
+            SourceChange::Reload => self.reload(true).map(drop),
+            SourceChange::Error => {
+                let bytes = compression::read_wasm(Path::new(path))?;
+                if cache::ArtifactMeta::new(&self.spec.engine, &bytes) == *meta {
+                    return Ok(());
+                }
+                Err(errors::WasmSourceChangedError::new_err(format!(
+                    "WasmRunner: {path} changed since it was loaded; reload() to pick it up"
+                )))
             }
-            self.env = match Env::instantiate_async(&mut self.store, &self.comp, &self.linker).await
-            {
-                Ok(env) => {
-                    if self.logging {
-                        eprintln!("WASMRunner: calling init_exec_env");
-                    }
-                    let init_res = env
-                        .call_init_exec_env(
-                            &mut self.store,
-                            &self.id_name,
-                            self.log_tags.as_deref(),
-                        )
-                        .await;
-                    match init_res {
-                        Ok(()) => Some(env),
-                        Err(e) => {
-                            eprintln!("WASMRunner: init_exec_env failed: {}", e);
-                            None
-                        }
-                    }
+        }
+    }
+
+    /// Load the component again from where it came from and swap it in,
+    /// together with a fresh store (as `reset()`), so the next loop
+    /// instantiates the new code. With `if_changed`, a `wasm_path` component
+    /// whose file still has the same contents is left alone. Returns whether
+    /// the component was replaced; on failure the old one stays in place.
+    fn reload(&mut self, if_changed: bool) -> PyResult<bool> {
+        let engine = self.spec.engine.clone();
+        let mut load = timings::StartupTimings::default();
+        let (component, meta) = match &self.source {
+            ComponentSource::Wasm {
+                path,
+                compiled_cache,
+                cache_required,
+                force_recompile,
+                compile_threads,
+                meta,
+            } => {
+                let bytes = load.time("read_bytes", || compression::read_wasm(Path::new(path)))?;
+                let fresh = cache::ArtifactMeta::new(&engine, &bytes);
+                if if_changed && fresh == *meta {
+                    return Ok(false);
                 }
-                Err(e) => {
-                    eprintln!("WASMRunner: failed to instantiate: {}", e);
-                    None
+                if let Some(verifier) = &self.verifier {
+                    verifier.verify(&bytes)?;
                 }
-            };
+                let write = CacheWrite::new(*cache_required, &self.diag);
+                let component = with_compile_threads(*compile_threads, || {
+                    load_or_precompile_component(&engine, &bytes, &fresh, compiled_cache, *force_recompile, write, &mut load)
+                })
+                .map_err(|e| pyerr(features::explain(e)))?;
+                (component, Some(fresh))
+            }
+            ComponentSource::Precompiled { path } => {
+                (load.time("deserialize", || load_precompiled(&engine, path))?, None)
+            }
+            ComponentSource::Bytes => {
+                return Err(pyerr("WasmRunner: cannot reload a component given as wasm_bytes"));
+            }
+        };
+        self.world.check(&engine, &component)?;
+        worlds::check_imports(&self.linker, &component)?;
+        self.reset()?;
+        *shutdown::write(&self.interface) = worlds::Interface::of(&engine, &component);
+        self.comp = component;
+        shutdown::lock(&self.timings).replace_load(load);
+        if let (ComponentSource::Wasm { meta, .. }, Some(fresh)) = (&mut self.source, meta) {
+            *meta = fresh;
         }
+        self.diag.debug(format_args!("WASMRunner: component reloaded"));
+        Ok(true)
     }
 
-    async fn run_msg_loop(&mut self) -> Result<(), Error> {
-        if self.logging {
-            eprintln!("WASMRunner: run_msg_loop()");
+    /// Instantiate the component and run `init-exec-env`, unless a live
+    /// instance already exists. On failure nothing is kept, so the next call
+    /// starts over, and the original error is returned.
+    /// Transient failures are retried up to `init_max_retries` times on a
+    /// fresh store, waiting `init_retry_backoff_ms` before the first retry
+    /// and twice as long before each one after. Under `init_timeout_ms`
+    /// startup is bounded by that alone: the call's `timeout_ms` deadline
+    /// is held back and starts once the guest is ready.
+    async fn instantiate(&mut self) -> Result<(), Error> {
+        if self.env.is_some() {
+            return Ok(());
+        }
+        self.diag.debug(format_args!("WASMRunner: instantiating"));
+        self.store.data_mut().profile_phase(Some("instantiate"));
+        let held = self.init_timeout.and_then(|_| self.store.data_mut().deadline.take());
+        let mut attempts = 0;
+        self.failed = true;
+        let res = loop {
+            attempts += 1;
+            match self.instantiate_and_init().await {
+                Err(e) if attempts <= self.init_max_retries && self.is_transient(&e) => {
+                    self.diag.debug(format_args!("WASMRunner: instantiation attempt {attempts} failed: {e}"));
+                    let backoff = self.init_retry_backoff.saturating_mul(1 << (attempts - 1).min(16));
+                    tokio::time::sleep(backoff).await;
+                    self.retry_store()?;
+                }
+                Ok(ok) => break Ok(ok),
+                Err(e) if attempts > 1 => {
+                    break Err(e.context(format!("WasmRunner: instantiation failed after {attempts} attempts")));
+                }
+                Err(e) => break Err(e),
+            }
+        };
+        self.store.data_mut().profile_phase(None);
+        if let Some((_, limit)) = held {
+            self.store.data_mut().deadline = Some((std::time::Instant::now() + limit, limit));
+        }
+        self.failed = res.is_err();
+        match res {
+            Ok((instance, env)) => {
+                self.instance = Some(instance);
+                self.env = Some(env);
+                self.instantiated.store(true, Ordering::Relaxed);
+                Ok(())
+            }
+            Err(e) => {
+                self.diag.debug(format_args!("WASMRunner: instantiation failed: {e}"));
+                match self.pool {
+                    Some(pool) => Err(e.context(format!(
+                        "WasmRunner: instantiation failed on a pooled engine; the pool may be \
+                         exhausted or too small for this component ({pool})"
+                    ))),
+                    None => Err(e),
+                }
+            }
+        }
+    }
+
+    /// Whether an instantiation failure may pass on a retry: a host
+    /// callback failed or `init-exec-env` ran out of `init_timeout_ms`. Link
+    /// errors, traps, limits and `close()` are final.
+    fn is_transient(&self, e: &Error) -> bool {
+        !*self.store.data().closed.borrow()
+            && (e.downcast_ref::<errors::HostCallbackError>().is_some()
+                || e.downcast_ref::<errors::InitTimeout>().is_some())
+    }
+
+    /// Swap in a fresh store for the next instantiation attempt, keeping the
+    /// running call's deadline and refilling its fuel.
+    fn retry_store(&mut self) -> Result<(), Error> {
+        let (deadline, cpu_budget) = (self.store.data().deadline, self.store.data().cpu_budget);
+        self.reset().map_err(|e| Error::msg(e.to_string()))?;
+        self.store.data_mut().deadline = deadline;
+        self.store.data_mut().cpu_budget = cpu_budget;
+        if let Some(fuel) = self.fuel_limit {
+            self.store.set_fuel(fuel)?;
+        }
+        Ok(())
+    }
+
+    async fn instantiate_and_init(&mut self) -> Result<(Instance, worlds::Guest), Error> {
+        let started = std::time::Instant::now();
+        let instance = self.linker.instantiate_async(&mut self.store, &self.comp).await?;
+        let env = worlds::Guest::new(self.world, &mut self.store, &instance)?;
+        shutdown::lock(&self.timings).record("instantiate", started.elapsed());
+        self.diag.debug(format_args!("WASMRunner: calling init_exec_env"));
+        let started = std::time::Instant::now();
+        self.store.data_mut().init_deadline = self.init_timeout.map(|t| (std::time::Instant::now() + t, t));
+        let deadline = self.store.data().next_deadline();
+        let init =
+            env.call_init_exec_env(&mut self.store, &self.id_name, self.log_tags.as_deref(), &self.init_config);
+        // cut short, the instance is dropped with the error like any failed init
+        let res = within(deadline, init).await.into_result();
+        self.store.data_mut().init_deadline = None;
+        shutdown::lock(&self.timings).record("init_exec_env", started.elapsed());
+        res?;
+        Ok((instance, env))
+    }
+
+    /// Call the component's optional `reinit-exec-env` export, which takes
+    /// the same arguments as `init-exec-env` and resets guest session state
+    /// in place. On success the new parameters are also kept for any later
+    /// instantiation.
+    async fn reinit(
+        &mut self,
+        id_name: String,
+        log_tags: Option<String>,
+        config: InitConfig,
+    ) -> Result<(), Error> {
+        let Some(instance) = self.instance else {
+            return Err(Error::msg("WasmRunner: cannot reinit before the guest is instantiated"));
+        };
+        let unsupported = |e: Error| Error::msg(format!("WasmRunner: component does not support reinit: {e}"));
+        self.diag.debug(format_args!("WASMRunner: calling reinit_exec_env"));
+        let res = if self.world.takes_config() {
+            let func = instance
+                .get_typed_func::<(&str, Option<&str>, &InitConfig), ()>(&mut self.store, "reinit-exec-env")
+                .map_err(unsupported)?;
+            self.failed = true;
+            match func.call_async(&mut self.store, (&id_name, log_tags.as_deref(), &config)).await {
+                Ok(()) => func.post_return_async(&mut self.store).await,
+                Err(e) => Err(e),
+            }
+        } else {
+            let func = instance
+                .get_typed_func::<(&str, Option<&str>), ()>(&mut self.store, "reinit-exec-env")
+                .map_err(unsupported)?;
+            self.failed = true;
+            match func.call_async(&mut self.store, (&id_name, log_tags.as_deref())).await {
+                Ok(()) => func.post_return_async(&mut self.store).await,
+                Err(e) => Err(e),
+            }
+        };
+        self.failed = res.is_err();
+        res?;
+        self.id_name = id_name;
+        self.log_tags = log_tags;
+        self.init_config = config;
+        Ok(())
+    }
+
+    /// Call the instance's export `name` with `params`, expecting `results`
+    /// results; wasmtime checks both against the export's type.
+    async fn call_export(&mut self, name: &str, params: Vec<Val>, results: usize) -> Result<Vec<Val>, Error> {
+        let Some(instance) = self.instance else {
+            return Err(Error::msg("WasmRunner: cannot call an export before the guest is instantiated"));
+        };
+        let func = instance
+            .get_func(&mut self.store, name)
+            .ok_or_else(|| Error::msg(format!("WasmRunner: component has no exported function {name:?}")))?;
+        self.diag.debug(format_args!("WASMRunner: calling export {name}"));
+        let mut results = vec![Val::Bool(false); results];
+        let deadline = self.store.data().next_deadline();
+        let store = &mut self.store;
+        let call = async {
+            func.call_async(&mut *store, &params, &mut results).await?;
+            func.post_return_async(&mut *store).await
+        };
+        self.failed = true;
+        let res = within(deadline, call).await;
+        if res.is_cut_short() {
+            self.release();
+        }
+        let res = res.into_result();
+        self.failed = res.is_err();
+        res?;
+        Ok(results)
+    }
+
+    /// Drop the guest instance; the next `instantiate` starts afresh.
+    fn release(&mut self) {
+        self.env = None;
+        self.instance = None;
+        self.instantiated.store(false, Ordering::Relaxed);
+    }
+
+    /// Replace the store with a fresh one, dropping the instance and all
+    /// guest state. Keeps the compiled component, the host-side message
+    /// queue (preloaded messages, a partly received frame), `next-id` and
+    /// the profile.
+    fn reset(&mut self) -> PyResult<()> {
+        self.panicked.store(false, Ordering::Relaxed);
+        self.failed = false;
+        let ctx = self.store.data_mut();
+        let next_id = ctx.next_id.load(Ordering::Relaxed);
+        let profile = ctx.profile.take();
+        let store = self.spec.build(next_id, profile)?;
+        self.release();
+        let old = std::mem::replace(&mut self.store, store).into_data();
+        let ctx = self.store.data_mut();
+        ctx.preloaded = old.preloaded;
+        ctx.framing = old.framing;
+        // the old store's tables go with it
+        self.spec.metrics.table_elements.store(0, Ordering::Relaxed);
+        self.spec.metrics.memory_bytes.store(0, Ordering::Relaxed);
+        self.spec.metrics.memory_limit_hit.store(false, Ordering::Relaxed);
+        *shutdown::lock(&self.last_error) = None;
+        Ok(())
+    }
+
+    /// Start over from a fresh store if the last call holding the lock
+    /// panicked. The lock itself does not poison, but the panic may have
+    /// left the store mid-call and the instance in any state, so neither is
+    /// reused. The same goes for a guest call that failed: wasmtime keeps
+    /// the store marked as inside the guest after a trap, a host error or a
+    /// call cut short, and would panic on the next call into it, as it does
+    /// after a call whose future was dropped (a cancelled task). Every call
+    /// that runs guest code starts with this.
+    fn recover(&mut self) -> PyResult<()> {
+        if self.panicked.load(Ordering::Relaxed) {
+            self.diag.warn(format_args!("WasmRunner: the last call panicked; starting again from a fresh store"));
+            self.reset()?;
+        } else if self.failed {
+            self.diag.debug(format_args!("WasmRunner: the last call failed; starting again from a fresh store"));
+            self.reset()?;
+        }
+        Ok(())
+    }
+
+    /// Start the per-call budgets: the `timeout_ms` deadline, a full
+    /// `fuel_limit` and the `cpu_timeout_ms` budget. The deadline is wall
+    /// clock: the epoch check enforces it while guest code runs and `within`
+    /// while the guest waits on a host import, dropping the instance in
+    /// that case since the call cannot be resumed. `cpu_timeout_ms` counts only
+    /// epoch ticks spent running guest code, so time the guest spends
+    /// waiting on host callbacks (`recv_bytes` awaiting a message, say) is
+    /// not charged to it; each host call that spans ticks costs at most one,
+    /// so the measure is accurate to a tick (10ms) per call.
+    fn arm(&mut self, timeout: Option<std::time::Duration>) -> Result<(), Error> {
+        self.store.data_mut().deadline = timeout.map(|t| (std::time::Instant::now() + t, t));
+        self.store.data_mut().cpu_budget = self.cpu_timeout.map(|t| (epoch::ticks(t), t));
+        if let Some(fuel) = self.fuel_limit {
+            self.store.set_fuel(fuel)?;
+        }
+        Ok(())
+    }
+
+    /// Finish a call started with `arm`: attribute a failure to the memory
+    /// limit if growth was refused, treat unwinding from `close()` as
+    /// success, and release the instance once closed.
+    fn disarm(&mut self, res: Result<(), Error>) -> Result<(), Error> {
+        self.store.data_mut().deadline = None;
+        self.store.data_mut().cpu_budget = None;
+        let limit_hit = self.store.data().metrics.memory_limit_hit.swap(false, Ordering::Relaxed);
+        let res = match (res, self.max_memory_bytes) {
+            (Err(e), Some(max)) if limit_hit => Err(e.context(errors::MemoryLimit(max))),
+            (res, _) => res,
+        };
+        let res = match res {
+            Err(e) if e.downcast_ref::<errors::Closed>().is_some() => Ok(()),
+            res => res,
+        };
+        if *self.store.data().closed.borrow() {
+            self.release();
+        }
+        res
+    }
+
+    /// A loop the guest ended with `proc_exit` as its exit status rather
+    /// than an error. The instance is released, since its guest is gone;
+    /// the next loop instantiates a new one.
+    fn exit_status(&mut self, res: Result<(), Error>) -> Result<Option<i32>, Error> {
+        match res {
+            Ok(()) => Ok(None),
+            Err(e) => match e.downcast_ref::<wasmtime_wasi::I32Exit>() {
+                Some(&wasmtime_wasi::I32Exit(code)) => {
+                    self.diag.debug(format_args!("WASMRunner: guest exited with status {code}"));
+                    self.release();
+                    Ok(Some(code))
+                }
+                None => Err(e),
+            },
         }
+    }
+
+    /// Keep a loop's failure for `last_error`, or clear it after a clean one.
+    fn record_result<T>(&self, res: &Result<T, Error>, phase: &str) {
+        *shutdown::lock(&self.last_error) = res.as_ref().err().map(|e| errors::ErrorRecord::new(e, phase));
+    }
+
+    async fn run_msg_loop(&mut self) -> Result<(), Error> {
+        self.diag.debug(format_args!("WASMRunner: run_msg_loop()"));
+        self.store.data_mut().profile_phase(Some("run_msg_loop"));
+        let deadline = self.store.data().next_deadline();
+        self.failed = true;
         let res = match &self.env {
-            Some(env) => env.call_run_msg_loop(&mut self.store).await.into(),
+            Some(env) => match within(deadline, env.call_run_msg_loop(&mut self.store)).await {
+                // the guest was dropped mid-call, so its instance cannot be entered again
+                res if res.is_cut_short() => {
+                    self.release();
+                    res.into_result()
+                }
+                res => res.into_result(),
+            },
             None => Err(Error::msg("WASMRunner: not started")),
         };
-        if self.logging {
-            if res.is_err() {
-                eprintln!("WASMRunner: run_msg_loop() returned error");
-            } else {
-                eprintln!("WASMRunner: run_msg_loop() finished normally");
+        self.failed = res.is_err();
+        // a loop is done once the sends it left running are
+        let res = match (res, self.store.data().send_window.clone()) {
+            (Ok(()), Some(window)) => {
+                let data = self.store.data();
+                window.flush(data.closed.clone(), data.deadline).await
             }
+            (res, _) => res,
         };
-        return res;
+        self.store.data_mut().profile_phase(None);
+        if res.is_err() {
+            self.diag.debug(format_args!("WASMRunner: run_msg_loop() returned error"));
+        } else {
+            self.diag.debug(format_args!("WASMRunner: run_msg_loop() finished normally"));
+        }
+        res
     }
 }
 
+/// The result of a guest call bounded by `within`.
+enum Bounded<T> {
+    Finished(Result<T, Error>),
+    /// The deadline passed first and the call was dropped; holds its error.
+    CutShort(Error),
+}
+
+impl<T> Bounded<T> {
+    fn is_cut_short(&self) -> bool {
+        matches!(self, Bounded::CutShort(_))
+    }
+
+    fn into_result(self) -> Result<T, Error> {
+        match self {
+            Bounded::Finished(res) => res,
+            Bounded::CutShort(e) => Err(e),
+        }
+    }
+}
+
+/// Run a guest call, dropping it once `deadline` passes. The epoch check
+/// only fires while guest code runs, so without this a guest parked in an
+/// awaited host import, such as a `recv_bytes` no message comes for,
+/// would outlive its timeout.
+async fn within<T>(
+    deadline: Option<(std::time::Instant, Error)>,
+    call: impl Future<Output = Result<T, Error>>,
+) -> Bounded<T> {
+    match deadline {
+        Some((at, expired)) => match tokio::time::timeout_at(at.into(), call).await {
+            Ok(res) => Bounded::Finished(res),
+            Err(_) => Bounded::CutShort(expired),
+        },
+        None => Bounded::Finished(call.await),
+    }
+}
+
+/// Check that `cb` can be called with `arity` positional arguments, so wiring
+/// mistakes surface here rather than as a trap on first use. Callables whose
+/// signature cannot be introspected (some builtins) are accepted as-is.
+fn check_callback_arity(py: Python<'_>, name: &str, cb: &PyObject, arity: usize) -> PyResult<()> {
+    let cb = cb.bind(py);
+    if !cb.is_callable() {
+        return Err(pyo3::exceptions::PyTypeError::new_err(format!(
+            "WasmRunner: {name} must be callable"
+        )));
+    }
+    let Ok(sig) = py.import("inspect")?.call_method1("signature", (cb,)) else {
+        return Ok(());
+    };
+    let args = (0..arity).map(|_| py.None());
+    if sig.call_method1("bind", pyo3::types::PyTuple::new(py, args)?).is_err() {
+        let actual = sig.getattr("parameters")?.len()?;
+        return Err(pyo3::exceptions::PyTypeError::new_err(format!(
+            "WasmRunner: {name} must accept {arity} positional argument(s), got {actual} parameter(s): {name}{}",
+            sig.str()?
+        )));
+    }
+    Ok(())
+}
+
+const INIT_CONFIG_FIELDS: &[&str] = &[
+    "log_level",
+    "max_message_size",
+    "max_memory_bytes",
+    "features",
+    "extra",
+];
+
+fn log_level_name(level: LogLevel) -> &'static str {
+    match level {
+        LogLevel::Trace => "trace",
+        LogLevel::Debug => "debug",
+        LogLevel::Info => "info",
+        LogLevel::Warn => "warn",
+        LogLevel::Error => "error",
+    }
+}
+
+fn parse_log_level(level: &str) -> PyResult<LogLevel> {
+    match level {
+        "trace" => Ok(LogLevel::Trace),
+        "debug" => Ok(LogLevel::Debug),
+        "info" => Ok(LogLevel::Info),
+        "warn" => Ok(LogLevel::Warn),
+        "error" => Ok(LogLevel::Error),
+        _ => Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "WasmRunner: unknown log level {level:?}; expected trace, debug, info, warn or error"
+        ))),
+    }
+}
+
+/// Build the `init-config` record from the `init_config` dict. Unknown keys
+/// are rejected so typos don't silently fall back to defaults.
+fn init_config_from_dict(d: Option<&Bound<'_, PyDict>>) -> PyResult<InitConfig> {
+    let mut config = InitConfig {
+        log_level: None,
+        max_message_size: None,
+        max_memory_bytes: None,
+        features: Vec::new(),
+        extra: None,
+    };
+    let Some(d) = d else { return Ok(config) };
+    for (key, value) in d.iter() {
+        let key: String = key.extract()?;
+        if value.is_none() {
+            continue;
+        }
+        match key.as_str() {
+            "log_level" => config.log_level = Some(parse_log_level(&value.extract::<String>()?)?),
+            "max_message_size" => config.max_message_size = Some(value.extract()?),
+            "max_memory_bytes" => config.max_memory_bytes = Some(value.extract()?),
+            "features" => config.features = value.extract()?,
+            "extra" => config.extra = Some(value.extract()?),
+            _ => {
+                return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "WasmRunner: unknown init_config field {key:?}; expected one of {}",
+                    INIT_CONFIG_FIELDS.join(", ")
+                )));
+            }
+        }
+    }
+    Ok(config)
+}
+
+/// Validate `wasm_env` and collapse duplicate keys, the last value winning
+/// but the key keeping its first position, so the guest sees one entry per
+/// name in a stable order.
+fn dedup_env(env: Vec<(String, String)>) -> PyResult<Vec<(String, String)>> {
+    let mut out: Vec<(String, String)> = Vec::with_capacity(env.len());
+    for (key, value) in env {
+        if key.is_empty() || key.contains('=') {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "WasmRunner: invalid wasm_env key {key:?}"
+            )));
+        }
+        match out.iter_mut().find(|(k, _)| *k == key) {
+            Some(entry) => entry.1 = value,
+            None => out.push((key, value)),
+        }
+    }
+    Ok(out)
+}
+
+/// Grant `host_path` to the guest as `guest_path`. Read-only grants allow
+/// listing and reading; writes fail inside the guest with a WASI error.
+fn preopen_dir(
+    builder: &mut WasiCtxBuilder,
+    host_path: &str,
+    guest_path: &str,
+    writable: bool,
+) -> PyResult<()> {
+    use wasmtime_wasi::{DirPerms, FilePerms};
+    if !Path::new(host_path).is_dir() {
+        return Err(pyo3::exceptions::PyFileNotFoundError::new_err(format!(
+            "WasmRunner: preopen_dirs host path {host_path:?} is not a directory"
+        )));
+    }
+    let (dir_perms, file_perms) = if writable {
+        (DirPerms::all(), FilePerms::all())
+    } else {
+        (DirPerms::READ, FilePerms::READ)
+    };
+    builder
+        .preopened_dir(host_path, guest_path, dir_perms, file_perms)
+        .map_err(|e| pyerr(format!("WasmRunner: cannot preopen {host_path:?}: {e}")))?;
+    Ok(())
+}
+
+/// What `Drop` does if the runner goes away while `run_msg_loop` is active.
+///
+/// The loop future keeps its own reference to the store, so the guest is
+/// never freed underneath itself; the question is whether it keeps running
+/// unobserved.
+///
+/// - `Detach` (default): log a warning and let the loop run to completion.
+///   No overhead, but the guest keeps running until its next exit.
+/// - `Wait`: ask the loop to finish as `drain()` does, then give it up to
+///   the timeout before closing it as `close()` does. Drop itself returns at
+///   once and the wait runs on the tokio runtime: blocking there would stall
+///   the asyncio thread that services the guest's host callbacks, so the
+///   loop could never finish in time.
+/// - `Interrupt`: trap the guest at its next epoch check. Requires epoch
+///   interruption to be compiled in, which costs a few percent of guest
+///   throughput, so it is only enabled for runners that ask for it (or
+///   that set `timeout_ms`, which needs it as well).
+#[derive(Clone, Copy, PartialEq)]
+enum DropBehavior {
+    Detach,
+    Wait(std::time::Duration),
+    Interrupt,
+}
+
+impl DropBehavior {
+    fn parse(name: &str, timeout_ms: u64) -> PyResult<Self> {
+        match name {
+            "detach" => Ok(DropBehavior::Detach),
+            "wait" => Ok(DropBehavior::Wait(std::time::Duration::from_millis(timeout_ms))),
+            "interrupt" => Ok(DropBehavior::Interrupt),
+            _ => Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "WasmRunner: unknown drop_behavior {name:?}; expected detach, wait or interrupt"
+            ))),
+        }
+    }
+}
+
+/// Fuel units between cooperative yields when `fuel_limit` is set.
+const FUEL_YIELD_INTERVAL: u64 = 10_000;
+
 #[pyclass]
 struct WasmRunner {
     wasm: Arc<Mutex<WasmData>>,
-    logging: bool,
+    diag: diag::Diag,
+    /* the component's import and export names; replaced by reload() */
+    interface: Arc<std::sync::RwLock<worlds::Interface>>,
+    world: worlds::World,
+    metrics: Arc<Metrics>,
+    on_loop_summary: Option<PyObject>,
+    engine: Engine,
+    drop_behavior: DropBehavior,
+    interrupted: Arc<AtomicBool>,
+    /* false when the engine has no epoch interruption to drive */
+    epochs: bool,
+    epoch_budget: Arc<AtomicU64>,
+    instantiated: Arc<AtomicBool>,
+    last_error: Arc<std::sync::Mutex<Option<errors::ErrorRecord>>>,
+    timings: Arc<std::sync::Mutex<timings::StartupTimings>>,
+    /* `exports=`: how call_export() marshals each export */
+    exports: Arc<std::collections::HashMap<String, custom::ExportSig>>,
+    /* the buffer take_stdout() drains, under capture_stdout=True */
+    captured_stdout: Option<stdio::Captured>,
+    call_stats: Option<Arc<std::sync::Mutex<callstats::CallStats>>>,
+    /* true while a run_msg_loop call is executing, for the `running` getter */
+    looping: Arc<AtomicBool>,
+    /* virtual time under deterministic=True, in nanoseconds */
+    clock: Option<Arc<AtomicU64>>,
+    closed: Arc<tokio::sync::watch::Sender<bool>>,
+    heartbeat: Option<heartbeat::Heartbeat>,
+    draining: Arc<tokio::sync::watch::Sender<bool>>,
+    stop_reason: StopReason,
+    /* mode="sync": the async methods block and return their result */
+    sync: bool,
+    /* set when the runtime dropped a call mid-flight; cleared by reset() */
+    torn_down: Arc<AtomicBool>,
+    /* random UUID from construction, for the `instance_id` getter */
+    instance_id: String,
+    created_at: std::time::SystemTime,
+    timeout: Option<std::time::Duration>,
+    /* advances the epoch under timeout_ms, init_timeout_ms or yield_interval_ms; stopped with its last user */
+    _ticker: Option<Arc<epoch::Ticker>>,
 }
 
 impl WasmRunner {
-    fn is_running(&self) -> bool {
-        match self.wasm.try_lock() {
-            Ok(_) => false,
-            Err(_) => true,
+    /// The error for a call on a closed runner, naming the `close()` reason;
+    /// also set as its `reason` attribute.
+    fn closed_error(&self) -> PyErr {
+        let reason = shutdown::lock(&self.stop_reason).clone().unwrap_or_else(|| UNSPECIFIED.to_owned());
+        let err = pyerr(format!("WasmRunner: closed ({reason})"));
+        Python::with_gil(|py| {
+            let _ = err.value(py).setattr("reason", reason);
+        });
+        err
+    }
+
+    /// Whether something holds the `WasmData` lock: a loop, `instantiate()`
+    /// or `reinit()`. Drop and close care about any guest execution, not just
+    /// loops.
+    fn is_busy(&self) -> bool {
+        self.wasm.try_lock().is_err()
+    }
+
+    /// Hand `fut` back to Python as `drive` does, marking this runner if
+    /// the runtime drops it.
+    fn drive<'py, T>(
+        &self,
+        py: Python<'py>,
+        fut: impl Future<Output = PyResult<T>> + Send + 'static,
+    ) -> PyResult<Bound<'py, PyAny>>
+    where
+        T: for<'a> IntoPyObject<'a> + Send + 'static,
+    {
+        drive(py, self.sync, self.torn_down.clone(), fut)
+    }
+
+    /// The call behind `instantiate()`, for callers that drive it
+    /// themselves.
+    fn instantiation(&self) -> PyResult<impl Future<Output = PyResult<()>> + Send + use<>> {
+        if *self.closed.borrow() {
+            return Err(self.closed_error());
+        }
+        self.check_intact()?;
+        let arc = self.wasm.clone();
+        let timeout = self.timeout;
+        Ok(async move {
+            let mut guard = arc
+                .try_lock()
+                .map_err(|_| pyerr("WasmRunner: cannot instantiate while run_msg_loop is running"))?;
+            guard.recover()?;
+            let _mark = PanicMark(guard.panicked.clone());
+            guard.arm(timeout).map_err(pyerr)?;
+            let res = guard.instantiate().await;
+            let res = guard.disarm(res);
+            if let Err(e) = &res {
+                *shutdown::lock(&guard.last_error) = Some(errors::ErrorRecord::new(e, "instantiate"));
+            }
+            res.map_err(|e| errors::to_pyerr(e, &guard.id_name, "instantiate"))
+        })
+    }
+
+    /// Refuse to run guest code on a store the runtime abandoned mid-call.
+    fn check_intact(&self) -> PyResult<()> {
+        if self.torn_down.load(Ordering::Relaxed) {
+            return Err(shutdown::stopped(true));
+        }
+        Ok(())
+    }
+}
+
+/// Close the runner behind `closed`, recording `reason` unless an earlier
+/// `close()` already gave one; a reason left by `drain()` gives way.
+fn stop(closed: &tokio::sync::watch::Sender<bool>, stop_reason: &StopReason, reason: String) {
+    let mut slot = shutdown::lock(stop_reason);
+    if !*closed.borrow() {
+        *slot = Some(reason);
+    }
+    closed.send_replace(true);
+}
+
+/// Hand `fut` back to Python: as an awaitable, or when `sync` run to
+/// completion on a runtime worker (as it would be when awaited) while the
+/// calling thread waits without the GIL. `torn_down` is set if the runtime
+/// drops it mid-flight, see `shutdown::guard`.
+fn drive<'py, T>(
+    py: Python<'py>,
+    sync: bool,
+    torn_down: Arc<AtomicBool>,
+    fut: impl Future<Output = PyResult<T>> + Send + 'static,
+) -> PyResult<Bound<'py, PyAny>>
+where
+    T: for<'a> IntoPyObject<'a> + Send + 'static,
+{
+    let (fut, attach) = shutdown::guard(torn_down, fut);
+    if !sync {
+        let py_fut = pyo3_async_runtimes::tokio::future_into_py(py, fut)?;
+        attach.attach(&py_fut)?;
+        return Ok(py_fut);
+    }
+    let rt = pyo3_async_runtimes::tokio::get_runtime();
+    let value = match py.allow_threads(|| rt.block_on(rt.spawn(fut))) {
+        Ok(res) => res?,
+        Err(e) if e.is_cancelled() => return Err(shutdown::stopped(false)),
+        Err(e) => return Err(pyerr(e)),
+    };
+    pyo3::IntoPyObjectExt::into_bound_py_any(value, py)
+}
+
+/// Held with the `WasmData` lock by calls that run guest code; marks the
+/// runner for `WasmData::recover` if dropped by a panic.
+struct PanicMark(Arc<AtomicBool>);
+
+impl Drop for PanicMark {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            self.0.store(true, Ordering::Relaxed);
         }
     }
 }
 
+/// Sets the `looping` flag for the life of a `run_msg_loop` call, clearing
+/// it even if the awaiting task is cancelled.
+struct Looping(Arc<AtomicBool>);
+
+impl Looping {
+    fn start(flag: &Arc<AtomicBool>) -> Self {
+        flag.store(true, Ordering::Relaxed);
+        Looping(flag.clone())
+    }
+}
+
+impl Drop for Looping {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
 #[pymethods]
 impl WasmRunner {
     #[new]
@@ -163,9 +1359,52 @@ impl WasmRunner {
         wasm_path=None,
         wasm_compiled_cache=None,
         runner_logging=false,
+        blocking_callbacks=false,
+        send_frame=None,
+        recv_frame=None,
+        max_table_elements=None,
+        id_base=0,
+        on_loop_summary=None,
+        spawn_task=None,
+        max_concurrent_tasks=8,
+        init_config=None,
+        drop_behavior="detach",
+        drop_timeout_ms=1000,
+        profile=false,
+        huge_pages=false,
+        on_send_transform=None,
+        on_recv_transform=None,
+        limits=None,
+        wasi=None,
+        wasm_bytes=None,
+        engine=None,
+        async_recv_ready=false,
+        transport=None,
+        write_log_record=None,
+        logger_name=None,
+        custom_imports=None,
+        deterministic=None,
+        precompiled_path=None,
+        compile=None,
+        world="env".to_string(),
+        mode="async",
+        init_max_retries=0,
+        init_retry_backoff_ms=100,
+        auto_reload=false,
+        host_call_stats=false,
+        signing=None,
+        manual_epochs=false,
+        heartbeat=None,
+        heartbeat_interval_ms=1000,
+        send_bytes_batch=None,
+        recv_bytes_batch=None,
+        base_dir=None,
+        exports=None,
+        on_source_change=None,
     ))]
+    #[allow(clippy::too_many_arguments)]
     fn new(
-        _py: Python<'_>,
+        py: Python<'_>,
         id_name: String,
         send_bytes: PyObject,
         recv_bytes: PyObject,
@@ -176,263 +1415,1249 @@ impl WasmRunner {
         wasm_path: Option<String>,
         wasm_compiled_cache: Option<String>,
         runner_logging: bool,
+        blocking_callbacks: bool,
+        send_frame: Option<PyObject>,
+        recv_frame: Option<PyObject>,
+        max_table_elements: Option<usize>,
+        id_base: u64,
+        on_loop_summary: Option<PyObject>,
+        spawn_task: Option<PyObject>,
+        max_concurrent_tasks: usize,
+        init_config: Option<Bound<'_, PyDict>>,
+        drop_behavior: &str,
+        drop_timeout_ms: u64,
+        profile: bool,
+        huge_pages: bool,
+        on_send_transform: Option<PyObject>,
+        on_recv_transform: Option<PyObject>,
+        limits: Option<Bound<'_, PyDict>>,
+        wasi: Option<Bound<'_, PyDict>>,
+        wasm_bytes: Option<Vec<u8>>,
+        engine: Option<Py<EngineHandle>>,
+        async_recv_ready: bool,
+        transport: Option<Bound<'_, PyDict>>,
+        write_log_record: Option<PyObject>,
+        logger_name: Option<String>,
+        custom_imports: Option<Bound<'_, PyDict>>,
+        deterministic: Option<Bound<'_, PyAny>>,
+        precompiled_path: Option<String>,
+        compile: Option<Bound<'_, PyDict>>,
+        world: String,
+        mode: &str,
+        init_max_retries: u32,
+        init_retry_backoff_ms: u64,
+        auto_reload: bool,
+        host_call_stats: bool,
+        signing: Option<Bound<'_, PyDict>>,
+        manual_epochs: bool,
+        heartbeat: Option<PyObject>,
+        heartbeat_interval_ms: u64,
+        send_bytes_batch: Option<PyObject>,
+        recv_bytes_batch: Option<PyObject>,
+        base_dir: Option<String>,
+        exports: Option<Bound<'_, PyDict>>,
+        on_source_change: Option<String>,
     ) -> PyResult<Self> {
-        if runner_logging {
-            eprintln!("WASMRunner: new()");
+        let (diag_logger, guest_logger) = match &logger_name {
+            Some(name) => (
+                Some(diag::get_logger(py, &format!("{name}.{id_name}"))?),
+                Some(diag::get_logger(py, &format!("{name}.{id_name}.guest"))?),
+            ),
+            None => (None, None),
+        };
+        let instance_id = uuid::Uuid::new_v4().to_string();
+        let created_at = std::time::SystemTime::now();
+        let diag = diag::Diag::new(runner_logging, diag_logger, &id_name, &instance_id);
+        let limits = options::LimitOptions::from_dict(limits.as_ref())?;
+        let compile = options::CompileOptions::from_dict(compile.as_ref())?;
+        let wasi = options::WasiOptions::from_dict(wasi.as_ref())?;
+        let transport = options::TransportOptions::from_dict(transport.as_ref())?;
+        let signing = options::SigningOptions::from_dict(signing.as_ref())?;
+        let deterministic = options::deterministic(deterministic.as_ref())?;
+        // both are required, so neither a stray list nor a bare flag opens the network
+        let network = match (wasi.allow_network, wasi.network_allow_list) {
+            (true, Some(list)) if !list.is_empty() => Some(network::NetworkPolicy::parse(&list, diag.clone())?),
+            (true, _) => {
+                return Err(pyo3::exceptions::PyValueError::new_err(
+                    "WasmRunner: wasi allow_network=True needs a non-empty network_allow_list",
+                ));
+            }
+            (false, Some(_)) => {
+                return Err(pyo3::exceptions::PyValueError::new_err(
+                    "WasmRunner: wasi network_allow_list needs allow_network=True",
+                ));
+            }
+            (false, None) => None,
+        };
+        diag.debug(format_args!("WASMRunner: new()"));
+        check_callback_arity(py, "send_bytes", &send_bytes, 1)?;
+        check_callback_arity(py, "recv_bytes", &recv_bytes, 0)?;
+        check_callback_arity(py, "recv_ready", &recv_ready, 0)?;
+        check_callback_arity(py, "write_log", &write_log, 1)?;
+        let optional_callbacks = [
+            ("send_bytes_batch", &send_bytes_batch, 1),
+            ("recv_bytes_batch", &recv_bytes_batch, 0),
+            ("send_frame", &send_frame, 3),
+            ("recv_frame", &recv_frame, 0),
+            ("spawn_task", &spawn_task, 1),
+            ("on_loop_summary", &on_loop_summary, 1),
+            ("on_send_transform", &on_send_transform, 1),
+            ("on_recv_transform", &on_recv_transform, 1),
+            ("on_stdout", &wasi.on_stdout, 1),
+            ("on_stderr", &wasi.on_stderr, 1),
+            ("write_log_record", &write_log_record, 3),
+            ("heartbeat", &heartbeat, 2),
+        ];
+        for (name, cb, arity) in optional_callbacks {
+            if let Some(cb) = cb {
+                check_callback_arity(py, name, cb, arity)?;
+            }
+        }
+        let init_config_given = init_config.is_some();
+        let init_config = init_config_from_dict(init_config.as_ref())?;
+        let max_message_size = transport.max_message_size;
+        let framing = match (transport.length_prefixed, max_message_size) {
+            (false, _) => None,
+            (true, Some(max)) if max > u32::MAX as usize => {
+                return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "max_message_size must fit the 4-byte length prefix, got {max}"
+                )));
+            }
+            (true, max) => Some(max.unwrap_or(framing::DEFAULT_MAX_MESSAGE_SIZE)),
+        };
+        let codec = transport.codec.as_deref().map(codec::Codec::parse).transpose()?;
+        if codec.is_some() && framing.is_some() {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "WasmRunner: codec exchanges whole messages; it cannot be combined with transport length_prefixed=True",
+            ));
+        }
+        if heartbeat.is_some() && heartbeat_interval_ms == 0 {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "WasmRunner: heartbeat_interval_ms must be at least 1",
+            ));
+        }
+        let drop_behavior = DropBehavior::parse(drop_behavior, drop_timeout_ms)?;
+        let sync = match mode {
+            "async" => false,
+            "sync" => true,
+            _ => {
+                return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "WasmRunner: unknown mode {mode:?}; expected async or sync"
+                )));
+            }
+        };
+        let (max_inflight_messages, max_inflight_bytes) = (transport.max_inflight_messages, transport.max_inflight_bytes);
+        if max_inflight_messages == Some(0) || max_inflight_bytes == Some(0) {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "WasmRunner: max_inflight_messages and max_inflight_bytes must be at least 1",
+            ));
+        }
+        let send_window = (max_inflight_messages.is_some() || max_inflight_bytes.is_some()).then_some(
+            backpressure::WindowLimits {
+                messages: max_inflight_messages,
+                bytes: max_inflight_bytes,
+            },
+        );
+        if sync && send_window.is_some() {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "WasmRunner: max_inflight_messages and max_inflight_bytes bound async send_bytes \
+                 callbacks; they cannot be used with mode=\"sync\"",
+            ));
+        }
+        if sync && (async_recv_ready || spawn_task.is_some()) {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "WasmRunner: async_recv_ready and spawn_task need an event loop; they cannot be used \
+                 with mode=\"sync\"",
+            ));
         }
         let imports = Imports {
             send_bytes,
             recv_bytes,
             recv_ready,
-            write_log
-        };
-        let mut cfg = Config::new();
-        cfg.async_support(true);
-
-        let engine = Engine::new(&cfg).map_err(pyerr)?;
-        let mut linker = Linker::<Ctx>::new(&engine);
-        add_to_linker_async(&mut linker).map_err(pyerr)?;
-        let mut root = linker.root();
-        root.func_wrap_async("send-bytes", host_imports::send_bytes)
-            .map_err(pyerr)?;
-        root.func_wrap_async("recv-bytes", host_imports::recv_bytes)
-            .map_err(pyerr)?;
-        root.func_wrap("recv-ready", host_imports::recv_ready)
-            .map_err(pyerr)?;
-        root.func_wrap("write-log", host_imports::write_log)
-            .map_err(pyerr)?;
-        let wasm_path = wasm_path.unwrap_or("../env.wasm".to_string());
-        let compiled_cache = wasm_compiled_cache.unwrap_or("env.wasm.compiled".to_string());
-
-        let component =
-            load_or_precompile_component(&engine, &wasm_path, &compiled_cache).map_err(pyerr)?;
+            write_log,
+            write_log_record,
+            guest_logger,
+            send_bytes_batch,
+            recv_bytes_batch,
+            send_frame,
+            recv_frame,
+            spawn_task,
+            on_send_transform,
+            on_recv_transform,
+            sync,
+            codec,
+        };
+        let options::LimitOptions {
+            timeout,
+            init_timeout,
+            cpu_timeout,
+            yield_interval,
+            recv_timeout,
+            fuel_limit,
+            max_memory_bytes,
+        } = limits;
+        // callers' own increments would count as guest compute
+        if cpu_timeout.is_some() && manual_epochs {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "WasmRunner: cpu_timeout_ms counts the epoch ticker's ticks; it cannot be combined with manual_epochs",
+            ));
+        }
+        if wasi.capture_stdout && wasi.on_stdout.is_some() {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "WasmRunner: capture_stdout and on_stdout both take the guest's stdout; pass one of them",
+            ));
+        }
+        let captured_stdout = wasi.capture_stdout.then(|| stdio::Captured::new(wasi.max_captured_stdout));
+        let mut wanted = EngineOptions {
+            epochs: manual_epochs || drop_behavior == DropBehavior::Interrupt || limits.ticks(),
+            manual_epochs,
+            fuel: fuel_limit.is_some(),
+            huge_pages,
+            ..EngineOptions::default()
+        };
+        compile.apply(&mut wanted);
+        let shared = engine.as_ref().map(|handle| handle.get());
+        let (engine, engine_options) = match shared {
+            Some(handle) => {
+                handle.options.check_supports(wanted, &compile.given())?;
+                (handle.engine.clone(), handle.options)
+            }
+            None => (wanted.build()?, wanted),
+        };
+        let linker = host_linker(&engine, async_recv_ready, wasi.virtual_files.is_some(), custom_imports.as_ref())?;
+        let exports = exports.as_ref().map(custom::exports_from_dict).transpose()?.unwrap_or_default();
+        let base_dir = paths::BaseDir::new(base_dir)?;
+        let wasm_path = base_dir.resolve(wasm_path.as_deref().unwrap_or(paths::DEFAULT_WASM));
+        let cache_required = wasm_compiled_cache.is_some();
+        let wasm_compiled_cache = base_dir.resolve(wasm_compiled_cache.as_deref().unwrap_or(paths::DEFAULT_COMPILED));
+        let precompiled_path = precompiled_path.map(|path| base_dir.resolve(&path));
+        let options::SigningOptions {
+            public_key,
+            signature,
+            signature_path,
+        } = signing;
+        let signature_path = signature_path.map(|path| base_dir.resolve(&path));
+        let verifier = match public_key {
+            Some(_) if precompiled_path.is_some() => {
+                return Err(pyo3::exceptions::PyValueError::new_err(
+                    "WasmRunner: public_key cannot verify a precompiled_path artifact; sign and load the wasm",
+                ));
+            }
+            Some(key) => {
+                let wasm_path = match wasm_bytes {
+                    Some(_) => None,
+                    None => Some(wasm_path.as_str()),
+                };
+                Some(signing::Verifier::new(&key, signature, signature_path, wasm_path)?)
+            }
+            None if signature.is_some() || signature_path.is_some() => {
+                return Err(pyo3::exceptions::PyValueError::new_err(
+                    "WasmRunner: signature and signature_path need a public_key",
+                ));
+            }
+            None => None,
+        };
+        let mut load_timings = timings::StartupTimings::default();
+        let (component, source) = match precompiled_path {
+            Some(path) => (
+                load_timings.time("deserialize", || load_precompiled(&engine, &path))?,
+                ComponentSource::Precompiled { path },
+            ),
+            None => {
+                // in-memory components skip the compiled cache; wasm_path and
+                // wasm_compiled_cache are ignored
+                let (bytes, compiled_cache, wasm_path) = match wasm_bytes {
+                    Some(bytes) => (bytes, None, None),
+                    None => {
+                        let bytes = load_timings.time("read_bytes", || compression::read_wasm(Path::new(&wasm_path)))?;
+                        (bytes, Some(wasm_compiled_cache), Some(wasm_path))
+                    }
+                };
+                if let Some(verifier) = &verifier {
+                    verifier.verify(&bytes)?;
+                }
+                let meta = cache::ArtifactMeta::new(&engine, &bytes);
+                let load = &mut load_timings;
+                let write = CacheWrite::new(cache_required, &diag);
+                let options::CompileOptions {
+                    compile_threads,
+                    force_recompile,
+                    ..
+                } = compile;
+                let mut compile = || {
+                    with_compile_threads(compile_threads, || match &compiled_cache {
+                        Some(path) => {
+                            load_or_precompile_component(&engine, &bytes, &meta, path, force_recompile, write, load)
+                        }
+                        None => load.time("compile", || Component::from_binary(&engine, &bytes)).map_err(|e| format!("{e:#}")),
+                    })
+                };
+                let component = match shared {
+                    // a forced rebuild also skips the handle's compiled copy
+                    Some(handle) if !force_recompile => handle.component(meta.clone(), compile),
+                    _ => compile(),
+                }
+                .map_err(|e| pyerr(features::explain(e)))?;
+                let source = match (wasm_path, compiled_cache) {
+                    (Some(path), Some(compiled_cache)) => ComponentSource::Wasm {
+                        path,
+                        compiled_cache,
+                        cache_required,
+                        force_recompile,
+                        compile_threads,
+                        meta,
+                    },
+                    _ => ComponentSource::Bytes,
+                };
+                (component, source)
+            }
+        };
+        // auto_reload=True is shorthand for on_source_change="reload"
+        let on_source_change = match (on_source_change.as_deref().map(SourceChange::parse).transpose()?, auto_reload) {
+            (None, false) => SourceChange::Ignore,
+            (None, true) | (Some(SourceChange::Reload), _) => SourceChange::Reload,
+            (Some(policy), false) => policy,
+            (Some(_), true) => {
+                return Err(pyo3::exceptions::PyValueError::new_err(
+                    "WasmRunner: auto_reload=True means on_source_change=\"reload\"; pass one or the other",
+                ));
+            }
+        };
+        if on_source_change != SourceChange::Ignore && !matches!(source, ComponentSource::Wasm { .. }) {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "WasmRunner: auto_reload and on_source_change need a component loaded from wasm_path",
+            ));
+        }
+        let world = worlds::World::parse(&world)?;
+        if init_config_given {
+            world.check_takes_config("init_config")?;
+        }
+        world.check(&engine, &component)?;
+        worlds::check_imports(&linker, &component)?;
+        let interface = Arc::new(std::sync::RwLock::new(worlds::Interface::of(&engine, &component)));
 
-        let mut wasi_builder = WasiCtxBuilder::new();
         if wasm_inherit_io {
-            eprintln!("WasmRunner: Debug enabled; inheriting WASM stdio to host");
-            wasi_builder.inherit_stdin();
-            wasi_builder.inherit_stdout();
-            wasi_builder.inherit_stderr();
+            diag.warn(format_args!("WasmRunner: Debug enabled; inheriting WASM stdio to host"));
         }
-
-        let wasi = wasi_builder.build();
-
-        let store = Store::new(
-            &engine,
-            Ctx {
-                table: ResourceTable::new(),
-                wasi,
-                imports,
-            },
-        );
+        let preopen_dirs = wasi.preopen_dirs;
+        // check the directories now rather than at the first reset()
+        for (host_path, guest_path, writable) in &preopen_dirs {
+            preopen_dir(&mut WasiCtxBuilder::new(), host_path, guest_path, *writable)?;
+        }
+        // the virtual tree replaces wasi:filesystem, so host directories could not be seen
+        if wasi.virtual_files.is_some() && !preopen_dirs.is_empty() {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "WasmRunner: give virtual_files or preopen_dirs, not both",
+            ));
+        }
+        let vfs = wasi
+            .virtual_files
+            .map(|files| vfs::VirtualFs::new(files.into_iter().collect()).map(Arc::new))
+            .transpose()?;
+        let metrics = Arc::new(Metrics::default());
+        let interrupted = Arc::new(AtomicBool::new(false));
+        let epoch_budget = Arc::new(AtomicU64::new(epoch::NO_DEADLINE));
+        let instantiated = Arc::new(AtomicBool::new(false));
+        if let Some(handle) = shared {
+            handle.register(&metrics, &instantiated);
+        }
+        let last_error = Arc::new(std::sync::Mutex::new(None));
+        let timings = Arc::new(std::sync::Mutex::new(load_timings));
+        let call_stats = host_call_stats.then(Arc::default);
+        let (closed, closed_rx) = tokio::sync::watch::channel(false);
+        let (draining, draining_rx) = tokio::sync::watch::channel(false);
+        let stop_reason = StopReason::default();
+        let spec = StoreSpec {
+            engine: engine.clone(),
+            wasm_inherit_io,
+            stdin: wasi.stdin_source.map(|src| stdio::PyInput::new(src.bind(py), blocking_callbacks)).transpose()?,
+            stdout: wasi.on_stdout.map(|cb| stdio::PyOutput::new(cb, blocking_callbacks)),
+            captured_stdout: captured_stdout.clone(),
+            stderr: wasi.on_stderr.map(|cb| stdio::PyOutput::new(cb, blocking_callbacks)),
+            env: dedup_env(wasi.env)?,
+            args: wasi.args,
+            preopen_dirs,
+            imports,
+            blocking_callbacks,
+            max_table_elements,
+            max_memory_bytes,
+            max_concurrent_tasks,
+            metrics: metrics.clone(),
+            interrupted: interrupted.clone(),
+            epoch_budget: epoch_budget.clone(),
+            closed: closed_rx,
+            draining: draining_rx,
+            stop_reason: stop_reason.clone(),
+            framing,
+            max_message_size,
+            epochs: engine_options.epochs,
+            yield_ticks: yield_interval.map(epoch::ticks),
+            fuel_limit,
+            engine_fuel: engine_options.fuel,
+            deterministic: deterministic.clone(),
+            network,
+            vfs,
+            send_window,
+            recv_timeout,
+            call_stats: call_stats.clone(),
+        };
+        let store = spec.build(id_base, profile.then(Profile::default))?;
+        let needs_ticker = !manual_epochs
+            && (timeout.is_some() || init_timeout.is_some() || yield_interval.is_some() || cpu_timeout.is_some());
+        let ticker = match (needs_ticker, shared) {
+            (false, _) => None,
+            (true, Some(handle)) => handle.ticker.clone(),
+            (true, None) => Some(Arc::new(epoch::Ticker::start(engine.clone()).map_err(pyerr)?)),
+        };
 
         let wasm = WasmData {
-            linker: linker,
+            linker,
             comp: component,
-            store: store,
+            store,
             env: None,
-            logging: runner_logging,
-            id_name: id_name,
-            log_tags: log_tags,
+            world,
+            instance: None,
+            diag: diag.clone(),
+            id_name,
+            log_tags,
+            init_config,
+            source,
+            verifier,
+            on_source_change,
+            interface: interface.clone(),
+            init_timeout,
+            init_max_retries,
+            init_retry_backoff: std::time::Duration::from_millis(init_retry_backoff_ms),
+            fuel_limit,
+            cpu_timeout,
+            max_memory_bytes,
+            pool: engine_options.pool,
+            instantiated: instantiated.clone(),
+            last_error: last_error.clone(),
+            panicked: Arc::default(),
+            failed: false,
+            timings: timings.clone(),
+            spec,
         };
 
-        if runner_logging {
-            eprintln!("WASMRunner: WasmData created");
-        }
+        diag.debug(format_args!("WASMRunner: WasmData created"));
 
         let s = Self {
             wasm: Arc::new(Mutex::new(wasm)),
-            logging: runner_logging,
+            diag,
+            interface,
+            world,
+            metrics,
+            on_loop_summary,
+            engine,
+            drop_behavior,
+            interrupted,
+            epochs: engine_options.epochs,
+            epoch_budget,
+            instantiated,
+            last_error,
+            timings,
+            exports: Arc::new(exports),
+            captured_stdout,
+            call_stats,
+            looping: Arc::new(AtomicBool::new(false)),
+            clock: deterministic.map(|d| d.nanos()),
+            closed: Arc::new(closed),
+            heartbeat: heartbeat
+                .map(|cb| heartbeat::Heartbeat::new(cb, std::time::Duration::from_millis(heartbeat_interval_ms))),
+            draining: Arc::new(draining),
+            stop_reason,
+            sync,
+            torn_down: Arc::default(),
+            instance_id,
+            created_at,
+            timeout,
+            _ticker: ticker,
         };
         Ok(s)
     }
 
+    /// True from the start of a `run_msg_loop` call until it returns, from
+    /// an explicit flag rather than lock contention. False once `close()`
+    /// has been called, even while the loop is still unwinding.
     #[getter]
     fn running(&self) -> bool {
-        return self.is_running();
+        self.looping.load(Ordering::Relaxed) && !*self.closed.borrow()
     }
 
+    /// Run the guest's message loop, instantiating it first if needed, and
+    /// resolve to a `NormalExit` outcome (`ExitStatus` if the guest called
+    /// `proc_exit` with a nonzero status, see `LoopOutcome.exit_code`;
+    /// other traps still raise). A `WasmTimeoutError` carries
+    /// `partial_output`, the messages and bytes this call delivered before
+    /// it timed out. Under `mode="sync"` this (like
+    /// `instantiate`, `reinit` and `aclose`) blocks the calling thread and
+    /// returns the result, no event loop needed; `send_bytes`, `recv_bytes`,
+    /// `send_frame` and `recv_frame` are then plain functions. `close()`
+    /// from another thread does not interrupt a sync callback that blocks.
     fn run_msg_loop<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
-        if self.logging {
-            eprintln!("WasmRunner: run_msg_loop()");
+        self.diag.debug(format_args!("WasmRunner: run_msg_loop()"));
+        if *self.closed.borrow() {
+            return Err(self.closed_error());
         }
+        self.check_intact()?;
         match self.wasm.try_lock() {
             Ok(_) => {}
             Err(_) => {
-                if self.logging {
-                    eprintln!("WasmRunner: run_msg_loop already running");
-                }
+                self.diag.debug(format_args!("WasmRunner: run_msg_loop already running"));
                 return Err(pyerr("WasmRunner: run_msg_loop already running"));
             }
         };
         let arc = self.wasm.clone();
-        let logging = self.logging;
-        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+        let diag = self.diag.clone();
+        let metrics = self.metrics.clone();
+        let on_loop_summary = self.on_loop_summary.as_ref().map(|cb| cb.clone_ref(py));
+        let timeout = self.timeout;
+        let looping = self.looping.clone();
+        let draining = self.draining.clone();
+        let heartbeat = self.heartbeat.clone();
+        let closed = self.closed.clone();
+        let stop_reason = self.stop_reason.clone();
+        let engine = self.engine.clone();
+        self.drive(py, async move {
             match arc.try_lock() {
                 Ok(mut guard) => {
-                    guard.instantiate().await;
-                    guard.run_msg_loop().await.map_err(pyerr)
+                    guard.recover()?;
+                    let _mark = PanicMark(guard.panicked.clone());
+                    let _looping = Looping::start(&looping);
+                    let _beating = heartbeat
+                        .map(|heartbeat| {
+                            let (closed, stop_reason) = (closed.clone(), stop_reason.clone());
+                            heartbeat.start(move || {
+                                stop(&closed, &stop_reason, "heartbeat".to_owned());
+                                engine.increment_epoch();
+                            })
+                        })
+                        .transpose()
+                        .map_err(pyerr)?;
+                    let started = std::time::Instant::now();
+                    let counts = metrics.message_counts();
+                    guard.check_source()?;
+                    guard.arm(timeout).map_err(pyerr)?;
+                    let (res, phase) = match guard.instantiate().await {
+                        Ok(()) => (guard.run_msg_loop().await, "run_msg_loop"),
+                        Err(e) => (Err(e), "instantiate"),
+                    };
+                    let res = guard.disarm(res);
+                    let res = guard.exit_status(res);
+                    let stopped_by = {
+                        let mut slot = shutdown::lock(&stop_reason);
+                        if *closed.borrow() { slot.clone() } else { slot.take() }
+                    };
+                    draining.send_replace(false);
+                    if let Some(cb) = on_loop_summary {
+                        let delta = metrics.message_counts().since(counts);
+                        report_loop_summary(&cb, res.is_ok(), started.elapsed(), delta);
+                    }
+                    guard.record_result(&res, phase);
+                    res.map(|exit| match exit {
+                        Some(code) => errors::LoopOutcome::exited(code),
+                        None => errors::LoopOutcome::normal_exit(),
+                    })
+                    .map(|outcome| outcome.with_stop_reason(stopped_by))
+                    .map_err(|e| {
+                        let totals = metrics.message_counts();
+                        errors::note_partial_output(errors::to_pyerr(e, &guard.id_name, phase), totals.since(counts), totals)
+                    })
                 }
                 Err(_) => {
-                    if logging {
-                        eprintln!("WasmRunner: event_loop already running");
-                    }
+                    diag.debug(format_args!("WasmRunner: event_loop already running"));
                     Err(pyerr("WasmRunner: event_loop already running"))
                 }
             }
         })
     }
 
-    fn close(&self) {
-        if self.logging {
-            eprintln!("WasmRunner: close()");
+    /// Instantiate the guest and run `init-exec-env` without entering the
+    /// message loop, e.g. to warm a pool of ready runners. Raises the
+    /// original failure (with `phase="instantiate"`) so a runner that cannot
+    /// start is told apart from a loop that errored. A no-op when already
+    /// instantiated; `run_msg_loop` still instantiates lazily otherwise.
+    /// `timeout_ms` and `fuel_limit` apply to this call on its own.
+    fn instantiate<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let fut = self.instantiation()?;
+        self.drive(py, fut)
+    }
+
+    /// Call the guest export `name`, typed in the `exports=` constructor
+    /// argument as `(params, result)` with the `custom_imports` type names,
+    /// and resolve to its result (None if it has none). For request/response
+    /// guests exporting functions besides `run-msg-loop`; `name` is the WIT
+    /// name, e.g. `handle-request`. Instantiates the guest first if needed.
+    /// `timeout_ms` and `fuel_limit` apply to each call on its own. Raises
+    /// while a loop is running.
+    #[pyo3(signature = (name, *args))]
+    fn call_export<'py>(
+        &self,
+        py: Python<'py>,
+        name: String,
+        args: Bound<'py, pyo3::types::PyTuple>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let Some(sig) = self.exports.get(&name) else {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "WasmRunner: export {name:?} is not declared in exports="
+            )));
+        };
+        let params = sig.args(&name, &args)?;
+        let instantiation = self.instantiation()?;
+        let exports = self.exports.clone();
+        let arc = self.wasm.clone();
+        let timeout = self.timeout;
+        self.drive(py, async move {
+            instantiation.await?;
+            let mut guard = arc
+                .try_lock()
+                .map_err(|_| pyerr("WasmRunner: cannot call an export while run_msg_loop is running"))?;
+            guard.recover()?;
+            let _mark = PanicMark(guard.panicked.clone());
+            let sig = &exports[&name];
+            guard.arm(timeout).map_err(pyerr)?;
+            let mut ret = None;
+            let res = guard
+                .call_export(&name, params, sig.results())
+                .await
+                .and_then(|results| Python::with_gil(|py| sig.result(py, &results)))
+                .map(|value| ret = Some(value));
+            // None when close() cut the call short
+            guard
+                .disarm(res)
+                .map(|()| ret)
+                .map_err(|e| errors::to_pyerr(e, &guard.id_name, "call_export"))
+        })
+    }
+
+    /// The most recent `instantiate` or `run_msg_loop` failure as an
+    /// `ErrorRecord`, or None. Cleared when a loop exits normally and by
+    /// `reset()`; readable while a loop runs.
+    #[getter]
+    fn last_error(&self) -> Option<errors::ErrorRecord> {
+        shutdown::lock(&self.last_error).clone()
+    }
+
+    /// Milliseconds spent in each phase of starting the guest, keyed by
+    /// phase: loading the component at construction or the latest
+    /// `reload()` (`read_bytes`, `deserialize`, `precompile`, `compile`)
+    /// and the latest instantiation (`instantiate`, `init_exec_env`). Only
+    /// phases that ran are present; see the `timings` module. Readable
+    /// while a loop runs.
+    fn startup_timings<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        shutdown::lock(&self.timings).to_dict(py)
+    }
+
+    /// The guest's stdout captured since the last call, as text (invalid
+    /// UTF-8 replaced), emptying the buffer. Requires `capture_stdout=True`.
+    /// Only the first `max_captured_stdout` bytes after each take are kept;
+    /// the rest is discarded and a warning names how much. Readable while a
+    /// loop runs, when a character still being written is left for the
+    /// next call.
+    fn take_stdout(&self) -> PyResult<String> {
+        let Some(captured) = &self.captured_stdout else {
+            return Err(pyerr("WasmRunner: stdout is not captured; pass wasi={\"capture_stdout\": True}"));
+        };
+        let (text, dropped) = captured.take();
+        if dropped > 0 {
+            self.diag.warn(format_args!(
+                "WasmRunner: {dropped} bytes of guest stdout were discarded past max_captured_stdout"
+            ));
         }
+        Ok(text)
     }
-}
 
-// end pymethods
+    /// Per-callback call counts and latency (`calls`, `total_ms`, `mean_ms`,
+    /// `max_ms` and approximate `p50_ms`/`p90_ms`/`p99_ms`) for the
+    /// `send_bytes`, `recv_bytes`, `recv_ready` and `write_log` callbacks,
+    /// keyed by callback name. Requires `host_call_stats=True`, which costs
+    /// a clock read and an uncontended lock per call; without it the host
+    /// imports skip timing altogether. Readable while a loop runs.
+    fn host_call_stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let Some(stats) = &self.call_stats else {
+            return Err(pyerr("WasmRunner: host call stats are not enabled; pass host_call_stats=True"));
+        };
+        shutdown::lock(stats).to_dict(py)
+    }
 
-#[pymodule]
-fn host(m: &Bound<'_, PyModule>) -> PyResult<()> {
-    m.add_class::<WasmRunner>()?;
-    Ok(())
-}
+    /// A random UUID generated when the runner was constructed, unlike
+    /// `id_name` unique to this runner and unchanged by `reset()` and
+    /// `reload()`. The runner's own log lines carry it; pass it to the guest
+    /// through `set_init_config()` if its logs should too.
+    #[getter]
+    fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
 
-impl Drop for WasmRunner {
-    fn drop(&mut self) {
-        if self.logging {
-            eprintln!("WasmRunner: drop()");
+    /// When the runner was constructed, in seconds since the epoch as
+    /// `time.time()` reports it.
+    #[getter]
+    fn created_at(&self) -> f64 {
+        self.created_at
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0.0, |d| d.as_secs_f64())
+    }
+
+    /// Whether a live guest instance exists, readable while a loop runs.
+    #[getter]
+    fn instantiated(&self) -> bool {
+        self.instantiated.load(Ordering::Relaxed)
+    }
+
+    /// Queue messages for the guest to receive before the `recv_bytes`
+    /// callback is consulted, e.g. to replay a recorded session. Appends to
+    /// anything already queued; `recv_ready` reports true while it is non-empty.
+    /// Under `codec` the messages are objects, encoded here.
+    fn preload_messages(&self, messages: Vec<Bound<'_, PyAny>>) -> PyResult<()> {
+        match self.wasm.try_lock() {
+            Ok(mut guard) => {
+                let codec = guard.store.data().imports.codec;
+                let messages = messages
+                    .iter()
+                    .map(|message| match codec {
+                        Some(codec) => codec
+                            .encode("preload_messages", message)
+                            .map_err(|e| errors::to_pyerr(e, &guard.id_name, "preload_messages")),
+                        None => extract_payload(message),
+                    })
+                    .collect::<PyResult<Vec<_>>>()?;
+                guard.store.data_mut().preloaded.extend(messages);
+                Ok(())
+            }
+            Err(_) => Err(pyerr("WasmRunner: cannot preload messages while run_msg_loop is running")),
         }
     }
-}
 
-macro_rules! host_fn_sync_ret {
-    ($fn_name:ident, $py_field:ident, ($($argn:ident : $argt:ty),*), $ret:ty) => {
-        pub fn $fn_name(
-            store: wasmtime::StoreContextMut<Ctx>,
-            ($($argn,)*): ($($argt,)*),
-        ) -> wasmtime::Result<($ret,)> {
-            pyo3::Python::with_gil(|py| {
-                use pyo3::types::PyAnyMethods;
-                let obj = store.data().imports.$py_field.bind(py).call1(($($argn,)*))?;
-                obj.extract::<$ret>()
-            }).map(|v| (v,)).map_err(pyerr_to_wasmtime_err)
+    /// Write the accumulated profile to `path` in folded-stack format (one
+    /// `phase;frame microseconds` line each), for flamegraph tooling.
+    /// Requires `profile=True`; not available while a loop is running.
+    fn dump_profile(&self, path: String) -> PyResult<()> {
+        let guard = self
+            .wasm
+            .try_lock()
+            .map_err(|_| pyerr("WasmRunner: cannot dump profile while run_msg_loop is running"))?;
+        let Some(profile) = &guard.store.data().profile else {
+            return Err(pyerr("WasmRunner: profiling is not enabled; pass profile=True"));
+        };
+        std::fs::write(path, profile.folded()).map_err(pyerr)
+    }
+
+    /// Fuel the guest has used since the last `run_msg_loop` call began (each
+    /// call refills the budget to `fuel_limit`), or None without fuel
+    /// metering. Not available while a loop is running.
+    fn fuel_consumed(&self) -> PyResult<Option<u64>> {
+        let guard = self
+            .wasm
+            .try_lock()
+            .map_err(|_| pyerr("WasmRunner: cannot read fuel while run_msg_loop is running"))?;
+        let Some(limit) = guard.fuel_limit else { return Ok(None) };
+        let remaining = guard.store.get_fuel().map_err(pyerr)?;
+        Ok(Some(limit.saturating_sub(remaining)))
+    }
+
+    /// Host import names the component requires, e.g. `send-bytes` or
+    /// `wasi:io/streams@0.2.0`. Read from the component type, so this does
+    /// not instantiate anything and is safe to call while a loop is running.
+    fn required_capabilities(&self) -> Vec<String> {
+        shutdown::read(&self.interface).imports.clone()
+    }
+
+    /// The component's interface for tooling: a dict with its `imports` and
+    /// `exports` names and the `world` the runner hosts it as. Like
+    /// `required_capabilities`, safe to call while a loop is running.
+    fn inspect<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let interface = shutdown::read(&self.interface);
+        let d = PyDict::new(py);
+        d.set_item("imports", &interface.imports)?;
+        d.set_item("exports", &interface.exports)?;
+        d.set_item("world", self.world.name())?;
+        Ok(d)
+    }
+
+    /// Snapshot of the runner's resource counters. Readable while a loop is
+    /// running. `table_elements` sums the current size of every table in the
+    /// store; `max_table_elements` applies to each table individually.
+    fn metrics<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        use std::sync::atomic::Ordering::Relaxed;
+        let m = &self.metrics;
+        let d = PyDict::new(py);
+        d.set_item("table_elements", m.table_elements.load(Relaxed))?;
+        d.set_item("peak_table_elements", m.peak_table_elements.load(Relaxed))?;
+        d.set_item("live_tasks", m.live_tasks.load(Relaxed))?;
+        d.set_item("slept_ms", m.slept_ns.load(Relaxed) as f64 / 1e6)?;
+        Ok(d)
+    }
+
+    /// Messages the guest passed to `send_bytes`/`send_frame` over the
+    /// runner's lifetime. Like the other counters below, counted as the guest
+    /// sees them (before `on_send_transform` and framing) and readable while
+    /// a loop is running.
+    #[getter]
+    fn messages_sent(&self) -> u64 {
+        self.metrics.message_counts().messages_sent
+    }
+
+    /// Messages handed to the guest by `recv_bytes`/`recv_frame`, preloaded
+    /// ones included.
+    #[getter]
+    fn messages_received(&self) -> u64 {
+        self.metrics.message_counts().messages_received
+    }
+
+    #[getter]
+    fn bytes_sent(&self) -> u64 {
+        self.metrics.message_counts().bytes_sent
+    }
+
+    #[getter]
+    fn bytes_received(&self) -> u64 {
+        self.metrics.message_counts().bytes_received
+    }
+
+    /// Current size in bytes of the guest's linear memories, summed over
+    /// every memory in the store (a componentize-py guest has a few), or
+    /// None when not instantiated. Tracked from growth the limiter approves,
+    /// so it is readable while a loop is running. Memories are only freed
+    /// with the store, so after a failed loop it also counts the previous
+    /// instance until `reset()`.
+    #[getter]
+    fn memory_bytes(&self) -> Option<usize> {
+        self.instantiated
+            .load(Ordering::Relaxed)
+            .then(|| self.metrics.memory_bytes.load(Ordering::Relaxed))
+    }
+
+    /// Move the virtual clock forward by `ns` nanoseconds. Only with
+    /// `deterministic=True`; callable while a loop is running.
+    fn advance_clock(&self, ns: u64) -> PyResult<()> {
+        let Some(clock) = &self.clock else {
+            return Err(pyerr("WasmRunner: advance_clock requires deterministic=True"));
+        };
+        clock.fetch_add(ns, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Advance the engine's epoch by one tick, for a scheduler driving
+    /// epochs itself under `manual_epochs=True` (the built-in ticker is
+    /// then off). Each tick is where a running guest checks `timeout_ms`,
+    /// `init_timeout_ms`, `close()` and `set_epoch_deadline`, and counts
+    /// towards `yield_interval_ms`, whose intervals become tick counts. The
+    /// epoch belongs to the engine: with a shared `EngineHandle` every
+    /// runner on it sees the tick. Needs an engine with epoch interruption.
+    fn increment_epoch(&self) -> PyResult<()> {
+        if !self.epochs {
+            return Err(pyerr(
+                "WasmRunner: increment_epoch needs epoch interruption; pass manual_epochs=True",
+            ));
         }
-    };
-}
+        self.engine.increment_epoch();
+        Ok(())
+    }
 
-macro_rules! host_fn_sync_void {
-    ($fn_name:ident, $py_field:ident, ($($argn:ident : $argt:ty),*)) => {
-        pub fn $fn_name(
-            store: wasmtime::StoreContextMut<Ctx>,
-            ($($argn,)*): ($($argt,)*),
-        ) -> wasmtime::Result<()> {
-            pyo3::Python::with_gil(|py| {
-                use pyo3::types::PyAnyMethods;
-                store.data().imports.$py_field.bind(py).call1(($($argn,)*)).map(|_| ())
-            }).map_err(pyerr_to_wasmtime_err)
+    /// Interrupt the guest once the epoch has advanced `ticks` more times
+    /// while it runs; the loop then fails with a `WasmTrapError` whose
+    /// `trap_code` is `Interrupt`. Advances are counted as the guest sees
+    /// them, at its next epoch check (loop heads and calls), so several
+    /// `increment_epoch()` calls before it checks again count as one. The deadline fires once and is then
+    /// cleared; None clears it beforehand. Kept across `reset()` and safe
+    /// to call while a loop is running. Needs an engine with epoch
+    /// interruption, e.g. `manual_epochs=True`.
+    #[pyo3(signature = (ticks))]
+    fn set_epoch_deadline(&self, ticks: Option<u64>) -> PyResult<()> {
+        if !self.epochs {
+            return Err(pyerr(
+                "WasmRunner: set_epoch_deadline needs epoch interruption; pass manual_epochs=True",
+            ));
         }
-    };
-}
+        let budget = ticks.map_or(epoch::NO_DEADLINE, |ticks| ticks.clamp(1, epoch::NO_DEADLINE - 1));
+        self.epoch_budget.store(budget, Ordering::Relaxed);
+        Ok(())
+    }
 
-macro_rules! host_fn_async_ret {
-    ($fn_name:ident, $py_field:ident, ($($argn:ident : $argt:ty),*), $ret:ty) => {
-        pub fn $fn_name(
-            store: wasmtime::StoreContextMut<Ctx>,
-            ($($argn,)*): ($($argt,)*),
-        ) -> Box<dyn std::future::Future<Output = wasmtime::Result<($ret,)>> + Send + '_> {
-            Box::new(async move {
-                let fut = pyo3::Python::with_gil(|py| {
-                    use pyo3::types::PyAnyMethods;
-                    let coro = store.data().imports.$py_field.bind(py).call1(($($argn,)*))?;
-                    pyo3_async_runtimes::tokio::into_future(coro)
-                }).map_err(pyerr_to_wasmtime_err)?;
-                let obj = fut.await.map_err(pyerr_to_wasmtime_err)?;
-                let r = pyo3::Python::with_gil(|py| obj.extract::<$ret>(py).map_err(pyerr_to_wasmtime_err))?;
-                Ok((r,))
-            })
+    /// Throw away the guest instance and its store, so the next
+    /// `run_msg_loop` or `instantiate()` starts a fresh one from the already
+    /// compiled component; a call that failed in the guest (a trap, a host
+    /// error, a timeout) has the next call do this by itself. All in-guest
+    /// state (memory, globals, tables, host resources, tasks it spawned) is
+    /// lost; counters and queued preloaded messages are kept. Raises while
+    /// a loop is running.
+    fn reset(&self) -> PyResult<()> {
+        let mut guard = self
+            .wasm
+            .try_lock()
+            .map_err(|_| pyerr("WasmRunner: cannot reset while run_msg_loop is running"))?;
+        guard.reset()?;
+        self.torn_down.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Load the component again from `wasm_path` (through the compiled
+    /// cache) or `precompiled_path`, e.g. after rebuilding the guest, and
+    /// drop the instance and store so the next `run_msg_loop` starts the new
+    /// code, as after `reset()`. Not available while a loop is running; on
+    /// failure the old component is kept. With `on_source_change="reload"`
+    /// (or `auto_reload=True`) this happens before each `run_msg_loop` call
+    /// whose wasm file changed; `"error"` raises `WasmSourceChangedError`
+    /// there instead, and the default `"ignore"` keeps the loaded code.
+    fn reload(&self) -> PyResult<()> {
+        let mut guard = self
+            .wasm
+            .try_lock()
+            .map_err(|_| pyerr("WasmRunner: cannot reload while run_msg_loop is running"))?;
+        guard.reload(false)?;
+        // reload() replaced the store as reset() does
+        self.torn_down.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Re-initialize the already-instantiated guest with new parameters,
+    /// keeping the warm instance and compiled code. Cheaper than a fresh
+    /// instance, but only for components exporting `reinit-exec-env`;
+    /// `config` only under `world="env-config"`.
+    #[pyo3(signature = (id_name, log_tags=None, config=None))]
+    fn reinit<'py>(
+        &self,
+        py: Python<'py>,
+        id_name: String,
+        log_tags: Option<String>,
+        config: Option<Bound<'py, PyDict>>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        self.check_intact()?;
+        if config.is_some() {
+            self.world.check_takes_config("reinit() config")?;
         }
-    };
-}
+        let config = init_config_from_dict(config.as_ref())?;
+        let arc = self.wasm.clone();
+        self.drive(py, async move {
+            match arc.try_lock() {
+                Ok(mut guard) => {
+                    guard.recover()?;
+                    let _mark = PanicMark(guard.panicked.clone());
+                    let phase_id = id_name.clone();
+                    guard
+                        .reinit(id_name, log_tags, config)
+                        .await
+                        .map_err(|e| errors::to_pyerr(e, &phase_id, "reinit"))
+                }
+                Err(_) => Err(pyerr("WasmRunner: cannot reinit while run_msg_loop is running")),
+            }
+        })
+    }
 
-macro_rules! host_fn_async_void {
-    ($fn_name:ident, $py_field:ident, ($($argn:ident : $argt:ty),*)) => {
-        pub fn $fn_name(
-            store: wasmtime::StoreContextMut<Ctx>,
-            ($($argn,)*): ($($argt,)*),
-        ) -> Box<dyn std::future::Future<Output = wasmtime::Result<()>> + Send + '_> {
-            Box::new(async move {
-                let fut = pyo3::Python::with_gil(|py| {
-                    use pyo3::types::PyAnyMethods;
-                    let coro = store.data().imports.$py_field.bind(py).call1(($($argn,)*))?;
-                    pyo3_async_runtimes::tokio::into_future(coro)
-                }).map_err(pyerr_to_wasmtime_err)?;
-
-                let _obj = fut.await.map_err(pyerr_to_wasmtime_err)?;
-                Ok(())
-            })
+    /// Replace the `log_tags` passed to `init-exec-env`. A live instance
+    /// keeps the tags it was initialized with; the new ones apply from the
+    /// next instantiation (after `reset()`, `reload()` or a failed
+    /// instance). Use `reinit()` to change them in place. Raises while a
+    /// loop is running.
+    #[pyo3(signature = (log_tags=None))]
+    fn set_log_tags(&self, log_tags: Option<String>) -> PyResult<()> {
+        let mut guard = self
+            .wasm
+            .try_lock()
+            .map_err(|_| pyerr("WasmRunner: cannot set log_tags while run_msg_loop is running"))?;
+        guard.log_tags = log_tags;
+        Ok(())
+    }
+
+    /// Replace the `init_config` passed to `init-exec-env`, applied from the
+    /// next instantiation as with `set_log_tags()`. Raises while a loop is
+    /// running, and unless the runner hosts `world="env-config"`.
+    #[pyo3(signature = (config=None))]
+    fn set_init_config(&self, config: Option<Bound<'_, PyDict>>) -> PyResult<()> {
+        self.world.check_takes_config("set_init_config()")?;
+        let config = init_config_from_dict(config.as_ref())?;
+        let mut guard = self
+            .wasm
+            .try_lock()
+            .map_err(|_| pyerr("WasmRunner: cannot set init_config while run_msg_loop is running"))?;
+        guard.init_config = config;
+        Ok(())
+    }
+
+    /// Turn the runner's own stderr diagnostics on or off, as
+    /// `runner_logging` does at construction; takes effect at once. Has no
+    /// effect under `logger_name`, where the logger's level decides. Raises
+    /// while a loop is running.
+    fn set_logging(&self, enabled: bool) -> PyResult<()> {
+        let _guard = self
+            .wasm
+            .try_lock()
+            .map_err(|_| pyerr("WasmRunner: cannot set logging while run_msg_loop is running"))?;
+        self.diag.set_stderr(enabled);
+        Ok(())
+    }
+
+    /// Let the running loop finish the message it is handling, then end it
+    /// cleanly: from now on `recv_ready` returns false and `recv_bytes`
+    /// returns an empty payload (end of stream), including a `recv_bytes`
+    /// already waiting, so a guest that exits its loop on end of stream
+    /// returns normally. Unlike `close()`, nothing is aborted mid-message,
+    /// the instance is kept and the runner stays usable; messages still
+    /// buffered or preloaded stay queued for the next loop. The flag clears
+    /// when the loop returns, and applies to the next loop if none is
+    /// running. Other imports, `recv_frame` included, are unaffected, and a
+    /// guest that keeps polling regardless is only stopped by `close()`.
+    ///
+    /// `reason` says why, for the guest's `stop-reason` import and the
+    /// outcome's `stop_reason`; "unspecified" when not given.
+    #[pyo3(signature = (reason=None))]
+    fn drain(&self, reason: Option<String>) {
+        self.diag.debug(format_args!("WasmRunner: drain()"));
+        let mut slot = shutdown::lock(&self.stop_reason);
+        if slot.is_none() {
+            *slot = Some(reason.unwrap_or_else(|| UNSPECIFIED.to_owned()));
         }
-    };
-}
+        drop(slot);
+        self.draining.send_replace(true);
+    }
 
-mod host_imports {
-    use super::{Ctx, pyerr_to_wasmtime_err};
+    /// Stop the runner for good. A running loop unwinds at the guest's next
+    /// host call (including one already waiting in `recv_bytes` or
+    /// `recv_frame`), or at its next epoch check when `timeout_ms` or
+    /// `drop_behavior="interrupt"` enabled epochs, and then resolves
+    /// normally. The guest instance is released, and later `run_msg_loop`
+    /// calls are rejected. Idempotent; the first call's `reason` is the one
+    /// kept, reported to the guest by `stop-reason`, on the loop's outcome
+    /// and in the errors of those later calls.
+    #[pyo3(signature = (reason=None))]
+    fn close(&self, reason: Option<String>) {
+        self.diag.debug(format_args!("WasmRunner: close()"));
+        stop(&self.closed, &self.stop_reason, reason.unwrap_or_else(|| UNSPECIFIED.to_owned()));
+        self.engine.increment_epoch();
+        if let Ok(mut guard) = self.wasm.try_lock() {
+            guard.release();
+        }
+    }
 
-    host_fn_async_void!(send_bytes, send_bytes, (payload: Vec<u8>));
-    host_fn_async_ret!(recv_bytes, recv_bytes, (), Vec<u8>);
-    host_fn_sync_ret!(recv_ready, recv_ready, (), bool);
-    host_fn_sync_void!(write_log, write_log, (text: String));
+    /// `close()`, then wait until whatever holds the runner (a loop,
+    /// `instantiate()` or `reinit()`) has unwound and the instance is
+    /// released, so no guest code runs once it resolves. Idempotent, and
+    /// resolves at once when nothing is running.
+    #[pyo3(signature = (reason=None))]
+    fn aclose<'py>(&self, py: Python<'py>, reason: Option<String>) -> PyResult<Bound<'py, PyAny>> {
+        self.close(reason);
+        let arc = self.wasm.clone();
+        self.drive(py, async move {
+            arc.lock().await.release();
+            Ok(())
+        })
+    }
 }
 
-fn load_or_precompile_component(
-    engine: &Engine,
-    wasm_path: &str,
-    compiled_path: &str,
-) -> Result<Component, String> {
-    use std::fs;
+// end pymethods
 
-    let compiled = Path::new(compiled_path);
-    let wasm = Path::new(wasm_path);
+/// Deliver the end-of-loop accounting to the `on_loop_summary` callback.
+/// The loop's own result takes priority, so a failing callback is reported
+/// through `sys.unraisablehook` rather than raised.
+fn report_loop_summary(cb: &PyObject, ok: bool, elapsed: std::time::Duration, delta: MessageCounts) {
+    Python::with_gil(|py| {
+        let summary = || -> PyResult<Bound<'_, PyDict>> {
+            let d = PyDict::new(py);
+            d.set_item("exit", if ok { "normal" } else { "error" })?;
+            d.set_item("duration_ms", elapsed.as_secs_f64() * 1000.0)?;
+            d.set_item("messages_sent", delta.messages_sent)?;
+            d.set_item("messages_received", delta.messages_received)?;
+            d.set_item("bytes_sent", delta.bytes_sent)?;
+            d.set_item("bytes_received", delta.bytes_received)?;
+            Ok(d)
+        };
+        if let Err(e) = summary().and_then(|d| cb.bind(py).call1((d,))) {
+            e.write_unraisable(py, Some(cb.bind(py)));
+        }
+    });
+}
 
-    let force_recompile = std::env::var("WASMTIME_FORCE_RECOMPILE")
-        .map(|v| v == "1")
-        .unwrap_or(false);
+/// Evict least-recently-used `*.compiled` artifacts from `cache_dir` until
+/// it fits the given budget. Returns the removed paths. Intended to be run
+/// periodically; safe alongside runners loading from the same directory.
+#[pyfunction]
+#[pyo3(signature = (cache_dir, max_bytes=None, max_files=None, max_age_secs=None))]
+fn evict_compiled_cache(
+    cache_dir: String,
+    max_bytes: Option<u64>,
+    max_files: Option<usize>,
+    max_age_secs: Option<f64>,
+) -> PyResult<Vec<String>> {
+    let budget = cache::EvictionBudget {
+        max_bytes,
+        max_files,
+        max_age: max_age_secs
+            .map(std::time::Duration::try_from_secs_f64)
+            .transpose()
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?,
+    };
+    let evicted = cache::evict(Path::new(&cache_dir), &budget).map_err(pyerr)?;
+    Ok(evicted
+        .into_iter()
+        .map(|p| p.to_string_lossy().into_owned())
+        .collect())
+}
 
-    // Decide whether to reuse the cached compiled component
-    // Recompile if the compiled artifact is missing, empty, older than the wasm,
-    // or if deserialization fails.
-    let need_recompile = force_recompile || {
-        match (fs::metadata(compiled), fs::metadata(wasm)) {
-            (Ok(compiled_meta), Ok(wasm_meta)) => {
-                let empty = compiled_meta.len() == 0;
-                let older = match (compiled_meta.modified(), wasm_meta.modified()) {
-                    (Ok(compiled_mtime), Ok(wasm_mtime)) => compiled_mtime < wasm_mtime,
-                    _ => false,
-                };
-                !compiled.exists() || empty || older
-            }
-            _ => true,
+/// Compile `wasm_path` ahead of time into `out_path`, in the same form a
+/// `WasmRunner` given `wasm_compiled_cache=out_path` writes and reuses.
+/// `config` holds the runner's engine-shaping keyword arguments (see
+/// `EngineOptions`); an artifact built with different ones is recompiled
+/// by the runner rather than reused. `compile_threads` caps the compile
+/// threads as for `WasmRunner`. Returns `out_path`.
+#[pyfunction]
+#[pyo3(signature = (wasm_path, out_path, config=None, compile_threads=None))]
+fn precompile(
+    wasm_path: String,
+    out_path: String,
+    config: Option<Bound<'_, PyDict>>,
+    compile_threads: Option<usize>,
+) -> PyResult<String> {
+    let engine = EngineOptions::from_runner_kwargs(config.as_ref())?.build()?;
+    let bytes = compression::read_wasm(Path::new(&wasm_path))?;
+    let blob = with_compile_threads(compile_threads, || {
+        engine
+            .precompile_component(&bytes)
+            .map_err(|e| features::explain(format!("{wasm_path} is not a valid component: {e:#}")))
+    })
+    .map_err(|e| pyo3::exceptions::PyValueError::new_err(format!("precompile: {e}")))?;
+    let out = Path::new(&out_path);
+    let meta = cache::ArtifactMeta::new(&engine, &bytes);
+    let _lock = cache::lock(out);
+    cache::write_artifact(out, &blob, &meta)?;
+    Ok(out_path)
+}
+
+/// Check, without running any guest code, that `wasm_path` would load in
+/// a `WasmRunner`: it compiles (through `compiled_cache` when given, as the
+/// runner's `wasm_compiled_cache`), implements `world` and has every import
+/// satisfied by the host and `custom_imports`. No store is created and
+/// `init-exec-env` is not called. `config` holds the runner's
+/// engine-shaping keyword arguments as for `precompile`, so the check
+/// matches what the runner would build; `async_recv_ready` and
+/// `virtual_files` (only whether it is set matters) shape the imports the
+/// same way. Raises ValueError naming the first problem found.
+#[pyfunction]
+#[pyo3(signature = (
+    wasm_path,
+    config=None,
+    world="env".to_string(),
+    compiled_cache=None,
+    force_recompile=false,
+    compile_threads=None,
+    custom_imports=None,
+    async_recv_ready=false,
+    virtual_files=false,
+))]
+#[allow(clippy::too_many_arguments)]
+fn validate(
+    wasm_path: String,
+    config: Option<Bound<'_, PyDict>>,
+    world: String,
+    compiled_cache: Option<String>,
+    force_recompile: bool,
+    compile_threads: Option<usize>,
+    custom_imports: Option<Bound<'_, PyDict>>,
+    async_recv_ready: bool,
+    virtual_files: bool,
+) -> PyResult<()> {
+    let engine = EngineOptions::from_runner_kwargs(config.as_ref())?.build()?;
+    let world = worlds::World::parse(&world)?;
+    let bytes = compression::read_wasm(Path::new(&wasm_path))?;
+    let mut timings = timings::StartupTimings::default();
+    let component = with_compile_threads(compile_threads, || match &compiled_cache {
+        Some(path) => {
+            let meta = cache::ArtifactMeta::new(&engine, &bytes);
+            load_or_precompile_component(&engine, &bytes, &meta, path, force_recompile, CacheWrite::Fail, &mut timings)
         }
-    };
+        None => Component::from_binary(&engine, &bytes).map_err(|e| format!("{e:#}")),
+    })
+    .map_err(|e| {
+        pyo3::exceptions::PyValueError::new_err(features::explain(format!(
+            "validate: {wasm_path} is not a valid component: {e}"
+        )))
+    })?;
+    world.check(&engine, &component)?;
+    let linker = host_linker(&engine, async_recv_ready, virtual_files, custom_imports.as_ref())?;
+    worlds::check_imports(&linker, &component)
+}
 
-    if !need_recompile {
-        match unsafe { Component::deserialize_file(engine, compiled_path) } {
-            Ok(component) => Ok(component),
-            Err(_) => {
-                let bytes = fs::read(wasm).map_err(|e| e.to_string())?;
-                let blob = engine
-                    .precompile_component(&bytes)
-                    .map_err(|e| e.to_string())?;
-                let _ = fs::write(compiled, blob);
-                Component::from_binary(engine, &bytes).map_err(|e| e.to_string())
+#[pymodule]
+fn host(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<WasmRunner>()?;
+    m.add_class::<EngineHandle>()?;
+    m.add_class::<pool::WasmRunnerPool>()?;
+    m.add_function(wrap_pyfunction!(evict_compiled_cache, m)?)?;
+    m.add_function(wrap_pyfunction!(precompile, m)?)?;
+    m.add_function(wrap_pyfunction!(validate, m)?)?;
+    errors::register(m)?;
+    Ok(())
+}
+
+impl Drop for WasmRunner {
+    fn drop(&mut self) {
+        self.diag.debug(format_args!("WasmRunner: drop()"));
+        if !self.is_busy() {
+            return;
+        }
+        match self.drop_behavior {
+            DropBehavior::Detach => {
+                self.diag.warn(format_args!("WasmRunner: dropped while run_msg_loop is running; loop left to finish"));
+            }
+            DropBehavior::Interrupt => {
+                self.diag.debug(format_args!("WasmRunner: dropped while running; interrupting guest"));
+                self.interrupted.store(true, std::sync::atomic::Ordering::Relaxed);
+                self.engine.increment_epoch();
+            }
+            DropBehavior::Wait(timeout) => {
+                self.diag.debug(format_args!("WasmRunner: dropped while running; draining loop"));
+                self.draining.send_replace(true);
+                let (wasm, closed, stop_reason, engine, diag) = (
+                    self.wasm.clone(),
+                    self.closed.clone(),
+                    self.stop_reason.clone(),
+                    self.engine.clone(),
+                    self.diag.clone(),
+                );
+                pyo3_async_runtimes::tokio::get_runtime().spawn(async move {
+                    if tokio::time::timeout(timeout, wasm.lock()).await.is_err() {
+                        diag.warn(format_args!(
+                            "WasmRunner: dropped while running; loop still active after {}ms, closing it",
+                            timeout.as_millis()
+                        ));
+                        stop(&closed, &stop_reason, "dropped".to_owned());
+                        engine.increment_epoch();
+                    }
+                });
             }
         }
-    } else {
-        let bytes = fs::read(wasm).map_err(|e| e.to_string())?;
-        let blob = engine
-            .precompile_component(&bytes)
-            .map_err(|e| e.to_string())?;
-        let _ = fs::write(compiled, blob);
-        Component::from_binary(engine, &bytes).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panic_mark_flags_only_a_panic() {
+        let panicked = Arc::new(AtomicBool::new(false));
+        drop(PanicMark(panicked.clone()));
+        assert!(!panicked.load(Ordering::Relaxed));
+        let mark = panicked.clone();
+        let res = std::panic::catch_unwind(move || {
+            let _mark = PanicMark(mark);
+            panic!("under the lock");
+        });
+        assert!(res.is_err());
+        assert!(panicked.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn looping_clears_even_after_a_panic() {
+        let looping = Arc::new(AtomicBool::new(false));
+        let flag = looping.clone();
+        let res = std::panic::catch_unwind(move || {
+            let _looping = Looping::start(&flag);
+            assert!(flag.load(Ordering::Relaxed));
+            panic!("mid-loop");
+        });
+        assert!(res.is_err());
+        // so `running` does not report a loop that unwound
+        assert!(!looping.load(Ordering::Relaxed));
     }
 }
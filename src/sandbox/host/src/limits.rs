@@ -0,0 +1,139 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+/// Counters shared between a store's `Ctx` and its `WasmRunner`. Atomics so
+/// `metrics()` can read them while a loop holds the `WasmData` lock.
+#[derive(Default)]
+pub(crate) struct Metrics {
+    pub table_elements: AtomicUsize,
+    pub peak_table_elements: AtomicUsize,
+    pub messages_sent: AtomicU64,
+    pub messages_received: AtomicU64,
+    pub bytes_sent: AtomicU64,
+    pub bytes_received: AtomicU64,
+    pub live_tasks: AtomicUsize,
+    /* bytes of linear memory the store's memories have grown to, summed */
+    pub memory_bytes: AtomicUsize,
+    /* set when a memory.grow is refused; the next loop error is attributed to it */
+    pub memory_limit_hit: AtomicBool,
+    /* time spent in the sleep import, to tell waiting from computing */
+    pub slept_ns: AtomicU64,
+}
+
+/// Plain copy of the message counters, for computing per-loop deltas.
+#[derive(Clone, Copy)]
+pub(crate) struct MessageCounts {
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+impl MessageCounts {
+    pub fn since(self, earlier: MessageCounts) -> MessageCounts {
+        MessageCounts {
+            messages_sent: self.messages_sent - earlier.messages_sent,
+            messages_received: self.messages_received - earlier.messages_received,
+            bytes_sent: self.bytes_sent - earlier.bytes_sent,
+            bytes_received: self.bytes_received - earlier.bytes_received,
+        }
+    }
+}
+
+impl Metrics {
+    pub fn sent(&self, len: usize) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub fn received(&self, len: usize) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub fn slept(&self, time: std::time::Duration) {
+        let ns = u64::try_from(time.as_nanos()).unwrap_or(u64::MAX);
+        self.slept_ns.fetch_add(ns, Ordering::Relaxed);
+    }
+
+    pub fn message_counts(&self) -> MessageCounts {
+        MessageCounts {
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+        }
+    }
+
+    fn table_grew(&self, by: usize) {
+        let now = self.table_elements.fetch_add(by, Ordering::Relaxed) + by;
+        self.peak_table_elements.fetch_max(now, Ordering::Relaxed);
+    }
+}
+
+/// Caps on how many of each instantiable resource one store may hold. A
+/// componentize-py guest needs a few dozen instances and a handful of
+/// memories and tables; these leave ample headroom while stopping a
+/// component from creating them without bound.
+const MAX_INSTANCES: usize = 1000;
+const MAX_TABLES: usize = 1000;
+const MAX_MEMORIES: usize = 100;
+
+/// `ResourceLimiter` installed on every store via `Store::limiter`.
+pub(crate) struct Limits {
+    /// Cap on the element count of any single table; `None` is unbounded.
+    pub max_table_elements: Option<usize>,
+    /// Cap on the byte size of any single linear memory; `None` is unbounded.
+    pub max_memory_bytes: Option<usize>,
+    pub metrics: Arc<Metrics>,
+}
+
+impl wasmtime::ResourceLimiter for Limits {
+    /// Refusing growth makes `memory.grow` return -1 to the guest rather than
+    /// trapping; the guest then typically fails with its own out-of-memory
+    /// trap, which `memory_limit_hit` lets the host attribute to the limit.
+    fn memory_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> wasmtime::Result<bool> {
+        if let Some(max) = self.max_memory_bytes
+            && desired > max
+        {
+            self.metrics.memory_limit_hit.store(true, Ordering::Relaxed);
+            return Ok(false);
+        }
+        self.metrics
+            .memory_bytes
+            .fetch_add(desired.saturating_sub(current), Ordering::Relaxed);
+        Ok(true)
+    }
+
+    fn table_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> wasmtime::Result<bool> {
+        if let Some(max) = self.max_table_elements
+            && desired > max
+        {
+            return Ok(false);
+        }
+        self.metrics.table_grew(desired.saturating_sub(current));
+        Ok(true)
+    }
+
+    fn instances(&self) -> usize {
+        MAX_INSTANCES
+    }
+
+    fn tables(&self) -> usize {
+        MAX_TABLES
+    }
+
+    fn memories(&self) -> usize {
+        MAX_MEMORIES
+    }
+}
@@ -0,0 +1,305 @@
+//! Loading a component: from a precompiled artifact, or by compiling it
+//! through the on-disk cache of compiled artifacts.
+
+use pyo3::prelude::*;
+use std::path::Path;
+use wasmtime::Engine;
+use wasmtime::component::Component;
+
+use crate::engine::ASYNC_STACK_HEADROOM;
+use crate::{cache, diag, timings};
+
+/// What `load_or_precompile_component` does when it cannot write the
+/// compiled artifact back: a cache the caller named is expected to work,
+/// while the default location is best effort.
+#[derive(Clone, Copy)]
+pub(crate) enum CacheWrite<'a> {
+    Warn(&'a diag::Diag),
+    Fail,
+}
+
+impl<'a> CacheWrite<'a> {
+    pub(crate) fn new(required: bool, diag: &'a diag::Diag) -> Self {
+        if required { CacheWrite::Fail } else { CacheWrite::Warn(diag) }
+    }
+}
+
+/// Load the component through the on-disk cache at `compiled_path`,
+/// compiling and rewriting it when stale. `force_recompile` (or
+/// `WASMTIME_FORCE_RECOMPILE=1`, which applies to every runner) rebuilds it
+/// even when fresh. Each phase it runs is timed into `timings`.
+pub(crate) fn load_or_precompile_component(
+    engine: &Engine,
+    bytes: &[u8],
+    meta: &cache::ArtifactMeta,
+    compiled_path: &str,
+    force_recompile: bool,
+    write: CacheWrite<'_>,
+    timings: &mut timings::StartupTimings,
+) -> Result<Component, String> {
+    let (component, unwritten) = through_cache(engine, bytes, meta, Path::new(compiled_path), force_recompile, timings)?;
+    if let Some(e) = unwritten {
+        match write {
+            CacheWrite::Warn(diag) => diag.warn(format_args!(
+                "WasmRunner: cannot write compiled cache {compiled_path}, compiling on every start: {e}"
+            )),
+            CacheWrite::Fail => return Err(format!("cannot write compiled cache {compiled_path}: {e}")),
+        }
+    }
+    Ok(component)
+}
+
+/// `load_or_precompile_component` up to writing the cache, with the error
+/// that kept a rebuilt artifact from being stored, if any.
+fn through_cache(
+    engine: &Engine,
+    bytes: &[u8],
+    meta: &cache::ArtifactMeta,
+    compiled: &Path,
+    force_recompile: bool,
+    timings: &mut timings::StartupTimings,
+) -> Result<(Component, Option<std::io::Error>), String> {
+    let force_recompile = force_recompile
+        || std::env::var("WASMTIME_FORCE_RECOMPILE")
+            .map(|v| v == "1")
+            .unwrap_or(false);
+
+    if !force_recompile && let Some(component) = load_cached_component(engine, compiled, meta, timings) {
+        return Ok((component, None));
+    }
+
+    // Serialise writers so concurrent starts compile once. Whoever waited
+    // retries the cache first: the holder may have just written it.
+    let _lock = cache::lock(compiled);
+    if !force_recompile && let Some(component) = load_cached_component(engine, compiled, meta, timings) {
+        return Ok((component, None));
+    }
+
+    let blob = timings
+        .time("precompile", || engine.precompile_component(bytes))
+        .map_err(|e| format!("{e:#}"))?;
+    let unwritten = cache::write_artifact(compiled, &blob, meta).err();
+    let component = timings
+        .time("compile", || Component::from_binary(engine, bytes))
+        .map_err(|e| format!("{e:#}"))?;
+    Ok((component, unwritten))
+}
+
+/// `max_wasm_stack` must leave the guest some stack, and the host stack
+/// sized from it must fit in memory.
+pub(crate) fn check_max_wasm_stack(size: usize) -> PyResult<usize> {
+    if size == 0 || size > isize::MAX as usize - ASYNC_STACK_HEADROOM {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "WasmRunner: max_wasm_stack must be a positive byte count, got {size}"
+        )));
+    }
+    Ok(size)
+}
+
+/// `compile_threads` must leave the compile at least one thread.
+pub(crate) fn check_compile_threads(threads: usize) -> PyResult<usize> {
+    if threads == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "WasmRunner: compile_threads must be at least 1",
+        ));
+    }
+    Ok(threads)
+}
+
+/// Run a compile with at most `threads` compile threads. Parallel compilation
+/// fans out on the rayon pool it runs in, which is otherwise the global one
+/// sized to every core; `None` keeps that.
+pub(crate) fn with_compile_threads<T: Send>(
+    threads: Option<usize>,
+    compile: impl FnOnce() -> Result<T, String> + Send,
+) -> Result<T, String> {
+    let Some(threads) = threads else {
+        return compile();
+    };
+    if threads == 0 {
+        return Err("compile_threads must be at least 1".to_string());
+    }
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|i| format!("wasm-compile-{i}"))
+        .build()
+        .map_err(|e| format!("could not start compile threads: {e}"))?
+        .install(compile)
+}
+
+/// The trusted fast path for `precompiled_path`: deserialize a `.cwasm`
+/// built ahead of time (e.g. by `precompile()` in CI) with no source, hash
+/// or sidecar checks. The artifact must come from the same Wasmtime version
+/// and an engine configured the same way; anything else is a ValueError.
+/// Bypasses the `EngineHandle` component cache.
+pub(crate) fn load_precompiled(engine: &Engine, path: &str) -> PyResult<Component> {
+    let invalid = |what: String| {
+        pyo3::exceptions::PyValueError::new_err(format!("WasmRunner: precompiled_path {path:?} {what}"))
+    };
+    if !Path::new(path).is_file() {
+        return Err(pyo3::exceptions::PyFileNotFoundError::new_err(format!(
+            "WasmRunner: precompiled_path {path:?} does not exist"
+        )));
+    }
+    // a file that is not ELF at all is an error here rather than None
+    match Engine::detect_precompiled_file(path) {
+        Ok(Some(wasmtime::Precompiled::Component)) => {}
+        Ok(Some(wasmtime::Precompiled::Module)) => {
+            return Err(invalid("is a precompiled core module, not a component".into()));
+        }
+        Ok(None) | Err(_) => return Err(invalid("is not a Wasmtime precompiled artifact".into())),
+    }
+    // SAFETY: precompiled_path is documented as trusted input
+    unsafe { Component::deserialize_file(engine, path) }.map_err(|e| {
+        invalid(format!(
+            "is incompatible with this engine ({e}); rebuild it with this Wasmtime version and the \
+             same engine options (timeout_ms, fuel_limit, huge_pages, pooling)"
+        ))
+    })
+}
+
+/// Reuse the cached artifact only if its sidecar says it was built from
+/// exactly these wasm bytes by a compatible engine.
+fn load_cached_component(
+    engine: &Engine,
+    compiled: &Path,
+    meta: &cache::ArtifactMeta,
+    timings: &mut timings::StartupTimings,
+) -> Option<Component> {
+    if cache::ArtifactMeta::read(compiled).as_ref() != Some(meta) {
+        return None;
+    }
+    let component = timings
+        .time("deserialize", || unsafe { Component::deserialize_file(engine, compiled) })
+        .ok()?;
+    cache::touch(compiled);
+    Some(component)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Scratch, component};
+
+    /// Load `bytes` through the cache at `compiled`, returning the phases run.
+    fn load(engine: &Engine, bytes: &[u8], compiled: &Path) -> timings::StartupTimings {
+        load_forced(engine, bytes, compiled, false)
+    }
+
+    fn load_forced(engine: &Engine, bytes: &[u8], compiled: &Path, force_recompile: bool) -> timings::StartupTimings {
+        let meta = cache::ArtifactMeta::new(engine, bytes);
+        let mut timings = timings::StartupTimings::default();
+        let (_, unwritten) = through_cache(engine, bytes, &meta, compiled, force_recompile, &mut timings).unwrap();
+        assert!(unwritten.is_none());
+        timings
+    }
+
+    #[test]
+    fn reuses_a_fresh_artifact() {
+        let dir = Scratch::new("fresh");
+        let compiled = dir.join("env.compiled");
+        let engine = Engine::default();
+        assert!(load(&engine, &component("a"), &compiled).ran("precompile"));
+        let again = load(&engine, &component("a"), &compiled);
+        assert!(again.ran("deserialize"));
+        assert!(!again.ran("precompile"));
+    }
+
+    #[test]
+    fn force_recompile_ignores_a_fresh_artifact() {
+        let dir = Scratch::new("forced");
+        let compiled = dir.join("env.compiled");
+        let engine = Engine::default();
+        load(&engine, &component("a"), &compiled);
+        let timings = load_forced(&engine, &component("a"), &compiled, true);
+        assert!(timings.ran("precompile"));
+        assert!(!timings.ran("deserialize"));
+        // and the rebuilt artifact serves the next start
+        assert!(load(&engine, &component("a"), &compiled).ran("deserialize"));
+    }
+
+    #[test]
+    fn recompiles_when_the_wasm_changes() {
+        let dir = Scratch::new("changed");
+        let compiled = dir.join("env.compiled");
+        let engine = Engine::default();
+        load(&engine, &component("a"), &compiled);
+        let modified = std::fs::metadata(&compiled).unwrap().modified().unwrap();
+        // the new bytes are the same size, and the artifact is newer than either
+        let timings = load(&engine, &component("b"), &compiled);
+        assert!(timings.ran("precompile"));
+        assert!(!timings.ran("deserialize"));
+        assert!(cache::ArtifactMeta::read(&compiled) == Some(cache::ArtifactMeta::new(&engine, &component("b"))));
+        assert!(std::fs::metadata(&compiled).unwrap().modified().unwrap() >= modified);
+    }
+
+    #[test]
+    fn never_deserializes_for_another_engine() {
+        let dir = Scratch::new("engine");
+        let compiled = dir.join("env.compiled");
+        let engine = Engine::default();
+        load(&engine, &component("a"), &compiled);
+        let mut fuel = wasmtime::Config::new();
+        fuel.consume_fuel(true);
+        let timings = load(&Engine::new(&fuel).unwrap(), &component("a"), &compiled);
+        assert!(!timings.ran("deserialize"));
+        assert!(timings.ran("precompile"));
+    }
+
+    #[test]
+    fn opt_levels_do_not_share_an_artifact() {
+        let dir = Scratch::new("opt");
+        let compiled = dir.join("env.compiled");
+        let at = |level| {
+            let mut config = wasmtime::Config::new();
+            config.cranelift_opt_level(level);
+            Engine::new(&config).unwrap()
+        };
+        let (none, speed) = (at(wasmtime::OptLevel::None), at(wasmtime::OptLevel::SpeedAndSize));
+        assert!(cache::ArtifactMeta::new(&none, &component("a")) != cache::ArtifactMeta::new(&speed, &component("a")));
+        load(&none, &component("a"), &compiled);
+        let timings = load(&speed, &component("a"), &compiled);
+        assert!(timings.ran("precompile"));
+        assert!(!timings.ran("deserialize"));
+    }
+
+    #[test]
+    fn never_deserializes_for_another_wasmtime() {
+        let dir = Scratch::new("version");
+        let compiled = dir.join("env.compiled");
+        let engine = Engine::default();
+        load(&engine, &component("a"), &compiled);
+        // as left by another wasmtime release: same wasm, other fingerprint
+        let sidecar = dir.join("env.compiled.meta");
+        let text = std::fs::read_to_string(&sidecar).unwrap();
+        let (wasm, _) = text.split_once("\nengine ").unwrap();
+        std::fs::write(&sidecar, format!("{wasm}\nengine 00\n")).unwrap();
+        let timings = load(&engine, &component("a"), &compiled);
+        assert!(!timings.ran("deserialize"));
+        assert!(timings.ran("precompile"));
+    }
+
+    #[test]
+    fn racing_warmers_share_one_artifact() {
+        let dir = Scratch::new("race");
+        let compiled = dir.join("env.compiled");
+        let engine = Engine::default();
+        let bytes = component("a");
+        let compiles: usize = std::thread::scope(|scope| {
+            let warmers: Vec<_> = (0..8)
+                .map(|_| scope.spawn(|| load(&engine, &bytes, &compiled).ran("precompile") as usize))
+                .collect();
+            warmers.into_iter().map(|w| w.join().unwrap()).sum()
+        });
+        // the lock lets one compile while the rest wait and deserialize it
+        assert_eq!(compiles, 1);
+        assert!(cache::ArtifactMeta::read(&compiled) == Some(cache::ArtifactMeta::new(&engine, &bytes)));
+        let leftovers: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.contains(".tmp."))
+            .collect();
+        assert!(leftovers.is_empty(), "{leftovers:?}");
+    }
+}
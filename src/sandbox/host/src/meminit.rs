@@ -0,0 +1,94 @@
+//! Linear-memory layout and initialization, from the `engine_config` dict.
+//! Like `engine_features`, these shape the engine and are part of its
+//! compatibility hash (and so of the compiled cache key): wasmtime bakes
+//! the reservation and guard sizes into bounds checks, and records whether
+//! memory images were prepared for copy-on-write.
+//!
+//! - `memory_init_cow` (default True): map a module's initial memory image
+//!   copy-on-write instead of copying its data segments into every fresh
+//!   memory. Instantiation then costs a few mmap calls whatever the size of
+//!   the guest's static data, where copying costs time proportional to it
+//!   (typically tens to hundreds of microseconds for a few MiB of segments,
+//!   paid again by every `reset()`); pages are only copied once the guest
+//!   writes to them. Where wasmtime cannot use virtual memory the default
+//!   quietly falls back to copying, so True never fails an engine.
+//! - `memory_reservation` (bytes): address space reserved per memory. A
+//!   large one (the 4 GiB default on 64-bit hosts) gives static memories
+//!   that never move and need few bounds checks; 0 gives dynamic memories
+//!   that are reallocated as they grow.
+//! - `memory_guard_size` (bytes): unmapped guard after each memory, which
+//!   lets compiled code drop bounds checks on accesses within it.
+//! - `memory_may_move`: whether a memory may be moved to grow past its
+//!   reservation. False makes growth beyond it fail instead.
+//! - `guard_before_linear_memory`: also guard the region before each
+//!   memory, as defense in depth against miscompiled negative offsets.
+//!
+//! Unset keys keep wasmtime's defaults.
+
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use wasmtime::Config;
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) struct MemoryConfig {
+    cow: bool,
+    reservation: Option<u64>,
+    guard_size: Option<u64>,
+    may_move: Option<bool>,
+    guard_before: Option<bool>,
+}
+
+impl Default for MemoryConfig {
+    fn default() -> Self {
+        MemoryConfig {
+            cow: true,
+            reservation: None,
+            guard_size: None,
+            may_move: None,
+            guard_before: None,
+        }
+    }
+}
+
+impl MemoryConfig {
+    pub fn from_dict(d: &Bound<'_, PyDict>) -> PyResult<Self> {
+        let mut mem = MemoryConfig::default();
+        for (key, value) in d.iter() {
+            let key: String = key.extract()?;
+            match key.as_str() {
+                "memory_init_cow" => mem.cow = value.extract::<Option<bool>>()?.unwrap_or(true),
+                "memory_reservation" => mem.reservation = value.extract()?,
+                "memory_guard_size" => mem.guard_size = value.extract()?,
+                "memory_may_move" => mem.may_move = value.extract()?,
+                "guard_before_linear_memory" => mem.guard_before = value.extract()?,
+                _ => {
+                    return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                        "engine_config: unknown setting {key:?}; expected memory_init_cow, \
+                         memory_reservation, memory_guard_size, memory_may_move or \
+                         guard_before_linear_memory"
+                    )));
+                }
+            }
+        }
+        Ok(mem)
+    }
+
+    pub fn apply(self, cfg: &mut Config) {
+        // left unset, wasmtime enables CoW only where it is supported
+        if !self.cow {
+            cfg.memory_init_cow(false);
+        }
+        if let Some(bytes) = self.reservation {
+            cfg.memory_reservation(bytes);
+        }
+        if let Some(bytes) = self.guard_size {
+            cfg.memory_guard_size(bytes);
+        }
+        if let Some(on) = self.may_move {
+            cfg.memory_may_move(on);
+        }
+        if let Some(on) = self.guard_before {
+            cfg.guard_before_linear_memory(on);
+        }
+    }
+}
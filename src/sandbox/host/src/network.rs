@@ -0,0 +1,171 @@
+//! Outbound network access, for `allow_network=True`. Without it the guest
+//! has no network: `wasi:sockets` is linked but every address is refused.
+//!
+//! With it, the guest may resolve names and connect (TCP, or UDP send) to
+//! the `network_allow_list` destinations only, each `host:port` (`[v6]:port`
+//! for IPv6 literals, `*` for any port). Other destinations fail in the
+//! guest with `access-denied`. Binding is limited to an ephemeral port on
+//! the unspecified address, which is what a client socket does, so the
+//! guest cannot listen on a port of its choosing.
+//!
+//! Risk: this widens the sandbox to whatever the allowed hosts expose.
+//! Addresses are checked, not names: a hostname allows every address it
+//! resolves to on the host at connect time, including any other service
+//! sharing that address (a CDN, a proxy), and a compromised resolver or
+//! name decides what that is. Prefer IP literals where they are stable.
+
+use pyo3::prelude::*;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use wasmtime_wasi::WasiCtxBuilder;
+use wasmtime_wasi::sockets::SocketAddrUse;
+
+use crate::diag::Diag;
+
+struct Dest {
+    host: String,
+    /* None for `*` */
+    port: Option<u16>,
+}
+
+/// The `network_allow_list` destinations.
+struct AllowList(Vec<Dest>);
+
+#[derive(Clone)]
+pub(crate) struct NetworkPolicy {
+    allow: Arc<AllowList>,
+    diag: Diag,
+}
+
+impl NetworkPolicy {
+    /// Parse `network_allow_list`.
+    pub fn parse(entries: &[String], diag: Diag) -> PyResult<Self> {
+        let allow = AllowList::parse(entries).map_err(pyo3::exceptions::PyValueError::new_err)?;
+        Ok(NetworkPolicy {
+            allow: Arc::new(allow),
+            diag,
+        })
+    }
+
+    pub fn apply(&self, builder: &mut WasiCtxBuilder) {
+        builder.allow_ip_name_lookup(true);
+        let policy = self.clone();
+        builder.socket_addr_check(move |addr, usage| {
+            let policy = policy.clone();
+            Box::pin(async move { policy.check(addr, usage).await })
+        });
+    }
+
+    async fn check(&self, addr: SocketAddr, usage: SocketAddrUse) -> bool {
+        let allowed = self.allow.permits(addr, usage).await;
+        if !allowed {
+            self.diag.debug(format_args!("WasmRunner: guest network use {usage:?} of {addr} denied"));
+        }
+        allowed
+    }
+}
+
+impl AllowList {
+    fn parse(entries: &[String]) -> Result<Self, String> {
+        entries.iter().map(|entry| Dest::parse(entry)).collect::<Result<_, _>>().map(AllowList)
+    }
+
+    async fn permits(&self, addr: SocketAddr, usage: SocketAddrUse) -> bool {
+        match usage {
+            SocketAddrUse::TcpBind | SocketAddrUse::UdpBind => addr.ip().is_unspecified() && addr.port() == 0,
+            SocketAddrUse::TcpConnect | SocketAddrUse::UdpConnect | SocketAddrUse::UdpOutgoingDatagram => {
+                self.allows(addr).await
+            }
+        }
+    }
+
+    async fn allows(&self, addr: SocketAddr) -> bool {
+        for dest in &self.0 {
+            if dest.port.is_some_and(|port| port != addr.port()) {
+                continue;
+            }
+            if let Ok(ip) = dest.host.parse::<IpAddr>() {
+                if ip == addr.ip() {
+                    return true;
+                }
+                continue;
+            }
+            if let Ok(mut resolved) = tokio::net::lookup_host((dest.host.as_str(), addr.port())).await
+                && resolved.any(|a| a.ip() == addr.ip())
+            {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl Dest {
+    fn parse(entry: &str) -> Result<Self, String> {
+        let invalid =
+            || format!("WasmRunner: network_allow_list entry {entry:?} must be host:port, [v6]:port or host:*");
+        let (host, port) = entry.rsplit_once(':').ok_or_else(invalid)?;
+        let host = match host.strip_prefix('[') {
+            Some(v6) => v6.strip_suffix(']').ok_or_else(invalid)?,
+            None if host.contains(':') => return Err(invalid()),
+            None => host,
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        let port = match port {
+            "*" => None,
+            port => Some(port.parse().map_err(|_| invalid())?),
+        };
+        Ok(Dest {
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(entries: &[&str]) -> AllowList {
+        AllowList::parse(&entries.iter().map(|e| e.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn connects_only_to_listed_destinations() {
+        let allow = list(&["10.0.0.1:443", "10.0.0.2:*", "[::1]:8080"]);
+        assert!(allow.permits(addr("10.0.0.1:443"), SocketAddrUse::TcpConnect).await);
+        assert!(!allow.permits(addr("10.0.0.1:80"), SocketAddrUse::TcpConnect).await);
+        assert!(!allow.permits(addr("10.0.0.3:443"), SocketAddrUse::TcpConnect).await);
+        assert!(allow.permits(addr("10.0.0.2:1234"), SocketAddrUse::UdpOutgoingDatagram).await);
+        assert!(allow.permits(addr("[::1]:8080"), SocketAddrUse::TcpConnect).await);
+        assert!(!allow.permits(addr("[::1]:8081"), SocketAddrUse::TcpConnect).await);
+    }
+
+    #[tokio::test]
+    async fn empty_list_denies_everything() {
+        assert!(!list(&[]).permits(addr("127.0.0.1:80"), SocketAddrUse::TcpConnect).await);
+    }
+
+    #[tokio::test]
+    async fn binds_only_an_ephemeral_port() {
+        let allow = list(&["10.0.0.1:443"]);
+        assert!(allow.permits(addr("0.0.0.0:0"), SocketAddrUse::TcpBind).await);
+        assert!(allow.permits(addr("[::]:0"), SocketAddrUse::UdpBind).await);
+        assert!(!allow.permits(addr("0.0.0.0:8000"), SocketAddrUse::TcpBind).await);
+        assert!(!allow.permits(addr("10.0.0.1:0"), SocketAddrUse::TcpBind).await);
+    }
+
+    #[test]
+    fn rejects_malformed_entries() {
+        for entry in ["example.com", "::1:80", "[::1:80", ":80", "host:port", "host:70000"] {
+            let err = AllowList::parse(&[entry.to_string()]).err().unwrap();
+            assert!(err.contains("network_allow_list"), "{err}");
+        }
+    }
+}
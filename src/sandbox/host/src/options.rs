@@ -0,0 +1,331 @@
+//! The grouped `WasmRunner` settings. Related settings arrive as one dict
+//! keyword (`limits`, `compile`, `wasi`, `transport`, `signing`,
+//! `deterministic`), as `engine_config` and `init_config` already do, and
+//! each dict is checked here so a misspelt key fails instead of falling
+//! back to the default. A key set to None counts as unset.
+
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::determinism::Deterministic;
+use crate::engine::{EngineOptions, OptLevel};
+use crate::loading::{check_compile_threads, check_max_wasm_stack};
+use crate::{features, meminit};
+
+/// Virtual time added per guest clock read under `deterministic`, so a
+/// guest polling the clock still sees it move.
+const DEFAULT_CLOCK_TICK_NS: u64 = 1_000;
+
+/// Iterate the set keys of an option dict, failing on one outside `fields`.
+fn fields<'py>(
+    group: &str,
+    d: Option<&Bound<'py, PyDict>>,
+    fields: &[&str],
+) -> PyResult<Vec<(String, Bound<'py, PyAny>)>> {
+    let Some(d) = d else { return Ok(Vec::new()) };
+    let mut set = Vec::new();
+    for (key, value) in d.iter() {
+        let key: String = key.extract()?;
+        if !fields.contains(&key.as_str()) {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "WasmRunner: unknown {group} field {key:?}; expected one of {}",
+                fields.join(", ")
+            )));
+        }
+        if !value.is_none() {
+            set.push((key, value));
+        }
+    }
+    Ok(set)
+}
+
+fn millis(value: &Bound<'_, PyAny>) -> PyResult<Option<Duration>> {
+    Ok(Some(Duration::from_millis(value.extract()?)))
+}
+
+const LIMIT_FIELDS: &[&str] = &[
+    "timeout_ms",
+    "init_timeout_ms",
+    "cpu_timeout_ms",
+    "yield_interval_ms",
+    "recv_timeout_ms",
+    "fuel_limit",
+    "max_memory_bytes",
+];
+
+/// `limits`: how long and how much the guest may run.
+#[derive(Clone, Copy, Default)]
+pub struct LimitOptions {
+    pub timeout: Option<Duration>,
+    pub init_timeout: Option<Duration>,
+    pub cpu_timeout: Option<Duration>,
+    pub yield_interval: Option<Duration>,
+    pub recv_timeout: Option<Duration>,
+    pub fuel_limit: Option<u64>,
+    pub max_memory_bytes: Option<usize>,
+}
+
+impl LimitOptions {
+    pub fn from_dict(d: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+        let mut limits = LimitOptions::default();
+        for (key, value) in fields("limits", d, LIMIT_FIELDS)? {
+            match key.as_str() {
+                "timeout_ms" => limits.timeout = millis(&value)?,
+                "init_timeout_ms" => limits.init_timeout = millis(&value)?,
+                "cpu_timeout_ms" => limits.cpu_timeout = millis(&value)?,
+                "yield_interval_ms" => limits.yield_interval = millis(&value)?,
+                "recv_timeout_ms" => limits.recv_timeout = millis(&value)?,
+                "fuel_limit" => limits.fuel_limit = Some(value.extract()?),
+                "max_memory_bytes" => limits.max_memory_bytes = Some(value.extract()?),
+                _ => unreachable!("checked against LIMIT_FIELDS"),
+            }
+        }
+        Ok(limits)
+    }
+
+    /// Whether any of these is enforced on epoch ticks.
+    pub fn ticks(&self) -> bool {
+        self.timeout.is_some()
+            || self.init_timeout.is_some()
+            || self.cpu_timeout.is_some()
+            || self.yield_interval.is_some()
+    }
+}
+
+const COMPILE_FIELDS: &[&str] = &[
+    "opt_level",
+    "debug_info",
+    "parallel_compilation",
+    "compile_threads",
+    "max_wasm_stack",
+    "engine_features",
+    "engine_config",
+    "force_recompile",
+];
+
+/// `compile`: how the component is compiled, and so which engine it needs.
+/// Everything but `compile_threads` and `force_recompile` shapes the engine.
+#[derive(Clone, Copy)]
+pub struct CompileOptions {
+    pub opt_level: Option<OptLevel>,
+    pub debug_info: Option<bool>,
+    pub parallel_compilation: bool,
+    pub compile_threads: Option<usize>,
+    pub max_wasm_stack: Option<usize>,
+    pub features: Option<features::WasmFeatures>,
+    pub memory: Option<meminit::MemoryConfig>,
+    pub force_recompile: bool,
+}
+
+impl CompileOptions {
+    pub fn from_dict(d: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+        let mut compile = CompileOptions {
+            opt_level: None,
+            debug_info: None,
+            parallel_compilation: true,
+            compile_threads: None,
+            max_wasm_stack: None,
+            features: None,
+            memory: None,
+            force_recompile: false,
+        };
+        for (key, value) in fields("compile", d, COMPILE_FIELDS)? {
+            match key.as_str() {
+                "opt_level" => compile.opt_level = Some(OptLevel::parse(&value.extract::<String>()?)?),
+                "debug_info" => compile.debug_info = Some(value.extract()?),
+                "parallel_compilation" => compile.parallel_compilation = value.extract()?,
+                "compile_threads" => compile.compile_threads = Some(check_compile_threads(value.extract()?)?),
+                "max_wasm_stack" => compile.max_wasm_stack = Some(check_max_wasm_stack(value.extract()?)?),
+                "engine_features" => compile.features = Some(features::WasmFeatures::from_dict(value.downcast()?)?),
+                "engine_config" => compile.memory = Some(meminit::MemoryConfig::from_dict(value.downcast()?)?),
+                "force_recompile" => compile.force_recompile = value.extract()?,
+                _ => unreachable!("checked against COMPILE_FIELDS"),
+            }
+        }
+        Ok(compile)
+    }
+
+    /// Set the engine-shaping settings on `opts`, leaving the unset ones.
+    pub fn apply(&self, opts: &mut EngineOptions) {
+        if let Some(level) = self.opt_level {
+            opts.opt_level = level;
+        }
+        if let Some(debug_info) = self.debug_info {
+            opts.debug_info = debug_info;
+        }
+        opts.parallel_compilation = self.parallel_compilation;
+        if self.max_wasm_stack.is_some() {
+            opts.max_wasm_stack = self.max_wasm_stack;
+        }
+        if let Some(features) = self.features {
+            opts.features = features;
+        }
+        if let Some(memory) = self.memory {
+            opts.memory = memory;
+        }
+    }
+
+    /// The settings given that a shared engine has to match, for
+    /// `EngineOptions::check_supports`.
+    pub fn given(&self) -> Vec<&'static str> {
+        [
+            ("opt_level", self.opt_level.is_some()),
+            ("debug_info", self.debug_info.is_some()),
+            ("max_wasm_stack", self.max_wasm_stack.is_some()),
+            ("engine_features", self.features.is_some()),
+            ("engine_config", self.memory.is_some()),
+        ]
+        .into_iter()
+        .filter(|(_, set)| *set)
+        .map(|(name, _)| name)
+        .collect()
+    }
+}
+
+const WASI_FIELDS: &[&str] = &[
+    "env",
+    "args",
+    "preopen_dirs",
+    "virtual_files",
+    "stdin_source",
+    "on_stdout",
+    "on_stderr",
+    "capture_stdout",
+    "max_captured_stdout",
+    "allow_network",
+    "network_allow_list",
+];
+
+/// `wasi`: what the guest's WASI context sees, its environment, files,
+/// stdio and network.
+pub struct WasiOptions {
+    pub env: Vec<(String, String)>,
+    pub args: Vec<String>,
+    pub preopen_dirs: Vec<(String, String, bool)>,
+    pub virtual_files: Option<HashMap<String, Vec<u8>>>,
+    pub stdin_source: Option<PyObject>,
+    pub on_stdout: Option<PyObject>,
+    pub on_stderr: Option<PyObject>,
+    pub capture_stdout: bool,
+    pub max_captured_stdout: usize,
+    pub allow_network: bool,
+    pub network_allow_list: Option<Vec<String>>,
+}
+
+impl WasiOptions {
+    pub fn from_dict(d: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+        let mut wasi = WasiOptions {
+            env: Vec::new(),
+            args: Vec::new(),
+            preopen_dirs: Vec::new(),
+            virtual_files: None,
+            stdin_source: None,
+            on_stdout: None,
+            on_stderr: None,
+            capture_stdout: false,
+            max_captured_stdout: 1024 * 1024,
+            allow_network: false,
+            network_allow_list: None,
+        };
+        for (key, value) in fields("wasi", d, WASI_FIELDS)? {
+            match key.as_str() {
+                "env" => wasi.env = value.extract()?,
+                "args" => wasi.args = value.extract()?,
+                "preopen_dirs" => wasi.preopen_dirs = value.extract()?,
+                "virtual_files" => wasi.virtual_files = Some(value.extract()?),
+                "stdin_source" => wasi.stdin_source = Some(value.unbind()),
+                "on_stdout" => wasi.on_stdout = Some(value.unbind()),
+                "on_stderr" => wasi.on_stderr = Some(value.unbind()),
+                "capture_stdout" => wasi.capture_stdout = value.extract()?,
+                "max_captured_stdout" => wasi.max_captured_stdout = value.extract()?,
+                "allow_network" => wasi.allow_network = value.extract()?,
+                "network_allow_list" => wasi.network_allow_list = Some(value.extract()?),
+                _ => unreachable!("checked against WASI_FIELDS"),
+            }
+        }
+        Ok(wasi)
+    }
+}
+
+const TRANSPORT_FIELDS: &[&str] = &[
+    "length_prefixed",
+    "max_message_size",
+    "codec",
+    "max_inflight_messages",
+    "max_inflight_bytes",
+];
+
+/// `transport`: how messages cross `send_bytes`/`recv_bytes`.
+#[derive(Default)]
+pub struct TransportOptions {
+    pub length_prefixed: bool,
+    pub max_message_size: Option<usize>,
+    pub codec: Option<String>,
+    pub max_inflight_messages: Option<u32>,
+    pub max_inflight_bytes: Option<u32>,
+}
+
+impl TransportOptions {
+    pub fn from_dict(d: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+        let mut transport = TransportOptions::default();
+        for (key, value) in fields("transport", d, TRANSPORT_FIELDS)? {
+            match key.as_str() {
+                "length_prefixed" => transport.length_prefixed = value.extract()?,
+                "max_message_size" => transport.max_message_size = Some(value.extract()?),
+                "codec" => transport.codec = Some(value.extract()?),
+                "max_inflight_messages" => transport.max_inflight_messages = Some(value.extract()?),
+                "max_inflight_bytes" => transport.max_inflight_bytes = Some(value.extract()?),
+                _ => unreachable!("checked against TRANSPORT_FIELDS"),
+            }
+        }
+        Ok(transport)
+    }
+}
+
+const SIGNING_FIELDS: &[&str] = &["public_key", "signature", "signature_path"];
+
+/// `signing`: the key the component must be signed with, and where the
+/// signature is.
+#[derive(Default)]
+pub struct SigningOptions {
+    pub public_key: Option<Vec<u8>>,
+    pub signature: Option<Vec<u8>>,
+    pub signature_path: Option<String>,
+}
+
+impl SigningOptions {
+    pub fn from_dict(d: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+        let mut signing = SigningOptions::default();
+        for (key, value) in fields("signing", d, SIGNING_FIELDS)? {
+            match key.as_str() {
+                "public_key" => signing.public_key = Some(value.extract()?),
+                "signature" => signing.signature = Some(value.extract()?),
+                "signature_path" => signing.signature_path = Some(value.extract()?),
+                _ => unreachable!("checked against SIGNING_FIELDS"),
+            }
+        }
+        Ok(signing)
+    }
+}
+
+const DETERMINISTIC_FIELDS: &[&str] = &["seed", "clock_tick_ns"];
+
+/// `deterministic`: True, or a dict giving the `seed` and `clock_tick_ns`
+/// (virtual time per clock read) of the guest's clocks and randomness.
+pub fn deterministic(value: Option<&Bound<'_, PyAny>>) -> PyResult<Option<Deterministic>> {
+    let Some(value) = value else { return Ok(None) };
+    if let Ok(on) = value.extract::<bool>() {
+        return Ok(on.then(|| Deterministic::new(0, DEFAULT_CLOCK_TICK_NS)));
+    }
+    let (mut seed, mut tick_ns) = (0, DEFAULT_CLOCK_TICK_NS);
+    for (key, value) in fields("deterministic", Some(value.downcast()?), DETERMINISTIC_FIELDS)? {
+        match key.as_str() {
+            "seed" => seed = value.extract()?,
+            "clock_tick_ns" => tick_ns = value.extract()?,
+            _ => unreachable!("checked against DETERMINISTIC_FIELDS"),
+        }
+    }
+    Ok(Some(Deterministic::new(seed, tick_ns)))
+}
@@ -0,0 +1,86 @@
+//! Where a runner's file arguments point. Relative `wasm_path`,
+//! `wasm_compiled_cache`, `precompiled_path` and `signature_path` values,
+//! and the defaults `../env.wasm` and `env.wasm.compiled`, resolve against
+//! `base_dir=`, else `$WASM_RUNNER_BASE_DIR`, else the working directory at
+//! construction. They are resolved once, to absolute paths with the
+//! directory canonicalized, so a later `chdir` does not move them and
+//! errors name the file actually tried.
+
+use pyo3::prelude::*;
+use std::path::PathBuf;
+
+pub(crate) const DEFAULT_WASM: &str = "../env.wasm";
+pub(crate) const DEFAULT_COMPILED: &str = "env.wasm.compiled";
+const BASE_DIR_ENV: &str = "WASM_RUNNER_BASE_DIR";
+
+pub(crate) struct BaseDir(PathBuf);
+
+impl BaseDir {
+    pub fn new(base_dir: Option<String>) -> PyResult<Self> {
+        let (dir, from) = match base_dir {
+            Some(dir) => (PathBuf::from(dir), "base_dir"),
+            None => match std::env::var_os(BASE_DIR_ENV) {
+                Some(dir) if !dir.is_empty() => (PathBuf::from(dir), BASE_DIR_ENV),
+                _ => (std::env::current_dir()?, "the working directory"),
+            },
+        };
+        match dir.canonicalize() {
+            Ok(dir) if dir.is_dir() => Ok(BaseDir(dir)),
+            Ok(_) => Err(pyo3::exceptions::PyNotADirectoryError::new_err(format!(
+                "WasmRunner: {from} {} is not a directory",
+                dir.display()
+            ))),
+            Err(e) => Err(pyo3::exceptions::PyFileNotFoundError::new_err(format!(
+                "WasmRunner: {from} {} cannot be used: {e}",
+                dir.display()
+            ))),
+        }
+    }
+
+    /// `path` made absolute against the base. The directory part is
+    /// canonicalized when it exists; the file itself need not, and a
+    /// symlink to it is kept, so `wasm_path.sig` stays beside the link.
+    pub fn resolve(&self, path: &str) -> String {
+        let joined = self.0.join(path);
+        let resolved = match (joined.parent(), joined.file_name()) {
+            (Some(dir), Some(name)) => dir.canonicalize().map(|dir| dir.join(name)).ok(),
+            _ => None,
+        };
+        resolved.unwrap_or(joined).to_string_lossy().into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Scratch;
+
+    #[test]
+    fn resolves_against_the_base() {
+        let dir = Scratch::new("base");
+        std::fs::create_dir(dir.join("pkg")).unwrap();
+        let base = BaseDir(dir.join("pkg").canonicalize().unwrap());
+        let root = dir.path().canonicalize().unwrap();
+        assert_eq!(base.resolve(DEFAULT_WASM), root.join("env.wasm").to_string_lossy());
+        assert_eq!(base.resolve(DEFAULT_COMPILED), root.join("pkg/env.wasm.compiled").to_string_lossy());
+    }
+
+    #[test]
+    fn keeps_absolute_paths() {
+        let dir = Scratch::new("absolute");
+        let base = BaseDir(std::env::temp_dir());
+        let path = dir.path().canonicalize().unwrap().join("env.wasm");
+        assert_eq!(base.resolve(&path.to_string_lossy()), path.to_string_lossy());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_a_symlinked_file() {
+        let dir = Scratch::new("symlink");
+        std::fs::write(dir.join("real.wasm"), b"").unwrap();
+        std::os::unix::fs::symlink(dir.join("real.wasm"), dir.join("env.wasm")).unwrap();
+        let base = BaseDir(dir.path().canonicalize().unwrap());
+        // so the signature is looked for beside the link, not its target
+        assert!(base.resolve("env.wasm").ends_with("/env.wasm"));
+    }
+}
@@ -0,0 +1,363 @@
+//! A fixed set of runners for one component on one shared engine, kept
+//! instantiated so a request takes a warm runner instead of paying for a
+//! cold instantiation. `acquire()` hands one out and `release()` resets it,
+//! instantiates it again and puts it back, so the cost of the fresh store
+//! falls between requests rather than on the next one.
+//!
+//! The pool never creates runners beyond `size`: once every runner is out,
+//! `acquire()` waits for a `release()`, at most `acquire_timeout_ms`
+//! before raising `WasmPoolExhaustedError`. A runner that is closed while
+//! out is not taken back, leaving the pool one runner smaller.
+//!
+//! With `idle_timeout_ms`, a runner left idle that long is reset, dropping
+//! its instance and store, as long as `min_warm` others stay instantiated;
+//! its next `acquire()` warms it again. This trades the warm start of
+//! rarely needed runners for their memory during quiet periods.
+
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+
+use crate::errors::WasmPoolExhaustedError;
+use crate::shutdown::lock;
+use crate::WasmRunner;
+use crate::engine::EngineHandle;
+
+struct Shared {
+    /* with the time each went idle; taken from the back, so the front idled longest */
+    idle: Mutex<Vec<(Py<WasmRunner>, Instant)>>,
+    /* handed out by acquire() and not yet released */
+    out: Mutex<Vec<Py<WasmRunner>>>,
+    /* one permit per idle runner */
+    ready: Semaphore,
+    /* idle runners reset under idle_timeout_ms */
+    evictions: AtomicU64,
+}
+
+/// `WasmRunnerPool(wasm_path, size, runner_kwargs)`: `size` runners built
+/// as `WasmRunner(**runner_kwargs, wasm_path=wasm_path)`, all on `engine`
+/// or, without one, on an `EngineHandle` made from the engine-shaping
+/// entries of `runner_kwargs`. Runners are constructed up front and
+/// instantiated by `prewarm()`, or otherwise by their first `acquire()`.
+/// Under `mode="sync"` the methods block as the runner's do.
+/// `idle_timeout_ms` and `min_warm` bound how long idle runners stay
+/// instantiated, see the module docs.
+#[pyclass(frozen)]
+pub(crate) struct WasmRunnerPool {
+    shared: Arc<Shared>,
+    size: usize,
+    acquire_timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    min_warm: usize,
+    sync: bool,
+    engine: Py<EngineHandle>,
+}
+
+/// A runner taken from the idle list, put back when dropped unless handed
+/// out. One whose instantiation failed or was cut short is reset first, so
+/// the pool never keeps a half-initialized store.
+struct Lease {
+    shared: Arc<Shared>,
+    runner: Option<Py<WasmRunner>>,
+}
+
+impl Lease {
+    /// Take an idle runner; the caller holds the permit for it.
+    fn take(shared: &Arc<Shared>) -> Self {
+        let (runner, _) = lock(&shared.idle).pop().expect("a ready permit stands for an idle runner");
+        Lease {
+            shared: shared.clone(),
+            runner: Some(runner),
+        }
+    }
+
+    async fn warm(&self) -> PyResult<()> {
+        let runner = self.runner.as_ref().expect("not handed out");
+        let fut = Python::with_gil(|py| runner.borrow(py).instantiation())?;
+        fut.await
+    }
+
+    fn hand_out(mut self) -> Py<WasmRunner> {
+        let runner = self.runner.take().expect("not handed out");
+        Python::with_gil(|py| lock(&self.shared.out).push(runner.clone_ref(py)));
+        runner
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        let Some(runner) = self.runner.take() else { return };
+        // SAFETY: only reads the interpreter's state
+        if unsafe { pyo3::ffi::Py_IsInitialized() } == 0 {
+            return;
+        }
+        let keep = Python::with_gil(|py| {
+            let runner = runner.borrow(py);
+            if *runner.closed.borrow() {
+                return false;
+            }
+            if !runner.instantiated.load(std::sync::atomic::Ordering::Relaxed) {
+                let _ = runner.reset();
+            }
+            true
+        });
+        if keep {
+            lock(&self.shared.idle).push((runner, Instant::now()));
+            self.shared.ready.add_permits(1);
+        }
+    }
+}
+
+#[pymethods]
+impl WasmRunnerPool {
+    #[new]
+    #[pyo3(signature = (
+        wasm_path,
+        size,
+        runner_kwargs,
+        engine=None,
+        acquire_timeout_ms=None,
+        idle_timeout_ms=None,
+        min_warm=0,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        py: Python<'_>,
+        wasm_path: String,
+        size: usize,
+        runner_kwargs: Bound<'_, PyDict>,
+        engine: Option<Py<EngineHandle>>,
+        acquire_timeout_ms: Option<u64>,
+        idle_timeout_ms: Option<u64>,
+        min_warm: usize,
+    ) -> PyResult<Self> {
+        if size == 0 {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "WasmRunnerPool: size must be at least 1",
+            ));
+        }
+        if min_warm > size {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "WasmRunnerPool: min_warm={min_warm} exceeds size={size}"
+            )));
+        }
+        if idle_timeout_ms == Some(0) {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "WasmRunnerPool: idle_timeout_ms must be positive",
+            ));
+        }
+        for key in ["wasm_path", "wasm_bytes", "precompiled_path", "engine"] {
+            if runner_kwargs.contains(key)? {
+                return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "WasmRunnerPool: {key} cannot be set in runner_kwargs; the pool supplies the \
+                     component and the engine"
+                )));
+            }
+        }
+        let engine = match engine {
+            Some(engine) => engine,
+            None => {
+                let config = PyDict::new(py);
+                for key in crate::engine::RUNNER_ENGINE_KWARGS {
+                    if let Some(value) = runner_kwargs.get_item(key)? {
+                        config.set_item(key, value)?;
+                    }
+                }
+                Py::new(py, EngineHandle::new(Some(config))?)?
+            }
+        };
+        let kwargs = runner_kwargs.copy()?;
+        kwargs.set_item("wasm_path", wasm_path)?;
+        kwargs.set_item("engine", engine.clone_ref(py))?;
+        let class = py.get_type::<WasmRunner>();
+        let runners = (0..size)
+            .map(|_| Ok(class.call((), Some(&kwargs))?.downcast_into::<WasmRunner>()?.unbind()))
+            .collect::<PyResult<Vec<_>>>()?;
+        let sync = runners[0].borrow(py).sync;
+        let now = Instant::now();
+        let shared = Arc::new(Shared {
+            idle: Mutex::new(runners.into_iter().map(|runner| (runner, now)).collect()),
+            out: Mutex::default(),
+            ready: Semaphore::new(size),
+            evictions: AtomicU64::new(0),
+        });
+        let idle_timeout = idle_timeout_ms.map(Duration::from_millis);
+        if let Some(timeout) = idle_timeout {
+            let shared = Arc::downgrade(&shared);
+            pyo3_async_runtimes::tokio::get_runtime().spawn(evict_idle(shared, timeout, min_warm));
+        }
+        Ok(WasmRunnerPool {
+            shared,
+            size,
+            acquire_timeout: acquire_timeout_ms.map(Duration::from_millis),
+            idle_timeout,
+            min_warm,
+            sync,
+            engine,
+        })
+    }
+
+    /// Instantiate every idle runner that is not yet, one after another.
+    /// `acquire()` waits meanwhile. Raises the first failure; the runners
+    /// warmed by then stay warm.
+    fn prewarm<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let shared = self.shared.clone();
+        drive(py, self.sync, async move {
+            let n = shared.ready.available_permits();
+            let Ok(permits) = shared.ready.try_acquire_many(n as u32) else {
+                return Ok(());
+            };
+            permits.forget();
+            let leases: Vec<Lease> = (0..n).map(|_| Lease::take(&shared)).collect();
+            for lease in &leases {
+                lease.warm().await?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Resolve to an instantiated runner, waiting while all `size` are out.
+    /// Raises `WasmPoolExhaustedError` if none is released within
+    /// `acquire_timeout_ms` (0 fails at once), and the instantiation error
+    /// if a cold runner fails to start, which then goes back to the pool.
+    fn acquire<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let shared = self.shared.clone();
+        let timeout = self.acquire_timeout;
+        let size = self.size;
+        drive(py, self.sync, async move {
+            let ready = shared.ready.acquire();
+            let permit = match timeout {
+                Some(limit) => tokio::time::timeout(limit, ready).await.map_err(|_| {
+                    WasmPoolExhaustedError::new_err(format!(
+                        "WasmRunnerPool: all {size} runners are in use and none was released \
+                         within {}ms",
+                        limit.as_millis()
+                    ))
+                })?,
+                None => ready.await,
+            };
+            permit.expect("the pool never closes its semaphore").forget();
+            let lease = Lease::take(&shared);
+            lease.warm().await?;
+            Ok(lease.hand_out())
+        })
+    }
+
+    /// Give back a runner from `acquire()`: it is reset, instantiated again
+    /// and made available, so the next `acquire()` gets a fresh warm
+    /// instance. Raises for a runner this pool did not hand out (or that was
+    /// already released) and, leaving it out, while its loop is running. If
+    /// the new instantiation fails the runner is still returned, cold, and
+    /// the error raised.
+    fn release<'py>(&self, py: Python<'py>, runner: Py<WasmRunner>) -> PyResult<Bound<'py, PyAny>> {
+        let mut out = lock(&self.shared.out);
+        let Some(at) = out.iter().position(|r| r.as_ptr() == runner.as_ptr()) else {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "WasmRunnerPool: release() takes a runner acquired from this pool and not yet released",
+            ));
+        };
+        if !*runner.borrow(py).closed.borrow() {
+            runner.borrow(py).reset()?;
+        }
+        out.swap_remove(at);
+        drop(out);
+        let lease = Lease {
+            shared: self.shared.clone(),
+            runner: Some(runner),
+        };
+        drive(py, self.sync, async move { lease.warm().await })
+    }
+
+    /// The most runners the pool holds, in use or not.
+    #[getter]
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Runners ready to be acquired now.
+    #[getter]
+    fn available(&self) -> usize {
+        self.shared.ready.available_permits()
+    }
+
+    /// Occupancy now and evictions so far: `size`, `in_use` (acquired and
+    /// not released), `idle`, `warm` (idle and instantiated), `evictions`
+    /// (idle runners reset under `idle_timeout_ms`), `min_warm` and
+    /// `idle_timeout_ms`.
+    fn stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let (idle, warm) = {
+            let idle = lock(&self.shared.idle);
+            let warm = idle.iter().filter(|(runner, _)| is_warm(&runner.borrow(py))).count();
+            (idle.len(), warm)
+        };
+        let d = PyDict::new(py);
+        d.set_item("size", self.size)?;
+        d.set_item("in_use", lock(&self.shared.out).len())?;
+        d.set_item("idle", idle)?;
+        d.set_item("warm", warm)?;
+        d.set_item("evictions", self.shared.evictions.load(Ordering::Relaxed))?;
+        d.set_item("min_warm", self.min_warm)?;
+        d.set_item("idle_timeout_ms", self.idle_timeout.map(|t| t.as_millis() as u64))?;
+        Ok(d)
+    }
+
+    /// The engine the runners share.
+    #[getter]
+    fn engine(&self, py: Python<'_>) -> Py<EngineHandle> {
+        self.engine.clone_ref(py)
+    }
+}
+
+fn is_warm(runner: &WasmRunner) -> bool {
+    runner.instantiated.load(Ordering::Relaxed)
+}
+
+/// Reset idle runners that have sat warm for `timeout`, oldest first,
+/// keeping `min_warm` warm ones. Checks at a quarter of the timeout, so a
+/// runner is evicted at most a quarter late, and ends with the pool.
+async fn evict_idle(shared: Weak<Shared>, timeout: Duration, min_warm: usize) {
+    let mut ticks = tokio::time::interval(timeout / 4);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        let Some(shared) = shared.upgrade() else { return };
+        // SAFETY: only reads the interpreter's state
+        if unsafe { pyo3::ffi::Py_IsInitialized() } == 0 {
+            return;
+        }
+        Python::with_gil(|py| {
+            let idle = lock(&shared.idle);
+            let mut warm = idle.iter().filter(|(runner, _)| is_warm(&runner.borrow(py))).count();
+            for (runner, since) in idle.iter() {
+                if warm <= min_warm {
+                    break;
+                }
+                let runner = runner.borrow(py);
+                if since.elapsed() < timeout || !is_warm(&runner) {
+                    continue;
+                }
+                if runner.reset().is_ok() {
+                    warm -= 1;
+                    shared.evictions.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        });
+    }
+}
+
+/// Drive a pool call as the runner's methods are driven. The lease resets
+/// a runner the runtime drops mid-instantiation, so there is no runner to
+/// mark torn down.
+fn drive<'py, T>(
+    py: Python<'py>,
+    sync: bool,
+    fut: impl Future<Output = PyResult<T>> + Send + 'static,
+) -> PyResult<Bound<'py, PyAny>>
+where
+    T: for<'a> IntoPyObject<'a> + Send + 'static,
+{
+    crate::drive(py, sync, Arc::default(), fut)
+}
@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+/// Wall-time breakdown of a runner, attributed to phases (`instantiate`,
+/// `run_msg_loop`) and, within each, to guest compute versus time spent in
+/// each host import. Host import time is split into `host;<import>` (running
+/// the Python callback under the GIL) and `wait;<import>` (awaiting the
+/// coroutine it returned). Guest time is whatever remains of the phase.
+///
+/// Output uses the folded-stack format flamegraph tools consume, with
+/// microseconds as the sample count.
+#[derive(Default)]
+pub(crate) struct Profile {
+    totals: HashMap<(&'static str, &'static str), Duration>,
+    phase: Option<Phase>,
+}
+
+struct Phase {
+    name: &'static str,
+    started: Instant,
+    host: Duration,
+}
+
+impl Profile {
+    pub fn begin(&mut self, name: &'static str) {
+        self.phase = Some(Phase {
+            name,
+            started: Instant::now(),
+            host: Duration::ZERO,
+        });
+    }
+
+    pub fn end(&mut self) {
+        if let Some(phase) = self.phase.take() {
+            let guest = phase.started.elapsed().saturating_sub(phase.host);
+            *self.totals.entry((phase.name, "guest")).or_default() += guest;
+        }
+    }
+
+    /// Attribute `spent` to `frame` within the current phase.
+    pub fn record(&mut self, frame: &'static str, spent: Duration) {
+        let name = match &mut self.phase {
+            Some(phase) => {
+                phase.host += spent;
+                phase.name
+            }
+            None => "idle",
+        };
+        *self.totals.entry((name, frame)).or_default() += spent;
+    }
+
+    pub fn folded(&self) -> String {
+        let mut lines: Vec<_> = self.totals.iter().collect();
+        lines.sort();
+        let mut out = String::new();
+        for ((phase, frame), spent) in lines {
+            let _ = writeln!(out, "{phase};{frame} {}", spent.as_micros());
+        }
+        out
+    }
+}
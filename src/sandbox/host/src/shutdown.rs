@@ -0,0 +1,195 @@
+//! Surviving a tokio runtime that shuts down under a pending call. The
+//! runtime then drops the call's future mid-await: under `mode="sync"` the
+//! caller would get a bare join error, and an awaited call's Python future
+//! would never resolve, leaving its awaiter hanging. `guard` catches that
+//! drop and turns it into `WasmShutdownError` for the caller, and marks the
+//! runner so later calls refuse to touch a store left mid-call until
+//! `reset()` replaces it.
+//!
+//! Python's own cancellation also drops the future, but only after
+//! cancelling its Python future, which is how the two are told apart.
+//!
+//! Shared state behind `std::sync` locks goes through `lock`, `read` and
+//! `write`, which take the data of a lock poisoned by a panic elsewhere:
+//! they hold counters, records and buffers, and carrying on with them beats
+//! failing every later call over a panic that was already reported.
+
+use pyo3::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::errors::WasmShutdownError;
+
+const STOPPED: &str = "WasmRunner: the tokio runtime shut down while this call was running";
+
+/// Fails an awaited call's Python future with `WasmShutdownError`, filled
+/// in by `Attach` once `future_into_py` has created it. Returns false when
+/// the future was already done, i.e. Python cancelled the call.
+type Fail = Box<dyn Fn() -> bool + Send + Sync>;
+
+type Slot = Arc<OnceLock<Fail>>;
+
+pub(crate) struct Attach(Slot);
+
+impl Attach {
+    /// Let the guard fail `py_fut` if the runtime drops the call.
+    pub fn attach(self, py_fut: &Bound<'_, PyAny>) -> PyResult<()> {
+        let event_loop = py_fut.call_method0("get_loop")?.unbind();
+        let py_fut = py_fut.clone().unbind();
+        let _ = self.0.set(Box::new(move || fail(&event_loop, &py_fut)));
+        Ok(())
+    }
+}
+
+/// Set `torn_down` and fail the attached Python future if `fut` is dropped
+/// before it finishes without Python having cancelled it.
+pub(crate) fn guard<F: Future>(torn_down: Arc<AtomicBool>, fut: F) -> (impl Future<Output = F::Output>, Attach) {
+    let slot: Slot = Arc::default();
+    let sentry = Sentry {
+        torn_down,
+        slot: slot.clone(),
+        finished: false,
+    };
+    let guarded = async move {
+        let mut sentry = sentry;
+        let out = fut.await;
+        sentry.finished = true;
+        out
+    };
+    (guarded, Attach(slot))
+}
+
+/// The error for an unawaited call whose task the runtime cancelled, and
+/// for later calls on a runner marked `torn_down`.
+pub(crate) fn stopped(after: bool) -> PyErr {
+    if after {
+        WasmShutdownError::new_err(format!(
+            "{STOPPED}; its store may be mid-call, so reset() the runner before using it again"
+        ))
+    } else {
+        WasmShutdownError::new_err(STOPPED)
+    }
+}
+
+struct Sentry {
+    torn_down: Arc<AtomicBool>,
+    slot: Slot,
+    finished: bool,
+}
+
+impl Drop for Sentry {
+    fn drop(&mut self) {
+        // a panic is reported as one, and PanicMark handles the store
+        if self.finished || std::thread::panicking() {
+            return;
+        }
+        // mode="sync": nothing cancels from Python
+        if self.slot.get().is_none_or(|fail| fail()) {
+            self.torn_down.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// `Fail` for the awaited call behind `py_fut`.
+fn fail(event_loop: &PyObject, py_fut: &PyObject) -> bool {
+    // SAFETY: only reads the interpreter's state
+    if unsafe { pyo3::ffi::Py_IsInitialized() } == 0 {
+        return true;
+    }
+    Python::with_gil(|py| {
+        let py_fut = py_fut.bind(py);
+        let done = py_fut.call_method0("done").and_then(|done| done.is_truthy()).unwrap_or(true);
+        if done {
+            return false;
+        }
+        let fail = pyo3::types::PyCFunction::new_closure(py, None, None, |args, _| -> PyResult<()> {
+            let py_fut = args.get_item(0)?;
+            if !py_fut.call_method0("done")?.is_truthy()? {
+                py_fut.call_method1("set_exception", (args.get_item(1)?,))?;
+            }
+            Ok(())
+        });
+        let exc = stopped(false).into_value(py);
+        // a closed loop has no awaiter left to tell
+        let _ = fail.and_then(|fail| event_loop.bind(py).call_method1("call_soon_threadsafe", (fail, py_fut, exc)));
+        true
+    })
+}
+
+pub(crate) fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+pub(crate) fn read<T>(l: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    l.read().unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+pub(crate) fn write<T>(l: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    l.write().unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A runtime of its own, so a test can shut it down.
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_multi_thread().worker_threads(1).enable_all().build().unwrap()
+    }
+
+    #[test]
+    fn a_runtime_shutdown_marks_a_pending_sync_call() {
+        let rt = runtime();
+        let torn_down = Arc::new(AtomicBool::new(false));
+        let (fut, _attach) = guard(torn_down.clone(), std::future::pending::<()>());
+        // as drive() runs a mode="sync" call: on a worker, the caller blocked
+        let handle = rt.handle().clone();
+        let call = std::thread::spawn(move || handle.block_on(handle.spawn(fut)));
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(!torn_down.load(Ordering::Relaxed));
+        rt.shutdown_background();
+        // which drive() reports as WasmShutdownError
+        assert!(call.join().unwrap().unwrap_err().is_cancelled());
+        assert!(torn_down.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn a_finished_call_is_not_a_shutdown() {
+        let rt = runtime();
+        let torn_down = Arc::new(AtomicBool::new(false));
+        let (fut, _attach) = guard(torn_down.clone(), async { 7 });
+        assert_eq!(rt.block_on(rt.spawn(fut)).unwrap(), 7);
+        rt.shutdown_background();
+        assert!(!torn_down.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn lock_takes_the_data_of_a_poisoned_mutex() {
+        let m = Arc::new(Mutex::new(7));
+        let held = m.clone();
+        let _ = std::thread::spawn(move || {
+            let mut guard = held.lock().unwrap();
+            *guard += 1;
+            panic!("under the lock");
+        })
+        .join();
+        assert!(m.is_poisoned());
+        *lock(&m) += 1;
+        assert_eq!(*lock(&m), 9);
+    }
+
+    #[test]
+    fn read_and_write_take_the_data_of_a_poisoned_rwlock() {
+        let l = Arc::new(RwLock::new(vec![1]));
+        let held = l.clone();
+        let _ = std::thread::spawn(move || {
+            let mut guard = held.write().unwrap();
+            guard.push(2);
+            panic!("under the lock");
+        })
+        .join();
+        assert!(l.is_poisoned());
+        write(&l).push(3);
+        assert_eq!(*read(&l), [1, 2, 3]);
+    }
+}
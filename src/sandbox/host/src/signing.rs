@@ -0,0 +1,91 @@
+//! Signed components, for `public_key=`. The component's wasm bytes (after
+//! decompression, as for the compiled cache) must carry a valid ed25519
+//! signature from that key, checked before anything is compiled or taken
+//! from a cache; a cached artifact is never trusted on its own.
+//!
+//! The key is the 32-byte raw public key and the signature the 64-byte raw
+//! detached signature, given as `signature=` or read from `signature_path`
+//! (default `<wasm_path>.sig`). A path is read again on every load, so a
+//! `reload()` of updated wasm needs its updated signature alongside.
+
+use ed25519_dalek::{Signature, VerifyingKey};
+use pyo3::prelude::*;
+
+use crate::errors::WasmSignatureError;
+
+enum SignatureSource {
+    Bytes(Vec<u8>),
+    Path(String),
+}
+
+pub(crate) struct Verifier {
+    key: VerifyingKey,
+    signature: SignatureSource,
+}
+
+impl Verifier {
+    /// Parse the constructor arguments. `wasm_path` is None for
+    /// `wasm_bytes`, which then needs `signature`.
+    pub fn new(
+        public_key: &[u8],
+        signature: Option<Vec<u8>>,
+        signature_path: Option<String>,
+        wasm_path: Option<&str>,
+    ) -> PyResult<Self> {
+        let key = <[u8; 32]>::try_from(public_key)
+            .ok()
+            .and_then(|key| VerifyingKey::from_bytes(&key).ok())
+            .ok_or_else(|| {
+                pyo3::exceptions::PyValueError::new_err(format!(
+                    "WasmRunner: public_key must be a 32-byte ed25519 public key, got {} bytes",
+                    public_key.len()
+                ))
+            })?;
+        let signature = match (signature, signature_path, wasm_path) {
+            (Some(_), Some(_), _) => {
+                return Err(pyo3::exceptions::PyValueError::new_err(
+                    "WasmRunner: give signature or signature_path, not both",
+                ));
+            }
+            (Some(bytes), None, _) => SignatureSource::Bytes(bytes),
+            (None, Some(path), _) => SignatureSource::Path(path),
+            (None, None, Some(wasm_path)) => SignatureSource::Path(format!("{wasm_path}.sig")),
+            (None, None, None) => {
+                return Err(pyo3::exceptions::PyValueError::new_err(
+                    "WasmRunner: public_key with wasm_bytes needs a signature",
+                ));
+            }
+        };
+        Ok(Verifier { key, signature })
+    }
+
+    /// Refuse `wasm` unless the signature matches it.
+    pub fn verify(&self, wasm: &[u8]) -> PyResult<()> {
+        let raw = match &self.signature {
+            SignatureSource::Bytes(bytes) => bytes.clone(),
+            SignatureSource::Path(path) => std::fs::read(path).map_err(|e| {
+                WasmSignatureError::new_err(format!("WasmRunner: cannot read component signature {path:?}: {e}"))
+            })?,
+        };
+        let signature = Signature::from_slice(&raw).map_err(|_| {
+            WasmSignatureError::new_err(format!(
+                "WasmRunner: component signature{} must be 64 raw bytes, got {}",
+                self.origin(),
+                raw.len()
+            ))
+        })?;
+        self.key.verify_strict(wasm, &signature).map_err(|_| {
+            WasmSignatureError::new_err(format!(
+                "WasmRunner: component signature{} does not match; refusing to load it",
+                self.origin()
+            ))
+        })
+    }
+
+    fn origin(&self) -> String {
+        match &self.signature {
+            SignatureSource::Bytes(_) => String::new(),
+            SignatureSource::Path(path) => format!(" {path:?}"),
+        }
+    }
+}
@@ -0,0 +1,439 @@
+use pyo3::prelude::*;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use wasmtime_wasi::cli::{IsTerminal, StdinStream, StdoutStream};
+use wasmtime_wasi_io::bytes::Bytes;
+use wasmtime_wasi_io::poll::Pollable;
+use wasmtime_wasi_io::streams::{InputStream, OutputStream, StreamError, StreamResult};
+
+/// Guest stdout/stderr forwarded to a Python callable, one call per
+/// complete line (newline included). A trailing partial line is delivered
+/// when the guest flushes. Callback errors go to `sys.unraisablehook`, since
+/// failing the guest's write would only surface as a confusing WASI error.
+#[derive(Clone)]
+pub(crate) struct PyOutput {
+    cb: Arc<PyObject>,
+    pending: Arc<Mutex<Vec<u8>>>,
+    blocking: bool,
+}
+
+impl PyOutput {
+    pub fn new(cb: PyObject, blocking: bool) -> Self {
+        PyOutput {
+            cb: Arc::new(cb),
+            pending: Arc::new(Mutex::new(Vec::new())),
+            blocking,
+        }
+    }
+
+    fn write(&self, bytes: &[u8]) {
+        let mut pending = crate::shutdown::lock(&self.pending);
+        pending.extend_from_slice(bytes);
+        if let Some(end) = pending.iter().rposition(|&b| b == b'\n') {
+            let lines: Vec<u8> = pending.drain(..=end).collect();
+            drop(pending);
+            for line in lines.split_inclusive(|&b| b == b'\n') {
+                self.deliver(line);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        let rest = std::mem::take(&mut *crate::shutdown::lock(&self.pending));
+        if !rest.is_empty() {
+            self.deliver(&rest);
+        }
+    }
+
+    fn deliver(&self, chunk: &[u8]) {
+        crate::with_gil_maybe_blocking(self.blocking, |py| {
+            let cb = self.cb.bind(py);
+            if let Err(e) = cb.call1((pyo3::types::PyBytes::new(py, chunk),)) {
+                e.write_unraisable(py, Some(cb));
+            }
+        });
+    }
+}
+
+impl IsTerminal for PyOutput {
+    fn is_terminal(&self) -> bool {
+        false
+    }
+}
+
+impl StdoutStream for PyOutput {
+    fn p2_stream(&self) -> Box<dyn OutputStream> {
+        Box::new(self.clone())
+    }
+
+    fn async_stream(&self) -> Box<dyn AsyncWrite + Send + Sync> {
+        Box::new(self.clone())
+    }
+}
+
+#[wasmtime_wasi_io::async_trait]
+impl Pollable for PyOutput {
+    async fn ready(&mut self) {}
+}
+
+impl OutputStream for PyOutput {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        PyOutput::write(self, &bytes);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        PyOutput::flush(self);
+        Ok(())
+    }
+
+    fn check_write(&mut self) -> StreamResult<usize> {
+        Ok(64 * 1024)
+    }
+}
+
+impl AsyncWrite for PyOutput {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        PyOutput::write(&self, buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        PyOutput::flush(&self);
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+/// Guest stdout kept in memory for `take_stdout()`, under
+/// `capture_stdout=True`. At most `limit` bytes are held: once the buffer is
+/// full, further output is discarded (and counted) until `take_stdout()`
+/// empties it, so a guest that prints without end costs a bounded amount of
+/// memory and the text kept is the start of what it printed. Shared by
+/// every store, so output from before a `reset()` is kept until taken.
+#[derive(Clone)]
+pub(crate) struct Captured {
+    inner: Arc<Mutex<Capture>>,
+    limit: usize,
+}
+
+#[derive(Default)]
+struct Capture {
+    buf: Vec<u8>,
+    dropped: u64,
+}
+
+impl Captured {
+    pub fn new(limit: usize) -> Self {
+        Captured {
+            inner: Arc::default(),
+            limit,
+        }
+    }
+
+    fn write(&self, bytes: &[u8]) {
+        let mut inner = crate::shutdown::lock(&self.inner);
+        let room = self.limit.saturating_sub(inner.buf.len());
+        let (kept, rest) = bytes.split_at(room.min(bytes.len()));
+        inner.buf.extend_from_slice(kept);
+        inner.dropped += rest.len() as u64;
+    }
+
+    /// The text held, decoded lossily, and the number of bytes discarded
+    /// since the last take; both are cleared. A multi-byte character cut off
+    /// at the end of the buffer stays for the next take, so output taken
+    /// mid-write is not turned into replacement characters.
+    pub fn take(&self) -> (String, u64) {
+        let mut inner = crate::shutdown::lock(&self.inner);
+        let end = match std::str::from_utf8(&inner.buf) {
+            Err(e) if e.error_len().is_none() && inner.dropped == 0 => e.valid_up_to(),
+            _ => inner.buf.len(),
+        };
+        let text = String::from_utf8_lossy(&inner.buf[..end]).into_owned();
+        inner.buf.drain(..end);
+        (text, std::mem::take(&mut inner.dropped))
+    }
+}
+
+impl IsTerminal for Captured {
+    fn is_terminal(&self) -> bool {
+        false
+    }
+}
+
+impl StdoutStream for Captured {
+    fn p2_stream(&self) -> Box<dyn OutputStream> {
+        Box::new(self.clone())
+    }
+
+    fn async_stream(&self) -> Box<dyn AsyncWrite + Send + Sync> {
+        Box::new(self.clone())
+    }
+}
+
+#[wasmtime_wasi_io::async_trait]
+impl Pollable for Captured {
+    async fn ready(&mut self) {}
+}
+
+impl OutputStream for Captured {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        Captured::write(self, &bytes);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        Ok(())
+    }
+
+    fn check_write(&mut self) -> StreamResult<usize> {
+        Ok(64 * 1024)
+    }
+}
+
+impl AsyncWrite for Captured {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Captured::write(&self, buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Guest stdin read from a Python source: `bytes` (the whole input), an
+/// async iterable or a plain iterable of bytes-like chunks. Chunks are
+/// pulled only as the guest reads, on the guest's task, so an async source
+/// runs on the runner's event loop. Exhausting the source is end of file;
+/// an exception from it fails the guest's read with its message, and
+/// then the stream is closed. Every store (after `reset()` too) reads the
+/// same source, continuing where the last one stopped.
+#[derive(Clone)]
+pub(crate) struct PyInput {
+    inner: Arc<Mutex<Input>>,
+    blocking: bool,
+}
+
+type Pull = Pin<Box<dyn Future<Output = PyResult<Option<Vec<u8>>>> + Send>>;
+
+enum Source {
+    Async(PyObject),
+    Sync(PyObject),
+    Done,
+}
+
+struct Input {
+    source: Source,
+    buf: VecDeque<u8>,
+    /* the chunk being fetched, kept when a poll gives up so no chunk is lost */
+    pull: Option<Pull>,
+    error: Option<wasmtime::Error>,
+}
+
+impl PyInput {
+    pub fn new(source: &Bound<'_, PyAny>, blocking: bool) -> PyResult<Self> {
+        let mut buf = VecDeque::new();
+        let source = if source.hasattr("__aiter__")? {
+            Source::Async(source.call_method0("__aiter__")?.unbind())
+        } else if source.downcast::<pyo3::types::PyBytes>().is_ok()
+            || pyo3::buffer::PyBuffer::<u8>::get(source).is_ok()
+        {
+            buf.extend(crate::extract_payload(source)?);
+            Source::Done
+        } else {
+            Source::Sync(source.try_iter()?.into_any().unbind())
+        };
+        Ok(PyInput {
+            inner: Arc::new(Mutex::new(Input {
+                source,
+                buf,
+                pull: None,
+                error: None,
+            })),
+            blocking,
+        })
+    }
+
+    /// Start fetching the next chunk; None once the source is exhausted.
+    fn next(&self, source: &Source) -> PyResult<Option<Pull>> {
+        crate::with_gil_maybe_blocking(self.blocking, |py| match source {
+            Source::Async(it) => {
+                let fut = pyo3_async_runtimes::tokio::into_future(it.bind(py).call_method0("__anext__")?)?;
+                let blocking = self.blocking;
+                Ok(Some(Box::pin(async move {
+                    let res = fut.await;
+                    crate::with_gil_maybe_blocking(blocking, |py| match res {
+                        Ok(chunk) => crate::extract_payload(chunk.bind(py)).map(Some),
+                        Err(e) if e.is_instance_of::<pyo3::exceptions::PyStopAsyncIteration>(py) => Ok(None),
+                        Err(e) => Err(e),
+                    })
+                }) as Pull))
+            }
+            Source::Sync(it) => {
+                let chunk = match it.bind(py).call_method0("__next__") {
+                    Ok(chunk) => crate::extract_payload(&chunk).map(Some),
+                    Err(e) if e.is_instance_of::<pyo3::exceptions::PyStopIteration>(py) => Ok(None),
+                    Err(e) => Err(e),
+                };
+                Ok(Some(Box::pin(std::future::ready(chunk)) as Pull))
+            }
+            Source::Done => Ok(None),
+        })
+    }
+
+    /// Ready once there is something to read, the source is exhausted or
+    /// it failed.
+    fn poll_fill(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut inner = crate::shutdown::lock(&self.inner);
+        loop {
+            if !inner.buf.is_empty() || matches!(inner.source, Source::Done) || inner.error.is_some() {
+                return Poll::Ready(());
+            }
+            if inner.pull.is_none() {
+                match self.next(&inner.source) {
+                    Ok(Some(pull)) => inner.pull = Some(pull),
+                    Ok(None) => inner.source = Source::Done,
+                    Err(e) => inner.fail(e),
+                }
+                continue;
+            }
+            let res = match inner.pull.as_mut().unwrap().as_mut().poll(cx) {
+                Poll::Ready(res) => res,
+                Poll::Pending => return Poll::Pending,
+            };
+            inner.pull = None;
+            match res {
+                Ok(Some(chunk)) => inner.buf.extend(chunk),
+                Ok(None) => inner.source = Source::Done,
+                Err(e) => inner.fail(e),
+            }
+        }
+    }
+
+    fn take(&self, size: usize) -> StreamResult<Bytes> {
+        let mut inner = crate::shutdown::lock(&self.inner);
+        if inner.buf.is_empty() {
+            if let Some(e) = inner.error.take() {
+                return Err(StreamError::LastOperationFailed(e));
+            }
+            if matches!(inner.source, Source::Done) {
+                return Err(StreamError::Closed);
+            }
+        }
+        let n = size.min(inner.buf.len());
+        Ok(inner.buf.drain(..n).collect::<Vec<u8>>().into())
+    }
+}
+
+impl Input {
+    fn fail(&mut self, e: PyErr) {
+        self.error = Some(crate::pyerr_to_wasmtime_err(e).context("WasmRunner: stdin source failed"));
+        self.source = Source::Done;
+    }
+}
+
+impl IsTerminal for PyInput {
+    fn is_terminal(&self) -> bool {
+        false
+    }
+}
+
+impl StdinStream for PyInput {
+    fn p2_stream(&self) -> Box<dyn InputStream> {
+        Box::new(self.clone())
+    }
+
+    fn async_stream(&self) -> Box<dyn AsyncRead + Send + Sync> {
+        Box::new(self.clone())
+    }
+}
+
+#[wasmtime_wasi_io::async_trait]
+impl Pollable for PyInput {
+    async fn ready(&mut self) {
+        std::future::poll_fn(|cx| self.poll_fill(cx)).await
+    }
+}
+
+impl InputStream for PyInput {
+    fn read(&mut self, size: usize) -> StreamResult<Bytes> {
+        self.take(size)
+    }
+}
+
+impl AsyncRead for PyInput {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        if self.poll_fill(cx).is_pending() {
+            return Poll::Pending;
+        }
+        match self.take(buf.remaining()) {
+            Ok(bytes) => buf.put_slice(&bytes),
+            Err(StreamError::Closed) => {}
+            Err(StreamError::LastOperationFailed(e) | StreamError::Trap(e)) => {
+                return Poll::Ready(Err(std::io::Error::other(format!("{e:#}"))));
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_empties_the_buffer() {
+        let out = Captured::new(64);
+        out.write(b"hello ");
+        out.write(b"world\n");
+        assert_eq!(out.take(), ("hello world\n".to_string(), 0));
+        assert_eq!(out.take(), (String::new(), 0));
+    }
+
+    #[test]
+    fn keeps_the_start_and_counts_the_rest() {
+        let out = Captured::new(4);
+        out.write(b"abcdef");
+        out.write(b"gh");
+        assert_eq!(out.take(), ("abcd".to_string(), 4));
+        out.write(b"ij");
+        assert_eq!(out.take(), ("ij".to_string(), 0));
+    }
+
+    #[test]
+    fn split_character_waits_for_the_next_take() {
+        let out = Captured::new(64);
+        let snow = "snow\u{2744}".as_bytes();
+        out.write(&snow[..snow.len() - 1]);
+        assert_eq!(out.take(), ("snow".to_string(), 0));
+        out.write(&snow[snow.len() - 1..]);
+        assert_eq!(out.take(), ("\u{2744}".to_string(), 0));
+    }
+
+    #[test]
+    fn truncated_character_is_not_held_back() {
+        // nothing more can arrive once output is being dropped
+        let out = Captured::new(5);
+        out.write("snow\u{2744}".as_bytes());
+        assert_eq!(out.take(), ("snow\u{fffd}".to_string(), 2));
+    }
+}
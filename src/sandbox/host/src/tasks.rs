@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
+
+use crate::limits::Metrics;
+
+pub(crate) type TaskResult = Result<Vec<u8>, String>;
+
+/// Host-side tasks started by the guest through `spawn-task`. Concurrency is
+/// capped by a semaphore: a spawn that cannot get a permit is rejected rather
+/// than queued, so the guest sees the limit immediately.
+pub(crate) struct Tasks {
+    permits: Arc<Semaphore>,
+    max: usize,
+    live: HashMap<u64, JoinHandle<TaskResult>>,
+    next_handle: u64,
+    metrics: Arc<Metrics>,
+}
+
+impl Tasks {
+    pub fn new(max_concurrent: usize, metrics: Arc<Metrics>) -> Self {
+        Tasks {
+            permits: Arc::new(Semaphore::new(max_concurrent)),
+            max: max_concurrent,
+            live: HashMap::new(),
+            next_handle: 0,
+            metrics,
+        }
+    }
+
+    pub fn try_permit(&self) -> Result<OwnedSemaphorePermit, String> {
+        self.permits
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| format!("WasmRunner: max_concurrent_tasks ({}) reached", self.max))
+    }
+
+    /// Spawn `fut` on the runtime, holding `permit` until it completes.
+    pub fn spawn<F>(&mut self, permit: OwnedSemaphorePermit, fut: F) -> u64
+    where
+        F: Future<Output = TaskResult> + Send + 'static,
+    {
+        let live = LiveTask::new(self.metrics.clone(), permit);
+        let join = tokio::spawn(async move {
+            let _live = live;
+            fut.await
+        });
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        self.live.insert(handle, join);
+        handle
+    }
+
+    pub fn take(&mut self, handle: u64) -> Option<JoinHandle<TaskResult>> {
+        self.live.remove(&handle)
+    }
+}
+
+/// Counts a task as live from spawn until it completes or is aborted.
+struct LiveTask {
+    metrics: Arc<Metrics>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl LiveTask {
+    fn new(metrics: Arc<Metrics>, permit: OwnedSemaphorePermit) -> Self {
+        metrics.live_tasks.fetch_add(1, Ordering::Relaxed);
+        LiveTask { metrics, _permit: permit }
+    }
+}
+
+impl Drop for LiveTask {
+    fn drop(&mut self) {
+        self.metrics.live_tasks.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Tasks {
+    fn drop(&mut self) {
+        for (_, join) in self.live.drain() {
+            join.abort();
+        }
+    }
+}
@@ -0,0 +1,46 @@
+//! Helpers for the unit tests: scratch directories and minimal components.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A fresh directory under the system temp dir, removed on drop.
+pub(crate) struct Scratch(PathBuf);
+
+impl Scratch {
+    pub fn new(name: &str) -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let dir = std::env::temp_dir().join(format!("host-test-{}-{n}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Scratch(dir)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// The binary of an empty component, with a custom section holding `tag`
+/// so components with different tags differ in their bytes only.
+pub(crate) fn component(tag: &str) -> Vec<u8> {
+    assert!(tag.len() < 127, "tag must fit a one-byte section size");
+    let mut bytes = b"\0asm\x0d\0\x01\0".to_vec();
+    // custom section: id 0, size, then the name "tag" and the payload
+    bytes.push(0);
+    bytes.push((1 + 3 + tag.len()) as u8);
+    bytes.push(3);
+    bytes.extend_from_slice(b"tag");
+    bytes.extend_from_slice(tag.as_bytes());
+    bytes
+}
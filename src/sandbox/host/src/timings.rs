@@ -0,0 +1,55 @@
+//! Where a runner's cold start went, for `WasmRunner.startup_timings()`:
+//! monotonic durations of loading the component (`read_bytes`, then
+//! `deserialize` for a cached or precompiled artifact, or `precompile` and
+//! `compile` when it had to be built) and of the latest instantiation
+//! (`instantiate`, then `init_exec_env`). Phases that did not run are
+//! absent, e.g. every loading phase but `read_bytes` when an `EngineHandle`
+//! already held the component.
+
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::time::{Duration, Instant};
+
+/// The phases of loading the component, all replaced by a reload.
+const LOAD: &[&str] = &["read_bytes", "precompile", "deserialize", "compile"];
+
+#[derive(Default)]
+pub(crate) struct StartupTimings {
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl StartupTimings {
+    /// Run `f` as `phase`.
+    pub fn time<T>(&mut self, phase: &'static str, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let out = f();
+        self.record(phase, started.elapsed());
+        out
+    }
+
+    /// Keep `took` for `phase`, in place of any earlier timing of it.
+    pub fn record(&mut self, phase: &'static str, took: Duration) {
+        self.phases.retain(|(p, _)| *p != phase);
+        self.phases.push((phase, took));
+    }
+
+    /// Take a reload's loading phases in place of the old ones.
+    pub fn replace_load(&mut self, load: StartupTimings) {
+        self.phases.retain(|(p, _)| !LOAD.contains(p));
+        self.phases.extend(load.phases);
+    }
+
+    /// Whether `phase` was timed.
+    #[cfg(test)]
+    pub fn ran(&self, phase: &str) -> bool {
+        self.phases.iter().any(|(p, _)| *p == phase)
+    }
+
+    pub fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let d = PyDict::new(py);
+        for (phase, took) in &self.phases {
+            d.set_item(phase, took.as_secs_f64() * 1000.0)?;
+        }
+        Ok(d)
+    }
+}
@@ -0,0 +1,415 @@
+//! A read-only in-memory filesystem, for `virtual_files=`: a dict of guest
+//! path to bytes, mounted at `/` in place of the host filesystem. Directories
+//! are implied by the paths, so `{"data/a.txt": b"..."}` also gives the guest
+//! a `/data` to list. The files are copied once at construction and shared
+//! by every store the runner builds.
+//!
+//! Only `wasi:filesystem` is replaced; the rest of WASI is still
+//! wasmtime-wasi's. Anything that would change the tree (opening for write,
+//! creating, truncating, renaming, removing, setting times) fails in the
+//! guest with `read-only`.
+
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use wasmtime::component::{HasSelf, Linker, Resource};
+use wasmtime_wasi_io::streams::{DynInputStream, DynOutputStream};
+
+use pyo3::prelude::*;
+
+mod bindings {
+    wasmtime::component::bindgen!({
+        path: "wit-vfs",
+        world: "exec:vfs/vfs",
+        require_store_data_send: true,
+        with: {
+            "wasi:io/poll": wasmtime_wasi_io::bindings::wasi::io::poll,
+            "wasi:io/streams": wasmtime_wasi_io::bindings::wasi::io::streams,
+            "wasi:io/error": wasmtime_wasi_io::bindings::wasi::io::error,
+            "wasi:clocks/wall-clock": wasmtime_wasi::p2::bindings::clocks::wall_clock,
+            "wasi:filesystem/types.descriptor": super::Descriptor,
+            "wasi:filesystem/types.directory-entry-stream": super::DirEntries,
+        },
+    });
+}
+
+use bindings::wasi::filesystem::{preopens, types};
+use types::{
+    Advice, DescriptorFlags, DescriptorStat, DescriptorType, DirectoryEntry, ErrorCode, Filesize,
+    MetadataHashValue, NewTimestamp, OpenFlags, PathFlags,
+};
+
+use crate::Ctx;
+
+/// The files, keyed by normalized path (no leading `/`, no `.` or `..`).
+pub(crate) struct VirtualFs {
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+impl VirtualFs {
+    pub fn new(files: Vec<(String, Vec<u8>)>) -> PyResult<Self> {
+        let invalid = |path: &str, why: &str| {
+            pyo3::exceptions::PyValueError::new_err(format!("WasmRunner: virtual_files path {path:?} {why}"))
+        };
+        let mut normalized = BTreeMap::new();
+        for (path, bytes) in files {
+            let parts: Vec<&str> = path.split('/').filter(|p| !p.is_empty() && *p != ".").collect();
+            if parts.is_empty() {
+                return Err(invalid(&path, "names no file"));
+            }
+            if parts.contains(&"..") {
+                return Err(invalid(&path, "must not contain .."));
+            }
+            if normalized.insert(parts.join("/"), bytes).is_some() {
+                return Err(invalid(&path, "is given twice"));
+            }
+        }
+        // a path cannot be both a file and the directory of another
+        for path in normalized.keys() {
+            if normalized.range(format!("{path}/")..).next().is_some_and(|(k, _)| k.starts_with(&format!("{path}/"))) {
+                return Err(invalid(path, "is a file and also a directory of other files"));
+            }
+        }
+        Ok(VirtualFs { files: normalized })
+    }
+
+    fn is_dir(&self, path: &str) -> bool {
+        if path.is_empty() {
+            return true;
+        }
+        let prefix = format!("{path}/");
+        self.files.range(prefix.clone()..).next().is_some_and(|(k, _)| k.starts_with(&prefix))
+    }
+
+    fn kind(&self, path: &str) -> Option<Kind> {
+        if self.files.contains_key(path) {
+            Some(Kind::File)
+        } else if self.is_dir(path) {
+            Some(Kind::Dir)
+        } else {
+            None
+        }
+    }
+
+    /// The immediate children of directory `path`, in name order.
+    fn children(&self, path: &str) -> VecDeque<DirectoryEntry> {
+        let prefix = if path.is_empty() { String::new() } else { format!("{path}/") };
+        let mut entries: VecDeque<DirectoryEntry> = VecDeque::new();
+        for key in self.files.range(prefix.clone()..).map(|(k, _)| k).take_while(|k| k.starts_with(&prefix)) {
+            let rest = &key[prefix.len()..];
+            let (name, type_) = match rest.split_once('/') {
+                Some((dir, _)) => (dir, DescriptorType::Directory),
+                None => (rest, DescriptorType::RegularFile),
+            };
+            if entries.back().is_none_or(|last| last.name != name) {
+                entries.push_back(DirectoryEntry {
+                    type_,
+                    name: name.to_string(),
+                });
+            }
+        }
+        entries
+    }
+
+    /// Register `wasi:filesystem` over the one `add_to_linker_async` installed.
+    pub fn add_to_linker(linker: &mut Linker<Ctx>) -> wasmtime::Result<()> {
+        linker.allow_shadowing(true);
+        types::add_to_linker::<Ctx, HasSelf<Ctx>>(linker, |ctx| ctx)?;
+        preopens::add_to_linker::<Ctx, HasSelf<Ctx>>(linker, |ctx| ctx)?;
+        linker.allow_shadowing(false);
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    File,
+    Dir,
+}
+
+pub struct Descriptor {
+    fs: Arc<VirtualFs>,
+    path: String,
+    kind: Kind,
+}
+
+pub struct DirEntries(VecDeque<DirectoryEntry>);
+
+impl Descriptor {
+    /// Resolve `path` relative to this directory. Absolute paths and `..`
+    /// above the root are refused, as WASI requires.
+    fn resolve(&self, path: &str) -> Result<String, ErrorCode> {
+        if self.kind != Kind::Dir {
+            return Err(ErrorCode::NotDirectory);
+        }
+        if path.starts_with('/') {
+            return Err(ErrorCode::NotPermitted);
+        }
+        let mut parts: Vec<&str> = self.path.split('/').filter(|p| !p.is_empty()).collect();
+        for part in path.split('/') {
+            match part {
+                "" | "." => {}
+                ".." => {
+                    parts.pop().ok_or(ErrorCode::NotPermitted)?;
+                }
+                part => parts.push(part),
+            }
+        }
+        Ok(parts.join("/"))
+    }
+
+    fn stat_of(&self, path: &str) -> Result<DescriptorStat, ErrorCode> {
+        let (type_, size) = match self.fs.kind(path).ok_or(ErrorCode::NoEntry)? {
+            Kind::File => (DescriptorType::RegularFile, self.fs.files[path].len() as u64),
+            Kind::Dir => (DescriptorType::Directory, 0),
+        };
+        Ok(DescriptorStat {
+            type_,
+            link_count: 1,
+            size,
+            data_access_timestamp: None,
+            data_modification_timestamp: None,
+            status_change_timestamp: None,
+        })
+    }
+
+    fn contents(&self) -> Result<&[u8], ErrorCode> {
+        match self.kind {
+            Kind::File => Ok(&self.fs.files[&self.path]),
+            Kind::Dir => Err(ErrorCode::IsDirectory),
+        }
+    }
+}
+
+fn hash_of(path: &str) -> MetadataHashValue {
+    let digest = Sha256::digest(path.as_bytes());
+    MetadataHashValue {
+        lower: u64::from_le_bytes(digest[..8].try_into().unwrap()),
+        upper: u64::from_le_bytes(digest[8..16].try_into().unwrap()),
+    }
+}
+
+fn tail(bytes: &[u8], offset: Filesize) -> &[u8] {
+    &bytes[usize::try_from(offset).unwrap_or(usize::MAX).min(bytes.len())..]
+}
+
+impl Ctx {
+    fn descriptor(&self, fd: &Resource<Descriptor>) -> Result<&Descriptor, ErrorCode> {
+        self.table.get(fd).map_err(|_| ErrorCode::BadDescriptor)
+    }
+}
+
+impl types::Host for Ctx {
+    fn filesystem_error_code(&mut self, _err: Resource<wasmtime_wasi_io::streams::Error>) -> Option<ErrorCode> {
+        // the only streams this filesystem hands out are in-memory and never fail
+        None
+    }
+}
+
+impl types::HostDescriptor for Ctx {
+    fn read_via_stream(&mut self, fd: Resource<Descriptor>, offset: Filesize) -> Result<Resource<DynInputStream>, ErrorCode> {
+        let bytes = tail(self.descriptor(&fd)?.contents()?, offset).to_vec();
+        let stream: DynInputStream = Box::new(wasmtime_wasi::p2::pipe::MemoryInputPipe::new(bytes));
+        self.table.push(stream).map_err(|_| ErrorCode::InsufficientMemory)
+    }
+
+    fn write_via_stream(&mut self, _fd: Resource<Descriptor>, _offset: Filesize) -> Result<Resource<DynOutputStream>, ErrorCode> {
+        Err(ErrorCode::ReadOnly)
+    }
+
+    fn append_via_stream(&mut self, _fd: Resource<Descriptor>) -> Result<Resource<DynOutputStream>, ErrorCode> {
+        Err(ErrorCode::ReadOnly)
+    }
+
+    fn advise(&mut self, fd: Resource<Descriptor>, _offset: Filesize, _length: Filesize, _advice: Advice) -> Result<(), ErrorCode> {
+        self.descriptor(&fd).map(|_| ())
+    }
+
+    fn sync_data(&mut self, fd: Resource<Descriptor>) -> Result<(), ErrorCode> {
+        self.descriptor(&fd).map(|_| ())
+    }
+
+    fn get_flags(&mut self, fd: Resource<Descriptor>) -> Result<DescriptorFlags, ErrorCode> {
+        self.descriptor(&fd).map(|_| DescriptorFlags::READ)
+    }
+
+    fn get_type(&mut self, fd: Resource<Descriptor>) -> Result<DescriptorType, ErrorCode> {
+        Ok(match self.descriptor(&fd)?.kind {
+            Kind::File => DescriptorType::RegularFile,
+            Kind::Dir => DescriptorType::Directory,
+        })
+    }
+
+    fn set_size(&mut self, _fd: Resource<Descriptor>, _size: Filesize) -> Result<(), ErrorCode> {
+        Err(ErrorCode::ReadOnly)
+    }
+
+    fn set_times(&mut self, _fd: Resource<Descriptor>, _atime: NewTimestamp, _mtime: NewTimestamp) -> Result<(), ErrorCode> {
+        Err(ErrorCode::ReadOnly)
+    }
+
+    fn read(&mut self, fd: Resource<Descriptor>, length: Filesize, offset: Filesize) -> Result<(Vec<u8>, bool), ErrorCode> {
+        let rest = tail(self.descriptor(&fd)?.contents()?, offset);
+        let len = usize::try_from(length).unwrap_or(usize::MAX).min(rest.len());
+        Ok((rest[..len].to_vec(), len == rest.len()))
+    }
+
+    fn write(&mut self, _fd: Resource<Descriptor>, _buffer: Vec<u8>, _offset: Filesize) -> Result<Filesize, ErrorCode> {
+        Err(ErrorCode::ReadOnly)
+    }
+
+    fn read_directory(&mut self, fd: Resource<Descriptor>) -> Result<Resource<DirEntries>, ErrorCode> {
+        let dir = self.descriptor(&fd)?;
+        if dir.kind != Kind::Dir {
+            return Err(ErrorCode::NotDirectory);
+        }
+        let entries = DirEntries(dir.fs.children(&dir.path));
+        self.table.push(entries).map_err(|_| ErrorCode::InsufficientMemory)
+    }
+
+    fn sync(&mut self, fd: Resource<Descriptor>) -> Result<(), ErrorCode> {
+        self.descriptor(&fd).map(|_| ())
+    }
+
+    fn create_directory_at(&mut self, _fd: Resource<Descriptor>, _path: String) -> Result<(), ErrorCode> {
+        Err(ErrorCode::ReadOnly)
+    }
+
+    fn stat(&mut self, fd: Resource<Descriptor>) -> Result<DescriptorStat, ErrorCode> {
+        let fd = self.descriptor(&fd)?;
+        fd.stat_of(&fd.path)
+    }
+
+    fn stat_at(&mut self, fd: Resource<Descriptor>, _path_flags: PathFlags, path: String) -> Result<DescriptorStat, ErrorCode> {
+        let fd = self.descriptor(&fd)?;
+        fd.stat_of(&fd.resolve(&path)?)
+    }
+
+    fn set_times_at(
+        &mut self,
+        _fd: Resource<Descriptor>,
+        _path_flags: PathFlags,
+        _path: String,
+        _atime: NewTimestamp,
+        _mtime: NewTimestamp,
+    ) -> Result<(), ErrorCode> {
+        Err(ErrorCode::ReadOnly)
+    }
+
+    fn link_at(
+        &mut self,
+        _fd: Resource<Descriptor>,
+        _old_path_flags: PathFlags,
+        _old_path: String,
+        _new_descriptor: Resource<Descriptor>,
+        _new_path: String,
+    ) -> Result<(), ErrorCode> {
+        Err(ErrorCode::ReadOnly)
+    }
+
+    fn open_at(
+        &mut self,
+        fd: Resource<Descriptor>,
+        _path_flags: PathFlags,
+        path: String,
+        open_flags: OpenFlags,
+        flags: DescriptorFlags,
+    ) -> Result<Resource<Descriptor>, ErrorCode> {
+        if open_flags.intersects(OpenFlags::CREATE | OpenFlags::EXCLUSIVE | OpenFlags::TRUNCATE)
+            || flags.intersects(DescriptorFlags::WRITE | DescriptorFlags::MUTATE_DIRECTORY)
+        {
+            return Err(ErrorCode::ReadOnly);
+        }
+        let dir = self.descriptor(&fd)?;
+        let path = dir.resolve(&path)?;
+        let kind = dir.fs.kind(&path).ok_or(ErrorCode::NoEntry)?;
+        if open_flags.contains(OpenFlags::DIRECTORY) && kind != Kind::Dir {
+            return Err(ErrorCode::NotDirectory);
+        }
+        let opened = Descriptor {
+            fs: dir.fs.clone(),
+            path,
+            kind,
+        };
+        self.table.push(opened).map_err(|_| ErrorCode::InsufficientMemory)
+    }
+
+    fn readlink_at(&mut self, _fd: Resource<Descriptor>, _path: String) -> Result<String, ErrorCode> {
+        // there are no symlinks, so every path is not one
+        Err(ErrorCode::Invalid)
+    }
+
+    fn remove_directory_at(&mut self, _fd: Resource<Descriptor>, _path: String) -> Result<(), ErrorCode> {
+        Err(ErrorCode::ReadOnly)
+    }
+
+    fn rename_at(
+        &mut self,
+        _fd: Resource<Descriptor>,
+        _old_path: String,
+        _new_descriptor: Resource<Descriptor>,
+        _new_path: String,
+    ) -> Result<(), ErrorCode> {
+        Err(ErrorCode::ReadOnly)
+    }
+
+    fn symlink_at(&mut self, _fd: Resource<Descriptor>, _old_path: String, _new_path: String) -> Result<(), ErrorCode> {
+        Err(ErrorCode::ReadOnly)
+    }
+
+    fn unlink_file_at(&mut self, _fd: Resource<Descriptor>, _path: String) -> Result<(), ErrorCode> {
+        Err(ErrorCode::ReadOnly)
+    }
+
+    fn is_same_object(&mut self, fd: Resource<Descriptor>, other: Resource<Descriptor>) -> bool {
+        match (self.descriptor(&fd), self.descriptor(&other)) {
+            (Ok(a), Ok(b)) => a.path == b.path,
+            _ => false,
+        }
+    }
+
+    fn metadata_hash(&mut self, fd: Resource<Descriptor>) -> Result<MetadataHashValue, ErrorCode> {
+        Ok(hash_of(&self.descriptor(&fd)?.path))
+    }
+
+    fn metadata_hash_at(&mut self, fd: Resource<Descriptor>, _path_flags: PathFlags, path: String) -> Result<MetadataHashValue, ErrorCode> {
+        let fd = self.descriptor(&fd)?;
+        let path = fd.resolve(&path)?;
+        fd.fs.kind(&path).ok_or(ErrorCode::NoEntry)?;
+        Ok(hash_of(&path))
+    }
+
+    fn drop(&mut self, fd: Resource<Descriptor>) -> wasmtime::Result<()> {
+        self.table.delete(fd)?;
+        Ok(())
+    }
+}
+
+impl types::HostDirectoryEntryStream for Ctx {
+    fn read_directory_entry(&mut self, entries: Resource<DirEntries>) -> Result<Option<DirectoryEntry>, ErrorCode> {
+        let entries = self.table.get_mut(&entries).map_err(|_| ErrorCode::BadDescriptor)?;
+        Ok(entries.0.pop_front())
+    }
+
+    fn drop(&mut self, entries: Resource<DirEntries>) -> wasmtime::Result<()> {
+        self.table.delete(entries)?;
+        Ok(())
+    }
+}
+
+impl preopens::Host for Ctx {
+    fn get_directories(&mut self) -> Vec<(Resource<Descriptor>, String)> {
+        let Some(fs) = self.vfs.clone() else {
+            return Vec::new();
+        };
+        let root = Descriptor {
+            fs,
+            path: String::new(),
+            kind: Kind::Dir,
+        };
+        match self.table.push(root) {
+            Ok(root) => vec![(root, "/".to_string())],
+            Err(_) => Vec::new(),
+        }
+    }
+}
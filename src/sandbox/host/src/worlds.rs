@@ -0,0 +1,268 @@
+//! The WIT worlds one host build can serve, picked with `world=`:
+//!
+//! - `env` (the default): the full `exec:env` world, whose `init-exec-env`
+//!   takes the id name and log tags.
+//! - `env-config`: `env` with an `init-exec-env` that also takes the
+//!   `init-config` record built from `init_config`.
+//! - `env-basic`: the original world, which imports just `write-log` and
+//!   the byte messaging functions.
+//!
+//! `init_config` is refused for the other two, whose guests could not
+//! receive it. All three share the host
+//! imports, so the linker is the same; only the exports the host calls
+//! differ.
+
+use wasmtime::Store;
+use wasmtime::component::{Component, Instance, Linker, types::ComponentItem};
+use wasmtime::{Engine, Error};
+
+use crate::{Ctx, EnvConfig, InitConfig};
+
+mod plain {
+    wasmtime::component::bindgen!({
+        path: "../wit/",
+        world: "env",
+        imports: { default: async },
+        exports: { default: async },
+    });
+}
+
+mod basic {
+    wasmtime::component::bindgen!({
+        path: "../wit/",
+        world: "env-basic",
+        imports: { default: async },
+        exports: { default: async },
+    });
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum World {
+    Env,
+    Config,
+    Basic,
+}
+
+impl World {
+    pub fn parse(name: &str) -> pyo3::PyResult<Self> {
+        match name {
+            "env" => Ok(World::Env),
+            "env-config" => Ok(World::Config),
+            "env-basic" => Ok(World::Basic),
+            _ => Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "WasmRunner: unknown world {name:?}; expected env, env-config or env-basic"
+            ))),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            World::Env => "env",
+            World::Config => "env-config",
+            World::Basic => "env-basic",
+        }
+    }
+
+    /// Parameters of the world's `init-exec-env`, which is what tells a
+    /// config-taking guest apart.
+    fn init_params(self) -> usize {
+        match self {
+            World::Env | World::Basic => 2,
+            World::Config => 3,
+        }
+    }
+
+    /// Fail unless `component` exports this world's `run-msg-loop` and
+    /// `init-exec-env`, so a mismatch surfaces at construction rather than
+    /// at the first instantiation.
+    pub fn check(self, engine: &Engine, component: &Component) -> pyo3::PyResult<()> {
+        let ty = component.component_type();
+        let mut has_loop = false;
+        let mut init_params = None;
+        for (name, item) in ty.exports(engine) {
+            match (name, item) {
+                ("run-msg-loop", ComponentItem::ComponentFunc(_)) => has_loop = true,
+                ("init-exec-env", ComponentItem::ComponentFunc(f)) => init_params = Some(f.params().len()),
+                _ => {}
+            }
+        }
+        let problem = match init_params {
+            _ if !has_loop => "it has no `run-msg-loop` export".to_string(),
+            None => "it has no `init-exec-env` export".to_string(),
+            Some(n) if n != self.init_params() => format!(
+                "its `init-exec-env` takes {n} parameters where the world's takes {}",
+                self.init_params()
+            ),
+            Some(_) => return Ok(()),
+        };
+        let hint = match (self, init_params) {
+            (World::Env | World::Basic, Some(3)) => "; it looks like an env-config component, pass world=\"env-config\"",
+            (World::Config, Some(2)) => "; it looks like an env component, pass world=\"env\"",
+            _ => "",
+        };
+        Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "WasmRunner: component does not implement the {} world: {problem}{hint}",
+            self.name()
+        )))
+    }
+
+    /// Refuse `what` unless this world's `init-exec-env` takes the
+    /// `init-config` record, rather than dropping the config unseen.
+    pub fn check_takes_config(self, what: &str) -> pyo3::PyResult<()> {
+        if self.takes_config() {
+            return Ok(());
+        }
+        Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "WasmRunner: {what} is only delivered to env-config guests; the {} world's init-exec-env \
+             takes no config, pass world=\"env-config\" for a guest built against it",
+            self.name()
+        )))
+    }
+
+    /// Whether the optional `reinit-exec-env`, which mirrors the world's
+    /// `init-exec-env`, takes the `init-config` record.
+    pub fn takes_config(self) -> bool {
+        self == World::Config
+    }
+}
+
+/// Fail unless `linker` satisfies every import of `component`, with
+/// wasmtime's account of the first unsatisfied one, rather than leaving
+/// it to the first instantiation.
+pub(crate) fn check_imports(linker: &Linker<Ctx>, component: &Component) -> pyo3::PyResult<()> {
+    linker.instantiate_pre(component).map(|_| ()).map_err(|e| {
+        pyo3::exceptions::PyValueError::new_err(format!(
+            "WasmRunner: component imports something this host does not provide: {e:#}; \
+             extra host functions can be supplied with custom_imports"
+        ))
+    })
+}
+
+/// A component's import and export names, e.g. `send-bytes` or
+/// `wasi:io/streams@0.2.0`, read from its type without instantiating it.
+pub(crate) struct Interface {
+    pub imports: Vec<String>,
+    pub exports: Vec<String>,
+}
+
+impl Interface {
+    pub fn of(engine: &Engine, component: &Component) -> Self {
+        let ty = component.component_type();
+        let mut imports: Vec<String> = ty.imports(engine).map(|(name, _)| name.to_string()).collect();
+        let mut exports: Vec<String> = ty.exports(engine).map(|(name, _)| name.to_string()).collect();
+        imports.sort();
+        exports.sort();
+        Interface { imports, exports }
+    }
+}
+
+/// The export bindings of an instantiated guest.
+pub(crate) enum Guest {
+    Env(plain::Env),
+    Config(EnvConfig),
+    Basic(basic::EnvBasic),
+}
+
+impl Guest {
+    pub fn new(world: World, store: &mut Store<Ctx>, instance: &Instance) -> Result<Self, Error> {
+        let guest = match world {
+            World::Env => plain::Env::new(store, instance).map(Guest::Env),
+            World::Config => EnvConfig::new(store, instance).map(Guest::Config),
+            World::Basic => basic::EnvBasic::new(store, instance).map(Guest::Basic),
+        };
+        guest.map_err(|e| e.context(format!("WasmRunner: component does not implement the {} world", world.name())))
+    }
+
+    pub async fn call_init_exec_env(
+        &self,
+        store: &mut Store<Ctx>,
+        id_name: &str,
+        log_tags: Option<&str>,
+        config: &InitConfig,
+    ) -> Result<(), Error> {
+        match self {
+            Guest::Env(env) => env.call_init_exec_env(store, id_name, log_tags).await,
+            Guest::Config(env) => env.call_init_exec_env(store, id_name, log_tags, config).await,
+            Guest::Basic(env) => env.call_init_exec_env(store, id_name, log_tags).await,
+        }
+    }
+
+    pub async fn call_run_msg_loop(&self, store: &mut Store<Ctx>) -> Result<(), Error> {
+        match self {
+            Guest::Env(env) => env.call_run_msg_loop(store).await,
+            Guest::Config(env) => env.call_run_msg_loop(store).await,
+            Guest::Basic(env) => env.call_run_msg_loop(store).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use wit_parser::{Resolve, Type, TypeDefKind, WorldItem};
+
+    /// `env-config` spells out the imports `env` includes from
+    /// `env-common`; see env.wit for why it can't include them too.
+    #[test]
+    fn env_config_imports_match_env() {
+        let mut resolve = Resolve::default();
+        let (pkg, _) = resolve.push_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/../wit")).unwrap();
+        let imports = |world: &str| {
+            let id = resolve.select_world(&[pkg], Some(world)).unwrap();
+            let world = &resolve.worlds[id];
+            world
+                .imports
+                .iter()
+                .chain(world.exports.iter().filter(|(key, _)| resolve.name_world_key(key) != "init-exec-env"))
+                .map(|(key, item)| (resolve.name_world_key(key), describe(&resolve, item)))
+                .collect::<BTreeMap<_, _>>()
+        };
+        let mut config = imports("env-config");
+        assert!(config.remove("init-config").is_some());
+        assert_eq!(imports("env"), config);
+    }
+
+    fn describe(resolve: &Resolve, item: &WorldItem) -> String {
+        match item {
+            WorldItem::Function(f) => {
+                let params = f.params.iter().map(|(name, ty)| format!("{name}: {}", type_name(resolve, ty)));
+                let result = f.result.as_ref().map_or("_".into(), |ty| type_name(resolve, ty));
+                format!("{:?} func({}) -> {result}", f.docs.contents, params.collect::<Vec<_>>().join(", "))
+            }
+            WorldItem::Type(id) => match &resolve.types[*id].kind {
+                TypeDefKind::Record(r) => {
+                    let fields = r.fields.iter().map(|f| format!("{}: {}", f.name, type_name(resolve, &f.ty)));
+                    format!("record {{ {} }}", fields.collect::<Vec<_>>().join(", "))
+                }
+                TypeDefKind::Enum(e) => {
+                    let cases = e.cases.iter().map(|c| c.name.as_str());
+                    format!("enum {{ {} }}", cases.collect::<Vec<_>>().join(", "))
+                }
+                kind => panic!("unexpected type {kind:?}"),
+            },
+            WorldItem::Interface { .. } => panic!("unexpected interface"),
+        }
+    }
+
+    fn type_name(resolve: &Resolve, ty: &Type) -> String {
+        let Type::Id(id) = ty else {
+            return format!("{ty:?}").to_lowercase();
+        };
+        let def = &resolve.types[*id];
+        if let Some(name) = &def.name {
+            return name.clone();
+        }
+        let name = |ty| type_name(resolve, ty);
+        match &def.kind {
+            TypeDefKind::List(ty) => format!("list<{}>", name(ty)),
+            TypeDefKind::Option(ty) => format!("option<{}>", name(ty)),
+            TypeDefKind::Tuple(t) => format!("tuple<{}>", t.types.iter().map(name).collect::<Vec<_>>().join(", ")),
+            TypeDefKind::Result(r) => {
+                let [ok, err] = [&r.ok, &r.err].map(|ty| ty.as_ref().map_or("_".into(), name));
+                format!("result<{ok}, {err}>")
+            }
+            kind => panic!("unexpected type {kind:?}"),
+        }
+    }
+}
@@ -0,0 +1,2027 @@
+import asyncio
+import json
+import os
+import re
+import shutil
+import subprocess
+import sys
+import time
+from pathlib import Path
+
+import pytest
+
+
+@pytest.mark.asyncio
+async def test_loop_exits_on_end_of_stream(make_dummy_sandbox):
+    # an empty payload from recv_bytes is end of stream: the guest leaves its
+    # loop and run_msg_loop resolves instead of waiting for more
+    with make_dummy_sandbox() as sb:
+        sb._inbox.put_nowait(b'')
+        outcome = await asyncio.wait_for(sb.wasm_runner.run_msg_loop(), timeout=10)
+        # host.LoopOutcome and py_runner.LoopOutcome alike
+        assert str(outcome.kind) == 'OutcomeKind.NormalExit'
+        assert outcome.exit_code is None
+
+
+async def _wait_for(predicate, timeout=10.0):
+    async with asyncio.timeout(timeout):
+        while not predicate():
+            await asyncio.sleep(0.01)
+
+
+@pytest.mark.asyncio
+async def test_drain_finishes_in_flight_message(make_dummy_sandbox, is_local_runner):
+    if is_local_runner:
+        pytest.skip("drain() is a WasmRunner method")
+    from host import OutcomeKind
+
+    with make_dummy_sandbox() as sb:
+        reply = asyncio.ensure_future(sb.repl_command("sum(range(100_000))"))
+        await _wait_for(lambda: sb._runner is not None and sb._runner.messages_received >= 1)
+        sb.wasm_runner.drain(reason="test")
+        out, _, _ = await asyncio.wait_for(reply, timeout=10)
+        assert out == str(sum(range(100_000)))
+        outcome = await asyncio.wait_for(sb._future, timeout=10)
+        assert outcome.kind == OutcomeKind.NormalExit
+        assert outcome.stop_reason == "test"
+
+
+def _configure(sb, **kwargs):
+    # the runner is built from these on first use, so this must come first
+    sb._lazy_init.update(kwargs)
+
+
+async def _start_loop(sb, line):
+    """Send `line` to the guest; return the pending reply and the loop future."""
+    reply = asyncio.ensure_future(sb.repl_command(line))
+    await _wait_for(lambda: sb._future is not None)
+    return reply, sb._future
+
+
+@pytest.mark.asyncio
+async def test_timeout_stops_a_runaway_guest(make_dummy_sandbox, is_local_runner):
+    if is_local_runner:
+        pytest.skip("timeout_ms is a WasmRunner limit")
+    from host import OutcomeKind, WasmTimeoutError
+
+    with make_dummy_sandbox() as sb:
+        _configure(sb, limits={"timeout_ms": 5_000})
+        reply, loop = await _start_loop(sb, "while True: pass")
+        with pytest.raises(WasmTimeoutError) as info:
+            await asyncio.wait_for(loop, timeout=30)
+        reply.cancel()
+        assert info.value.outcome.kind == OutcomeKind.Timeout
+        assert info.value.phase == "run_msg_loop"
+        # the request itself did reach the guest before it hung
+        assert info.value.partial_output["messages_received"] >= 1
+
+
+@pytest.mark.asyncio
+async def test_fuel_limit_stops_a_runaway_guest(make_dummy_sandbox, is_local_runner):
+    if is_local_runner:
+        pytest.skip("fuel_limit is a WasmRunner limit")
+    from host import OutcomeKind, WasmFuelExhaustedError
+
+    with make_dummy_sandbox() as sb:
+        _configure(sb, limits={"fuel_limit": 20_000_000_000})
+        reply, loop = await _start_loop(sb, "while True: pass")
+        with pytest.raises(WasmFuelExhaustedError) as info:
+            await asyncio.wait_for(loop, timeout=60)
+        reply.cancel()
+        assert info.value.outcome.kind == OutcomeKind.FuelExhausted
+        assert info.value.trap_code == "OutOfFuel"
+
+
+@pytest.mark.asyncio
+async def test_close_stops_a_waiting_loop(make_dummy_sandbox, is_local_runner):
+    if is_local_runner:
+        pytest.skip("close(reason) is a WasmRunner method")
+    from host import OutcomeKind
+
+    with make_dummy_sandbox() as sb:
+        out, _, _ = await asyncio.wait_for(sb.repl_command("1 + 1"), timeout=30)
+        assert out == "2"
+        runner, loop = sb.wasm_runner, sb._future
+        # the guest is now blocked in recv_bytes, waiting for the next message
+        assert runner.running
+
+        async def close_later():
+            await asyncio.sleep(0.1)
+            runner.close(reason="test")
+
+        closer = asyncio.create_task(close_later())
+        outcome = await asyncio.wait_for(loop, timeout=5)
+        await closer
+        assert outcome.kind == OutcomeKind.NormalExit
+        assert outcome.stop_reason == "test"
+        assert not runner.running
+        with pytest.raises(RuntimeError) as info:
+            await runner.run_msg_loop()
+        assert info.value.reason == "test"
+
+
+@pytest.mark.asyncio
+async def test_runs_a_component_given_as_bytes(make_dummy_sandbox, is_local_runner, tmp_path):
+    if is_local_runner:
+        pytest.skip("wasm_bytes is a WasmRunner argument")
+
+    with make_dummy_sandbox() as sb:
+        with open(sb._lazy_init["wasm_path"], "rb") as f:
+            wasm = f.read()
+        # neither path is read for an in-memory component
+        _configure(
+            sb,
+            wasm_bytes=wasm,
+            wasm_path=str(tmp_path / "missing.wasm"),
+            wasm_compiled_cache=str(tmp_path / "missing.compiled"),
+        )
+        out, _, _ = await asyncio.wait_for(sb.repl_command("1 + 1"), timeout=30)
+        assert out == "2"
+        timings = sb.wasm_runner.startup_timings()
+        assert "compile" in timings and "read_bytes" not in timings
+        assert not (tmp_path / "missing.compiled").exists()
+
+
+@pytest.mark.asyncio
+async def test_runs_a_precompiled_artifact(make_dummy_sandbox, is_local_runner, tmp_path):
+    if is_local_runner:
+        pytest.skip("precompile() is a host function")
+    import host
+
+    with make_dummy_sandbox() as sb:
+        artifact = str(tmp_path / "env.cwasm")
+        assert host.precompile(sb._lazy_init["wasm_path"], artifact) == artifact
+        _configure(sb, precompiled_path=artifact)
+        out, _, _ = await asyncio.wait_for(sb.repl_command("1 + 1"), timeout=30)
+        assert out == "2"
+        timings = sb.wasm_runner.startup_timings()
+        assert "deserialize" in timings and "compile" not in timings
+
+
+@pytest.mark.asyncio
+async def test_runners_share_one_engine(make_dummy_sandbox, is_local_runner):
+    if is_local_runner:
+        pytest.skip("EngineHandle is a WasmRunner argument")
+    from host import EngineHandle
+
+    engine = EngineHandle()
+    with make_dummy_sandbox() as a, make_dummy_sandbox() as b:
+        _configure(a, engine=engine)
+        _configure(b, engine=engine)
+        for sb in (a, b):
+            out, _, _ = await asyncio.wait_for(sb.repl_command("1 + 1"), timeout=30)
+            assert out == "2"
+        stats = engine.stats()
+        assert stats["runners"] == 2
+        assert stats["instantiated"] == 2
+        # the second runner reused the component the first compiled
+        assert stats["components"] == 1
+
+
+async def _seeded_output(make_dummy_sandbox, seed):
+    with make_dummy_sandbox() as sb:
+        _configure(sb, deterministic={"seed": seed})
+        out, _, _ = await asyncio.wait_for(
+            sb.repl_command("(__import__('os').urandom(8).hex(), __import__('random').random())"),
+            timeout=30,
+        )
+        return out
+
+
+@pytest.mark.asyncio
+async def test_same_seed_gives_the_same_output(make_dummy_sandbox, is_local_runner):
+    if is_local_runner:
+        pytest.skip("deterministic is a WasmRunner argument")
+
+    first = await _seeded_output(make_dummy_sandbox, 7)
+    assert await _seeded_output(make_dummy_sandbox, 7) == first
+    assert await _seeded_output(make_dummy_sandbox, 8) != first
+
+
+@pytest.mark.asyncio
+async def test_memory_bytes_follows_guest_growth(make_dummy_sandbox, is_local_runner):
+    if is_local_runner:
+        pytest.skip("memory_bytes is a WasmRunner getter")
+
+    with make_dummy_sandbox() as sb:
+        assert sb.wasm_runner.memory_bytes is None
+        await asyncio.wait_for(sb.repl_command("1 + 1"), timeout=30)
+        before = sb.wasm_runner.memory_bytes
+        assert before > 0
+        await asyncio.wait_for(sb.repl_command("grown = bytearray(64 << 20)"), timeout=30)
+        assert sb.wasm_runner.memory_bytes > before + (32 << 20)
+
+
+# the ed25519 base point, a valid public key whose private key is not at hand
+_STRANGER_KEY = bytes([0x58] + [0x66] * 31)
+
+
+def _copy_wasm(sb, tmp_path):
+    wasm = tmp_path / "env.wasm"
+    with open(sb._lazy_init["wasm_path"], "rb") as f:
+        wasm.write_bytes(f.read())
+    _configure(sb, wasm_path=str(wasm), wasm_compiled_cache=str(tmp_path / "env.wasm.compiled"))
+    return wasm
+
+
+def test_refuses_a_bad_signature(make_dummy_sandbox, is_local_runner, tmp_path):
+    if is_local_runner:
+        pytest.skip("signing is a WasmRunner argument")
+    from host import WasmSignatureError
+
+    with make_dummy_sandbox() as sb:
+        _copy_wasm(sb, tmp_path)
+        _configure(sb, signing={"public_key": _STRANGER_KEY, "signature": bytes(64)})
+        with pytest.raises(WasmSignatureError, match="does not match"):
+            sb.wasm_runner
+        assert not (tmp_path / "env.wasm.compiled").exists()
+
+
+def test_refuses_a_missing_signature(make_dummy_sandbox, is_local_runner, tmp_path):
+    if is_local_runner:
+        pytest.skip("signing is a WasmRunner argument")
+    from host import WasmSignatureError
+
+    with make_dummy_sandbox() as sb:
+        _copy_wasm(sb, tmp_path)
+        # looked for as env.wasm.sig, which does not exist
+        _configure(sb, signing={"public_key": _STRANGER_KEY})
+        with pytest.raises(WasmSignatureError, match="cannot read component signature"):
+            sb.wasm_runner
+
+
+@pytest.mark.asyncio
+async def test_runs_a_signed_component(make_dummy_sandbox, is_local_runner, tmp_path):
+    if is_local_runner:
+        pytest.skip("signing is a WasmRunner argument")
+    ed25519 = pytest.importorskip("cryptography.hazmat.primitives.asymmetric.ed25519")
+    from cryptography.hazmat.primitives.serialization import Encoding, PublicFormat
+
+    key = ed25519.Ed25519PrivateKey.generate()
+    public_key = key.public_key().public_bytes(Encoding.Raw, PublicFormat.Raw)
+    with make_dummy_sandbox() as sb:
+        wasm = _copy_wasm(sb, tmp_path)
+        (tmp_path / "env.wasm.sig").write_bytes(key.sign(wasm.read_bytes()))
+        _configure(sb, signing={"public_key": public_key})
+        out, _, _ = await asyncio.wait_for(sb.repl_command("1 + 1"), timeout=30)
+        assert out == "2"
+
+
+@pytest.mark.asyncio
+async def test_instance_ids_are_distinct_and_stable(make_dummy_sandbox, is_local_runner):
+    if is_local_runner:
+        pytest.skip("instance_id is a WasmRunner getter")
+    import time
+
+    with make_dummy_sandbox() as a, make_dummy_sandbox() as b:
+        first = a.wasm_runner.instance_id
+        assert first != b.wasm_runner.instance_id
+        assert a.wasm_runner.created_at <= time.time()
+        await asyncio.wait_for(a.repl_command("1 + 1"), timeout=30)
+        assert a.wasm_runner.instance_id == first
+
+
+@pytest.mark.asyncio
+async def test_guest_exit_status_is_reported(make_dummy_sandbox, is_local_runner):
+    if is_local_runner:
+        pytest.skip("ExitStatus is a WasmRunner outcome")
+    from host import OutcomeKind
+
+    with make_dummy_sandbox() as sb:
+        reply, loop = await _start_loop(sb, "__import__('os')._exit(3)")
+        # an outcome, not a trap
+        outcome = await asyncio.wait_for(loop, timeout=30)
+        reply.cancel()
+        assert outcome.kind == OutcomeKind.ExitStatus
+        assert outcome.exit_code == 3
+
+
+@pytest.mark.asyncio
+async def test_pool_enforces_its_size(make_dummy_sandbox, is_local_runner):
+    if is_local_runner:
+        pytest.skip("WasmRunnerPool is a host class")
+    from host import WasmPoolExhaustedError, WasmRunnerPool
+
+    with make_dummy_sandbox() as sb:
+        kwargs = dict(sb._lazy_init)
+        wasm_path = kwargs.pop("wasm_path")
+        pool = WasmRunnerPool(wasm_path, 1, kwargs, acquire_timeout_ms=0)
+        runner = await asyncio.wait_for(pool.acquire(), timeout=30)
+        assert pool.available == 0
+        assert pool.stats()["in_use"] == 1
+        with pytest.raises(WasmPoolExhaustedError):
+            await pool.acquire()
+        await asyncio.wait_for(pool.release(runner), timeout=30)
+        assert pool.available == 1
+        again = await asyncio.wait_for(pool.acquire(), timeout=30)
+        assert again.instantiated
+
+
+def test_validate_checks_without_running(make_dummy_sandbox, is_local_runner, tmp_path):
+    if is_local_runner:
+        pytest.skip("validate() is a host function")
+    import host
+
+    with make_dummy_sandbox() as sb:
+        wasm_path = sb._lazy_init["wasm_path"]
+    assert host.validate(wasm_path) is None
+    with pytest.raises(ValueError, match='pass world="env"'):
+        host.validate(wasm_path, world="env-config")
+    corrupt = tmp_path / "corrupt.wasm"
+    corrupt.write_bytes(b"\0asm\x0d\0\x01\0garbage")
+    with pytest.raises(ValueError, match="is not a valid component"):
+        host.validate(str(corrupt))
+    # a well-formed component that exports nothing of the env world
+    empty = tmp_path / "empty.wasm"
+    empty.write_bytes(b"\0asm\x0d\0\x01\0")
+    with pytest.raises(ValueError, match="does not implement the env world"):
+        host.validate(str(empty))
+
+
+@pytest.mark.asyncio
+async def test_take_stdout_returns_guest_prints(make_dummy_sandbox, is_local_runner):
+    if is_local_runner:
+        pytest.skip("take_stdout() is a WasmRunner method")
+
+    with make_dummy_sandbox() as sb:
+        _configure(sb, wasi={"capture_stdout": True})
+        # straight to fd 1, past the REPL's own sys.stdout capture
+        await asyncio.wait_for(sb.repl_command("__import__('os').write(1, b'hello\\n')"), timeout=30)
+        await asyncio.wait_for(sb.repl_command("__import__('os').write(1, b'world\\n')"), timeout=30)
+        assert sb.wasm_runner.take_stdout().endswith("hello\nworld\n")
+        assert sb.wasm_runner.take_stdout() == ""
+
+
+def _failing_recv(error):
+    async def recv_bytes() -> bytes:
+        raise error
+
+    return recv_bytes
+
+
+@pytest.mark.asyncio
+async def test_raised_errors_carry_their_attributes(make_dummy_sandbox, is_local_runner):
+    if is_local_runner:
+        pytest.skip("error_types() is a host function")
+    import host
+
+    types = host.error_types()
+    assert types["WasmError"] is host.WasmError
+    assert all(issubclass(cls, host.WasmError) for cls in types.values())
+    with make_dummy_sandbox() as sb:
+        _configure(sb, recv_bytes=_failing_recv(KeyError("boom")))
+        with pytest.raises(types["WasmHostError"]) as info:
+            await asyncio.wait_for(sb.wasm_runner.run_msg_loop(), timeout=30)
+        err = info.value
+        assert err.id_name == sb._lazy_init["id_name"]
+        assert err.phase == "run_msg_loop"
+        assert err.trap_code is None
+        assert err.outcome.kind == host.OutcomeKind.HostError
+        assert "boom" in err.outcome.reason
+
+
+@pytest.mark.asyncio
+async def test_memory_cap_raises_its_own_error(make_dummy_sandbox, is_local_runner):
+    if is_local_runner:
+        pytest.skip("max_memory_bytes is a WasmRunner limit")
+    from host import OutcomeKind, WasmMemoryLimitError
+
+    with make_dummy_sandbox() as sb:
+        # well below what the interpreter needs just to start
+        _configure(sb, limits={"max_memory_bytes": 1 << 20})
+        with pytest.raises(WasmMemoryLimitError) as info:
+            await asyncio.wait_for(sb.wasm_runner.run_msg_loop(), timeout=30)
+        assert info.value.outcome.kind == OutcomeKind.MemoryLimit
+        assert "max_memory_bytes=1048576" in str(info.value)
+
+
+@pytest.mark.asyncio
+async def test_read_only_dir_refuses_writes(make_dummy_sandbox, is_local_runner, tmp_path):
+    if is_local_runner:
+        pytest.skip("preopen_dirs is a WasmRunner argument")
+
+    (tmp_path / "seen.txt").write_text("shared")
+    with make_dummy_sandbox() as sb:
+        _configure(sb, wasi={"preopen_dirs": [(str(tmp_path), "/data", False)]})
+        out, _, _ = await asyncio.wait_for(sb.repl_command("open('/data/seen.txt').read() == 'shared'"), timeout=30)
+        assert out == "True"
+        await asyncio.wait_for(
+            sb.repl_exec(
+                "try:\n"
+                "    open('/data/new.txt', 'w').write('x')\n"
+                "    denied = False\n"
+                "except OSError:\n"
+                "    denied = True\n"
+            ),
+            timeout=30,
+        )
+        out, _, _ = await asyncio.wait_for(sb.repl_command("denied"), timeout=30)
+        assert out == "True"
+    assert not (tmp_path / "new.txt").exists()
+
+
+def test_missing_preopen_dir_fails_construction(make_dummy_sandbox, is_local_runner, tmp_path):
+    if is_local_runner:
+        pytest.skip("preopen_dirs is a WasmRunner argument")
+
+    with make_dummy_sandbox() as sb:
+        _configure(sb, wasi={"preopen_dirs": [(str(tmp_path / "missing"), "/data", False)]})
+        with pytest.raises(FileNotFoundError, match="is not a directory"):
+            sb.wasm_runner
+
+
+@pytest.mark.asyncio
+async def test_init_failure_keeps_its_cause(make_dummy_sandbox, is_local_runner):
+    if is_local_runner:
+        pytest.skip("init errors come from the WasmRunner")
+    from host import WasmFuelExhaustedError
+
+    with make_dummy_sandbox() as sb:
+        # enough to start instantiating, not to finish init-exec-env
+        _configure(sb, limits={"fuel_limit": 10_000})
+        with pytest.raises(WasmFuelExhaustedError) as info:
+            await asyncio.wait_for(sb.wasm_runner.run_msg_loop(), timeout=30)
+        assert info.value.phase == "instantiate"
+        assert info.value.trap_code == "OutOfFuel"
+        assert "fuel" in str(info.value)
+        assert sb.wasm_runner.last_error.phase == "instantiate"
+
+
+@pytest.mark.asyncio
+async def test_pooled_engine_reuses_memory_slots(make_dummy_sandbox, is_local_runner):
+    if is_local_runner:
+        pytest.skip("pooling is an EngineHandle option")
+    import gc
+    import time
+
+    from host import EngineHandle
+
+    engine = EngineHandle({"pooling": True, "pool_max_instances": 4})
+    first_reply_ms = []
+    # more runners than slots, one after another: each reuses a freed slot
+    for _ in range(6):
+        with make_dummy_sandbox() as sb:
+            _configure(sb, engine=engine)
+            started = time.perf_counter()
+            out, _, _ = await asyncio.wait_for(sb.repl_command("1 + 1"), timeout=30)
+            assert out == "2"
+            first_reply_ms.append((time.perf_counter() - started) * 1000)
+        await _wait_for(lambda: gc.collect() is not None and engine.stats()["pooling"]["component_instances"] == 0)
+        assert engine.stats()["pooling"]["unused_warm_memories"] > 0
+    print("first reply, ms:", [round(ms, 1) for ms in first_reply_ms])
+
+
+@pytest.mark.asyncio
+async def test_host_callback_traceback_survives(make_dummy_sandbox, is_local_runner):
+    if is_local_runner:
+        pytest.skip("host_traceback is set by the WasmRunner")
+    from host import WasmHostError
+
+    error = KeyError("boom")
+    with make_dummy_sandbox() as sb:
+        _configure(sb, recv_bytes=_failing_recv(error))
+        with pytest.raises(WasmHostError) as info:
+            await asyncio.wait_for(sb.wasm_runner.run_msg_loop(), timeout=30)
+        err = info.value
+        assert err.__cause__ is error
+        assert err.outcome.py_exception is error
+        # the callback's own frame, not just its message
+        assert "in recv_bytes" in err.host_traceback
+        assert "KeyError: 'boom'" in err.host_traceback
+
+
+def _record_messages(sb):
+    """Wrap the runner's callbacks; return the lists of payloads received and sent."""
+    received, sent = [], []
+    recv, send = sb._lazy_init["recv_bytes"], sb._lazy_init["send_bytes"]
+
+    async def recv_bytes() -> bytes:
+        data = await recv()
+        received.append(data)
+        return data
+
+    async def send_bytes(data: bytes) -> None:
+        sent.append(bytes(data))
+        await send(data)
+
+    _configure(sb, recv_bytes=recv_bytes, send_bytes=send_bytes)
+    return received, sent
+
+
+@pytest.mark.asyncio
+async def test_max_message_size_boundary(make_dummy_sandbox, is_local_runner):
+    if is_local_runner:
+        pytest.skip("max_message_size is a WasmRunner limit")
+    from host import OutcomeKind, WasmMessageTooLargeError
+
+    with make_dummy_sandbox() as sb:
+        received, sent = _record_messages(sb)
+        await asyncio.wait_for(sb.repl_command("1 + 1"), timeout=30)
+    largest = max(len(m) for m in received + sent)
+
+    # the same exchange again, with the largest message exactly at the limit
+    with make_dummy_sandbox() as sb:
+        _configure(sb, transport={"max_message_size": largest})
+        out, _, _ = await asyncio.wait_for(sb.repl_command("1 + 1"), timeout=30)
+        assert out == "2"
+
+    with make_dummy_sandbox() as sb:
+        _configure(sb, transport={"max_message_size": largest - 1})
+        reply, loop = await _start_loop(sb, "1 + 1")
+        with pytest.raises(WasmMessageTooLargeError) as info:
+            await asyncio.wait_for(loop, timeout=30)
+        reply.cancel()
+        assert info.value.outcome.kind == OutcomeKind.MessageTooLarge
+        assert f"of {largest} bytes exceeds max_message_size={largest - 1}" in str(info.value)
+
+
+@pytest.mark.asyncio
+async def test_message_counters_match_the_traffic(make_dummy_sandbox, is_local_runner):
+    if is_local_runner:
+        pytest.skip("message counters are WasmRunner getters")
+
+    with make_dummy_sandbox() as sb:
+        received, sent = _record_messages(sb)
+        for n in range(3):
+            out, _, _ = await asyncio.wait_for(sb.repl_command(f"{n} * 2"), timeout=30)
+            assert out == str(n * 2)
+        runner = sb.wasm_runner
+        assert runner.messages_received == len(received) >= 3
+        assert runner.messages_sent == len(sent) >= 3
+        assert runner.bytes_received == sum(map(len, received))
+        assert runner.bytes_sent == sum(map(len, sent))
+
+
+# a wasm stack far too small for the interpreter, so starting the guest traps
+_TINY_STACK = 16 << 10
+
+
+@pytest.mark.asyncio
+async def test_trap_carries_its_wasm_frames(make_dummy_sandbox, is_local_runner):
+    if is_local_runner:
+        pytest.skip("wasm_frames come from a wasm trap")
+    from host import WasmTrapError
+
+    with make_dummy_sandbox() as sb:
+        _configure(sb, compile={"max_wasm_stack": _TINY_STACK})
+        with pytest.raises(WasmTrapError) as info:
+            await asyncio.wait_for(sb.wasm_runner.run_msg_loop(), timeout=30)
+        err = info.value
+        assert err.trap_code == "StackOverflow"
+        assert err.wasm_frames
+        for frame in err.wasm_frames:
+            assert {"module", "func_index", "func_name", "func_offset", "module_offset"} <= frame.keys()
+        assert err.outcome.backtrace
+
+
+@pytest.mark.asyncio
+async def test_reset_recovers_after_a_trap(make_dummy_sandbox, is_local_runner):
+    if is_local_runner:
+        pytest.skip("reset() is a WasmRunner method")
+    from host import WasmTimeoutError
+
+    with make_dummy_sandbox() as sb:
+        _configure(sb, limits={"timeout_ms": 5_000})
+        await asyncio.wait_for(sb.repl_exec("marker = 1"), timeout=30)
+        out, _, _ = await asyncio.wait_for(sb.repl_command("'marker' in globals()"), timeout=30)
+        assert out == "True"
+        runner, loop = sb.wasm_runner, sb._future
+        hung = asyncio.ensure_future(sb.repl_command("while True: pass"))
+        with pytest.raises(WasmTimeoutError):
+            await asyncio.wait_for(loop, timeout=30)
+        hung.cancel()
+        load_phases = {"read_bytes", "deserialize", "precompile", "compile"}
+        load_timings = {k: v for k, v in runner.startup_timings().items() if k in load_phases}
+
+        runner.reset()
+        assert not runner.instantiated
+        # a new loop on the same runner, fed by the sandbox's running tasks
+        sb._future = runner.run_msg_loop()
+        out, _, _ = await asyncio.wait_for(sb.repl_command("'marker' in globals()"), timeout=30)
+        assert out == "False"
+        # instantiated afresh, without loading the component again
+        assert {k: v for k, v in runner.startup_timings().items() if k in load_timings} == load_timings
+
+
+@pytest.mark.asyncio
+async def test_instantiated_tells_the_three_states_apart(make_dummy_sandbox, is_local_runner):
+    if is_local_runner:
+        pytest.skip("instantiated is a WasmRunner getter")
+
+    with make_dummy_sandbox() as sb:
+        runner = sb.wasm_runner
+        # never started
+        assert not runner.instantiated and not runner.running
+        await asyncio.wait_for(runner.instantiate(), timeout=30)
+        # started but idle
+        assert runner.instantiated and not runner.running
+        await asyncio.wait_for(sb.repl_command("1 + 1"), timeout=30)
+        # in a loop
+        assert runner.instantiated and runner.running
+
+
+@pytest.mark.asyncio
+async def test_failed_instantiation_leaves_it_unset(make_dummy_sandbox, is_local_runner):
+    if is_local_runner:
+        pytest.skip("instantiated is a WasmRunner getter")
+    from host import WasmTrapError
+
+    with make_dummy_sandbox() as sb:
+        _configure(sb, compile={"max_wasm_stack": _TINY_STACK})
+        with pytest.raises(WasmTrapError):
+            await asyncio.wait_for(sb.wasm_runner.instantiate(), timeout=30)
+        assert not sb.wasm_runner.instantiated
+
+
+@pytest.mark.asyncio
+async def test_running_follows_the_loop(make_dummy_sandbox, is_local_runner):
+    if is_local_runner:
+        pytest.skip("running is a WasmRunner getter")
+
+    with make_dummy_sandbox() as sb:
+        runner = sb.wasm_runner
+        assert not runner.running
+        await asyncio.wait_for(sb.repl_command("1 + 1"), timeout=30)
+        loop = sb._future
+        # waiting in recv_bytes is still running, and reads do not disturb it
+        assert runner.running
+        runner.metrics()
+        assert runner.running
+        sb._inbox.put_nowait(b'')
+        await asyncio.wait_for(loop, timeout=10)
+        assert not runner.running
+
+
+_GUESTS = Path(__file__).parent / "wasm"
+
+
+def _awaitable(fn):
+    async def call(*args):
+        return fn(*args)
+
+    return call
+
+
+def _guest(
+    name, tmp_path, inbox=(), write_log=lambda text: None, consume=None, pace=None, recv_ready=None,
+    **kwargs,
+):
+    """A WasmRunner over the test/wasm/<name>.wasm fixture, fed `inbox`;
+    also returns the list the guest's messages are sent to. Each send then
+    awaits `consume(payload)`, and each receive first awaits `pace()`, when
+    given. `recv_ready` defaults to whether `inbox` has messages left."""
+    host = pytest.importorskip("host")
+    inbox, sent = list(inbox), []
+
+    def send_bytes(payload):
+        sent.append(bytes(payload))
+
+    def recv_bytes():
+        # running out of messages is end of stream
+        return inbox.pop(0) if inbox else b''
+
+    if kwargs.get("mode") != "sync":
+        send_bytes, recv_bytes = _awaitable(send_bytes), _awaitable(recv_bytes)
+    if consume is not None:
+        record = send_bytes
+
+        async def send_bytes(payload):
+            await record(payload)
+            await consume(payload)
+
+    if pace is not None:
+        take = recv_bytes
+
+        async def recv_bytes():
+            await pace()
+            return await take()
+
+    runner = host.WasmRunner(
+        name,
+        send_bytes,
+        recv_bytes,
+        recv_ready or (lambda: bool(inbox)),
+        write_log,
+        **{
+            "wasm_path": str(_GUESTS / f"{name}.wasm"),
+            "wasm_compiled_cache": str(tmp_path / f"{name}.compiled"),
+            **kwargs,
+        },
+    )
+    return runner, sent
+
+
+@pytest.mark.asyncio
+async def test_custom_import_reaches_the_guest(tmp_path):
+    runner, sent = _guest(
+        "clock", tmp_path, custom_imports={"now-millis": ([], "s64", lambda: 1_700_000_000_123)}
+    )
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert sent == [(1_700_000_000_123).to_bytes(8, "little")]
+
+
+# Two compute-bound guests started together, printing the order in which
+# they make progress; each `progress` call sleeps for `pause` seconds, and
+# `options` go to both runners. Run in a child process because the tokio runtime is
+# created once per process and the worker count has to be set before that.
+_TWO_SPINNERS = """
+import asyncio, json, sys, time
+import host
+
+wasm, cache, limits = sys.argv[1], sys.argv[2], json.loads(sys.argv[3])
+pause, options = float(sys.argv[4]), json.loads(sys.argv[5])
+order = []
+
+def runner(name):
+    def progress():
+        order.append(name)
+        time.sleep(pause)
+        return order.count(name) < 20
+
+    async def idle(*args):
+        pass
+
+    return host.WasmRunner(
+        name, idle, idle, lambda: False, lambda text: None,
+        wasm_path=wasm, wasm_compiled_cache=f"{cache}.{name}", wasm_inherit_io=False,
+        custom_imports={"progress": ([], "bool", progress)}, limits=limits, **options,
+    )
+
+async def main():
+    a, b = runner("a"), runner("b")
+    await asyncio.gather(a.run_msg_loop(), b.run_msg_loop())
+
+asyncio.run(main())
+print("".join(order), flush=True)
+"""
+
+
+def _spin_order(tmp_path, limits, pause=0, **options):
+    pytest.importorskip("host")
+    env = dict(os.environ, TOKIO_WORKER_THREADS="1", PYTHONPATH=os.pathsep.join(sys.path))
+    args = [str(_GUESTS / "spin.wasm"), str(tmp_path / "spin.compiled"), json.dumps(limits), str(pause)]
+    args.append(json.dumps(options))
+    proc = subprocess.run(
+        [sys.executable, "-c", _TWO_SPINNERS, *args], capture_output=True, text=True, env=env, timeout=60
+    )
+    lines = proc.stdout.split()
+    assert lines, proc.stderr
+    return lines[-1]
+
+
+def test_yield_interval_interleaves_runners_on_one_worker(tmp_path):
+    # without yielding, whichever guest the only worker polls first keeps it
+    # until it returns
+    order = _spin_order(tmp_path, {})
+    assert order in ("a" * 20 + "b" * 20, "b" * 20 + "a" * 20)
+    order = _spin_order(tmp_path, {"yield_interval_ms": 10})
+    assert sorted(order) == sorted("ab" * 20)
+    assert order not in ("a" * 20 + "b" * 20, "b" * 20 + "a" * 20)
+
+
+def test_blocking_callbacks_free_the_worker_for_other_runners(tmp_path):
+    # a callback that blocks holds the only worker, and the other guest waits
+    order = _spin_order(tmp_path, {}, pause=0.01)
+    assert order in ("a" * 20 + "b" * 20, "b" * 20 + "a" * 20)
+    # unless it hands the worker's other tasks off first
+    order = _spin_order(tmp_path, {}, pause=0.01, blocking_callbacks=True)
+    assert sorted(order) == sorted("ab" * 20)
+    assert order not in ("a" * 20 + "b" * 20, "b" * 20 + "a" * 20)
+
+
+@pytest.mark.asyncio
+async def test_aclose_returns_after_the_guest_stopped(tmp_path):
+    from host import OutcomeKind
+
+    rounds = []
+    runner, _ = _guest("spin", tmp_path, custom_imports={"progress": ([], "bool", lambda: rounds.append(1) or True)})
+    loop = asyncio.ensure_future(runner.run_msg_loop())
+    await _wait_for(lambda: len(rounds) >= 3)
+    await asyncio.wait_for(runner.aclose(reason="test"), timeout=10)
+    stopped_at = len(rounds)
+    assert not runner.running
+    await asyncio.sleep(0.2)
+    assert len(rounds) == stopped_at
+    outcome = await asyncio.wait_for(loop, timeout=10)
+    assert outcome.kind == OutcomeKind.NormalExit
+    assert outcome.stop_reason == "test"
+    # a second call has nothing left to wait for
+    await asyncio.wait_for(runner.aclose(), timeout=1)
+
+
+@pytest.mark.asyncio
+async def test_precompiled_path_needs_a_matching_engine(tmp_path):
+    host = pytest.importorskip("host")
+
+    artifact = str(tmp_path / "echo.cwasm")
+    host.precompile(str(_GUESTS / "echo.wasm"), artifact, {"limits": {"fuel_limit": 10**9}})
+    runner, sent = _guest("echo", tmp_path, [b"hi"], precompiled_path=artifact, limits={"fuel_limit": 10**9})
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert sent == [b"hi"]
+    assert "deserialize" in runner.startup_timings()
+    # an engine without fuel metering cannot run code that meters it
+    with pytest.raises(ValueError, match="is incompatible with this engine"):
+        _guest("echo", tmp_path, precompiled_path=artifact)
+    with pytest.raises(ValueError, match="is not a Wasmtime precompiled artifact"):
+        _guest("echo", tmp_path, precompiled_path=str(_GUESTS / "echo.wasm"))
+
+
+@pytest.mark.asyncio
+@pytest.mark.parametrize(
+    "compile", [{"parallel_compilation": False}, {"compile_threads": 1}, {"compile_threads": 4}]
+)
+async def test_compiles_with_thread_settings(tmp_path, compile):
+    runner, sent = _guest("echo", tmp_path, [b"hi"], compile=compile)
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert sent == [b"hi"]
+    assert "compile" in runner.startup_timings()
+
+
+def test_rejects_zero_compile_threads(tmp_path):
+    with pytest.raises(ValueError, match="compile_threads"):
+        _guest("echo", tmp_path, compile={"compile_threads": 0})
+
+
+@pytest.mark.asyncio
+async def test_world_picks_the_init_exec_env_signature(tmp_path):
+    for world in ("env", "env-basic"):
+        runner, sent = _guest("echo", tmp_path, [b"hi"], world=world)
+        await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+        assert sent == [b"hi"]
+    runner, sent = _guest(
+        "config", tmp_path, world="env-config", log_tags="a,b", init_config={"max_message_size": 4096}
+    )
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert sent == [b"a,b", (4096).to_bytes(8, "little")]
+
+
+def test_world_mismatch_names_the_right_world(tmp_path):
+    # checked when the runner is built, before any guest code runs
+    with pytest.raises(ValueError, match='does not implement the env-config world.*pass world="env"'):
+        _guest("echo", tmp_path, world="env-config")
+    with pytest.raises(ValueError, match='does not implement the env world.*pass world="env-config"'):
+        _guest("config", tmp_path, world="env")
+
+
+def test_sync_mode_exchanges_messages_without_an_event_loop(tmp_path):
+    from host import OutcomeKind
+
+    runner, sent = _guest("echo", tmp_path, [b"one", b"two"], mode="sync")
+    outcome = runner.run_msg_loop()
+    assert outcome.kind == OutcomeKind.NormalExit
+    assert sent == [b"one", b"two"]
+    assert runner.messages_received == 3 and runner.messages_sent == 2
+
+
+@pytest.mark.asyncio
+async def test_init_timeout_bounds_only_the_start(tmp_path):
+    from host import OutcomeKind, WasmInitTimeoutError
+
+    # slowinit sleeps 300ms in init-exec-env
+    runner, _ = _guest("slowinit", tmp_path, limits={"init_timeout_ms": 50})
+    with pytest.raises(WasmInitTimeoutError) as info:
+        await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert info.value.outcome.kind == OutcomeKind.InitTimeout
+    assert info.value.phase == "instantiate"
+    # a slow start is allowed even when the loop itself must be quick
+    runner, _ = _guest("slowinit", tmp_path, limits={"init_timeout_ms": 5_000, "timeout_ms": 100})
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+
+
+def _failing_first(n):
+    """A write_log callback that raises on its first `n` calls."""
+    calls = []
+
+    def write_log(text):
+        calls.append(text)
+        if len(calls) <= n:
+            raise OSError("log sink is down")
+
+    return write_log, calls
+
+
+@pytest.mark.asyncio
+async def test_retries_a_failing_init(tmp_path):
+    from host import WasmHostError
+
+    write_log, calls = _failing_first(2)
+    runner, _ = _guest("slowinit", tmp_path, write_log=write_log, init_max_retries=2, init_retry_backoff_ms=1)
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert calls == ["init"] * 3
+    assert runner.instantiated
+
+    write_log, calls = _failing_first(2)
+    runner, _ = _guest("slowinit", tmp_path, write_log=write_log, init_max_retries=1, init_retry_backoff_ms=1)
+    with pytest.raises(WasmHostError, match="instantiation failed after 2 attempts"):
+        await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert calls == ["init"] * 2
+    assert not runner.instantiated
+
+
+@pytest.mark.asyncio
+async def test_max_wasm_stack_moves_the_overflow(tmp_path):
+    from host import WasmStackOverflowError
+
+    recurse = {"recurse": (["u32"], "u32")}
+    small, _ = _guest("echo", tmp_path, compile={"max_wasm_stack": 64 << 10}, exports=recurse)
+    large, _ = _guest("echo", tmp_path, compile={"max_wasm_stack": 4 << 20}, exports=recurse)
+    assert await small.call_export("recurse", 1_000) == 1_000
+    with pytest.raises(WasmStackOverflowError) as info:
+        await small.call_export("recurse", 20_000)
+    assert info.value.trap_code == "StackOverflow"
+    assert await large.call_export("recurse", 20_000) == 20_000
+
+
+def test_rejects_a_zero_max_wasm_stack(tmp_path):
+    with pytest.raises(ValueError, match="max_wasm_stack must be a positive byte count"):
+        _guest("echo", tmp_path, compile={"max_wasm_stack": 0})
+
+
+@pytest.mark.asyncio
+async def test_engine_features_toggle_simd(tmp_path):
+    no_simd = {"engine_features": {"simd": False}}
+    runner, sent = _guest("simd", tmp_path, compile={"engine_features": {"simd": True}})
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert sent == [(7).to_bytes(4, "little") * 4]
+    with pytest.raises(RuntimeError, match='needs the simd feature.*engine_features=\\{"simd": True\\}'):
+        _guest("simd", tmp_path, compile=no_simd)
+    # components that do not use SIMD are unaffected
+    runner, sent = _guest("echo", tmp_path, [b"hi"], compile=no_simd)
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert sent == [b"hi"]
+
+
+_SEVENS = (7).to_bytes(4, "little") * 4
+
+
+@pytest.mark.asyncio
+async def test_reload_picks_up_a_rebuilt_component(tmp_path):
+    wasm = tmp_path / "guest.wasm"
+    shutil.copy(_GUESTS / "echo.wasm", wasm)
+    runner, sent = _guest("echo", tmp_path, [b"hi"], wasm_path=str(wasm))
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert sent == [b"hi"]
+    # the simd guest sends one message of its own whatever it is sent
+    shutil.copy(_GUESTS / "simd.wasm", wasm)
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert sent == [b"hi"]
+    runner.reload()
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert sent == [b"hi", _SEVENS]
+
+
+@pytest.mark.asyncio
+async def test_source_change_policies(tmp_path):
+    from host import WasmSourceChangedError
+
+    wasm = tmp_path / "guest.wasm"
+    shutil.copy(_GUESTS / "echo.wasm", wasm)
+    reloading, sent = _guest("echo", tmp_path, wasm_path=str(wasm), on_source_change="reload")
+    refusing, _ = _guest("echo", tmp_path, wasm_path=str(wasm), on_source_change="error")
+    for runner in (reloading, refusing):
+        await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    shutil.copy(_GUESTS / "simd.wasm", wasm)
+    await asyncio.wait_for(reloading.run_msg_loop(), timeout=30)
+    assert sent == [_SEVENS]
+    with pytest.raises(WasmSourceChangedError):
+        await asyncio.wait_for(refusing.run_msg_loop(), timeout=30)
+
+
+@pytest.mark.asyncio
+async def test_reload_is_refused_during_a_loop(tmp_path):
+    started = asyncio.Event()
+    loop = asyncio.get_running_loop()
+
+    def progress():
+        loop.call_soon_threadsafe(started.set)
+        return True
+
+    runner, _ = _guest("spin", tmp_path, custom_imports={"progress": ([], "bool", progress)})
+    running = asyncio.ensure_future(runner.run_msg_loop())
+    await asyncio.wait_for(started.wait(), timeout=30)
+    with pytest.raises(RuntimeError, match="cannot reload while run_msg_loop is running"):
+        runner.reload()
+    await asyncio.wait_for(runner.aclose(), timeout=10)
+    await running
+
+
+@pytest.mark.asyncio
+async def test_last_error_records_and_clears(tmp_path):
+    from host import OutcomeKind, WasmHostError
+
+    failures = ["progress is broken", "still broken"]
+
+    def progress():
+        if failures:
+            raise RuntimeError(failures.pop(0))
+        return False
+
+    runner, _ = _guest("spin", tmp_path, custom_imports={"progress": ([], "bool", progress)})
+    assert runner.last_error is None
+    before = time.time()
+    with pytest.raises(WasmHostError):
+        await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    record = runner.last_error
+    assert record.kind == OutcomeKind.HostError
+    assert record.phase == "run_msg_loop"
+    assert "progress is broken" in record.message
+    assert before <= record.timestamp <= time.time()
+    runner.reset()
+    assert runner.last_error is None
+    with pytest.raises(WasmHostError):
+        await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert "still broken" in runner.last_error.message
+    # a clean loop clears it too
+    runner.reset()
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert runner.last_error is None
+
+
+@pytest.mark.asyncio
+async def test_host_call_stats_count_the_calls(tmp_path):
+    runner, sent = _guest("echo", tmp_path, [b"a", b"b", b"c"], host_call_stats=True)
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    stats = runner.host_call_stats()
+    assert stats["send_bytes"]["calls"] == 3
+    # the three messages and the end of stream
+    assert stats["recv_bytes"]["calls"] == 4
+    for s in stats.values():
+        assert 0 <= s["p50_ms"] <= s["p99_ms"] <= s["max_ms"] <= s["total_ms"]
+
+
+def test_host_call_stats_are_opt_in(tmp_path):
+    runner, _ = _guest("echo", tmp_path)
+    with pytest.raises(RuntimeError, match="pass host_call_stats=True"):
+        runner.host_call_stats()
+
+
+def test_inspect_lists_the_interface(tmp_path):
+    runner, _ = _guest("echo", tmp_path)
+    assert runner.inspect() == {
+        "imports": ["recv-bytes", "send-bytes"],
+        "exports": ["add", "crash", "init-exec-env", "recurse", "run-msg-loop"],
+        "world": "env",
+    }
+
+
+def test_interface_mismatches_are_named_up_front(tmp_path):
+    with pytest.raises(ValueError, match="no `run-msg-loop` export"):
+        _guest("noloop", tmp_path)
+    with pytest.raises(ValueError, match="imports function `now-millis`.*custom_imports"):
+        _guest("clock", tmp_path)
+
+
+@pytest.mark.asyncio
+async def test_runners_make_progress_concurrently(tmp_path):
+    host = pytest.importorskip("host")
+    waiting, most_waiting, replies = 0, 0, {}
+
+    def runner(name):
+        inbox = [name.encode()] * 3
+
+        async def recv_bytes():
+            nonlocal waiting, most_waiting
+            waiting += 1
+            most_waiting = max(most_waiting, waiting)
+            await asyncio.sleep(0.05)
+            waiting -= 1
+            return inbox.pop() if inbox else b''
+
+        async def send_bytes(payload):
+            replies.setdefault(name, []).append(bytes(payload))
+
+        return host.WasmRunner(
+            name,
+            send_bytes,
+            recv_bytes,
+            lambda: bool(inbox),
+            lambda text: None,
+            wasm_path=str(_GUESTS / "echo.wasm"),
+            wasm_compiled_cache=str(tmp_path / "echo.compiled"),
+        )
+
+    names = [f"r{i}" for i in range(4)]
+    runners = [runner(name) for name in names]
+    await asyncio.wait_for(asyncio.gather(*(r.run_msg_loop() for r in runners)), timeout=30)
+    assert replies == {name: [name.encode()] * 3 for name in names}
+    # every guest was waiting on its host at the same time
+    assert most_waiting == len(names)
+
+
+@pytest.mark.asyncio
+async def test_stdin_source_feeds_the_guest_in_order(tmp_path):
+    async def chunks():
+        for chunk in (b"one", b"two", b"three"):
+            await asyncio.sleep(0.01)
+            yield chunk
+
+    # the stdin guest sends each chunk it reads until end of file
+    runner, sent = _guest("stdin", tmp_path, wasi={"stdin_source": chunks()}, wasm_inherit_io=False)
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert sent == [b"one", b"two", b"three"]
+    runner, sent = _guest("stdin", tmp_path, wasi={"stdin_source": [b"a", b"b"]}, wasm_inherit_io=False)
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert sent == [b"a", b"b"]
+
+
+@pytest.mark.asyncio
+async def test_manual_epochs_interrupt_at_the_deadline(tmp_path):
+    from host import WasmTrapError
+
+    rounds = []
+    runner, _ = _guest(
+        "spin", tmp_path, manual_epochs=True, custom_imports={"progress": ([], "bool", lambda: rounds.append(1) or True)}
+    )
+    runner.set_epoch_deadline(3)
+    loop = asyncio.ensure_future(runner.run_msg_loop())
+
+    async def tick():
+        # let the guest reach an epoch check, so no two ticks fold into one
+        seen = len(rounds)
+        runner.increment_epoch()
+        await _wait_for(lambda: len(rounds) > seen + 1 or loop.done())
+
+    await _wait_for(lambda: len(rounds) > 0)
+    # nothing advances the epoch but us
+    await asyncio.sleep(0.2)
+    assert not loop.done()
+    await tick()
+    await tick()
+    assert not loop.done()
+    runner.increment_epoch()
+    with pytest.raises(WasmTrapError) as info:
+        await asyncio.wait_for(loop, timeout=10)
+    assert info.value.trap_code == "Interrupt"
+
+
+def test_epoch_control_needs_manual_epochs(tmp_path):
+    runner, _ = _guest("echo", tmp_path)
+    with pytest.raises(RuntimeError, match="pass manual_epochs=True"):
+        runner.increment_epoch()
+
+
+@pytest.mark.asyncio
+async def test_heartbeat_fires_at_its_interval(tmp_path):
+    beats = []
+    started = time.monotonic()
+    runner, _ = _guest(
+        "spin",
+        tmp_path,
+        custom_imports={"progress": ([], "bool", lambda: time.monotonic() - started < 0.5)},
+        heartbeat=lambda count, elapsed_ms: beats.append((count, elapsed_ms)),
+        heartbeat_interval_ms=50,
+    )
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    counts = [count for count, _ in beats]
+    assert counts == list(range(1, len(beats) + 1))
+    # about ten in half a second, with room for a slow machine
+    assert 4 <= len(beats) <= 12
+    for count, elapsed_ms in beats:
+        assert elapsed_ms >= count * 50 * 0.9
+
+
+@pytest.mark.asyncio
+async def test_heartbeat_returning_false_stops_the_loop(tmp_path):
+    runner, _ = _guest(
+        "spin",
+        tmp_path,
+        custom_imports={"progress": ([], "bool", lambda: True)},
+        heartbeat=lambda count, elapsed_ms: count < 3,
+        heartbeat_interval_ms=20,
+    )
+    outcome = await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert outcome.stop_reason == "heartbeat"
+
+
+@pytest.mark.asyncio
+async def test_virtual_files_are_readable_but_not_writable(tmp_path):
+    runner, sent = _guest(
+        "vfs",
+        tmp_path,
+        [b"ra.txt", b"rdata/b.bin", b"wa.txt", b"rmissing"],
+        wasi={"virtual_files": {"a.txt": b"alpha", "data/b.bin": b"\x00\x01"}},
+        wasm_inherit_io=False,
+    )
+    await runner.run_msg_loop()
+    # 33 is error-code.read-only and 20 is error-code.no-entry
+    assert sent == [b"alpha", b"\x00\x01", b"error:" + bytes([33]), b"error:" + bytes([20])]
+
+
+@pytest.mark.asyncio
+async def test_set_log_tags_applies_from_the_next_instance(tmp_path):
+    runner, sent = _guest("config", tmp_path, world="env-config", log_tags="a")
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    runner.set_log_tags("c")
+    # the live instance keeps the tags it was initialized with
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    runner.reset()
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    # each run sends the tags, then the (unset) max-message-size
+    assert sent[::2] == [b"a", b"a", b"c"]
+
+
+@pytest.mark.asyncio
+@pytest.mark.parametrize("batched", [True, False])
+async def test_batches_cross_into_python_once_per_batch(tmp_path, batched):
+    # each callback call is one GIL acquisition, so counting calls counts them
+    messages = [b"m%d" % i for i in range(100)]
+    batches = [messages[i : i + 10] for i in range(0, 100, 10)]
+    calls, received = {"recv": 0, "send": 0}, []
+
+    async def recv_bytes_batch():
+        calls["recv"] += 1
+        return batches.pop(0) if batches else []
+
+    async def send_bytes_batch(payloads):
+        calls["send"] += 1
+        received.extend(bytes(payload) for payload in payloads)
+
+    kwargs = {"recv_bytes_batch": recv_bytes_batch, "send_bytes_batch": send_bytes_batch} if batched else {}
+    runner, sent = _guest("batch", tmp_path, [] if batched else messages, host_call_stats=True, **kwargs)
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    singles = {name: stats["calls"] for name, stats in runner.host_call_stats().items()}
+    if batched:
+        assert received == messages and sent == []
+        # ten batches and the empty one that ends the stream
+        assert calls == {"recv": 11, "send": 10}
+        assert "recv_bytes" not in singles and "send_bytes" not in singles
+    else:
+        # without the callbacks each message falls back to a call of its own
+        assert sent == messages
+        assert singles["recv_bytes"] == 101 and singles["send_bytes"] == 100
+
+
+@pytest.mark.asyncio
+async def test_inflight_window_throttles_the_guest_to_the_consumer(tmp_path):
+    inflight, most = 0, 0
+
+    async def slow(payload):
+        nonlocal inflight, most
+        inflight += 1
+        most = max(most, inflight)
+        await asyncio.sleep(0.02)
+        inflight -= 1
+
+    messages = [b"m%d" % i for i in range(10)]
+    runner, sent = _guest("echo", tmp_path, messages, consume=slow, transport={"max_inflight_messages": 2})
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert sent == messages
+    # the guest ran ahead of the consumer, but never past the window
+    assert most == 2
+
+
+@pytest.mark.asyncio
+async def test_stalled_consumer_times_out_instead_of_hanging(tmp_path):
+    from host import OutcomeKind, WasmTimeoutError
+
+    async def stalled(payload):
+        await asyncio.Event().wait()
+
+    runner, _ = _guest(
+        "echo",
+        tmp_path,
+        [b"m%d" % i for i in range(10)],
+        consume=stalled,
+        transport={"max_inflight_messages": 2},
+        limits={"timeout_ms": 300},
+    )
+    with pytest.raises(WasmTimeoutError) as info:
+        await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert info.value.outcome.kind == OutcomeKind.Timeout
+
+
+@pytest.mark.asyncio
+@pytest.mark.parametrize("delay,polls", [(0.01, 0), (0.3, 2)])
+async def test_recv_timeout_lets_the_guest_poll(tmp_path, delay, polls):
+    delays = [delay]
+    runner, sent = _guest(
+        "poll",
+        tmp_path,
+        [b"late"],
+        pace=lambda: asyncio.sleep(delays.pop() if delays else 0),
+        limits={"recv_timeout_ms": 100},
+    )
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    # a wait that timed out resumes on the next poll, so nothing is lost
+    assert sent[-1] == b"late"
+    assert set(sent[:-1]) <= {b"-"} and len(sent) - 1 >= polls
+    if not polls:
+        assert sent == [b"late"]
+
+
+@pytest.mark.asyncio
+@pytest.mark.parametrize(
+    "engine_config",
+    [
+        {},
+        {"memory_init_cow": False},
+        # dynamic memories, reallocated as they grow
+        {"memory_reservation": 0, "memory_guard_size": 0},
+        {"memory_reservation": 1 << 20, "memory_may_move": False},
+        {"guard_before_linear_memory": True},
+    ],
+)
+async def test_instantiates_under_each_memory_config(tmp_path, engine_config):
+    # cow only changes how fast a fresh store's memory is set up, so each
+    # layout must behave the same, including after reset()
+    runner, sent = _guest("echo", tmp_path, [b"one"], compile={"engine_config": engine_config})
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    runner.reset()
+    runner.preload_messages([b"two"])
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert sent == [b"one", b"two"]
+
+
+@pytest.mark.asyncio
+async def test_memory_config_is_part_of_the_cache_key(tmp_path):
+    cached = tmp_path / "shared.compiled"
+    for engine_config in ({}, {"memory_reservation": 0}):
+        runner, sent = _guest(
+            "echo", tmp_path, [b"x"], wasm_compiled_cache=str(cached), compile={"engine_config": engine_config}
+        )
+        # the second runner recompiles rather than loading code laid out
+        # for the first one's memories
+        assert "precompile" in runner.startup_timings()
+        await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+        assert sent == [b"x"]
+
+
+@pytest.mark.asyncio
+async def test_startup_timings_cover_a_cold_start(tmp_path):
+    runner, _ = _guest("echo", tmp_path)
+    assert set(runner.startup_timings()) == {"read_bytes", "precompile", "compile"}
+    await runner.instantiate()
+    timings = runner.startup_timings()
+    assert set(timings) == {"read_bytes", "precompile", "compile", "instantiate", "init_exec_env"}
+    assert all(ms > 0 for ms in timings.values())
+    # a warm start loads the artifact the cold one wrote
+    warm, _ = _guest("echo", tmp_path)
+    assert set(warm.startup_timings()) == {"read_bytes", "deserialize"}
+
+
+@pytest.mark.asyncio
+async def test_paths_resolve_against_the_base_dir_not_the_cwd(tmp_path):
+    elsewhere = tmp_path / "elsewhere"
+    elsewhere.mkdir()
+    cwd = os.getcwd()
+    os.chdir(elsewhere)
+    try:
+        runner, sent = _guest("echo", tmp_path, [b"x"], wasm_path="echo.wasm", base_dir=str(_GUESTS))
+        await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+        assert sent == [b"x"]
+        os.environ["WASM_RUNNER_BASE_DIR"] = str(_GUESTS)
+        try:
+            # the error names the absolute path tried
+            missing = str(_GUESTS.resolve() / "missing.wasm")
+            with pytest.raises(FileNotFoundError, match=re.escape(missing)):
+                _guest("echo", tmp_path, wasm_path="missing.wasm")
+        finally:
+            del os.environ["WASM_RUNNER_BASE_DIR"]
+    finally:
+        os.chdir(cwd)
+
+
+@pytest.mark.asyncio
+async def test_call_export_dispatches_by_name(tmp_path):
+    from host import WasmTrapError
+
+    runner, sent = _guest(
+        "echo", tmp_path, [b"after"], exports={"add": (["s32", "s32"], "s32"), "crash": ([], None)}
+    )
+    assert await runner.call_export("add", 2, 40) == 42
+    assert await runner.call_export("add", -5, 3) == -2
+    with pytest.raises(ValueError, match='export "recurse" is not declared'):
+        runner.call_export("recurse", 1)
+    with pytest.raises(WasmTrapError):
+        await runner.call_export("crash")
+    # a trapped instance is not entered again; the next call starts afresh
+    assert await runner.call_export("add", 1, 2) == 3
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert sent == [b"after"]
+
+
+def _sleepy(rounds, seconds):
+    """A `progress` import that waits `seconds` on each of `rounds` calls."""
+    left = [rounds]
+
+    def progress():
+        time.sleep(seconds)
+        left[0] -= 1
+        return left[0] > 0
+
+    return progress
+
+
+@pytest.mark.asyncio
+async def test_cpu_timeout_ignores_time_in_host_calls(tmp_path):
+    from host import OutcomeKind, WasmTimeoutError
+
+    # half a second of waiting on the host, little of computing; each
+    # wait is charged as the one tick it ends on, see epoch::install
+    runner, _ = _guest(
+        "spin",
+        tmp_path,
+        custom_imports={"progress": ([], "bool", _sleepy(5, 0.1))},
+        limits={"cpu_timeout_ms": 300},
+    )
+    outcome = await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert outcome.kind == OutcomeKind.NormalExit
+    # the same wait runs out a wall-clock timeout
+    runner, _ = _guest(
+        "spin",
+        tmp_path,
+        custom_imports={"progress": ([], "bool", _sleepy(5, 0.1))},
+        limits={"timeout_ms": 300},
+    )
+    with pytest.raises(WasmTimeoutError):
+        await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    # and computing without end still runs out the CPU one
+    runner, _ = _guest(
+        "spin", tmp_path, custom_imports={"progress": ([], "bool", lambda: True)}, limits={"cpu_timeout_ms": 300}
+    )
+    with pytest.raises(WasmTimeoutError, match="cpu_timeout_ms=300") as info:
+        await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert info.value.outcome.kind == OutcomeKind.Timeout
+
+
+@pytest.mark.asyncio
+async def test_preloaded_messages_come_before_the_callback(tmp_path):
+    runner, sent = _guest("echo", tmp_path, [b"live"])
+    runner.preload_messages([b"one", b"two"])
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert sent == [b"one", b"two", b"live"]
+
+
+def _frames(inbox):
+    """`recv_frame`/`send_frame` callbacks over `inbox`, and the list sent
+    frames go to; running out of frames is an empty one."""
+    inbox, sent = list(inbox), []
+
+    async def recv_frame():
+        return inbox.pop(0) if inbox else (0, 0, b"")
+
+    async def send_frame(kind, flags, body):
+        sent.append((kind, flags, bytes(body)))
+
+    return {"recv_frame": recv_frame, "send_frame": send_frame}, sent
+
+
+@pytest.mark.asyncio
+async def test_frames_round_trip_with_their_header(tmp_path):
+    callbacks, sent = _frames([(7, 3, b"body"), (1, 0, bytearray(b"more"))])
+    runner, _ = _guest("frame", tmp_path, host_call_stats=True, **callbacks)
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert sent == [(7, 3, b"body"), (1, 0, b"more")]
+    assert runner.messages_sent == 2 and runner.messages_received == 3
+    stats = runner.host_call_stats()
+    assert stats["recv_frame"]["calls"] == 3 and stats["send_frame"]["calls"] == 2
+
+
+@pytest.mark.asyncio
+async def test_max_message_size_bounds_frame_bodies(tmp_path):
+    from host import OutcomeKind, WasmMessageTooLargeError
+
+    callbacks, sent = _frames([(1, 0, b"x" * 8)])
+    runner, _ = _guest("frame", tmp_path, transport={"max_message_size": 8}, **callbacks)
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert sent == [(1, 0, b"x" * 8)]
+
+    callbacks, sent = _frames([(1, 0, b"x" * 9)])
+    runner, _ = _guest("frame", tmp_path, transport={"max_message_size": 8}, **callbacks)
+    with pytest.raises(WasmMessageTooLargeError) as info:
+        await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert info.value.outcome.kind == OutcomeKind.MessageTooLarge
+    assert "recv_frame message of 9 bytes exceeds max_message_size=8" in str(info.value)
+    assert sent == []
+
+
+def _nap(ms):
+    """A message the `nap` fixture sleeps `ms` milliseconds on."""
+    return ms.to_bytes(8, "little")
+
+
+@pytest.mark.asyncio
+async def test_sleep_accumulates_slept_ms(tmp_path):
+    runner, sent = _guest("nap", tmp_path, [_nap(50), _nap(50)])
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert sent == [_nap(50), _nap(50)]
+    assert 100 <= runner.metrics()["slept_ms"] < 1_000
+
+
+@pytest.mark.asyncio
+async def test_drain_cuts_a_sleep_short(tmp_path):
+    runner, sent = _guest("nap", tmp_path, [_nap(60_000)])
+    loop = asyncio.ensure_future(runner.run_msg_loop())
+    await asyncio.sleep(0.1)
+    started = time.monotonic()
+    runner.drain("enough")
+    outcome = await asyncio.wait_for(loop, timeout=30)
+    assert time.monotonic() - started < 5
+    # the sleep returned early, so the guest finished the message
+    assert sent == [_nap(60_000)]
+    assert outcome.stop_reason == "enough"
+
+
+@pytest.mark.asyncio
+async def test_close_unwinds_a_sleep(tmp_path):
+    runner, sent = _guest("nap", tmp_path, [_nap(60_000)])
+    loop = asyncio.ensure_future(runner.run_msg_loop())
+    await asyncio.sleep(0.1)
+    started = time.monotonic()
+    runner.close("stop")
+    outcome = await asyncio.wait_for(loop, timeout=30)
+    assert time.monotonic() - started < 5
+    # the guest never got back from the sleep
+    assert sent == []
+    assert outcome.stop_reason == "stop"
+    assert not runner.instantiated
+
+
+@pytest.mark.asyncio
+async def test_cancelling_a_call_is_not_a_runtime_shutdown(tmp_path):
+    runner, sent = _guest("nap", tmp_path, [_nap(60_000)])
+    loop = asyncio.ensure_future(runner.run_msg_loop())
+    await asyncio.sleep(0.1)
+    loop.cancel()
+    with pytest.raises(asyncio.CancelledError):
+        await loop
+    # the runner is not marked as torn down: calls go on, without reset()
+    await asyncio.sleep(0.1)
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+
+
+def _replies(sent):
+    """The task guest's replies as (ok, body) pairs."""
+    return [(sent[i] == b"\x00", sent[i + 1]) for i in range(0, len(sent), 2)]
+
+
+@pytest.mark.asyncio
+async def test_spawned_tasks_are_capped_and_awaited_by_handle(tmp_path):
+    gate = asyncio.Event()
+
+    async def spawn_task(payload):
+        await gate.wait()
+        return bytes(payload).upper()
+
+    async def pace():
+        # hold the tasks until all three spawns have been answered
+        if len(sent) >= 6:
+            gate.set()
+
+    def handle(n):
+        return n.to_bytes(8, "little")
+
+    def wait(n):
+        return b"a" + handle(n)
+
+    inbox = [b"sone", b"stwo", b"sthree", wait(0), wait(1), wait(0), b"sfour", wait(2), wait(99)]
+    runner, sent = _guest("task", tmp_path, inbox, pace=pace, spawn_task=spawn_task, max_concurrent_tasks=2)
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert _replies(sent) == [
+        (True, handle(0)),
+        (True, handle(1)),
+        (False, b"WasmRunner: max_concurrent_tasks (2) reached"),
+        (True, b"ONE"),
+        (True, b"TWO"),
+        # a handle is gone once awaited
+        (False, b"WasmRunner: unknown task handle 0"),
+        # the finished tasks gave their permits back
+        (True, handle(2)),
+        (True, b"FOUR"),
+        (False, b"WasmRunner: unknown task handle 99"),
+    ]
+    assert runner.metrics()["live_tasks"] == 0
+
+
+@pytest.mark.asyncio
+async def test_pool_evicts_idle_runners_down_to_min_warm(tmp_path):
+    host = pytest.importorskip("host")
+
+    async def idle(*args):
+        return b""
+
+    kwargs = dict(
+        id_name="echo", send_bytes=idle, recv_bytes=idle, recv_ready=lambda: False,
+        write_log=lambda text: None, wasm_compiled_cache=str(tmp_path / "echo.compiled"),
+    )
+    pool = host.WasmRunnerPool(str(_GUESTS / "echo.wasm"), 2, kwargs, idle_timeout_ms=100, min_warm=1)
+    await asyncio.wait_for(pool.prewarm(), timeout=30)
+    assert pool.stats()["warm"] == 2
+    await asyncio.sleep(0.5)
+    stats = pool.stats()
+    assert stats["warm"] == 1 and stats["evictions"] == 1
+    # min_warm keeps the other one, so there is nothing more to evict
+    await asyncio.sleep(0.3)
+    assert pool.stats()["evictions"] == 1
+    # whichever is handed out, the evicted runner is warmed again
+    runners = [await asyncio.wait_for(pool.acquire(), timeout=30) for _ in range(2)]
+    assert all(runner.instantiated for runner in runners)
+    assert pool.stats()["in_use"] == 2
+
+
+@pytest.mark.asyncio
+async def test_drop_behavior_interrupt_stops_the_guest(tmp_path):
+    import gc
+
+    from host import WasmTrapError
+
+    started = asyncio.Event()
+    loop = asyncio.get_running_loop()
+    rounds = []
+
+    def progress():
+        rounds.append(1)
+        loop.call_soon_threadsafe(started.set)
+        return True
+
+    runner, _ = _guest(
+        "spin", tmp_path, drop_behavior="interrupt", custom_imports={"progress": ([], "bool", progress)}
+    )
+    running = asyncio.ensure_future(runner.run_msg_loop())
+    await asyncio.wait_for(started.wait(), timeout=30)
+    del runner
+    gc.collect()
+    # the guest would spin forever; the drop traps it at its next epoch check
+    with pytest.raises(WasmTrapError):
+        await asyncio.wait_for(running, timeout=10)
+    spun = len(rounds)
+    await asyncio.sleep(0.1)
+    assert len(rounds) == spun
+
+
+@pytest.mark.asyncio
+async def test_drop_behavior_wait_lets_the_loop_finish(tmp_path):
+    import gc
+
+    started = asyncio.Event()
+    loop = asyncio.get_running_loop()
+    dropped = []
+    after = []
+
+    def progress():
+        loop.call_soon_threadsafe(started.set)
+        if dropped:
+            after.append(1)
+        return len(after) < 20
+
+    runner, _ = _guest(
+        "spin", tmp_path, drop_behavior="wait", drop_timeout_ms=30_000,
+        custom_imports={"progress": ([], "bool", progress)},
+    )
+    running = asyncio.ensure_future(runner.run_msg_loop())
+    await asyncio.wait_for(started.wait(), timeout=30)
+    began = time.monotonic()
+    del runner
+    gc.collect()
+    dropped.append(1)
+    # Drop hands the wait to the runtime instead of blocking on the loop
+    assert time.monotonic() - began < 1
+    assert not running.done()
+    outcome = await asyncio.wait_for(running, timeout=30)
+    assert len(after) == 20
+    assert outcome.stop_reason is None
+
+
+@pytest.mark.asyncio
+async def test_reinit_reinitializes_the_live_instance(tmp_path):
+    runner, sent = _guest("config", tmp_path, world="env-config", log_tags="a")
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    await asyncio.wait_for(runner.reinit("config", "b", {"max_message_size": 7}), timeout=30)
+    assert runner.instantiated
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    # no tags keeps the guest's earlier ones, as only the same instance can
+    await asyncio.wait_for(runner.reinit("config"), timeout=30)
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert sent == [b"a", bytes(8), b"b", (7).to_bytes(8, "little"), b"b", bytes(8)]
+
+
+@pytest.mark.asyncio
+async def test_reinit_needs_the_export(tmp_path):
+    from host import WasmError
+
+    runner, sent = _guest("echo", tmp_path, [b"hi"])
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    with pytest.raises(WasmError, match="component does not support reinit"):
+        await asyncio.wait_for(runner.reinit("echo", "b"), timeout=30)
+    # the instance is untouched
+    assert runner.instantiated
+
+
+@pytest.mark.asyncio
+async def test_transforms_rewrite_payloads_both_ways(tmp_path):
+    seen = []
+
+    def on_recv_transform(payload):
+        seen.append(bytes(payload))
+        # end of stream stays empty
+        return bytes(payload)[::-1]
+
+    runner, sent = _guest(
+        "echo", tmp_path, [b"abc", b"hi"],
+        on_recv_transform=on_recv_transform, on_send_transform=lambda payload: bytes(payload).upper(),
+    )
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert seen == [b"abc", b"hi", b""]
+    assert sent == [b"CBA", b"IH"]
+
+
+@pytest.mark.asyncio
+@pytest.mark.parametrize("side", ["send", "recv"])
+async def test_a_raising_transform_fails_the_loop(tmp_path, side):
+    from host import WasmHostError
+
+    def broken(payload):
+        raise ValueError("no thanks")
+
+    runner, sent = _guest("echo", tmp_path, [b"hi"], **{f"on_{side}_transform": broken})
+    with pytest.raises(WasmHostError, match=f"on_{side}_transform failed") as info:
+        await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert "no thanks" in str(info.value)
+    assert sent == []
+
+
+@pytest.mark.asyncio
+async def test_max_table_elements_refuses_table_growth(tmp_path):
+    def grow(by):
+        return by.to_bytes(4, "little")
+
+    runner, sent = _guest("table", tmp_path, [grow(3), grow(4), grow(1)], max_table_elements=8)
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    # table.grow gives the old size, and -1 for the step past the limit
+    assert [int.from_bytes(reply, "little", signed=True) for reply in sent] == [1, 4, -1]
+    metrics = runner.metrics()
+    assert metrics["table_elements"] == 8
+    assert metrics["peak_table_elements"] == 8
+
+
+
+@pytest.mark.asyncio
+async def test_max_table_elements_below_a_table_minimum_fails_instantiation(tmp_path):
+    from host import WasmError
+
+    runner, _ = _guest("table", tmp_path, max_table_elements=0)
+    with pytest.raises(WasmError, match="table minimum size of 1 elements exceeds table limits"):
+        await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert not runner.instantiated
+
+
+@pytest.mark.asyncio
+async def test_required_capabilities_lists_the_guest_imports(tmp_path):
+    runner, _ = _guest("task", tmp_path)
+    # read from the component type, before anything is instantiated
+    assert sorted(runner.required_capabilities()) == ["await-task", "recv-bytes", "send-bytes", "spawn-task"]
+    assert not runner.instantiated
+    runner, _ = _guest("nap", tmp_path, [_nap(60_000)])
+    loop = asyncio.ensure_future(runner.run_msg_loop())
+    await asyncio.sleep(0.1)
+    assert sorted(runner.required_capabilities()) == ["recv-bytes", "send-bytes", "sleep"]
+    runner.close("done")
+    await asyncio.wait_for(loop, timeout=30)
+
+
+@pytest.mark.asyncio
+async def test_next_id_counts_up_from_id_base_across_resets(tmp_path):
+    runner, sent = _guest("ids", tmp_path, [b"x", b"y"], id_base=1000)
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    runner.preload_messages([b"z"])
+    # a fresh store carries the counter on, so ids stay unique
+    runner.reset()
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert [int.from_bytes(reply, "little") for reply in sent] == [1000, 1001, 1002]
+
+
+@pytest.mark.asyncio
+async def test_on_loop_summary_reports_each_loop(tmp_path):
+    from host import WasmHostError
+
+    summaries = []
+    runner, sent = _guest("echo", tmp_path, [b"abc", b"de"], on_loop_summary=summaries.append)
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    runner.preload_messages([b"xyz"])
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    counts = [
+        {key: summary[key] for key in ("exit", "messages_sent", "messages_received", "bytes_sent", "bytes_received")}
+        for summary in summaries
+    ]
+    # each loop counts its own messages, the end of stream included
+    assert counts == [
+        {"exit": "normal", "messages_sent": 2, "messages_received": 3, "bytes_sent": 5, "bytes_received": 5},
+        {"exit": "normal", "messages_sent": 1, "messages_received": 2, "bytes_sent": 3, "bytes_received": 3},
+    ]
+    assert all(summary["duration_ms"] >= 0 for summary in summaries)
+
+    def progress():
+        raise RuntimeError("progress is broken")
+
+    summaries.clear()
+    runner, _ = _guest(
+        "spin", tmp_path, on_loop_summary=summaries.append, custom_imports={"progress": ([], "bool", progress)}
+    )
+    with pytest.raises(WasmHostError):
+        await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert [summary["exit"] for summary in summaries] == ["error"]
+
+
+def test_callback_arity_is_checked_at_construction(tmp_path):
+    expected = "write_log must accept 1 positional argument(s), got 0 parameter(s): write_log()"
+    with pytest.raises(TypeError, match=re.escape(expected)):
+        _guest("echo", tmp_path, write_log=lambda: None)
+    expected = "on_send_transform must accept 1 positional argument(s), got 2 parameter(s)"
+    with pytest.raises(TypeError, match=re.escape(expected)):
+        _guest("echo", tmp_path, on_send_transform=lambda payload, extra: payload)
+    with pytest.raises(TypeError, match="on_loop_summary must be callable"):
+        _guest("echo", tmp_path, on_loop_summary=3)
+    # anything that binds fits, as do builtins without a signature
+    _guest(
+        "echo", tmp_path, write_log=lambda *args: None, on_send_transform=bytes, on_loop_summary=print
+    )
+
+
+
+@pytest.mark.asyncio
+async def test_dump_profile_splits_guest_host_and_wait_time(tmp_path):
+    async def pace():
+        await asyncio.sleep(0.05)
+
+    runner, _ = _guest("echo", tmp_path, [b"a", b"b"], pace=pace, profile=True)
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    path = tmp_path / "echo.folded"
+    runner.dump_profile(str(path))
+    folded = {}
+    for line in path.read_text().splitlines():
+        stack, micros = line.rsplit(" ", 1)
+        folded[stack] = int(micros)
+    assert {"instantiate;guest", "run_msg_loop;guest"} <= folded.keys()
+    assert {"run_msg_loop;host;recv_bytes", "run_msg_loop;host;send_bytes"} <= folded.keys()
+    # three receives, each awaiting the paced callback
+    assert folded["run_msg_loop;wait;recv_bytes"] >= 150_000
+    runner, _ = _guest("echo", tmp_path)
+    with pytest.raises(RuntimeError, match="profiling is not enabled; pass profile=True"):
+        runner.dump_profile(str(path))
+
+
+
+def _huge_page_reservations():
+    """How many of this process' mappings of 1 GiB or more are marked
+    MADV_HUGEPAGE (`hg` in their smaps VmFlags)."""
+    count = 0
+    for entry in re.split(r"\n(?=[0-9a-f]+-[0-9a-f]+ )", Path("/proc/self/smaps").read_text()):
+        start, end = (int(bound, 16) for bound in entry.split(None, 1)[0].split("-"))
+        flags = re.search(r"^VmFlags:(.*)$", entry, re.MULTILINE)
+        if end - start >= 1 << 30 and flags and "hg" in flags.group(1).split():
+            count += 1
+    return count
+
+
+@pytest.mark.asyncio
+async def test_huge_pages_marks_guest_memory(tmp_path):
+    host = pytest.importorskip("host")
+    if not Path("/proc/self/smaps").exists():
+        pytest.skip("huge_pages only applies on Linux")
+    before = _huge_page_reservations()
+    runner, sent = _guest("nap", tmp_path, [_nap(300)], huge_pages=True)
+    loop = asyncio.ensure_future(runner.run_msg_loop())
+    await _wait_for(lambda: runner.instantiated)
+    # the guest's linear memory is reserved with the madvise hint
+    assert _huge_page_reservations() > before
+    await asyncio.wait_for(loop, timeout=30)
+    assert sent == [_nap(300)]
+    with pytest.raises(ValueError, match="huge_pages .* cannot be combined with pooling"):
+        host.EngineHandle({"pooling": True, "huge_pages": True})
+
+
+@pytest.mark.asyncio
+async def test_wasi_env_and_args_reach_the_guest(tmp_path):
+    env = [("API", "one"), ("MODE", "fast"), ("API", "two")]
+    runner, sent = _guest("cli", tmp_path, wasi={"env": env, "args": ["agent", "--verbose"]})
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    # a repeated key keeps its first place and takes the last value
+    assert sent == [b"agent", b"--verbose", b"API", b"two", b"MODE", b"fast"]
+    with pytest.raises(ValueError, match='invalid wasm_env key "A=B"'):
+        _guest("cli", tmp_path, wasi={"env": [("A=B", "x")]})
+
+
+@pytest.mark.asyncio
+async def test_on_stdout_and_on_stderr_get_whole_lines(tmp_path):
+    out, err = [], []
+    inbox = [b"1hello, ", b"1world\nsecond\nthi", b"2oops\n", b"1rd"]
+    wasi = {"on_stdout": out.append, "on_stderr": err.append}
+    runner, _ = _guest("print", tmp_path, inbox, wasm_inherit_io=False, wasi=wasi)
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    # one call per line; the partial last one arrives with the flush
+    assert out == [b"hello, world\n", b"second\n", b"third"]
+    assert err == [b"oops\n"]
+
+
+@pytest.mark.asyncio
+async def test_async_recv_ready_awaits_the_readiness_check(tmp_path):
+    from host import WasmHostError
+
+    answers = [False, True, True, False]
+
+    async def recv_ready():
+        await asyncio.sleep(0.01)
+        return answers.pop(0) if answers else True
+
+    runner, sent = _guest(
+        "ready", tmp_path, [b"a", b"b"], recv_ready=recv_ready, async_recv_ready=True
+    )
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert sent == [b"-", b"a", b"b", b"-"]
+    # the flag picks the calling convention: a plain function is refused
+    runner, _ = _guest("ready", tmp_path, recv_ready=lambda: True, async_recv_ready=True)
+    with pytest.raises(WasmHostError, match="an async callback returned bool, not an awaitable"):
+        await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+
+
+@pytest.mark.asyncio
+async def test_recv_payloads_take_any_byte_buffer(tmp_path):
+    import array
+
+    from host import WasmHostError
+
+    inbox = [
+        bytearray(b"array"),
+        memoryview(b"view"),
+        # strided, so copied out through PyBuffer rather than in place
+        memoryview(b"s-t-r-i-d-e")[::2],
+        array.array("B", b"uint8"),
+        [108, 105, 115, 116],
+        None,
+    ]
+    runner, sent = _guest("echo", tmp_path, inbox)
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    # None is end of stream, like an empty message
+    assert sent == [b"array", b"view", b"stride", b"uint8", b"list"]
+    runner, sent = _guest("echo", tmp_path, ["text"])
+    with pytest.raises(WasmHostError):
+        await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert sent == []
+
+
+def _log(level, text, tagged=False):
+    """A message asking the log guest for a record at `level` (None for
+    none), or for a plain `write-log` line when `level` is "plain"."""
+    levels = ["trace", "debug", "info", "warn", "error", None]
+    if level == "plain":
+        return b"\x06" + text
+    return bytes([levels.index(level), tagged]) + text
+
+
+@pytest.mark.asyncio
+async def test_write_log_record_passes_level_and_tags(tmp_path):
+    inbox = [_log("warn", b"low disk", tagged=True), _log(None, b"no level"), _log("plain", b"flat")]
+    records, lines = [], []
+
+    def write_log_record(level, msg, tags):
+        records.append((level, msg, tags))
+
+    runner, _ = _guest(
+        "log", tmp_path, list(inbox), write_log=lines.append, write_log_record=write_log_record
+    )
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    # a missing level is info, as is a plain write-log line
+    assert records == [
+        ("warn", "low disk", {"source": "guest"}),
+        ("info", "no level", {}),
+        ("info", "flat", {}),
+    ]
+    assert lines == []
+    # without the callback, only the messages reach write_log
+    runner, _ = _guest("log", tmp_path, list(inbox), write_log=lines.append)
+    await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    assert lines == ["low disk", "no level", "flat"]
+
+
+@pytest.mark.asyncio
+async def test_logger_name_routes_guest_logs_through_logging(tmp_path):
+    import logging
+
+    class Keep(logging.Handler):
+        def __init__(self):
+            super().__init__(1)
+            self.records = []
+
+        def emit(self, record):
+            self.records.append(record)
+
+    keep = Keep()
+    base = logging.getLogger("wasmtest")
+    base.addHandler(keep)
+    base.setLevel(1)
+    try:
+        inbox = [_log("trace", b"fine", tagged=True), _log("error", b"bad"), _log("plain", b"ok")]
+        runner, _ = _guest("log", tmp_path, inbox, logger_name="wasmtest")
+        await asyncio.wait_for(runner.run_msg_loop(), timeout=30)
+    finally:
+        base.removeHandler(keep)
+        base.setLevel(logging.NOTSET)
+    guest = [record for record in keep.records if record.name == "wasmtest.log.guest"]
+    # trace has no stdlib level and sits below DEBUG
+    assert [(record.levelno, record.getMessage()) for record in guest] == [
+        (5, "fine"), (logging.ERROR, "bad"), (logging.INFO, "ok"),
+    ]
+    assert guest[0].wasm_tags == {"source": "guest"}
+    assert not hasattr(guest[1], "wasm_tags")
+    # the runner's own diagnostics go to the per-instance logger above it
+    assert any(record.name == "wasmtest.log" for record in keep.records)
@@ -0,0 +1,50 @@
+;; A guest for the `env` world that echoes a batch at a time:
+;; `run-msg-loop` sends each batch `recv-bytes-batch` returns back through
+;; `send-bytes-batch` until it returns an empty one. Rebuild with
+;;   wasm-tools parse batch.wat -o batch.wasm
+(component
+  (import "recv-bytes-batch" (func $recv-bytes-batch (result (list (list u8)))))
+  (import "send-bytes-batch" (func $send-bytes-batch (param "payloads" (list (list u8)))))
+
+  (core module $libc
+    (memory (export "memory") 1)
+    (global $heap (mut i32) (i32.const 1024))
+    ;; never frees; the batches a test sends fit in the first page
+    (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+      (local $at i32)
+      (local.set $at
+        (i32.and (i32.add (global.get $heap) (i32.sub (local.get 2) (i32.const 1)))
+                 (i32.sub (i32.const 0) (local.get 2))))
+      (global.set $heap (i32.add (local.get $at) (local.get 3)))
+      (local.get $at)))
+  (core instance $libc (instantiate $libc))
+
+  (core func $recv
+    (canon lower (func $recv-bytes-batch) (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (core func $send (canon lower (func $send-bytes-batch) (memory $libc "memory")))
+
+  (core module $main
+    (import "host" "recv-bytes-batch" (func $recv (param i32)))
+    (import "host" "send-bytes-batch" (func $send (param i32 i32)))
+    (import "libc" "memory" (memory 1))
+    (func (export "init-exec-env") (param i32 i32 i32 i32 i32))
+    ;; the batch lands as (ptr, len) at 0, already laid out as the
+    ;; list<list<u8>> send-bytes-batch takes
+    (func (export "run-msg-loop")
+      (block $end
+        (loop $next
+          (call $recv (i32.const 0))
+          (br_if $end (i32.eqz (i32.load (i32.const 4))))
+          (call $send (i32.load (i32.const 0)) (i32.load (i32.const 4)))
+          (br $next)))))
+  (core instance $main
+    (instantiate $main
+      (with "libc" (instance $libc))
+      (with "host" (instance
+        (export "recv-bytes-batch" (func $recv))
+        (export "send-bytes-batch" (func $send))))))
+
+  (func (export "init-exec-env") (param "id-name" string) (param "log-tags" (option string))
+    (canon lift (core func $main "init-exec-env") (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (func (export "run-msg-loop")
+    (canon lift (core func $main "run-msg-loop"))))
@@ -0,0 +1,66 @@
+;; A guest for the `env` world that reports its WASI command line:
+;; `run-msg-loop` sends each of `get-arguments`, then the key and the value
+;; of each `get-environment` entry, and returns. Rebuild with
+;;   wasm-tools parse cli.wat -o cli.wasm
+(component
+  (import "send-bytes" (func $send-bytes (param "payload" (list u8))))
+  (import "wasi:cli/environment@0.2.0" (instance $environment
+    (export "get-environment" (func (result (list (tuple string string)))))
+    (export "get-arguments" (func (result (list string))))))
+
+  (core module $libc
+    (memory (export "memory") 1)
+    (global $heap (mut i32) (i32.const 1024))
+    ;; never frees; what a test passes fits in the first page
+    (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+      (local $at i32)
+      (local.set $at
+        (i32.and (i32.add (global.get $heap) (i32.sub (local.get 2) (i32.const 1)))
+                 (i32.sub (i32.const 0) (local.get 2))))
+      (global.set $heap (i32.add (local.get $at) (local.get 3)))
+      (local.get $at)))
+  (core instance $libc (instantiate $libc))
+
+  (core func $send (canon lower (func $send-bytes) (memory $libc "memory")))
+  (core func $get-environment
+    (canon lower (func $environment "get-environment") (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (core func $get-arguments
+    (canon lower (func $environment "get-arguments") (memory $libc "memory") (realloc (func $libc "realloc"))))
+
+  (core module $main
+    (import "libc" "memory" (memory 1))
+    (import "host" "send-bytes" (func $send (param i32 i32)))
+    (import "host" "get-environment" (func $get-environment (param i32)))
+    (import "host" "get-arguments" (func $get-arguments (param i32)))
+    (func (export "init-exec-env") (param i32 i32 i32 i32 i32))
+    ;; send the strings of a list whose (ptr, len) is at 0, each `stride`
+    ;; bytes of (ptr, len) pairs
+    (func $send-all (param $stride i32)
+      (local $at i32) (local $end i32)
+      (local.set $at (i32.load (i32.const 0)))
+      (local.set $end (i32.add (local.get $at) (i32.mul (i32.load (i32.const 4)) (local.get $stride))))
+      (block $done
+        (loop $next
+          (br_if $done (i32.ge_u (local.get $at) (local.get $end)))
+          (call $send (i32.load (local.get $at)) (i32.load offset=4 (local.get $at)))
+          (if (i32.eq (local.get $stride) (i32.const 16))
+            (then (call $send (i32.load offset=8 (local.get $at)) (i32.load offset=12 (local.get $at)))))
+          (local.set $at (i32.add (local.get $at) (local.get $stride)))
+          (br $next))))
+    (func (export "run-msg-loop")
+      (call $get-arguments (i32.const 0))
+      (call $send-all (i32.const 8))
+      (call $get-environment (i32.const 0))
+      (call $send-all (i32.const 16))))
+  (core instance $main
+    (instantiate $main
+      (with "libc" (instance $libc))
+      (with "host" (instance
+        (export "send-bytes" (func $send))
+        (export "get-environment" (func $get-environment))
+        (export "get-arguments" (func $get-arguments))))))
+
+  (func (export "init-exec-env") (param "id-name" string) (param "log-tags" (option string))
+    (canon lift (core func $main "init-exec-env") (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (func (export "run-msg-loop")
+    (canon lift (core func $main "run-msg-loop"))))
@@ -0,0 +1,71 @@
+;; A guest for the `env-config` world that reports what `init-exec-env`
+;; was given: `run-msg-loop` sends the log tags (empty when None), then
+;; `max-message-size` from the config record as a little-endian u64 (zero
+;; when None), and returns. It also exports `reinit-exec-env`, which stores
+;; the same things in place; tags left None keep the earlier ones. Rebuild
+;; with
+;;   wasm-tools parse config.wat -o config.wasm
+(component
+  (import "send-bytes" (func $send-bytes (param "payload" (list u8))))
+
+  (core module $libc
+    (memory (export "memory") 1)
+    (global $heap (mut i32) (i32.const 1024))
+    ;; never frees, so the init-exec-env arguments stay where they were put
+    (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+      (local $at i32)
+      (local.set $at
+        (i32.and (i32.add (global.get $heap) (i32.sub (local.get 2) (i32.const 1)))
+                 (i32.sub (i32.const 0) (local.get 2))))
+      (global.set $heap (i32.add (local.get $at) (local.get 3)))
+      (local.get $at)))
+  (core instance $libc (instantiate $libc))
+
+  (core func $send (canon lower (func $send-bytes) (memory $libc "memory")))
+
+  (core module $main
+    (import "libc" "memory" (memory 1))
+    (import "host" "send-bytes" (func $send (param i32 i32)))
+    (global $tags (mut i32) (i32.const 0))
+    (global $tags-len (mut i32) (i32.const 0))
+    ;; the flattened id-name, log-tags and init-config record
+    (func (export "init-exec-env")
+      (param $id i32) (param $id-len i32)
+      (param $has-tags i32) (param $tags i32) (param $tags-len i32)
+      (param $has-level i32) (param $level i32)
+      (param $has-mms i32) (param $mms i64)
+      (param $has-mmb i32) (param $mmb i64)
+      (param $features i32) (param $features-len i32)
+      (param $has-extra i32) (param $extra i32) (param $extra-len i32)
+      (if (local.get $has-tags)
+        (then
+          (global.set $tags (local.get $tags))
+          (global.set $tags-len (local.get $tags-len))))
+      (i64.store (i32.const 0)
+        (select (local.get $mms) (i64.const 0) (local.get $has-mms))))
+    (func (export "run-msg-loop")
+      (call $send (global.get $tags) (global.get $tags-len))
+      (call $send (i32.const 0) (i32.const 8))))
+  (core instance $main
+    (instantiate $main
+      (with "libc" (instance $libc))
+      (with "host" (instance
+        (export "send-bytes" (func $send))))))
+
+  (type $log-level (enum "trace" "debug" "info" "warn" "error"))
+  (export $log-level' "log-level" (type $log-level))
+  (type $init-config (record
+    (field "log-level" (option $log-level'))
+    (field "max-message-size" (option u64))
+    (field "max-memory-bytes" (option u64))
+    (field "features" (list string))
+    (field "extra" (option (list u8)))))
+  (export $init-config' "init-config" (type $init-config))
+  (func (export "init-exec-env")
+      (param "id-name" string) (param "log-tags" (option string)) (param "config" $init-config')
+    (canon lift (core func $main "init-exec-env") (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (func (export "reinit-exec-env")
+      (param "id-name" string) (param "log-tags" (option string)) (param "config" $init-config')
+    (canon lift (core func $main "init-exec-env") (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (func (export "run-msg-loop")
+    (canon lift (core func $main "run-msg-loop"))))
@@ -0,0 +1,72 @@
+;; A minimal guest for the `env` world, for runner tests that do not need
+;; the full interpreter: `run-msg-loop` sends every message back until end
+;; of stream, and a few extra exports serve `call_export`. Rebuild with
+;;   wasm-tools parse echo.wat -o echo.wasm
+(component
+  (import "recv-bytes" (func $recv-bytes (result (list u8))))
+  (import "send-bytes" (func $send-bytes (param "payload" (list u8))))
+
+  ;; memory and a bump allocator, instantiated first so the imports can be
+  ;; lowered against them
+  (core module $libc
+    (memory (export "memory") 1)
+    (global $heap (mut i32) (i32.const 1024))
+    (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+      (local $at i32)
+      (local.set $at
+        (i32.and (i32.add (global.get $heap) (i32.sub (local.get 2) (i32.const 1)))
+                 (i32.sub (i32.const 0) (local.get 2))))
+      (global.set $heap (i32.add (local.get $at) (local.get 3)))
+      (block $fits
+        (br_if $fits (i32.le_u (global.get $heap) (i32.shl (memory.size) (i32.const 16))))
+        (br_if $fits
+          (i32.ne
+            (memory.grow (i32.add (i32.shr_u (i32.sub (global.get $heap) (i32.shl (memory.size) (i32.const 16))) (i32.const 16))
+                                  (i32.const 1)))
+            (i32.const -1)))
+        unreachable)
+      (local.get $at)))
+  (core instance $libc (instantiate $libc))
+
+  (core func $recv (canon lower (func $recv-bytes) (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (core func $send (canon lower (func $send-bytes) (memory $libc "memory")))
+
+  (core module $main
+    (import "libc" "memory" (memory 1))
+    (import "host" "recv-bytes" (func $recv (param i32)))
+    (import "host" "send-bytes" (func $send (param i32 i32)))
+    (func (export "init-exec-env") (param i32 i32 i32 i32 i32))
+    (func (export "run-msg-loop")
+      (block $end
+        (loop $next
+          ;; the payload's pointer and length land at 0 and 4
+          (call $recv (i32.const 0))
+          (br_if $end (i32.eqz (i32.load (i32.const 4))))
+          (call $send (i32.load (i32.const 0)) (i32.load (i32.const 4)))
+          (br $next))))
+    (func (export "add") (param i32 i32) (result i32)
+      (i32.add (local.get 0) (local.get 1)))
+    (func (export "crash")
+      unreachable)
+    ;; one frame per level, returning the depth reached
+    (func $recurse (export "recurse") (param i32) (result i32)
+      (if (result i32) (i32.eqz (local.get 0))
+        (then (i32.const 0))
+        (else (i32.add (call $recurse (i32.sub (local.get 0) (i32.const 1))) (i32.const 1))))))
+  (core instance $main
+    (instantiate $main
+      (with "libc" (instance $libc))
+      (with "host" (instance
+        (export "recv-bytes" (func $recv))
+        (export "send-bytes" (func $send))))))
+
+  (func (export "init-exec-env") (param "id-name" string) (param "log-tags" (option string))
+    (canon lift (core func $main "init-exec-env") (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (func (export "run-msg-loop")
+    (canon lift (core func $main "run-msg-loop")))
+  (func (export "add") (param "a" s32) (param "b" s32) (result s32)
+    (canon lift (core func $main "add")))
+  (func (export "crash")
+    (canon lift (core func $main "crash")))
+  (func (export "recurse") (param "depth" u32) (result u32)
+    (canon lift (core func $main "recurse"))))
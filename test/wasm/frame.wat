@@ -0,0 +1,53 @@
+;; A guest for the `env` world that echoes frames: `run-msg-loop` sends
+;; each frame `recv-frame` returns back through `send-frame`, header and
+;; body unchanged, until one has an empty body. Rebuild with
+;;   wasm-tools parse frame.wat -o frame.wasm
+(component
+  (type $frame-header' (record (field "kind" u32) (field "flags" u32)))
+  (import "frame-header" (type $frame-header (eq $frame-header')))
+  (import "recv-frame" (func $recv-frame (result (tuple $frame-header (list u8)))))
+  (import "send-frame" (func $send-frame (param "header" $frame-header) (param "body" (list u8))))
+
+  (core module $libc
+    (memory (export "memory") 1)
+    (global $heap (mut i32) (i32.const 1024))
+    ;; never frees; the frames a test sends fit in the first page
+    (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+      (local $at i32)
+      (local.set $at
+        (i32.and (i32.add (global.get $heap) (i32.sub (local.get 2) (i32.const 1)))
+                 (i32.sub (i32.const 0) (local.get 2))))
+      (global.set $heap (i32.add (local.get $at) (local.get 3)))
+      (local.get $at)))
+  (core instance $libc (instantiate $libc))
+
+  (core func $recv
+    (canon lower (func $recv-frame) (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (core func $send (canon lower (func $send-frame) (memory $libc "memory")))
+
+  (core module $main
+    (import "host" "recv-frame" (func $recv (param i32)))
+    (import "host" "send-frame" (func $send (param i32 i32 i32 i32)))
+    (import "libc" "memory" (memory 1))
+    (func (export "init-exec-env") (param i32 i32 i32 i32 i32))
+    ;; the frame lands at 0: kind, flags, then the body's (ptr, len)
+    (func (export "run-msg-loop")
+      (block $end
+        (loop $next
+          (call $recv (i32.const 0))
+          (br_if $end (i32.eqz (i32.load (i32.const 12))))
+          (call $send
+            (i32.load (i32.const 0)) (i32.load (i32.const 4))
+            (i32.load (i32.const 8)) (i32.load (i32.const 12)))
+          (br $next)))))
+  (core instance $main
+    (instantiate $main
+      (with "libc" (instance $libc))
+      (with "host" (instance
+        (export "recv-frame" (func $recv))
+        (export "send-frame" (func $send))))))
+
+  (func (export "init-exec-env") (param "id-name" string) (param "log-tags" (option string))
+    (canon lift (core func $main "init-exec-env") (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (func (export "run-msg-loop")
+    (canon lift (core func $main "run-msg-loop"))))
@@ -0,0 +1,78 @@
+;; A guest for the `env` world that logs on request: for each message
+;; `run-msg-loop` logs the rest of it with `write-log-record`, at the level
+;; its first byte names (0 trace to 4 error, 5 for none), tagged
+;; `source=guest` when its second byte is 1; a first byte of 6 logs the rest
+;; with the plain `write-log` instead. Returns on an empty message.
+;; Rebuild with
+;;   wasm-tools parse log.wat -o log.wasm
+(component
+  (import "recv-bytes" (func $recv-bytes (result (list u8))))
+  (type $log-level' (enum "trace" "debug" "info" "warn" "error"))
+  (import "log-level" (type $log-level (eq $log-level')))
+  (import "write-log" (func $write-log (param "text" string)))
+  (import "write-log-record"
+    (func $write-log-record
+      (param "level" (option $log-level)) (param "msg" string)
+      (param "tags" (list (tuple string string)))))
+
+  (core module $libc
+    (memory (export "memory") 1)
+    ;; the one tag, as a (key, value) pair of strings at 32
+    (data (i32.const 32) "\40\00\00\00\06\00\00\00\48\00\00\00\05\00\00\00")
+    (data (i32.const 64) "source")
+    (data (i32.const 72) "guest")
+    (global $heap (mut i32) (i32.const 1024))
+    ;; never frees; the messages a test sends fit in the first page
+    (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+      (local $at i32)
+      (local.set $at
+        (i32.and (i32.add (global.get $heap) (i32.sub (local.get 2) (i32.const 1)))
+                 (i32.sub (i32.const 0) (local.get 2))))
+      (global.set $heap (i32.add (local.get $at) (local.get 3)))
+      (local.get $at)))
+  (core instance $libc (instantiate $libc))
+
+  (core func $recv (canon lower (func $recv-bytes) (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (core func $log (canon lower (func $write-log) (memory $libc "memory")))
+  (core func $record (canon lower (func $write-log-record) (memory $libc "memory")))
+
+  (core module $main
+    (import "host" "recv-bytes" (func $recv (param i32)))
+    (import "host" "write-log" (func $log (param i32 i32)))
+    (import "host" "write-log-record" (func $record (param i32 i32 i32 i32 i32 i32)))
+    (import "libc" "memory" (memory 1))
+    (func (export "init-exec-env") (param i32 i32 i32 i32 i32))
+    ;; recv-bytes puts the message's (ptr, len) at 0
+    (func (export "run-msg-loop")
+      (local $msg i32) (local $len i32) (local $level i32)
+      (block $end
+        (loop $next
+          (call $recv (i32.const 0))
+          (local.set $msg (i32.load (i32.const 0)))
+          (local.set $len (i32.load (i32.const 4)))
+          (br_if $end (i32.eqz (local.get $len)))
+          (local.set $level (i32.load8_u (local.get $msg)))
+          (if (i32.eq (local.get $level) (i32.const 6))
+            (then
+              (call $log (i32.add (local.get $msg) (i32.const 1)) (i32.sub (local.get $len) (i32.const 1))))
+            (else
+              (call $record
+                (i32.lt_u (local.get $level) (i32.const 5))
+                (local.get $level)
+                (i32.add (local.get $msg) (i32.const 2))
+                (i32.sub (local.get $len) (i32.const 2))
+                (i32.const 32)
+                (i32.load8_u offset=1 (local.get $msg)))))
+          (br $next)))))
+  (core instance $main
+    (instantiate $main
+      (with "libc" (instance $libc))
+      (with "host" (instance
+        (export "recv-bytes" (func $recv))
+        (export "write-log" (func $log))
+        (export "write-log-record" (func $record))))))
+
+  (func (export "init-exec-env") (param "id-name" string) (param "log-tags" (option string))
+    (canon lift (core func $main "init-exec-env") (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (func (export "run-msg-loop")
+    (canon lift (core func $main "run-msg-loop"))))
@@ -0,0 +1,54 @@
+;; A guest for the `env` world that sleeps on request: `run-msg-loop`
+;; takes each message's first eight bytes as a little-endian u64, calls
+;; `sleep` with that many milliseconds and then echoes the message, until
+;; an empty one. Rebuild with
+;;   wasm-tools parse nap.wat -o nap.wasm
+(component
+  (import "recv-bytes" (func $recv-bytes (result (list u8))))
+  (import "send-bytes" (func $send-bytes (param "payload" (list u8))))
+  (import "sleep" (func $sleep (param "ms" u64)))
+
+  (core module $libc
+    (memory (export "memory") 1)
+    (global $heap (mut i32) (i32.const 1024))
+    ;; never frees; the messages a test sends fit in the first page
+    (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+      (local $at i32)
+      (local.set $at
+        (i32.and (i32.add (global.get $heap) (i32.sub (local.get 2) (i32.const 1)))
+                 (i32.sub (i32.const 0) (local.get 2))))
+      (global.set $heap (i32.add (local.get $at) (local.get 3)))
+      (local.get $at)))
+  (core instance $libc (instantiate $libc))
+
+  (core func $recv (canon lower (func $recv-bytes) (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (core func $send (canon lower (func $send-bytes) (memory $libc "memory")))
+  (core func $sleep (canon lower (func $sleep)))
+
+  (core module $main
+    (import "host" "recv-bytes" (func $recv (param i32)))
+    (import "host" "send-bytes" (func $send (param i32 i32)))
+    (import "host" "sleep" (func $sleep (param i64)))
+    (import "libc" "memory" (memory 1))
+    (func (export "init-exec-env") (param i32 i32 i32 i32 i32))
+    ;; recv-bytes puts the message's (ptr, len) at 0
+    (func (export "run-msg-loop")
+      (block $end
+        (loop $next
+          (call $recv (i32.const 0))
+          (br_if $end (i32.eqz (i32.load (i32.const 4))))
+          (call $sleep (i64.load (i32.load (i32.const 0))))
+          (call $send (i32.load (i32.const 0)) (i32.load (i32.const 4)))
+          (br $next)))))
+  (core instance $main
+    (instantiate $main
+      (with "libc" (instance $libc))
+      (with "host" (instance
+        (export "recv-bytes" (func $recv))
+        (export "send-bytes" (func $send))
+        (export "sleep" (func $sleep))))))
+
+  (func (export "init-exec-env") (param "id-name" string) (param "log-tags" (option string))
+    (canon lift (core func $main "init-exec-env") (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (func (export "run-msg-loop")
+    (canon lift (core func $main "run-msg-loop"))))
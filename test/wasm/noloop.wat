@@ -0,0 +1,13 @@
+;; An incomplete guest: it exports `init-exec-env` but no `run-msg-loop`,
+;; for the up-front interface checks. Rebuild with
+;;   wasm-tools parse noloop.wat -o noloop.wasm
+(component
+  (core module $main
+    (memory (export "memory") 1)
+    (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+      (i32.const 1024))
+    (func (export "init-exec-env") (param i32 i32 i32 i32 i32)))
+  (core instance $main (instantiate $main))
+
+  (func (export "init-exec-env") (param "id-name" string) (param "log-tags" (option string))
+    (canon lift (core func $main "init-exec-env") (memory $main "memory") (realloc (func $main "realloc")))))
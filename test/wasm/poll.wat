@@ -0,0 +1,53 @@
+;; A guest for the `env` world that polls with `try-recv-bytes`:
+;; `run-msg-loop` echoes each message, sends "-" each time the wait ends
+;; without one, and returns on an empty message. Rebuild with
+;;   wasm-tools parse poll.wat -o poll.wasm
+(component
+  (import "try-recv-bytes" (func $try-recv-bytes (result (option (list u8)))))
+  (import "send-bytes" (func $send-bytes (param "payload" (list u8))))
+
+  (core module $libc
+    (memory (export "memory") 1)
+    (data (i32.const 16) "-")
+    (global $heap (mut i32) (i32.const 1024))
+    ;; never frees; the messages a test sends fit in the first page
+    (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+      (local $at i32)
+      (local.set $at
+        (i32.and (i32.add (global.get $heap) (i32.sub (local.get 2) (i32.const 1)))
+                 (i32.sub (i32.const 0) (local.get 2))))
+      (global.set $heap (i32.add (local.get $at) (local.get 3)))
+      (local.get $at)))
+  (core instance $libc (instantiate $libc))
+
+  (core func $recv
+    (canon lower (func $try-recv-bytes) (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (core func $send (canon lower (func $send-bytes) (memory $libc "memory")))
+
+  (core module $main
+    (import "host" "try-recv-bytes" (func $recv (param i32)))
+    (import "host" "send-bytes" (func $send (param i32 i32)))
+    (import "libc" "memory" (memory 1))
+    (func (export "init-exec-env") (param i32 i32 i32 i32 i32))
+    ;; the option lands at 0: its case byte, then (ptr, len) at 4
+    (func (export "run-msg-loop")
+      (block $end
+        (loop $next
+          (call $recv (i32.const 0))
+          (if (i32.load8_u (i32.const 0))
+            (then
+              (br_if $end (i32.eqz (i32.load (i32.const 8))))
+              (call $send (i32.load (i32.const 4)) (i32.load (i32.const 8))))
+            (else (call $send (i32.const 16) (i32.const 1))))
+          (br $next)))))
+  (core instance $main
+    (instantiate $main
+      (with "libc" (instance $libc))
+      (with "host" (instance
+        (export "try-recv-bytes" (func $recv))
+        (export "send-bytes" (func $send))))))
+
+  (func (export "init-exec-env") (param "id-name" string) (param "log-tags" (option string))
+    (canon lift (core func $main "init-exec-env") (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (func (export "run-msg-loop")
+    (canon lift (core func $main "run-msg-loop"))))
@@ -0,0 +1,90 @@
+;; A guest for the `env` world that writes to WASI stdout and stderr:
+;; `run-msg-loop` writes the rest of each message to stdout when it starts
+;; with `1` and to stderr when it starts with `2`, without flushing, then
+;; flushes both at the first empty message and returns. Rebuild with
+;;   wasm-tools parse print.wat -o print.wasm
+(component $C
+  (import "recv-bytes" (func $recv-bytes (result (list u8))))
+  (import "wasi:io/error@0.2.0" (instance $io-error
+    (export "error" (type (sub resource)))))
+  (alias export $io-error "error" (type $error))
+  (import "wasi:io/streams@0.2.0" (instance $streams
+    (alias outer $C $error (type $error))
+    (export "output-stream" (type $output-stream (sub resource)))
+    (type $stream-error (variant (case "last-operation-failed" (own $error)) (case "closed")))
+    (export "stream-error" (type $stream-error' (eq $stream-error)))
+    (export "[method]output-stream.write"
+      (func (param "self" (borrow $output-stream)) (param "contents" (list u8))
+            (result (result (error $stream-error')))))
+    (export "[method]output-stream.blocking-flush"
+      (func (param "self" (borrow $output-stream)) (result (result (error $stream-error')))))))
+  (alias export $streams "output-stream" (type $output-stream))
+  (import "wasi:cli/stdout@0.2.0" (instance $stdout
+    (alias outer $C $output-stream (type $output-stream))
+    (export "get-stdout" (func (result (own $output-stream))))))
+  (import "wasi:cli/stderr@0.2.0" (instance $stderr
+    (alias outer $C $output-stream (type $output-stream))
+    (export "get-stderr" (func (result (own $output-stream))))))
+
+  (core module $libc
+    (memory (export "memory") 1)
+    (global $heap (mut i32) (i32.const 1024))
+    ;; never frees; the messages a test sends fit in the first page
+    (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+      (local $at i32)
+      (local.set $at
+        (i32.and (i32.add (global.get $heap) (i32.sub (local.get 2) (i32.const 1)))
+                 (i32.sub (i32.const 0) (local.get 2))))
+      (global.set $heap (i32.add (local.get $at) (local.get 3)))
+      (local.get $at)))
+  (core instance $libc (instantiate $libc))
+
+  (core func $recv (canon lower (func $recv-bytes) (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (core func $get-stdout (canon lower (func $stdout "get-stdout")))
+  (core func $get-stderr (canon lower (func $stderr "get-stderr")))
+  (core func $write
+    (canon lower (func $streams "[method]output-stream.write") (memory $libc "memory")))
+  (core func $flush
+    (canon lower (func $streams "[method]output-stream.blocking-flush") (memory $libc "memory")))
+
+  (core module $main
+    (import "libc" "memory" (memory 1))
+    (import "host" "recv-bytes" (func $recv (param i32)))
+    (import "host" "get-stdout" (func $get-stdout (result i32)))
+    (import "host" "get-stderr" (func $get-stderr (result i32)))
+    (import "host" "write" (func $write (param i32 i32 i32 i32)))
+    (import "host" "blocking-flush" (func $flush (param i32 i32)))
+    (func (export "init-exec-env") (param i32 i32 i32 i32 i32))
+    ;; recv-bytes puts the message's (ptr, len) at 0; stream results go at 8
+    (func (export "run-msg-loop")
+      (local $out i32) (local $err i32) (local $msg i32) (local $len i32)
+      (local.set $out (call $get-stdout))
+      (local.set $err (call $get-stderr))
+      (block $end
+        (loop $next
+          (call $recv (i32.const 0))
+          (local.set $msg (i32.load (i32.const 0)))
+          (local.set $len (i32.load (i32.const 4)))
+          (br_if $end (i32.eqz (local.get $len)))
+          (call $write
+            (select (local.get $err) (local.get $out) (i32.eq (i32.load8_u (local.get $msg)) (i32.const 50)))
+            (i32.add (local.get $msg) (i32.const 1))
+            (i32.sub (local.get $len) (i32.const 1))
+            (i32.const 8))
+          (br $next)))
+      (call $flush (local.get $out) (i32.const 8))
+      (call $flush (local.get $err) (i32.const 8))))
+  (core instance $main
+    (instantiate $main
+      (with "libc" (instance $libc))
+      (with "host" (instance
+        (export "recv-bytes" (func $recv))
+        (export "get-stdout" (func $get-stdout))
+        (export "get-stderr" (func $get-stderr))
+        (export "write" (func $write))
+        (export "blocking-flush" (func $flush))))))
+
+  (func (export "init-exec-env") (param "id-name" string) (param "log-tags" (option string))
+    (canon lift (core func $main "init-exec-env") (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (func (export "run-msg-loop")
+    (canon lift (core func $main "run-msg-loop"))))
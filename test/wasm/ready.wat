@@ -0,0 +1,56 @@
+;; A guest for the `env` world that polls with `recv-ready`: `run-msg-loop`
+;; sends "-" each time no message is ready, and otherwise receives one and
+;; echoes it, until an empty one. Rebuild with
+;;   wasm-tools parse ready.wat -o ready.wasm
+(component
+  (import "recv-ready" (func $recv-ready (result bool)))
+  (import "recv-bytes" (func $recv-bytes (result (list u8))))
+  (import "send-bytes" (func $send-bytes (param "payload" (list u8))))
+
+  (core module $libc
+    (memory (export "memory") 1)
+    (data (i32.const 16) "-")
+    (global $heap (mut i32) (i32.const 1024))
+    ;; never frees; the messages a test sends fit in the first page
+    (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+      (local $at i32)
+      (local.set $at
+        (i32.and (i32.add (global.get $heap) (i32.sub (local.get 2) (i32.const 1)))
+                 (i32.sub (i32.const 0) (local.get 2))))
+      (global.set $heap (i32.add (local.get $at) (local.get 3)))
+      (local.get $at)))
+  (core instance $libc (instantiate $libc))
+
+  (core func $ready (canon lower (func $recv-ready)))
+  (core func $recv (canon lower (func $recv-bytes) (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (core func $send (canon lower (func $send-bytes) (memory $libc "memory")))
+
+  (core module $main
+    (import "host" "recv-ready" (func $ready (result i32)))
+    (import "host" "recv-bytes" (func $recv (param i32)))
+    (import "host" "send-bytes" (func $send (param i32 i32)))
+    (import "libc" "memory" (memory 1))
+    (func (export "init-exec-env") (param i32 i32 i32 i32 i32))
+    ;; recv-bytes puts the message's (ptr, len) at 0
+    (func (export "run-msg-loop")
+      (block $end
+        (loop $next
+          (if (call $ready)
+            (then
+              (call $recv (i32.const 0))
+              (br_if $end (i32.eqz (i32.load (i32.const 4))))
+              (call $send (i32.load (i32.const 0)) (i32.load (i32.const 4))))
+            (else (call $send (i32.const 16) (i32.const 1))))
+          (br $next)))))
+  (core instance $main
+    (instantiate $main
+      (with "libc" (instance $libc))
+      (with "host" (instance
+        (export "recv-ready" (func $ready))
+        (export "recv-bytes" (func $recv))
+        (export "send-bytes" (func $send))))))
+
+  (func (export "init-exec-env") (param "id-name" string) (param "log-tags" (option string))
+    (canon lift (core func $main "init-exec-env") (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (func (export "run-msg-loop")
+    (canon lift (core func $main "run-msg-loop"))))
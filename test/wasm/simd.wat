@@ -0,0 +1,38 @@
+;; A guest for the `env` world that needs the SIMD proposal: `run-msg-loop`
+;; sends one v128 of four little-endian i32 sevens and returns. Rebuild with
+;;   wasm-tools parse simd.wat -o simd.wasm
+(component
+  (import "send-bytes" (func $send-bytes (param "payload" (list u8))))
+
+  (core module $libc
+    (memory (export "memory") 1)
+    (global $heap (mut i32) (i32.const 1024))
+    ;; only the init-exec-env arguments are allocated, so one page is plenty
+    (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+      (local $at i32)
+      (local.set $at
+        (i32.and (i32.add (global.get $heap) (i32.sub (local.get 2) (i32.const 1)))
+                 (i32.sub (i32.const 0) (local.get 2))))
+      (global.set $heap (i32.add (local.get $at) (local.get 3)))
+      (local.get $at)))
+  (core instance $libc (instantiate $libc))
+
+  (core func $send (canon lower (func $send-bytes) (memory $libc "memory")))
+
+  (core module $main
+    (import "libc" "memory" (memory 1))
+    (import "host" "send-bytes" (func $send (param i32 i32)))
+    (func (export "init-exec-env") (param i32 i32 i32 i32 i32))
+    (func (export "run-msg-loop")
+      (v128.store (i32.const 0) (i32x4.splat (i32.const 7)))
+      (call $send (i32.const 0) (i32.const 16))))
+  (core instance $main
+    (instantiate $main
+      (with "libc" (instance $libc))
+      (with "host" (instance
+        (export "send-bytes" (func $send))))))
+
+  (func (export "init-exec-env") (param "id-name" string) (param "log-tags" (option string))
+    (canon lift (core func $main "init-exec-env") (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (func (export "run-msg-loop")
+    (canon lift (core func $main "run-msg-loop"))))
@@ -0,0 +1,42 @@
+;; A guest for the `env` world with a slow start: `init-exec-env` calls
+;; `write-log` with "init", then sleeps for 300ms; `run-msg-loop` returns at
+;; once. Rebuild with
+;;   wasm-tools parse slowinit.wat -o slowinit.wasm
+(component
+  (import "write-log" (func $write-log (param "msg" string)))
+  (import "sleep" (func $sleep (param "ms" u64)))
+
+  (core module $libc
+    (memory (export "memory") 1)
+    (data (i32.const 16) "init")
+    (global $heap (mut i32) (i32.const 1024))
+    ;; only the init-exec-env arguments are allocated, so one page is plenty
+    (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+      (local $at i32)
+      (local.set $at
+        (i32.and (i32.add (global.get $heap) (i32.sub (local.get 2) (i32.const 1)))
+                 (i32.sub (i32.const 0) (local.get 2))))
+      (global.set $heap (i32.add (local.get $at) (local.get 3)))
+      (local.get $at)))
+  (core instance $libc (instantiate $libc))
+
+  (core func $log (canon lower (func $write-log) (memory $libc "memory")))
+  (core func $sleep (canon lower (func $sleep)))
+
+  (core module $main
+    (import "host" "write-log" (func $log (param i32 i32)))
+    (import "host" "sleep" (func $sleep (param i64)))
+    (func (export "init-exec-env") (param i32 i32 i32 i32 i32)
+      (call $log (i32.const 16) (i32.const 4))
+      (call $sleep (i64.const 300)))
+    (func (export "run-msg-loop")))
+  (core instance $main
+    (instantiate $main
+      (with "host" (instance
+        (export "write-log" (func $log))
+        (export "sleep" (func $sleep))))))
+
+  (func (export "init-exec-env") (param "id-name" string) (param "log-tags" (option string))
+    (canon lift (core func $main "init-exec-env") (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (func (export "run-msg-loop")
+    (canon lift (core func $main "run-msg-loop"))))
@@ -0,0 +1,44 @@
+;; A compute-bound guest for the `env` world: `run-msg-loop` calls the
+;; custom `progress` import, does a few milliseconds of work without any
+;; host call, and repeats for as long as `progress` returns true. Rebuild
+;; with
+;;   wasm-tools parse spin.wat -o spin.wasm
+(component
+  (import "progress" (func $progress (result bool)))
+
+  (core module $libc
+    (memory (export "memory") 1)
+    (global $heap (mut i32) (i32.const 1024))
+    ;; only the init-exec-env arguments are allocated, so one page is plenty
+    (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+      (local $at i32)
+      (local.set $at
+        (i32.and (i32.add (global.get $heap) (i32.sub (local.get 2) (i32.const 1)))
+                 (i32.sub (i32.const 0) (local.get 2))))
+      (global.set $heap (i32.add (local.get $at) (local.get 3)))
+      (local.get $at)))
+  (core instance $libc (instantiate $libc))
+
+  (core func $progress (canon lower (func $progress)))
+
+  (core module $main
+    (import "host" "progress" (func $progress (result i32)))
+    (func (export "init-exec-env") (param i32 i32 i32 i32 i32))
+    (func (export "run-msg-loop")
+      (local $i i32)
+      (loop $round
+        (if (call $progress)
+          (then
+            (local.set $i (i32.const 5000000))
+            (loop $work
+              (br_if $work (local.tee $i (i32.sub (local.get $i) (i32.const 1)))))
+            (br $round))))))
+  (core instance $main
+    (instantiate $main
+      (with "host" (instance
+        (export "progress" (func $progress))))))
+
+  (func (export "init-exec-env") (param "id-name" string) (param "log-tags" (option string))
+    (canon lift (core func $main "init-exec-env") (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (func (export "run-msg-loop")
+    (canon lift (core func $main "run-msg-loop"))))
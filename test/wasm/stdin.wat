@@ -0,0 +1,70 @@
+;; A guest for the `env` world that reads WASI stdin: `run-msg-loop` sends
+;; every chunk `blocking-read` returns until the stream is closed or fails.
+;; Rebuild with
+;;   wasm-tools parse stdin.wat -o stdin.wasm
+(component $C
+  (import "send-bytes" (func $send-bytes (param "payload" (list u8))))
+  (import "wasi:io/error@0.2.0" (instance $io-error
+    (export "error" (type (sub resource)))))
+  (alias export $io-error "error" (type $error))
+  (import "wasi:io/streams@0.2.0" (instance $streams
+    (alias outer $C $error (type $error))
+    (export "input-stream" (type $input-stream (sub resource)))
+    (type $stream-error (variant (case "last-operation-failed" (own $error)) (case "closed")))
+    (export "stream-error" (type $stream-error' (eq $stream-error)))
+    (export "[method]input-stream.blocking-read"
+      (func (param "self" (borrow $input-stream)) (param "len" u64)
+            (result (result (list u8) (error $stream-error')))))))
+  (alias export $streams "input-stream" (type $input-stream))
+  (import "wasi:cli/stdin@0.2.0" (instance $stdin
+    (alias outer $C $input-stream (type $input-stream))
+    (export "get-stdin" (func (result (own $input-stream))))))
+
+  (core module $libc
+    (memory (export "memory") 1)
+    (global $heap (mut i32) (i32.const 1024))
+    ;; never frees; the chunks a test feeds fit in the first page
+    (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+      (local $at i32)
+      (local.set $at
+        (i32.and (i32.add (global.get $heap) (i32.sub (local.get 2) (i32.const 1)))
+                 (i32.sub (i32.const 0) (local.get 2))))
+      (global.set $heap (i32.add (local.get $at) (local.get 3)))
+      (local.get $at)))
+  (core instance $libc (instantiate $libc))
+
+  (core func $send (canon lower (func $send-bytes) (memory $libc "memory")))
+  (core func $get-stdin (canon lower (func $stdin "get-stdin")))
+  (core func $read
+    (canon lower (func $streams "[method]input-stream.blocking-read")
+      (memory $libc "memory") (realloc (func $libc "realloc"))))
+
+  (core module $main
+    (import "libc" "memory" (memory 1))
+    (import "host" "send-bytes" (func $send (param i32 i32)))
+    (import "host" "get-stdin" (func $get-stdin (result i32)))
+    (import "host" "blocking-read" (func $read (param i32 i64 i32)))
+    (func (export "init-exec-env") (param i32 i32 i32 i32 i32))
+    (func (export "run-msg-loop")
+      (local $stdin i32)
+      (local.set $stdin (call $get-stdin))
+      (block $end
+        (loop $next
+          ;; result<list<u8>, stream-error> lands at 0: the case, then the
+          ;; list's pointer and length at 4 and 8
+          (call $read (local.get $stdin) (i64.const 64) (i32.const 0))
+          (br_if $end (i32.load8_u (i32.const 0)))
+          (call $send (i32.load (i32.const 4)) (i32.load (i32.const 8)))
+          (br $next)))))
+  (core instance $main
+    (instantiate $main
+      (with "libc" (instance $libc))
+      (with "host" (instance
+        (export "send-bytes" (func $send))
+        (export "get-stdin" (func $get-stdin))
+        (export "blocking-read" (func $read))))))
+
+  (func (export "init-exec-env") (param "id-name" string) (param "log-tags" (option string))
+    (canon lift (core func $main "init-exec-env") (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (func (export "run-msg-loop")
+    (canon lift (core func $main "run-msg-loop"))))
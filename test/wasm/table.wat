@@ -0,0 +1,53 @@
+;; A guest for the `env` world that grows a table on request:
+;; `run-msg-loop` takes each message's first four bytes as a little-endian
+;; u32, grows its one-element funcref table by that many and sends back
+;; what `table.grow` returned (the old size, or -1 when refused) as a
+;; little-endian i32, until an empty message. Rebuild with
+;;   wasm-tools parse table.wat -o table.wasm
+(component
+  (import "recv-bytes" (func $recv-bytes (result (list u8))))
+  (import "send-bytes" (func $send-bytes (param "payload" (list u8))))
+
+  (core module $libc
+    (memory (export "memory") 1)
+    (global $heap (mut i32) (i32.const 1024))
+    ;; never frees; the messages a test sends fit in the first page
+    (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+      (local $at i32)
+      (local.set $at
+        (i32.and (i32.add (global.get $heap) (i32.sub (local.get 2) (i32.const 1)))
+                 (i32.sub (i32.const 0) (local.get 2))))
+      (global.set $heap (i32.add (local.get $at) (local.get 3)))
+      (local.get $at)))
+  (core instance $libc (instantiate $libc))
+
+  (core func $recv (canon lower (func $recv-bytes) (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (core func $send (canon lower (func $send-bytes) (memory $libc "memory")))
+
+  (core module $main
+    (import "host" "recv-bytes" (func $recv (param i32)))
+    (import "host" "send-bytes" (func $send (param i32 i32)))
+    (import "libc" "memory" (memory 1))
+    (table $funcs 1 funcref)
+    (func (export "init-exec-env") (param i32 i32 i32 i32 i32))
+    ;; recv-bytes puts the message's (ptr, len) at 0; the reply goes at 8
+    (func (export "run-msg-loop")
+      (block $end
+        (loop $next
+          (call $recv (i32.const 0))
+          (br_if $end (i32.eqz (i32.load (i32.const 4))))
+          (i32.store (i32.const 8)
+            (table.grow $funcs (ref.null func) (i32.load (i32.load (i32.const 0)))))
+          (call $send (i32.const 8) (i32.const 4))
+          (br $next)))))
+  (core instance $main
+    (instantiate $main
+      (with "libc" (instance $libc))
+      (with "host" (instance
+        (export "recv-bytes" (func $recv))
+        (export "send-bytes" (func $send))))))
+
+  (func (export "init-exec-env") (param "id-name" string) (param "log-tags" (option string))
+    (canon lift (core func $main "init-exec-env") (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (func (export "run-msg-loop")
+    (canon lift (core func $main "run-msg-loop"))))
@@ -0,0 +1,75 @@
+;; A guest for the `env` world that runs host tasks on request: for each
+;; message `run-msg-loop` either spawns a task (`s` then its payload) or
+;; awaits one (`a` then a little-endian u64 handle), until an empty one.
+;; Each reply is two messages: a one-byte result discriminant, then the
+;; handle, the task's payload or the error. Rebuild with
+;;   wasm-tools parse task.wat -o task.wasm
+(component
+  (import "recv-bytes" (func $recv-bytes (result (list u8))))
+  (import "send-bytes" (func $send-bytes (param "payload" (list u8))))
+  (import "spawn-task" (func $spawn-task (param "payload" (list u8)) (result (result u64 (error string)))))
+  (import "await-task" (func $await-task (param "handle" u64) (result (result (list u8) (error string)))))
+
+  (core module $libc
+    (memory (export "memory") 1)
+    (global $heap (mut i32) (i32.const 1024))
+    ;; never frees; the messages a test sends fit in the first page
+    (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+      (local $at i32)
+      (local.set $at
+        (i32.and (i32.add (global.get $heap) (i32.sub (local.get 2) (i32.const 1)))
+                 (i32.sub (i32.const 0) (local.get 2))))
+      (global.set $heap (i32.add (local.get $at) (local.get 3)))
+      (local.get $at)))
+  (core instance $libc (instantiate $libc))
+
+  (core func $recv (canon lower (func $recv-bytes) (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (core func $send (canon lower (func $send-bytes) (memory $libc "memory")))
+  (core func $spawn (canon lower (func $spawn-task) (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (core func $await (canon lower (func $await-task) (memory $libc "memory") (realloc (func $libc "realloc"))))
+
+  (core module $main
+    (import "host" "recv-bytes" (func $recv (param i32)))
+    (import "host" "send-bytes" (func $send (param i32 i32)))
+    (import "host" "spawn-task" (func $spawn (param i32 i32 i32)))
+    (import "host" "await-task" (func $await (param i64 i32)))
+    (import "libc" "memory" (memory 1))
+    (func (export "init-exec-env") (param i32 i32 i32 i32 i32))
+    ;; recv-bytes puts the message's (ptr, len) at 0; results go at 16,
+    ;; the discriminant first and the payload at 24 (spawn) or 20 (await)
+    (func (export "run-msg-loop")
+      (local $msg i32) (local $len i32)
+      (block $end
+        (loop $next
+          (call $recv (i32.const 0))
+          (local.set $msg (i32.load (i32.const 0)))
+          (local.set $len (i32.load (i32.const 4)))
+          (br_if $end (i32.eqz (local.get $len)))
+          (if (i32.eq (i32.load8_u (local.get $msg)) (i32.const 115))
+            (then
+              (call $spawn
+                (i32.add (local.get $msg) (i32.const 1))
+                (i32.sub (local.get $len) (i32.const 1))
+                (i32.const 16))
+              (call $send (i32.const 16) (i32.const 1))
+              (if (i32.load8_u (i32.const 16))
+                (then (call $send (i32.load (i32.const 24)) (i32.load (i32.const 28))))
+                (else (call $send (i32.const 24) (i32.const 8)))))
+            (else
+              (call $await (i64.load (i32.add (local.get $msg) (i32.const 1))) (i32.const 16))
+              (call $send (i32.const 16) (i32.const 1))
+              (call $send (i32.load (i32.const 20)) (i32.load (i32.const 24)))))
+          (br $next)))))
+  (core instance $main
+    (instantiate $main
+      (with "libc" (instance $libc))
+      (with "host" (instance
+        (export "recv-bytes" (func $recv))
+        (export "send-bytes" (func $send))
+        (export "spawn-task" (func $spawn))
+        (export "await-task" (func $await))))))
+
+  (func (export "init-exec-env") (param "id-name" string) (param "log-tags" (option string))
+    (canon lift (core func $main "init-exec-env") (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (func (export "run-msg-loop")
+    (canon lift (core func $main "run-msg-loop"))))
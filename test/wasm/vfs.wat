@@ -0,0 +1,118 @@
+;; A guest for the `env` world that opens files through wasi:filesystem.
+;; Each message is an operation byte and a path under the first preopen:
+;; `r` sends back the file's contents, `w` opens it for writing and sends
+;; "opened". A failure sends "error:" and the error-code's index. An empty
+;; message ends the loop. Rebuild with
+;;   wasm-tools parse vfs.wat -o vfs.wasm
+(component $C
+  (import "recv-bytes" (func $recv-bytes (result (list u8))))
+  (import "send-bytes" (func $send-bytes (param "payload" (list u8))))
+  (import "wasi:filesystem/types@0.2.6" (instance $types
+    (export "descriptor" (type $descriptor (sub resource)))
+    (type $descriptor-flags'
+      (flags "read" "write" "file-integrity-sync" "data-integrity-sync" "requested-write-sync"
+             "mutate-directory"))
+    (export "descriptor-flags" (type $descriptor-flags (eq $descriptor-flags')))
+    (type $path-flags' (flags "symlink-follow"))
+    (export "path-flags" (type $path-flags (eq $path-flags')))
+    (type $open-flags' (flags "create" "directory" "exclusive" "truncate"))
+    (export "open-flags" (type $open-flags (eq $open-flags')))
+    (type $error-code'
+      (enum "access" "would-block" "already" "bad-descriptor" "busy" "deadlock" "quota" "exist"
+            "file-too-large" "illegal-byte-sequence" "in-progress" "interrupted" "invalid" "io"
+            "is-directory" "loop" "too-many-links" "message-size" "name-too-long" "no-device"
+            "no-entry" "no-lock" "insufficient-memory" "insufficient-space" "not-directory"
+            "not-empty" "not-recoverable" "unsupported" "no-tty" "no-such-device" "overflow"
+            "not-permitted" "pipe" "read-only" "invalid-seek" "text-file-busy" "cross-device"))
+    (export "error-code" (type $error-code (eq $error-code')))
+    (export "[method]descriptor.open-at"
+      (func (param "self" (borrow $descriptor)) (param "path-flags" $path-flags) (param "path" string)
+            (param "open-flags" $open-flags) (param "flags" $descriptor-flags)
+            (result (result (own $descriptor) (error $error-code)))))
+    (export "[method]descriptor.read"
+      (func (param "self" (borrow $descriptor)) (param "length" u64) (param "offset" u64)
+            (result (result (tuple (list u8) bool) (error $error-code)))))))
+  (alias export $types "descriptor" (type $descriptor))
+  (import "wasi:filesystem/preopens@0.2.6" (instance $preopens
+    (alias outer $C $descriptor (type $descriptor))
+    (export "get-directories" (func (result (list (tuple (own $descriptor) string)))))))
+
+  (core module $libc
+    (memory (export "memory") 1)
+    (data (i32.const 16) "error:")
+    (data (i32.const 32) "opened")
+    (global $heap (mut i32) (i32.const 1024))
+    ;; never frees; the files and messages a test uses fit in the first page
+    (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+      (local $at i32)
+      (local.set $at
+        (i32.and (i32.add (global.get $heap) (i32.sub (local.get 2) (i32.const 1)))
+                 (i32.sub (i32.const 0) (local.get 2))))
+      (global.set $heap (i32.add (local.get $at) (local.get 3)))
+      (local.get $at)))
+  (core instance $libc (instantiate $libc))
+
+  (core func $recv (canon lower (func $recv-bytes) (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (core func $send (canon lower (func $send-bytes) (memory $libc "memory")))
+  (core func $get-directories
+    (canon lower (func $preopens "get-directories") (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (core func $open-at
+    (canon lower (func $types "[method]descriptor.open-at") (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (core func $read
+    (canon lower (func $types "[method]descriptor.read") (memory $libc "memory") (realloc (func $libc "realloc"))))
+
+  (core module $main
+    (import "libc" "memory" (memory 1))
+    (import "host" "recv-bytes" (func $recv (param i32)))
+    (import "host" "send-bytes" (func $send (param i32 i32)))
+    (import "host" "get-directories" (func $get-directories (param i32)))
+    (import "host" "open-at" (func $open-at (param i32 i32 i32 i32 i32 i32 i32)))
+    (import "host" "read" (func $read (param i32 i64 i64 i32)))
+    (func $fail (param $code i32)
+      (i32.store8 (i32.const 22) (local.get $code))
+      (call $send (i32.const 16) (i32.const 7)))
+    (func (export "init-exec-env") (param i32 i32 i32 i32 i32))
+    ;; results land at fixed addresses: the message at 0, the preopens at
+    ;; 40, open-at's result at 48 and read's at 56, each a case byte
+    ;; followed by the payload at the next word
+    (func (export "run-msg-loop")
+      (local $root i32) (local $msg i32) (local $len i32) (local $write i32)
+      (call $get-directories (i32.const 40))
+      (local.set $root (i32.load (i32.load (i32.const 40))))
+      (block $end
+        (loop $next
+          (call $recv (i32.const 0))
+          (local.set $msg (i32.load (i32.const 0)))
+          (local.set $len (i32.load (i32.const 4)))
+          (br_if $end (i32.eqz (local.get $len)))
+          (local.set $write (i32.eq (i32.load8_u (local.get $msg)) (i32.const 119)))
+          (call $open-at
+            (local.get $root) (i32.const 0)
+            (i32.add (local.get $msg) (i32.const 1)) (i32.sub (local.get $len) (i32.const 1))
+            (i32.const 0) (select (i32.const 2) (i32.const 1) (local.get $write))
+            (i32.const 48))
+          (if (i32.load8_u (i32.const 48))
+            (then (call $fail (i32.load8_u (i32.const 52))))
+            (else
+              (if (local.get $write)
+                (then (call $send (i32.const 32) (i32.const 6)))
+                (else
+                  (call $read (i32.load (i32.const 52)) (i64.const 4096) (i64.const 0) (i32.const 56))
+                  (if (i32.load8_u (i32.const 56))
+                    (then (call $fail (i32.load8_u (i32.const 60))))
+                    (else (call $send (i32.load (i32.const 60)) (i32.load (i32.const 64)))))))))
+          (br $next)))))
+  (core instance $main
+    (instantiate $main
+      (with "libc" (instance $libc))
+      (with "host" (instance
+        (export "recv-bytes" (func $recv))
+        (export "send-bytes" (func $send))
+        (export "get-directories" (func $get-directories))
+        (export "open-at" (func $open-at))
+        (export "read" (func $read))))))
+
+  (func (export "init-exec-env") (param "id-name" string) (param "log-tags" (option string))
+    (canon lift (core func $main "init-exec-env") (memory $libc "memory") (realloc (func $libc "realloc"))))
+  (func (export "run-msg-loop")
+    (canon lift (core func $main "run-msg-loop"))))